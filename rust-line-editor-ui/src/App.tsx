@@ -12,6 +12,13 @@ const rustPaths = {
   answers: "/state",
   solution: "/rust-src/src/solution.rs",
 };
+// CSRF token set by the server in the fplab_csrf cookie,
+// sent back in the X-CSRF-Token header of every POST
+const csrfToken = () => {
+  const match = document.cookie.match(/(?:^|;\s*)fplab_csrf=([^;]*)/);
+  return match ? decodeURIComponent(match[1]) : "";
+};
+
 type Status = "correct" | "incorrect" | "invalid";
 type Editable = { answer: string; solution: string } | null;
 type Section = {
@@ -165,7 +172,11 @@ export default function App() {
               fetch("/reset", {
                 method: "POST",
                 credentials: 'same-origin',
-                headers: { 'Accept': 'application/json', 'Content-Type': 'application/json' },
+                headers: {
+                  'Accept': 'application/json',
+                  'Content-Type': 'application/json',
+                  'X-CSRF-Token': csrfToken()
+                },
               })
               .then((_) => {
                 const problemSubsections = getSubsections(problems);
@@ -228,7 +239,11 @@ export default function App() {
         fetch(url, {
           method: "POST",
           credentials: 'same-origin',
-          headers: { 'Accept': 'application/json', 'Content-Type': 'application/json' },
+          headers: {
+            'Accept': 'application/json',
+            'Content-Type': 'application/json',
+            'X-CSRF-Token': csrfToken()
+          },
           body: JSON.stringify({
             to_grade: src,
             problem: i,
@@ -9,6 +9,13 @@ var ans_vec = [];
 var saved_answers = [];
 var saved_text = [];
 
+// CSRF token set by the server in the fplab_csrf cookie,
+// sent back in the X-CSRF-Token header of every POST
+function csrfToken() {
+  const match = document.cookie.match(/(?:^|;\s*)fplab_csrf=([^;]*)/);
+  return match ? decodeURIComponent(match[1]) : '';
+}
+
 // Child class
 // Stores question info:
 //      -- prompt
@@ -229,9 +236,9 @@ class Quiz extends React.Component {
   //      (1) retrieve last unanswered question
   //      (2) begin stopwatch
   componentDidMount() {
-    const { url } = this.props;
+    const { quiz } = this.props;
 
-    fetch(url, { credentials: 'same-origin' })
+    fetch(quiz, { credentials: 'same-origin' })
     .then((response) => {
         if (!response.ok) {
           // if not logged in, redirect home
@@ -308,6 +315,7 @@ class Quiz extends React.Component {
     let req_body = {};
     if (a_id === null) {
       req_body = {
+        type: 'heartbeat',
         ques_id: curr_qid,
         time_elapsed_question: timeElapsed,
         time_elapsed_hover: hoverTimeElapsed
@@ -315,6 +323,7 @@ class Quiz extends React.Component {
     }
     else {
       req_body = {
+          type: 'answer',
           ques_id: curr_qid,
           ans_id: a_id,
           free_response: text,
@@ -324,11 +333,15 @@ class Quiz extends React.Component {
       update = true;
     }
 
-    fetch('/submit',
+    fetch(`${this.props.quiz}submit/`,
       {
         method: 'POST',
         credentials: 'same-origin',
-        headers: { 'Accept': 'application/json', 'Content-Type': 'application/json' },
+        headers: {
+          'Accept': 'application/json',
+          'Content-Type': 'application/json',
+          'X-CSRF-Token': csrfToken()
+        },
         body: JSON.stringify(req_body)
       }
     )
@@ -351,7 +364,7 @@ class Quiz extends React.Component {
 
   render() {
     //   const { ques_url } = this.state;
-    const { url } = this.props;
+    const { quiz } = this.props;
     const { curr_qid } = this.state;
     const { total } = this.state;
 
@@ -380,7 +393,7 @@ class Quiz extends React.Component {
         Question, {
           // props
           key: curr_qid,
-          url: `${url + curr_qid}/`,
+          url: `${quiz}question/${curr_qid}/`,
           ans_vec: ans_vec,
           postResponse: this.postResponse,
           ref: 'quiz'
@@ -424,4 +437,6 @@ const debounce = (func, wait) => {
 };
 
 const domContainer = document.querySelector('#quiz');
-ReactDOM.render(create(Quiz, {url: '/question/'}), domContainer);
\ No newline at end of file
+// quiz to take, e.g. /fp/?quiz=ownership
+const slug = new URLSearchParams(window.location.search).get('quiz') || 'ownership';
+ReactDOM.render(create(Quiz, {quiz: `/quiz/${encodeURIComponent(slug)}/`}), domContainer);
\ No newline at end of file
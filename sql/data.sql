@@ -1,10 +1,10 @@
 PRAGMA foreign_keys = ON;
 
 -- INITIALIZE USERS TABLE (with admins)
-INSERT INTO users(token)
-VALUES ('f6e035e8c2a2898938e59e5a361b9faddf4e68e');
-INSERT INTO users(token)
-VALUES ('59e5a361b9faddf4e68ef6e035e8c2a2898938e');
+INSERT INTO users(token, admin)
+VALUES ('f6e035e8c2a2898938e59e5a361b9faddf4e68e', 1);
+INSERT INTO users(token, admin)
+VALUES ('59e5a361b9faddf4e68ef6e035e8c2a2898938e', 1);
 
 -- INITIALIZE QUESTIONS TABLE (with answers)
 INSERT INTO questions (question_id, answer, filename, prompt, contains_fr)
@@ -103,3 +103,10 @@ VALUES (9, 0, 'option 9');
 
 INSERT INTO choices (question_id, ans_id, choice_text)
 VALUES (10, 0, 'option 10');
+
+-- INITIALIZE QUIZZES TABLE (questions in order)
+INSERT INTO quizzes (quiz_id, slug, title, chapter)
+VALUES (1, 'ownership', 'What is Ownership?', 'ch04-01-what-is-ownership.html');
+
+INSERT INTO quiz_questions (quiz_id, position, question_id)
+SELECT 1, question_id, question_id FROM questions;
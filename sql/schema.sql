@@ -1,43 +1,259 @@
 PRAGMA journal_mode=WAL;
 PRAGMA foreign_keys = ON;
+-- bump together with db::SCHEMA_VERSION whenever tables change, adding a
+-- step to db::upgrade for existing databases
+PRAGMA user_version = 1;
 
 CREATE TABLE users (
   token VARCHAR(20) NOT NULL, -- username, at most 20 chars, primary key
-  curr_ques INTEGER DEFAULT 0,
 	created DATETIME DEFAULT CURRENT_TIMESTAMP, -- created, DATETIME type, automatically set by SQL engine to current date/time
+  username VARCHAR(64) UNIQUE, -- login name for password accounts, NULL for token-only participants
+  password TEXT, -- argon2 PHC string or legacy sha512$<salt>$<hash>, NULL for token-only participants
+  admin INTEGER NOT NULL DEFAULT 0, -- Boolean: instructor with access to /admin routes
+  section VARCHAR(64), -- course/section of the invite used to sign up
 	PRIMARY KEY (token)
 );
 
+CREATE TABLE invites (
+  code VARCHAR(20) NOT NULL,
+  section VARCHAR(64) NOT NULL, -- course/section new accounts are enrolled in
+  max_uses INTEGER NOT NULL DEFAULT 1, -- 1 for one-time codes
+  uses INTEGER NOT NULL DEFAULT 0,
+  revoked INTEGER NOT NULL DEFAULT 0, -- Boolean: revoked codes cannot be used
+  created_by VARCHAR(20),
+  created DATETIME DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (code),
+  FOREIGN KEY (created_by) REFERENCES users(token) ON UPDATE CASCADE ON DELETE SET NULL
+);
+
+/* SESSIONS */
+
+CREATE TABLE sessions (
+  id VARCHAR(64) NOT NULL, -- random id kept in the signed session cookie
+  token VARCHAR(20) NOT NULL,
+  created INTEGER NOT NULL, -- unix time
+  last_seen INTEGER NOT NULL, -- unix time, updated at most once a minute
+  user_agent VARCHAR(256), -- browser the session was started in
+  PRIMARY KEY (id),
+  FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+/* RESEARCH CONSENT */
+
+CREATE TABLE consents (
+  ID INTEGER PRIMARY KEY AUTOINCREMENT,
+  token VARCHAR(20) NOT NULL,
+  version INTEGER NOT NULL, -- version of the consent form answered
+  consented INTEGER NOT NULL, -- Boolean: opted in, the latest answer counts
+  created INTEGER NOT NULL, -- unix time
+  FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+/* SINGLE SIGN-ON (OIDC) */
+
+CREATE TABLE oidc_states (
+  state VARCHAR(64) NOT NULL, -- OAuth2 state of a sign-in, used once
+  nonce VARCHAR(64) NOT NULL, -- nonce the id_token must carry
+  verifier VARCHAR(128) NOT NULL, -- PKCE code verifier
+  link_token VARCHAR(20), -- user linking their account, NULL for sign-ins
+  invite VARCHAR(20), -- invite code enrolling a new user, if any
+  created INTEGER NOT NULL, -- unix time
+  PRIMARY KEY (state)
+);
+
+CREATE TABLE oidc_users (
+  issuer VARCHAR(256) NOT NULL, -- identity provider
+  sub VARCHAR(256) NOT NULL, -- provider's user id
+  token VARCHAR(20) NOT NULL,
+  email VARCHAR(256), -- verified email at the time of linking
+  PRIMARY KEY (issuer, sub),
+  UNIQUE (issuer, token),
+  FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+/* LOGIN THROTTLING */
+
+CREATE TABLE lockouts (
+  key VARCHAR(300) NOT NULL, -- "ip:<address>" or "credential:<token, username or invite>"
+  failures INTEGER NOT NULL DEFAULT 0, -- failures since the window was last reset
+  last_failure INTEGER NOT NULL, -- unix time
+  locked_until INTEGER NOT NULL DEFAULT 0, -- unix time, requests are refused until then
+  PRIMARY KEY (key)
+);
+
+CREATE TABLE failed_attempts (
+  ID INTEGER PRIMARY KEY AUTOINCREMENT,
+  route VARCHAR(256) NOT NULL,
+  ip VARCHAR(64) NOT NULL,
+  credential VARCHAR(256), -- token, username or invite tried, if any
+  status INTEGER NOT NULL, -- HTTP status returned
+  created DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+/* LMS INTEGRATION (LTI 1.3) */
+
+CREATE TABLE lti_states (
+  state VARCHAR(64) NOT NULL, -- OIDC state of a login initiation, used once
+  nonce VARCHAR(64) NOT NULL, -- nonce the launch's id_token must carry
+  issuer VARCHAR(256) NOT NULL,
+  created INTEGER NOT NULL, -- unix time
+  PRIMARY KEY (state)
+);
+
+CREATE TABLE lti_users (
+  issuer VARCHAR(256) NOT NULL, -- platform the user launched from
+  sub VARCHAR(256) NOT NULL, -- platform's user id
+  token VARCHAR(20) NOT NULL, -- local user created on first launch
+  PRIMARY KEY (issuer, sub),
+  FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE TABLE lti_lineitems (
+  token VARCHAR(20) NOT NULL,
+  path VARCHAR(256) NOT NULL, -- local path of the launched content, e.g. /assignment/
+  issuer VARCHAR(256) NOT NULL,
+  lineitem TEXT NOT NULL, -- AGS line item URL scores are posted to
+  client_id VARCHAR(256) NOT NULL DEFAULT '', -- tool's client id at the issuer, '' if saved before it was kept
+  PRIMARY KEY (token, path),
+  FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
 /* QUIZ RELATED */
 
 CREATE TABLE responses (
   token INTEGER NOT NULL,
+  quiz_id INTEGER NOT NULL, -- quiz the question was answered in, kept once the quiz is deleted
   question_id INTEGER NOT NULL,
-  answer INTEGER NOT NULL DEFAULT -2, -- default is -2 (not submited)
+  answer INTEGER NOT NULL DEFAULT -2, -- default is -2 (not submited), -1 if in response
   time_elapsed INTEGER NOT NULL DEFAULT 0, -- time in ms
   hover_time INTEGER NOT NULL DEFAULT 0, -- time spent hovering in ms
   free_response TEXT NOT NULL DEFAULT '',
-  PRIMARY KEY(token, question_id)
+  correct INTEGER NOT NULL DEFAULT 0, -- Boolean: answer matches questions.answer
+  response TEXT NOT NULL DEFAULT '', -- JSON answer to questions other than choice ones, see db::answer::Answer
+  attempts INTEGER NOT NULL DEFAULT 0, -- answers submitted so far, see quizzes.max_attempts
+  PRIMARY KEY(token, quiz_id, question_id)
   FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE
 );
 
+CREATE TABLE response_events ( -- every answer a user submitted, oldest first, see route::history
+  event_id INTEGER PRIMARY KEY AUTOINCREMENT,
+  token VARCHAR(20) NOT NULL,
+  quiz_id INTEGER, -- quiz the answer was given in, NULL once it is deleted
+  question_id INTEGER NOT NULL,
+  answer INTEGER NOT NULL, -- as in responses, -1 if in response
+  response TEXT NOT NULL DEFAULT '',
+  free_response TEXT NOT NULL DEFAULT '',
+  correct INTEGER NOT NULL DEFAULT 0, -- Boolean
+  time_elapsed INTEGER NOT NULL DEFAULT 0, -- total time spent on the question so far in ms
+  hover_time INTEGER NOT NULL DEFAULT 0, -- total time spent hovering so far in ms
+  created INTEGER NOT NULL, -- unix time
+  FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE,
+  FOREIGN KEY (quiz_id) REFERENCES quizzes(quiz_id) ON UPDATE CASCADE ON DELETE SET NULL
+);
+
 CREATE TABLE questions (
   question_id INTEGER NOT NULL,
-  answer INTEGER NOT NULL,
+  kind VARCHAR(16) NOT NULL DEFAULT 'choice', -- choice, multi_select, output, compile_fail or ordering
+  answer INTEGER NOT NULL, -- choice id or line number, -1 if in solution
+  solution TEXT NOT NULL DEFAULT '', -- JSON answer of other kinds, see db::answer::Answer
   filename VARCHAR(20) NOT NULL,
   prompt VARCHAR(256) NOT NULL,
   contains_fr INTEGER NOT NULL DEFAULT 0, -- Boolean: contains free response, default is no
+  explanation TEXT NOT NULL DEFAULT '', -- shown with the results once the quiz is finished
+  pool VARCHAR(64), -- equivalent questions share a pool, a quiz shows each user one of them, see route::shuffle
   PRIMARY KEY (question_id)
 );
 
+CREATE TABLE quizzes (
+  quiz_id INTEGER PRIMARY KEY AUTOINCREMENT,
+  slug VARCHAR(64) NOT NULL UNIQUE, -- used in URLs, e.g. /quiz/ownership/
+  title VARCHAR(256) NOT NULL,
+  chapter VARCHAR(256), -- book chapter the quiz is attached to, e.g. ch04-01-what-is-ownership.html
+  max_attempts INTEGER, -- answers a user may submit to each question, NULL for unlimited
+  time_limit INTEGER, -- seconds from when a user opens the quiz, NULL for untimed
+  feedback VARCHAR(16) NOT NULL DEFAULT 'end' -- immediate: grade each answer as it is submitted, end: only in the results
+);
+
+CREATE TABLE quiz_questions (
+  quiz_id INTEGER NOT NULL,
+  position INTEGER NOT NULL, -- 0-based order of the question within the quiz
+  question_id INTEGER NOT NULL,
+  PRIMARY KEY (quiz_id, position),
+  UNIQUE (quiz_id, question_id),
+  FOREIGN KEY (quiz_id) REFERENCES quizzes(quiz_id) ON UPDATE CASCADE ON DELETE CASCADE,
+  FOREIGN KEY (question_id) REFERENCES questions(question_id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE TABLE quiz_progress (
+  token VARCHAR(20) NOT NULL,
+  quiz_id INTEGER NOT NULL,
+  curr_ques INTEGER NOT NULL DEFAULT 0, -- position of the question to resume at
+  started INTEGER, -- unix time the user first opened the quiz
+  submitted INTEGER, -- unix time the user submitted the quiz, locking their answers
+  PRIMARY KEY (token, quiz_id),
+  FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE,
+  FOREIGN KEY (quiz_id) REFERENCES quizzes(quiz_id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE TABLE arrangements ( -- the order each user sees a quiz in, see route::shuffle
+  token VARCHAR(20) NOT NULL,
+  quiz_id INTEGER NOT NULL,
+  position INTEGER NOT NULL, -- where the user sees question_id, starting at 0
+  slot INTEGER NOT NULL, -- question of the quiz that question_id was drawn for
+  question_id INTEGER NOT NULL, -- slot itself or another question of its pool
+  choice_order TEXT NOT NULL DEFAULT '[]', -- JSON ans_ids in the order the user sees them
+  PRIMARY KEY (token, quiz_id, position),
+  FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE,
+  FOREIGN KEY (quiz_id) REFERENCES quizzes(quiz_id) ON UPDATE CASCADE ON DELETE CASCADE,
+  FOREIGN KEY (slot) REFERENCES questions(question_id) ON UPDATE CASCADE ON DELETE CASCADE,
+  FOREIGN KEY (question_id) REFERENCES questions(question_id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
 CREATE TABLE choices (
   question_id INTEGER NOT NULL,
   ans_id INTEGER NOT NULL,
   choice_text VARCHAR(256) NOT NULL,
+  explanation TEXT NOT NULL DEFAULT '', -- why the choice is right or wrong, shown with feedback
   PRIMARY KEY (question_id, ans_id)
   FOREIGN KEY (question_id) REFERENCES questions(question_id) ON UPDATE CASCADE ON DELETE CASCADE
 );
 
+/* FREE RESPONSE GRADING */
+
+CREATE TABLE rubric_items ( -- what free responses to a question are graded on, see api::grading
+  item_id INTEGER PRIMARY KEY AUTOINCREMENT,
+  question_id INTEGER NOT NULL,
+  position INTEGER NOT NULL, -- 0-based order within the rubric
+  description TEXT NOT NULL,
+  points INTEGER NOT NULL, -- negative for deductions
+  FOREIGN KEY (question_id) REFERENCES questions(question_id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE TABLE fr_grades (
+  token VARCHAR(20) NOT NULL, -- student whose free response is graded
+  quiz_id INTEGER NOT NULL, -- quiz the response was given in, as in responses
+  question_id INTEGER NOT NULL,
+  score INTEGER NOT NULL,
+  comment TEXT NOT NULL DEFAULT '',
+  grader VARCHAR(20), -- instructor who graded it last
+  graded INTEGER NOT NULL, -- unix time
+  released INTEGER NOT NULL DEFAULT 0, -- Boolean: shown to the student
+  PRIMARY KEY (token, quiz_id, question_id),
+  FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE,
+  FOREIGN KEY (grader) REFERENCES users(token) ON UPDATE CASCADE ON DELETE SET NULL,
+  FOREIGN KEY (question_id) REFERENCES questions(question_id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE TABLE fr_grade_items ( -- rubric items awarded in a grade
+  token VARCHAR(20) NOT NULL,
+  quiz_id INTEGER NOT NULL,
+  question_id INTEGER NOT NULL,
+  item_id INTEGER NOT NULL,
+  PRIMARY KEY (token, quiz_id, question_id, item_id),
+  FOREIGN KEY (token, quiz_id, question_id) REFERENCES fr_grades(token, quiz_id, question_id) ON UPDATE CASCADE ON DELETE CASCADE,
+  FOREIGN KEY (item_id) REFERENCES rubric_items(item_id) ON DELETE CASCADE
+);
+
 /* ASSIGNMENT-RELATED */
 
 CREATE TABLE edit_states (
@@ -54,6 +270,16 @@ CREATE TABLE scores (
   FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE
 );
 
+CREATE TABLE events ( -- learning analytics events of any type, see api::events::REGISTRY
+  event_id INTEGER PRIMARY KEY AUTOINCREMENT,
+  token VARCHAR(20) NOT NULL,
+  kind VARCHAR(32) NOT NULL, -- type of event, e.g. page_view or hover
+  client_time INTEGER NOT NULL, -- unix time in ms on the client's clock
+  received INTEGER NOT NULL, -- unix time the server recorded it
+  data TEXT NOT NULL DEFAULT '{}', -- JSON object of the fields of its type
+  FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
 CREATE TABLE user_hover (
   token INTEGER NOT NULL,
   svg_name VARCHAR(256) NOT NULL,
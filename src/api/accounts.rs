@@ -1,105 +1,190 @@
 // crates.io
 use actix_web::{
-    web, http::header, HttpResponse,
-    HttpRequest
+    web, HttpResponse
 };
 use actix_session::{Session};
-use actix_files::NamedFile;
 use serde::{Deserialize, Serialize};
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
 use handlebars::Handlebars;
+use std::collections::HashMap;
+use uuid::Uuid;
+// local
+use crate::db;
+use crate::api::encrypt;
+use crate::api::invites;
+use crate::api::nav::{self, Flash, Layout};
+use crate::middleware::sessions::{self, SID_KEY};
 
 // Page information fed to Handlebars
 #[derive(Serialize)]
 struct Context {
-    logged_in: u32
+    #[serde(flatten)]
+    layout: Layout,
+    // page to go to after logging in, sent back in a hidden field
+    next: String,
+    // "?next=..." for links between the login and create pages
+    next_query: String,
+    // values entered before a failed submission, never passwords
+    values: HashMap<&'static str, String>,
+    // validation errors by form field, "form" for the whole form
+    errors: HashMap<&'static str, String>
+}
+
+// Query of the login and create account pages
+// e.g.: GET /accounts/login/?next=/fp/
+#[derive(Deserialize, Debug)]
+pub struct NextQuery {
+    #[serde(default)]
+    next: String
+}
+
+// REQUIRES: n/a
+// MODIFIES: Cookies
+// EFFECTS: Render an account page, refilling the form with values
+//          and showing errors next to their fields
+fn render_page(
+    hb: &Handlebars<'_>,
+    session: &Session,
+    page: &str,
+    next: &str,
+    values: HashMap<&'static str, String>,
+    errors: HashMap<&'static str, String>
+) -> String {
+    let data = Context {
+        layout: Layout::new(session),
+        next: nav::safe_next(next).unwrap_or_default().to_string(),
+        next_query: nav::next_query(next),
+        values,
+        errors
+    };
+    hb.render(page, &data).unwrap()
 }
 
 // REQUIRES: GET method
-// MODIFIES: n/a
+// MODIFIES: Cookies
 // EFFECTS: Render login page
-//          If logged in, redirect to index page
-//          with appropriate login/logout button
+//          If logged in, redirect to the next page
 pub async fn render_login(
-    req: HttpRequest,
     session: Session,
-    hb: web::Data<Handlebars<'_>>
+    hb: web::Data<Handlebars<'_>>,
+    query: web::Query<NextQuery>
 ) -> HttpResponse {
     if let Some(_id) = session.get::<String>("login").unwrap() {
-        let data = Context {
-            logged_in: 1
-        };
-        let body = hb.render("index", &data).unwrap();
-
-        // redirect
-        return HttpResponse::Found().body(body)
+        return nav::redirect(nav::next_or_home(&query.next))
     }
 
-    //else open login page
-    let file : NamedFile = NamedFile::open("./rustviz/html/login.html").unwrap();
-    file.into_response(&req).unwrap()
+    HttpResponse::Ok()
+        .body(render_page(&hb, &session, "login", &query.next, HashMap::new(), HashMap::new()))
 }
 
 // Used for deserializing login form data
-// Matches key information in db
-// Used for querying, inserting into db
-#[derive(Deserialize, Serialize, Debug)]
-pub struct User {
-    token: String
+// Token-only participants fill in token,
+// password accounts fill in username and password
+#[derive(Deserialize, Debug)]
+pub struct LoginForm {
+    #[serde(default)]
+    token: String,
+    #[serde(default)]
+    username: String,
+    #[serde(default)]
+    password: String,
+    #[serde(default)]
+    next: String
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return token if it belongs to a token-only user
+//          Password accounts must log in with their password
+pub fn login_token(conn: &Connection, token: &str) -> rusqlite::Result<Option<String>> {
+    conn.query_row(
+        "SELECT token FROM users
+        WHERE token = ?1 AND password IS NULL",
+        params![token],
+        |row| row.get(0)
+    ).optional()
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database
+// EFFECTS: Return the user's token if username and password match
+//          Rehashes legacy sha512 passwords with Argon2 on success
+pub fn login_password(
+    conn: &Connection,
+    username: &str,
+    password: &str
+) -> rusqlite::Result<Option<String>> {
+    let row : Option<(String, String)> = conn.query_row(
+        "SELECT token, password FROM users
+        WHERE username = ?1 AND password IS NOT NULL",
+        params![username],
+        |row| Ok((row.get(0)?, row.get(1)?))
+    ).optional()?;
+
+    let (token, password_db) = match row {
+        Some(row) => row,
+        None => return Ok(None)
+    };
+
+    let (matches, upgraded) = encrypt::verify_password(password, &password_db);
+    if !matches {
+        return Ok(None)
+    }
+    if let Some(hash) = upgraded {
+        conn.execute(
+            "UPDATE users SET password = ?1
+            WHERE token = ?2",
+            params![hash, &token]
+        )?;
+    }
+    Ok(Some(token))
 }
 
 // REQUIRES: POST method
-// MODIFIES: Cookies
+// MODIFIES: Cookies, Database
 // EFFECTS: Creates cookie, logs user into site
+//          and redirects to the next page
 pub async fn user_login(
     session: Session,
-    form: web::Form<User>
+    hb: web::Data<Handlebars<'_>>,
+    form: web::Form<LoginForm>
 ) -> HttpResponse {
-    // if already logged in, redirect to home
+    // if already logged in, redirect to the next page
     if let Some(_id) = session.get::<String>("login").unwrap() {
-        // redirect
-        return HttpResponse::Found()
-                .header(header::LOCATION, "/")
-                .finish()
+        return nav::redirect(nav::next_or_home(&form.next))
     }
 
-    let conn = Connection::open("db/fp.sqlite3").unwrap();
-
-    let mut stmt = conn.prepare(
-        "SELECT * FROM users
-        WHERE token=?"
-    ).unwrap();
+    let conn = db::connect();
 
-    let mut qry = None;
-    
-    if let Ok(rows) = stmt.query_map(&[&form.token], |row| {
-        Ok(User {
-            token: row.get_unwrap(0),
-        })
-    }) {
-        // compile into Vec<User>
-        let mut vec : Vec<User> = rows.map(|r| r.unwrap()).collect();
-        qry = vec.pop();
+    let qry = if form.username.trim().is_empty() {
+        login_token(&conn, form.token.trim()).unwrap()
     }
+    else {
+        login_password(&conn, form.username.trim(), &form.password).unwrap()
+    };
 
     // check valid credentials
-    if let Some(usr) = qry {
-        if &form.token == usr.token.as_str() {
-            // remember user
-            session.set("login", form.token.clone()).unwrap();
-            // extend cookie every time user logs in
-            session.renew();
-
-            // StatusCode Found allows redirect
-            return HttpResponse::Found()
-                .header(header::LOCATION, "/rust-tutorial")
-                .finish()
-        }
+    if let Some(token) = qry {
+        // remember user
+        session.set("login", token).unwrap();
+        // extend cookie every time user logs in
+        session.renew();
+
+        return nav::redirect(nav::next_or_home(&form.next))
     }
-    
-    // else user not found, token incorrect return 403
+
+    // else user not found, token incorrect return 401
+    // with the login page, so the rate limiter counts the failure
+    let (field, value, error) = if form.username.trim().is_empty() {
+        ("token", form.token.trim(), "Incorrect token!")
+    }
+    else {
+        ("username", form.username.trim(), "Incorrect username or password!")
+    };
+    let values = HashMap::from([(field, value.to_string())]);
+    let errors = HashMap::from([(field, error.to_string())]);
     HttpResponse::Unauthorized()
-        .body("Incorrect token!")
+        .body(render_page(&hb, &session, "login", &form.next, values, errors))
 }
 
 // REQUIRES: POST method
@@ -107,91 +192,390 @@ pub async fn user_login(
 // EFFECTS: Remove cookies, log out current user
 pub async fn user_logout(session: Session) -> HttpResponse {
     session.remove("login");
-    HttpResponse::Found()
-        .header(header::LOCATION, "/rust-tutorial")
-        .finish()
+    nav::flash(&session, Flash::info("You have been logged out."));
+    nav::redirect(nav::HOME)
 }
 
 // REQUIRES: GET method
-// MODIFIES: n/a
+// MODIFIES: Cookies
 // EFFECTS: Render create account page
-//          If logged in, redirect to index page
+//          If logged in, redirect to the next page
 pub async fn render_create(
-    req: HttpRequest,
-    session: Session
+    session: Session,
+    hb: web::Data<Handlebars<'_>>,
+    query: web::Query<NextQuery>
 ) -> HttpResponse {
-    // if already logged in, redirect to home
     if let Some(_id) = session.get::<String>("login").unwrap() {
-        // redirect
-        return HttpResponse::Found()
-            .header(header::LOCATION, "/")
-            .finish()
+        return nav::redirect(nav::next_or_home(&query.next))
+    }
+
+    HttpResponse::Ok()
+        .body(render_page(&hb, &session, "create", &query.next, HashMap::new(), HashMap::new()))
+}
+
+// Tokens double as usernames, see users.token in sql/schema.sql
+pub const MAX_TOKEN_LEN: usize = 20;
+// see users.username in sql/schema.sql
+pub const MAX_USERNAME_LEN: usize = 64;
+pub const MIN_PASSWORD_LEN: usize = 8;
+
+// Reasons a new account cannot be created
+#[derive(Debug, PartialEq)]
+pub enum CreateError {
+    Empty,
+    TooLong(usize),
+    InvalidChar(char),
+    WeakPassword,
+    InvalidInvite,
+    Taken,
+    Db(String)
+}
+
+impl CreateError {
+    // message shown to the user on the create account page
+    pub fn message(&self) -> String {
+        match self {
+            CreateError::Empty => "Form field must be non-empty!".to_string(),
+            CreateError::TooLong(max) => format!("Must be at most {} characters!", max),
+            CreateError::InvalidChar(c) => format!(
+                "May only contain letters, digits, '-' and '_' (found '{}')!", c
+            ),
+            CreateError::WeakPassword => format!(
+                "Password must be at least {} characters!", MIN_PASSWORD_LEN
+            ),
+            CreateError::InvalidInvite => "Invalid or expired invitation code!".to_string(),
+            CreateError::Taken => "Token taken!".to_string(),
+            CreateError::Db(_) => "Could not create account, please try again!".to_string()
+        }
+    }
+}
+
+impl From<rusqlite::Error> for CreateError {
+    fn from(e: rusqlite::Error) -> Self {
+        match e {
+            // token is the primary key of users, username is unique
+            rusqlite::Error::SqliteFailure(ref err, _)
+                if err.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_PRIMARYKEY
+                    || err.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE =>
+                CreateError::Taken,
+            e => CreateError::Db(e.to_string())
+        }
     }
+}
 
-    //else open login page
-    let file : NamedFile = NamedFile::open("./rustviz/html/create.html").unwrap();
-    file.into_response(&req).unwrap()
+// REQUIRES: name has been trimmed
+// MODIFIES: n/a
+// EFFECTS: Check name is non-empty, has at most max_len characters
+//          and only uses URL-safe characters
+fn validate_name(name: &str, max_len: usize) -> Result<(), CreateError> {
+    if name.is_empty() {
+        return Err(CreateError::Empty)
+    }
+    if name.chars().count() > max_len {
+        return Err(CreateError::TooLong(max_len))
+    }
+    if let Some(c) = name.chars().find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_')) {
+        return Err(CreateError::InvalidChar(c))
+    }
+    Ok(())
+}
+
+// REQUIRES: token has been trimmed
+// MODIFIES: n/a
+// EFFECTS: Check token is non-empty, fits in users.token
+//          and only uses URL-safe characters
+pub fn validate_token(token: &str) -> Result<(), CreateError> {
+    validate_name(token, MAX_TOKEN_LEN)
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Check password is long enough
+pub fn validate_password(password: &str) -> Result<(), CreateError> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(CreateError::WeakPassword)
+    }
+    Ok(())
+}
+
+// REQUIRES: token, username and password have been validated
+// MODIFIES: Database
+// EFFECTS: Redeem invite and insert a new user in a single transaction
+//          Nothing is written if either step fails
+fn insert(
+    conn: &mut Connection,
+    token: &str,
+    username: Option<&str>,
+    password: Option<String>,
+    invite: &str
+) -> Result<(), CreateError> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let section = invites::redeem(&tx, invite)?
+        .ok_or(CreateError::InvalidInvite)?;
+    tx.execute(
+        "INSERT INTO users (token, username, password, section)
+        VALUES (?1, ?2, ?3, ?4)",
+        params![token, username, password, section]
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database
+// EFFECTS: Validate token and create a token-only user with the invite
+//          Returns CreateError::Taken if the token already exists,
+//          including when another request inserted it concurrently
+pub fn insert_user(conn: &mut Connection, token: &str, invite: &str) -> Result<(), CreateError> {
+    validate_token(token)?;
+    insert(conn, token, None, None, invite)
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database
+// EFFECTS: Validate username and password and create a password account
+//          with the invite and a freshly generated token
+//          Returns the new user's token
+pub fn insert_password_user(
+    conn: &mut Connection,
+    username: &str,
+    password: &str,
+    invite: &str
+) -> Result<String, CreateError> {
+    validate_name(username, MAX_USERNAME_LEN)?;
+    validate_password(password)?;
+
+    let token : String = Uuid::new_v4().to_simple().to_string()
+        .chars().take(MAX_TOKEN_LEN).collect();
+    insert(conn, &token, Some(username), Some(encrypt::hash_password(password)), invite)?;
+    Ok(token)
+}
+
+// Used for deserializing create account form data
+// token is used for anonymous participants, username and password
+// for password accounts; invite is always required
+#[derive(Deserialize, Debug)]
+pub struct CreateForm {
+    #[serde(default)]
+    token: String,
+    #[serde(default)]
+    username: String,
+    #[serde(default)]
+    password: String,
+    invite: String,
+    #[serde(default)]
+    next: String
 }
 
 // REQUIRES: POST method
 // MODIFIES: Database
 // EFFECTS: Create new user instance in db
 //          Create session cookies, track user
+//          and redirect to the next page
 pub async fn user_create(
     session: Session,
-    form: web::Form<User>
+    hb: web::Data<Handlebars<'_>>,
+    form: web::Form<CreateForm>
 ) -> HttpResponse {
+    // trim whitespaces
+    let invite = form.invite.trim();
+    let username = form.username.trim();
+    // refill the form after a failure
+    let values = HashMap::from([
+        ("token", form.token.trim().to_string()),
+        ("username", username.to_string()),
+        ("invite", invite.to_string())
+    ]);
+
     // if already logged in, return error
     if let Some(_id) = session.get::<String>("login").unwrap() {
+        let errors = HashMap::from([("form", "Please log out!".to_string())]);
         return HttpResponse::Conflict()
-                .body("Please log out!")
+            .body(render_page(&hb, &session, "create", &form.next, values, errors))
     }
 
-    // trim whitespaces
-    let token = form.token.trim().to_string();
+    let mut conn = db::connect();
+    let created = if username.is_empty() {
+        let token = form.token.trim().to_string();
+        insert_user(&mut conn, &token, invite).map(|_| token)
+    }
+    else {
+        insert_password_user(&mut conn, username, &form.password, invite)
+    };
 
-    // check all fields are non-empty
-    if token.is_empty() {
-        return HttpResponse::BadRequest()
-            .body("Form field must be non-empty!")
+    let token = match created {
+        Ok(token) => token,
+        Err(e) => {
+            let mut res = match e {
+                CreateError::Taken => HttpResponse::Conflict(),
+                // counted by the rate limiter against invite guessing
+                CreateError::InvalidInvite => HttpResponse::Forbidden(),
+                CreateError::Db(_) => {
+                    eprintln!("user_create: {:?}", e);
+                    HttpResponse::InternalServerError()
+                },
+                _ => HttpResponse::BadRequest()
+            };
+            let field = match e {
+                CreateError::InvalidInvite => "invite",
+                CreateError::WeakPassword => "password",
+                CreateError::Db(_) => "form",
+                _ if username.is_empty() => "token",
+                _ => "username"
+            };
+            let errors = HashMap::from([(field, e.message())]);
+            return res.body(render_page(&hb, &session, "create", &form.next, values, errors))
+        }
+    };
+
+    // only remember user once the account exists
+    session.set("login", token).unwrap();
+    nav::flash(&session, Flash::info("Welcome! Your account was created."));
+
+    nav::redirect(nav::next_or_home(&form.next))
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the logged in user's token if they are an admin,
+//          else the response to send back instead
+pub fn require_admin(session: &Session, conn: &Connection) -> Result<String, HttpResponse> {
+    let user = match session.get::<String>("login").unwrap() {
+        Some(user) => user,
+        None => return Err(HttpResponse::Unauthorized()
+            .body("Please log in!"))
+    };
+
+    let admin : Option<bool> = conn.query_row(
+        "SELECT admin FROM users
+        WHERE token = ?1",
+        params![&user],
+        |row| row.get(0)
+    ).optional().unwrap();
+
+    if admin == Some(true) {
+        Ok(user)
+    }
+    else {
+        Err(HttpResponse::Forbidden()
+            .body("Instructors only!"))
     }
+}
 
-    let conn = Connection::open("db/fp.sqlite3").unwrap();
+#[derive(Deserialize, Debug)]
+pub struct PasswordForm {
+    current: String,
+    new_password: String
+}
 
-    let mut stmt = conn.prepare(
-        "SELECT * FROM users
-        WHERE token"
-    ).unwrap();
+// REQUIRES: password is valid, see validate_password
+// MODIFIES: Database
+// EFFECTS: Set the password of the password account username and end
+//          its sessions except the one with id keep, in a single
+//          transaction, so whoever knew the old password is logged out
+//          Returns false if there is no such password account
+pub fn set_password(
+    conn: &mut Connection,
+    username: &str,
+    password: &str,
+    keep: Option<&str>
+) -> rusqlite::Result<bool> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let token : Option<String> = tx.query_row(
+        "SELECT token FROM users
+        WHERE username = ?1 AND password IS NOT NULL",
+        params![username],
+        |row| row.get(0)
+    ).optional()?;
+    let token = match token {
+        Some(token) => token,
+        None => return Ok(false)
+    };
 
-    let mut qry = None;
-    if let Ok(rows) = stmt.query_map(&[&token], |row| {
-        Ok(User {
-            token: row.get_unwrap(0),
-        })
-    }) {
-        // compile into Vec<User>
-        let mut vec : Vec<User> = rows.map(|r| r.unwrap()).collect();
-        qry = vec.pop();
+    tx.execute(
+        "UPDATE users SET password = ?1
+        WHERE token = ?2",
+        params![encrypt::hash_password(password), &token]
+    )?;
+    sessions::revoke_all(&tx, &token, keep)?;
+    tx.commit()?;
+    Ok(true)
+}
+
+// REQUIRES: POST method, logged in password account
+// MODIFIES: Database
+// EFFECTS: Replace the user's password after checking the current one
+pub async fn change_password(
+    session: Session,
+    form: web::Form<PasswordForm>
+) -> HttpResponse {
+    let user = match session.get::<String>("login").unwrap() {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized()
+            .body("Please log in!")
+    };
+
+    if let Err(e) = validate_password(&form.new_password) {
+        return HttpResponse::BadRequest()
+            .body(e.message())
     }
 
-    // check if token was already used
-    if let Some(_usr) = qry {
+    let mut conn = db::connect();
+
+    let username : Option<String> = conn.query_row(
+        "SELECT username FROM users
+        WHERE token = ?1 AND password IS NOT NULL",
+        params![&user],
+        |row| row.get(0)
+    ).optional().unwrap();
+    let username = match username {
+        Some(username) => username,
+        None => return HttpResponse::BadRequest()
+            .body("Account has no password!")
+    };
+
+    if login_password(&conn, &username, &form.current).unwrap().is_none() {
         return HttpResponse::Forbidden()
-            .body("Token taken!")
+            .body("Incorrect password!")
     }
 
-    // else
-    // set cookies, remember user
-    session.set("login", token.clone()).unwrap();
+    // other browsers are logged out, this one stays
+    let sid = session.get::<String>(SID_KEY).unwrap();
+    set_password(&mut conn, &username, &form.new_password, sid.as_deref()).unwrap();
 
-    // create user
-    conn.execute(
-        "INSERT INTO users (token)
-        VALUES (?1)",
-        params![&token]
-    ).unwrap();
+    HttpResponse::Ok()
+        .body("Password changed!")
+}
+
+#[derive(Serialize)]
+struct TemporaryPassword {
+    username: String,
+    password: String
+}
+
+// REQUIRES: POST method, logged in admin
+// MODIFIES: Database
+// EFFECTS: Replace a password account's password with a generated one
+//          and return it so the instructor can pass it on
+pub async fn reset_password(
+    session: Session,
+    username: web::Path<String>
+) -> HttpResponse {
+    let mut conn = db::connect();
+    if let Err(res) = require_admin(&session, &conn) {
+        return res
+    }
 
-    return HttpResponse::Found()
-        .header(header::LOCATION, "/")
-        .finish()
+    // every session of the account ends with the old password
+    let password = Uuid::new_v4().to_simple().to_string()[..12].to_string();
+    if !set_password(&mut conn, username.as_str(), &password, None).unwrap() {
+        return HttpResponse::NotFound()
+            .body("No such password account!")
+    }
+
+    HttpResponse::Ok()
+        .json(TemporaryPassword {
+            username: username.into_inner(),
+            password
+        })
 }
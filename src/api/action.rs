@@ -3,146 +3,337 @@ use actix_web::{
 };
 use actix_session::{Session};
 use rusqlite::{params, Connection};
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+// local
+use crate::config::env_or;
+use crate::db;
+use crate::api::consent::has_consented;
+use crate::middleware::csrf::{self, tokens_match};
 
+// Most actions a single request may carry
+pub const MAX_BATCH: usize = 500;
 
+// How actions are buffered before they are written, read from
+//      FPLAB_TELEMETRY_INTERVAL     seconds between flushes, 0 flushes every request (default: 10)
+//      FPLAB_TELEMETRY_MAX_PENDING  actions buffered before flushing early (default: 10000)
+#[derive(Debug, Clone)]
+pub struct TelemetryConfig {
+    pub interval: u64,
+    pub max_pending: usize
+}
+
+impl TelemetryConfig {
+    pub fn from_env() -> Self {
+        TelemetryConfig {
+            interval: env_or("FPLAB_TELEMETRY_INTERVAL", 10),
+            max_pending: env_or("FPLAB_TELEMETRY_MAX_PENDING", 10_000)
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct HoverInfo {
+    pub svg_name: String,
+    pub hover_item: String
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct SwitchInfo {
+    pub directory: String,
+    pub time_elpse: i64
+}
+
+// An action of a logged in user waiting to be written
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Hover(String, HoverInfo),
+    Switch(String, SwitchInfo)
+}
+
+// Actions sent on page unload with navigator.sendBeacon, which
+// cannot set headers, so the CSRF token travels in the body
+// e.g.: {"csrf_token": "...",
+//        "hovers": [{"svg_name": "vis_04_01_01", "hover_item": "s"}],
+//        "switches": [{"directory": "ch04-01", "time_elpse": 5000}]}
 #[derive(Deserialize, Debug)]
-pub struct hover_info {
-    svg_name: String,
-    hover_item: String
+pub struct Beacon {
+    pub csrf_token: String,
+    #[serde(default)]
+    pub hovers: Vec<HoverInfo>,
+    #[serde(default)]
+    pub switches: Vec<SwitchInfo>
+}
+
+// A single action or a batch of them
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Batch<T> {
+    Many(Vec<T>),
+    One(T)
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Parse a JSON action or array of actions, whatever the
+//          content type it was sent as (beacons send text/plain)
+pub fn parse_batch<T: DeserializeOwned>(body: &[u8]) -> Result<Vec<T>, String> {
+    let batch = match serde_json::from_slice(body).map_err(|e| e.to_string())? {
+        Batch::Many(actions) => actions,
+        Batch::One(action) => vec![action]
+    };
+    if batch.len() > MAX_BATCH {
+        return Err(format!("send at most {} actions at once", MAX_BATCH))
+    }
+    Ok(batch)
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Parse a beacon, see Beacon
+pub fn parse_beacon(body: &[u8]) -> Result<Beacon, String> {
+    let beacon : Beacon = serde_json::from_slice(body).map_err(|e| e.to_string())?;
+    if beacon.hovers.len() + beacon.switches.len() > MAX_BATCH {
+        return Err(format!("send at most {} actions at once", MAX_BATCH))
+    }
+    Ok(beacon)
+}
+
+// Actions received since the last flush, shared by all workers
+// Requests only append to memory; a background thread writes
+// everything in a single transaction, see schedule
+pub struct Telemetry {
+    pending: Mutex<Vec<Action>>,
+    config: TelemetryConfig
+}
+
+impl Telemetry {
+    pub fn new(config: TelemetryConfig) -> Self {
+        Telemetry {
+            pending: Mutex::new(Vec::new()),
+            config
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Telemetry::new(TelemetryConfig::from_env())
+    }
+
+    // MODIFIES: self
+    // EFFECTS: Buffer actions, returning true if they should be
+    //          flushed right away rather than waiting for the next flush
+    pub fn push<I: IntoIterator<Item = Action>>(&self, actions: I) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        pending.extend(actions);
+        self.config.interval == 0 || pending.len() >= self.config.max_pending
+    }
+
+    // MODIFIES: self
+    // EFFECTS: Remove and return the buffered actions
+    pub fn take(&self) -> Vec<Action> {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+
+    // MODIFIES: self
+    // EFFECTS: Put actions that failed to be written back in front of the
+    //          buffer, so the next flush retries them
+    //          Keeps at most config.max_pending actions, dropping the oldest
+    //          Returns the number of actions dropped
+    pub fn restore(&self, mut actions: Vec<Action>) -> usize {
+        let mut pending = self.pending.lock().unwrap();
+        actions.append(&mut pending);
+        let dropped = actions.len().saturating_sub(self.config.max_pending);
+        actions.drain(..dropped);
+        *pending = actions;
+        dropped
+    }
+
+    // MODIFIES: self, user_hover, user_page
+    // EFFECTS: Write the buffered actions with conn, returning how many
+    //          were recorded
+    //          Actions are kept in the buffer if writing them fails
+    pub fn flush_to(&self, conn: &mut Connection) -> rusqlite::Result<usize> {
+        let actions = self.take();
+        if actions.is_empty() {
+            return Ok(0)
+        }
+        match flush(conn, &actions) {
+            Ok(recorded) => Ok(recorded),
+            Err(e) => {
+                let dropped = self.restore(actions);
+                if dropped > 0 {
+                    eprintln!("telemetry: buffer full, dropped {} actions", dropped);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    // MODIFIES: self, user_hover, user_page
+    // EFFECTS: Write the buffered actions to the server's database,
+    //          logging failures, see flush_to
+    pub fn flush_pending(&self) {
+        if let Err(e) = self.flush_to(&mut db::connect()) {
+            eprintln!("telemetry: failed to record actions, retrying later: {}", e);
+        }
+    }
+}
+
+// REQUIRES: n/a
+// MODIFIES: user_hover, user_page
+// EFFECTS: Record actions in a single transaction, returning how many
+//          were recorded
+//          Actions of users who have not consented to the study (or no
+//          longer exist) are dropped; hovers over the same item are counted
+//          together
+pub fn flush(conn: &mut Connection, actions: &[Action]) -> rusqlite::Result<usize> {
+    let tx = conn.transaction()?;
+    let mut consented = HashMap::new();
+    let mut hovers : HashMap<(String, String, String), i64> = HashMap::new();
+    let mut recorded = 0;
+    for action in actions {
+        let token = match &action {
+            Action::Hover(token, _) | Action::Switch(token, _) => token.clone()
+        };
+        if !consented.contains_key(&token) {
+            consented.insert(token.clone(), has_consented(&tx, &token)?);
+        }
+        if !consented[&token] {
+            continue
+        }
+        match action {
+            Action::Hover(_, info) => {
+                *hovers.entry((token, info.svg_name.clone(), info.hover_item.clone())).or_insert(0) += 1;
+            },
+            Action::Switch(_, info) => {
+                tx.execute(
+                    "INSERT INTO user_page (token, page_item, page_hover)
+                    VALUES (?1, ?2, ?3)",
+                    params![&token, &info.directory, info.time_elpse]
+                )?;
+            }
+        }
+        recorded += 1;
+    }
+    for ((token, svg_name, hover_item), times) in hovers {
+        tx.execute(
+            "INSERT INTO user_hover (token, svg_name, hover_item, hover_times)
+            VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (token, svg_name, hover_item) DO UPDATE SET
+                hover_times = hover_times + excluded.hover_times",
+            params![&token, &svg_name, &hover_item, times]
+        )?;
+    }
+    tx.commit()?;
+    Ok(recorded)
+}
+
+// REQUIRES: n/a
+// MODIFIES: user_hover, user_page
+// EFFECTS: Flush telemetry every config.interval seconds
+//          on a background thread; does nothing if the interval is 0,
+//          in which case requests flush themselves
+pub fn schedule(telemetry: web::Data<Telemetry>) {
+    let interval = telemetry.config.interval;
+    if interval == 0 {
+        return
+    }
+
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(interval));
+        telemetry.flush_pending();
+    });
+}
+
+// REQUIRES: n/a
+// MODIFIES: telemetry
+// EFFECTS: Buffer actions and flush them if the buffer is full
+//          Answers 202 Accepted, since they are written later
+fn accept<I: IntoIterator<Item = Action>>(telemetry: &Telemetry, actions: I) -> HttpResponse {
+    if telemetry.push(actions) {
+        telemetry.flush_pending();
+    }
+    HttpResponse::Accepted().finish()
 }
 
 // REQUIRES: POST method
 // MODIFIES: Number of hover for each user
-// EFFECTS: record user hover
+// EFFECTS: record user hovers, one or a batch of them
+//          Dropped for users who have not consented to the study
 pub async fn user_hover(
     session: Session,
-    info: web::Json<hover_info>,
+    telemetry: web::Data<Telemetry>,
+    body: web::Bytes,
 ) -> HttpResponse {
     // if not logged in, require authentication
-    let user;
-    if let None = session.get::<String>("login").unwrap() {
-        return HttpResponse::Unauthorized()
+    let user = match session.get::<String>("login").unwrap() {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized()
             .body("Please log in!")
-    }
-    else {
-        user = session.get::<String>("login").unwrap().unwrap();
-    }
-
-    // connect to sqlite
-    let conn = Connection::open("db/fp.sqlite3").unwrap();
-
-    let mut stmt = conn.prepare(
-        "SELECT * FROM user_hover
-        WHERE token = ?1 AND svg_name = ?2 AND hover_item = ?3").unwrap();
-    
-    // update sql if no row found
-    if let Ok(mut rows) = stmt.query(&[&user, &info.svg_name, &info.hover_item]) {
-        if let None = rows.next().unwrap() {
-            conn.execute(
-                "INSERT INTO user_hover (token, svg_name, hover_item, hover_times)
-                VALUES (?1, ?2, ?3, ?4)",
-                params![&user, &info.svg_name, &info.hover_item, 0]
-            ).unwrap();
-        }
     };
 
-    // update sql
-    conn.execute(
-        "UPDATE user_hover
-        SET hover_times = hover_times + 1
-        WHERE token = ?1 AND svg_name = ?2 AND hover_item = ?3",
-        params![
-            &user, &info.svg_name, &info.hover_item
-        ]
-    ).unwrap();
-
-    println!("{:?}", info);
-    return HttpResponse::Found().finish()
+    let hovers = match parse_batch::<HoverInfo>(&body) {
+        Ok(hovers) => hovers,
+        Err(e) => return HttpResponse::BadRequest().body(e)
+    };
+    accept(&telemetry, hovers.into_iter().map(|info| Action::Hover(user.clone(), info)))
 }
 
-// REQUIRES: POST method
-// MODIFIES: change user's SQL record
-// EFFECTS: batch user's session result and push to SQL
-// pub async fn user_close(
-//     session: Session,
-//     info: web::Json<Info>,
-// ) -> HttpResponse {
-//     // connect to sqlite
-//     // let conn = Connection::open("db/fp.sqlite3").unwrap();
-//     println!("{:?}", info);
-//     return HttpResponse::Found().finish()
-// }
+// REQUIRES: POST method, sent with navigator.sendBeacon on page unload
+// MODIFIES: user_hover, user_page
+// EFFECTS: record the hovers and page switches a page had not sent yet
+//          Dropped for users who have not consented to the study
+//          Exempt from the CSRF middleware, the token is checked here
+pub async fn user_close(
+    session: Session,
+    telemetry: web::Data<Telemetry>,
+    body: web::Bytes,
+) -> HttpResponse {
+    // if not logged in, require authentication
+    let user = match session.get::<String>("login").unwrap() {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized()
+            .body("Please log in!")
+    };
 
-#[derive(Deserialize, Debug)]
-pub struct switch_info {
-    directory: String,
-    time_elpse: i64
+    let beacon = match parse_beacon(&body) {
+        Ok(beacon) => beacon,
+        Err(e) => return HttpResponse::BadRequest().body(e)
+    };
+    let token = session.get::<String>(csrf::SESSION_KEY).unwrap_or(None);
+    if !token.is_some_and(|token| tokens_match(&beacon.csrf_token, &token)) {
+        return HttpResponse::Forbidden()
+            .body("Request refused, please reload the page and try again!")
+    }
+
+    let hovers = beacon.hovers.into_iter().map(|info| Action::Hover(user.clone(), info));
+    let switches = beacon.switches.into_iter().map(|info| Action::Switch(user.clone(), info));
+    accept(&telemetry, hovers.chain(switches))
 }
-// REQUIRES: POST method
-// MODIFIES: SQL
-// EFFECTS: record user's time spend on each chapter
-// pub async fn user_switch(
-//     session: Session,
-//     info: web::Json<switch_info>,
-// ) -> HttpResponse {
-//     // if not logged in, require authentication
-//     let user;
-//     if let None = session.get::<String>("login").unwrap() {
-//         return HttpResponse::Unauthorized()
-//             .body("Please log in!")
-//     }
-//     else {
-//         user = session.get::<String>("login").unwrap().unwrap();
-//     }
-
-//     // connect to sqlite
-//     let conn = Connection::open("db/fp.sqlite3").unwrap();
-
-//     let mut stmt = conn.prepare(
-//         "SELECT * FROM user_page
-//         WHERE token=?1 AND page_item=?2").unwrap();
-
-//     if let Ok(mut rows) = stmt.query(&[&user, &info.directory]) {
-//         if let None = rows.next().unwrap() {
-//             conn.execute(
-//                 "INSERT INTO user_page(token, page_item, page_hover, page_visit)
-//                 VALUES(?1, ?2, ?3, ?4)", 
-//                 params![&user, &info.directory, 0, 0]).unwrap();
-//         }
-//     }
-//     conn.execute(
-//         "UPDATE user_page
-//         SET page_hover = page_hover + ?1, page_visit = page_visit + 1
-//         WHERE token = ?2 AND page_item = ?3",
-//         params![&info.time_elpse, &user, &info.directory]).unwrap();
-    
-//     // println!("-----------");
-//     // println!("{:?}", info);
-//     return HttpResponse::Found().finish()
-// }
 
+// REQUIRES: POST method
+// MODIFIES: user_page
+// EFFECTS: record user's time spent on each chapter, one or a batch of switches
+//          Dropped for users who have not consented to the study
 pub async fn user_switch(
     session: Session,
-    info: web::Json<switch_info>,
+    telemetry: web::Data<Telemetry>,
+    body: web::Bytes,
 ) -> HttpResponse {
     // if not logged in, require authentication
-    let user;
-    if let None = session.get::<String>("login").unwrap() {
-        return HttpResponse::Unauthorized()
+    let user = match session.get::<String>("login").unwrap() {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized()
             .body("Please log in!")
-    }
-    else {
-        user = session.get::<String>("login").unwrap().unwrap();
-    }
-
-    // connect to sqlite
-    let conn = Connection::open("db/fp.sqlite3").unwrap();
-    
-    conn.execute(
-        "INSERT INTO user_page(token, page_item, page_hover)
-        VALUES(?1, ?2, ?3)", 
-        params![&user, &info.directory, &info.time_elpse]).unwrap();
+    };
 
-    return HttpResponse::Found().finish()
-}
\ No newline at end of file
+    let switches = match parse_batch::<SwitchInfo>(&body) {
+        Ok(switches) => switches,
+        Err(e) => return HttpResponse::BadRequest().body(e)
+    };
+    accept(&telemetry, switches.into_iter().map(|info| Action::Switch(user.clone(), info)))
+}
@@ -0,0 +1,151 @@
+// crates.io
+use actix_web::{web, HttpResponse};
+use actix_session::{Session};
+use chrono::Utc;
+use handlebars::Handlebars;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+// local
+use crate::config::env_or;
+use crate::db;
+use crate::api::nav::{self, Flash, Layout};
+use crate::middleware::sessions::LOGIN_KEY;
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the version of the consent form shown to users, read from
+//          FPLAB_CONSENT_VERSION (default: 1)
+//          Bump it whenever rustviz/html/consent.html changes in substance,
+//          so everyone is asked again
+pub fn current_version() -> i64 {
+    env_or("FPLAB_CONSENT_VERSION", 1)
+}
+
+// REQUIRES: token belongs to a user
+// MODIFIES: Database
+// EFFECTS: Record token's answer to the given version of the consent form
+pub fn record(
+    conn: &Connection,
+    token: &str,
+    version: i64,
+    consented: bool,
+    now: i64
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO consents (token, version, consented, created)
+        VALUES (?1, ?2, ?3, ?4)",
+        params![token, version, consented, now]
+    )?;
+    Ok(())
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return token's latest answer to the given version
+//          of the consent form, None if they have not answered it
+pub fn answer(conn: &Connection, token: &str, version: i64) -> rusqlite::Result<Option<bool>> {
+    conn.query_row(
+        "SELECT consented FROM consents
+        WHERE token = ?1 AND version = ?2
+        ORDER BY ID DESC LIMIT 1",
+        params![token, version],
+        |row| row.get(0)
+    ).optional()
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return true if token opted in to the current consent form,
+//          i.e. their study telemetry may be recorded
+pub fn has_consented(conn: &Connection, token: &str) -> rusqlite::Result<bool> {
+    Ok(answer(conn, token, current_version())? == Some(true))
+}
+
+// Page information fed to Handlebars
+#[derive(Serialize)]
+struct Context {
+    #[serde(flatten)]
+    layout: Layout,
+    version: i64,
+    // latest answer to this version, None if not answered yet
+    consented: Option<bool>,
+    next: String
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ConsentQuery {
+    #[serde(default)]
+    next: String
+}
+
+// REQUIRES: GET method
+// MODIFIES: Cookies
+// EFFECTS: Render the consent form with the user's current answer
+//          If logged out, redirect to the login page
+pub async fn render_consent(
+    session: Session,
+    hb: web::Data<Handlebars<'_>>,
+    query: web::Query<ConsentQuery>
+) -> HttpResponse {
+    let user = match session.get::<String>(LOGIN_KEY).unwrap() {
+        Some(user) => user,
+        None => return nav::redirect(&format!("/accounts/login/{}", nav::next_query("/consent/")))
+    };
+
+    let version = current_version();
+    let data = Context {
+        layout: Layout::new(&session),
+        version,
+        consented: answer(&db::connect(), &user, version).unwrap(),
+        next: nav::safe_next(&query.next).unwrap_or_default().to_string()
+    };
+    HttpResponse::Ok()
+        .body(hb.render("consent", &data).unwrap())
+}
+
+// Used for deserializing the consent form
+// version is the one the user read, so a form left open
+// while the version changes is not counted for the new one
+#[derive(Deserialize, Debug)]
+pub struct ConsentForm {
+    version: i64,
+    consent: String,
+    #[serde(default)]
+    next: String
+}
+
+// REQUIRES: POST method, logged in
+// MODIFIES: Database, Cookies
+// EFFECTS: Record the user's answer and redirect to the next page
+pub async fn record_consent(
+    session: Session,
+    form: web::Form<ConsentForm>
+) -> HttpResponse {
+    let user = match session.get::<String>(LOGIN_KEY).unwrap() {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized()
+            .body("Please log in!")
+    };
+
+    let consented = match form.consent.as_str() {
+        "yes" => true,
+        "no" => false,
+        _ => return HttpResponse::BadRequest()
+            .body("Please choose whether you consent!")
+    };
+    if form.version != current_version() {
+        nav::flash(&session, Flash::error("The consent form has changed, please read it again."));
+        return nav::redirect("/consent/")
+    }
+
+    record(&db::connect(), &user, form.version, consented, Utc::now().timestamp()).unwrap();
+    let message = if consented {
+        "Thank you for taking part in the study!"
+    }
+    else {
+        "You have opted out, your activity will not be recorded."
+    };
+    nav::flash(&session, Flash::info(message));
+
+    nav::redirect(nav::next_or_home(&form.next))
+}
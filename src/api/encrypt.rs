@@ -1,5 +1,7 @@
 use sha2::{Sha512, Digest};
 use uuid::Uuid;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::{rand_core::OsRng, SaltString};
 
 // REQUIRES: Non-empty, UTF-8 password
 // MODIFIES: n/a
@@ -26,7 +28,7 @@ pub fn format_credentials(password: &str) -> String {
 // EFFECTS: Compare input password with DB password
 //          Decrypts using unique salt and returns true
 //          if passwords are the same
-pub fn correct_cred(pass_in: &String, password_db: String) -> bool {
+pub fn correct_cred(pass_in: &str, password_db: String) -> bool {
 
     // get original salt
     let v: Vec<&str> = password_db.split("$").collect();
@@ -41,3 +43,41 @@ pub fn correct_cred(pass_in: &String, password_db: String) -> bool {
     // v2 := original password
     hashed_in == v[2]
 }
+
+// REQUIRES: Non-empty, UTF-8 password
+// MODIFIES: n/a
+// EFFECTS: Hash password using Argon2id with a unique salt
+//          Return password string in PHC format
+//              i.e.: $argon2id$v=19$m=...,t=...,p=...$<salt>$<hash>
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .unwrap()
+        .to_string()
+}
+
+// REQUIRES: Non-empty, UTF-8 password
+// MODIFIES: n/a
+// EFFECTS: Compare input password with DB password, which is
+//          either an Argon2 PHC string or a legacy sha512$<salt>$<hash>
+//          Returns (matches, upgraded) where upgraded is a new Argon2 hash
+//          to store if the DB password used the legacy format
+pub fn verify_password(pass_in: &str, password_db: &str) -> (bool, Option<String>) {
+    if password_db.starts_with("sha512$") {
+        if password_db.split('$').count() != 3 {
+            return (false, None)
+        }
+        let matches = correct_cred(pass_in, password_db.to_string());
+        let upgraded = if matches { Some(hash_password(pass_in)) } else { None };
+        return (matches, upgraded)
+    }
+
+    let matches = match PasswordHash::new(password_db) {
+        Ok(parsed) => Argon2::default()
+            .verify_password(pass_in.as_bytes(), &parsed)
+            .is_ok(),
+        Err(_) => false
+    };
+    (matches, None)
+}
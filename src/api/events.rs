@@ -0,0 +1,279 @@
+// crates.io
+use actix_web::{web, HttpResponse};
+use actix_session::{Session};
+use chrono::Utc;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+// local
+use crate::db;
+use crate::api::consent::has_consented;
+use crate::middleware::sessions::LOGIN_KEY;
+
+// Most events a single request may carry
+pub const MAX_BATCH: usize = 200;
+
+// What a field of an event's data holds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldType {
+    // at most this many bytes
+    Text(usize),
+    // at least 0 and at most this much
+    Count(i64)
+}
+
+// A field of an event's data and whether it must be sent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Field {
+    pub name: &'static str,
+    pub ty: FieldType,
+    pub required: bool
+}
+
+// The data one type of event carries, see REGISTRY
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Schema {
+    pub name: &'static str,
+    pub fields: &'static [Field]
+}
+
+const fn required(name: &'static str, ty: FieldType) -> Field {
+    Field { name, ty, required: true }
+}
+
+const fn optional(name: &'static str, ty: FieldType) -> Field {
+    Field { name, ty, required: false }
+}
+
+// longest time one event may report, in milliseconds
+const MAX_MS: i64 = 6 * 60 * 60 * 1000;
+
+// Every type of event the server accepts
+// Add a schema here to collect a new kind of event; events table rows keep
+// their type's name, so rename types only together with their rows
+pub const REGISTRY: [Schema; 5] = [
+    Schema { name: "page_view", fields: &[
+        required("page", FieldType::Text(256)),
+        optional("referrer", FieldType::Text(256)),
+        optional("duration_ms", FieldType::Count(MAX_MS))
+    ]},
+    Schema { name: "hover", fields: &[
+        required("svg_name", FieldType::Text(256)),
+        required("hover_item", FieldType::Text(256)),
+        optional("duration_ms", FieldType::Count(MAX_MS))
+    ]},
+    Schema { name: "quiz_interaction", fields: &[
+        required("quiz", FieldType::Text(64)),
+        required("ques_id", FieldType::Count(u32::MAX as i64)),
+        // e.g. select, next, previous
+        required("action", FieldType::Text(32)),
+        optional("ans_id", FieldType::Count(u32::MAX as i64))
+    ]},
+    Schema { name: "editor_edit", fields: &[
+        required("document", FieldType::Text(256)),
+        optional("chars_added", FieldType::Count(1 << 20)),
+        optional("chars_removed", FieldType::Count(1 << 20)),
+        optional("content", FieldType::Text(64 * 1024))
+    ]},
+    Schema { name: "visualization_step", fields: &[
+        required("svg_name", FieldType::Text(256)),
+        required("step", FieldType::Count(10_000)),
+        // e.g. forward, back
+        optional("direction", FieldType::Text(16))
+    ]}
+];
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the schema of events of type name
+pub fn schema(name: &str) -> Option<&'static Schema> {
+    REGISTRY.iter().find(|schema| schema.name == name)
+}
+
+// An event as sent by clients
+// e.g.: {"type": "hover", "client_time": 1600000000000,
+//        "data": {"svg_name": "vis_04_01_01", "hover_item": "s"}}
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Event {
+    #[serde(rename = "type")]
+    pub kind: String,
+    // unix time in milliseconds on the client's clock
+    pub client_time: i64,
+    #[serde(default)]
+    pub data: Map<String, Value>
+}
+
+impl Schema {
+    // EFFECTS: Return what is wrong with data for this type of event
+    pub fn check(&self, data: &Map<String, Value>) -> Vec<String> {
+        let mut errors = Vec::new();
+        for field in self.fields {
+            let value = match data.get(field.name) {
+                Some(Value::Null) | None => {
+                    if field.required {
+                        errors.push(format!("{} is missing", field.name));
+                    }
+                    continue
+                },
+                Some(value) => value
+            };
+            match (field.ty, value) {
+                (FieldType::Text(max), Value::String(text)) if text.len() > max =>
+                    errors.push(format!("{} must be at most {} bytes", field.name, max)),
+                (FieldType::Text(_), Value::String(_)) => (),
+                (FieldType::Text(_), _) =>
+                    errors.push(format!("{} must be a string", field.name)),
+                (FieldType::Count(max), value) => match value.as_i64() {
+                    Some(n) if (0..=max).contains(&n) => (),
+                    _ => errors.push(format!("{} must be a whole number from 0 to {}", field.name, max))
+                }
+            }
+        }
+        for name in data.keys() {
+            if !self.fields.iter().any(|field| field.name == name) {
+                errors.push(format!("{} is not a field of {} events", name, self.name));
+            }
+        }
+        errors
+    }
+
+    // EFFECTS: Return the most bytes a valid event of this type takes as JSON
+    //          Text may be escaped, which takes two bytes for common
+    //          characters such as newlines and quotes
+    pub const fn max_len(&self) -> usize {
+        let mut len = EVENT_OVERHEAD;
+        let mut i = 0;
+        while i < self.fields.len() {
+            len += FIELD_OVERHEAD;
+            if let FieldType::Text(max) = self.fields[i].ty {
+                len += 2 * max;
+            }
+            i += 1;
+        }
+        len
+    }
+}
+
+// Bytes a field takes besides its value, e.g. "duration_ms": 1500,
+const FIELD_OVERHEAD: usize = 64;
+// Bytes an event takes besides its data, e.g. {"type": ..., "client_time": ...}
+const EVENT_OVERHEAD: usize = 128;
+
+const fn max_event_len() -> usize {
+    let mut len = 0;
+    let mut i = 0;
+    while i < REGISTRY.len() {
+        if REGISTRY[i].max_len() > len {
+            len = REGISTRY[i].max_len();
+        }
+        i += 1;
+    }
+    len
+}
+
+// Largest body a valid batch may be sent in
+pub const MAX_BODY: usize = MAX_BATCH * max_event_len();
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the JSON extractor's configuration for /events, which
+//          would otherwise refuse bodies over 32 KiB
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default().limit(MAX_BODY)
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return what is wrong with a batch of events, empty if
+//          it can be recorded, naming each event by its index
+pub fn validate(events: &[Event]) -> Vec<String> {
+    if events.len() > MAX_BATCH {
+        return vec![format!("send at most {} events at once", MAX_BATCH)]
+    }
+    let mut errors = Vec::new();
+    for (i, event) in events.iter().enumerate() {
+        let schema = match schema(&event.kind) {
+            Some(schema) => schema,
+            None => {
+                errors.push(format!("event {}: unknown type {:?}", i, event.kind));
+                continue
+            }
+        };
+        if event.client_time < 0 {
+            errors.push(format!("event {}: client_time must be unix time in milliseconds", i));
+        }
+        errors.extend(schema.check(&event.data).into_iter().map(|e| format!("event {}: {}", i, e)));
+    }
+    errors
+}
+
+// REQUIRES: validate(events) is empty, token belongs to a user
+// MODIFIES: events
+// EFFECTS: Record events from token received at now in a single
+//          transaction, returning how many were recorded
+pub fn record(conn: &mut Connection, token: &str, events: &[Event], now: i64) -> rusqlite::Result<usize> {
+    let tx = conn.transaction()?;
+    {
+        let mut stmt = tx.prepare(
+            "INSERT INTO events (token, kind, client_time, received, data)
+            VALUES (?1, ?2, ?3, ?4, ?5)"
+        )?;
+        for event in events {
+            stmt.execute(params![
+                token, &event.kind, event.client_time, now,
+                Value::Object(event.data.clone()).to_string()
+            ])?;
+        }
+    }
+    tx.commit()?;
+    Ok(events.len())
+}
+
+// Body of 400 responses listing everything wrong with a batch
+#[derive(Serialize)]
+struct Invalid {
+    errors: Vec<String>
+}
+
+#[derive(Serialize)]
+struct Recorded {
+    recorded: usize
+}
+
+// REQUIRES: POST method, logged in user
+// MODIFIES: events
+// EFFECTS: Record a batch of typed events, all or none of them
+//          Dropped for users who have not consented to the study
+//          e.g.: POST /events with
+//              [
+//                  {"type": "page_view", "client_time": 1600000000000,
+//                   "data": {"page": "ch04-01-what-is-ownership.html"}},
+//                  {"type": "visualization_step", "client_time": 1600000004000,
+//                   "data": {"svg_name": "vis_04_01_01", "step": 3}}
+//              ]
+pub async fn post_events(
+    session: Session,
+    events: web::Json<Vec<Event>>
+) -> HttpResponse {
+    let user = match session.get::<String>(LOGIN_KEY).unwrap() {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized()
+            .body("Please log in!")
+    };
+
+    let errors = validate(&events);
+    if !errors.is_empty() {
+        return HttpResponse::BadRequest()
+            .json(Invalid { errors })
+    }
+
+    let mut conn = db::connect();
+    // only record users who opted in
+    if !has_consented(&conn, &user).unwrap() {
+        return HttpResponse::NoContent().finish()
+    }
+
+    let recorded = record(&mut conn, &user, &events, Utc::now().timestamp()).unwrap();
+    HttpResponse::Created()
+        .json(Recorded { recorded })
+}
@@ -0,0 +1,523 @@
+// crates.io
+use actix_web::{web, HttpResponse};
+use actix_session::{Session};
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension, NO_PARAMS};
+use serde::{Deserialize, Serialize};
+// local
+use crate::db;
+use crate::api::accounts::require_admin;
+use crate::middleware::sessions::LOGIN_KEY;
+use crate::db::answer::Answer;
+use crate::route::quiz::find_quiz;
+use crate::route::shuffle;
+
+// Something free responses to a question are graded on
+// e.g.: {"item_id": 3, "description": "Mentions the move", "points": 2}
+// item_id is 0 for items added when setting a rubric
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct RubricItem {
+    #[serde(default)]
+    pub item_id: i64,
+    pub description: String,
+    // negative for deductions
+    pub points: i64
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the rubric of question_id in order
+pub fn rubric(conn: &Connection, question_id: i64) -> rusqlite::Result<Vec<RubricItem>> {
+    let mut stmt = conn.prepare(
+        "SELECT item_id, description, points FROM rubric_items
+        WHERE question_id = ?1
+        ORDER BY position"
+    )?;
+    let items = stmt.query_map(params![question_id], |row| Ok(RubricItem {
+        item_id: row.get(0)?,
+        description: row.get(1)?,
+        points: row.get(2)?
+    }))?;
+    items.collect()
+}
+
+// REQUIRES: question_id exists, the nonzero item_ids of items are distinct
+//           and in question_id's rubric
+// MODIFIES: rubric_items, fr_grade_items
+// EFFECTS: Set the rubric of question_id in a single transaction
+//          Items with an item_id are updated in place and stay awarded,
+//          items without one are added, and items left out are removed
+//          along with their awards; grades keep their scores and comments
+pub fn set_rubric(conn: &mut Connection, question_id: i64, items: &[RubricItem]) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    for old in rubric(&tx, question_id)? {
+        if !items.iter().any(|item| item.item_id == old.item_id) {
+            tx.execute(
+                "DELETE FROM rubric_items
+                WHERE item_id = ?1",
+                params![old.item_id]
+            )?;
+        }
+    }
+    for (position, item) in items.iter().enumerate() {
+        if item.item_id == 0 {
+            tx.execute(
+                "INSERT INTO rubric_items (question_id, position, description, points)
+                VALUES (?1, ?2, ?3, ?4)",
+                params![question_id, position as i64, &item.description, item.points]
+            )?;
+        }
+        else {
+            tx.execute(
+                "UPDATE rubric_items
+                SET position = ?1, description = ?2, points = ?3
+                WHERE item_id = ?4 AND question_id = ?5",
+                params![position as i64, &item.description, item.points, item.item_id, question_id]
+            )?;
+        }
+    }
+    tx.commit()
+}
+
+// A free response question and how far grading it has come
+#[derive(Serialize, Debug, PartialEq)]
+pub struct QueueEntry {
+    pub question_id: i64,
+    pub prompt: String,
+    pub responses: i64,
+    pub graded: i64,
+    pub released: i64
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return every free response question with the number of
+//          responses to it, graded and released, by id
+pub fn queue(conn: &Connection) -> rusqlite::Result<Vec<QueueEntry>> {
+    let mut stmt = conn.prepare(
+        "SELECT q.question_id, q.prompt,
+            (SELECT COUNT(*) FROM responses r
+                WHERE r.question_id = q.question_id AND r.free_response <> ''),
+            (SELECT COUNT(*) FROM fr_grades g
+                WHERE g.question_id = q.question_id),
+            (SELECT COUNT(*) FROM fr_grades g
+                WHERE g.question_id = q.question_id AND g.released)
+        FROM questions q
+        WHERE q.contains_fr
+        ORDER BY q.question_id"
+    )?;
+    let entries = stmt.query_map(NO_PARAMS, |row| Ok(QueueEntry {
+        question_id: row.get(0)?,
+        prompt: row.get(1)?,
+        responses: row.get(2)?,
+        graded: row.get(3)?,
+        released: row.get(4)?
+    }))?;
+    entries.collect()
+}
+
+// An instructor's grade of one free response
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Grade {
+    pub items: Vec<i64>, // item_ids awarded
+    pub score: i64,
+    pub comment: String,
+    pub grader: Option<String>,
+    pub graded: i64, // unix time
+    pub released: bool
+}
+
+// A free response waiting for or having a grade
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Submission {
+    pub token: String,
+    // quiz the response was given in, quizzes may share questions
+    pub quiz_id: i64,
+    pub free_response: String,
+    // answer to the rest of the question in canonical choice ids,
+    // None if not submitted
+    pub answer: Option<Answer>,
+    pub grade: Option<Grade>
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return token's grade of their free response to question_id
+//          in quiz_id
+pub fn grade(conn: &Connection, token: &str, quiz_id: i64, question_id: i64) -> rusqlite::Result<Option<Grade>> {
+    let grade = conn.query_row(
+        "SELECT score, comment, grader, graded, released FROM fr_grades
+        WHERE token = ?1 AND quiz_id = ?2 AND question_id = ?3",
+        params![token, quiz_id, question_id],
+        |row| Ok(Grade {
+            items: Vec::new(),
+            score: row.get(0)?,
+            comment: row.get(1)?,
+            grader: row.get(2)?,
+            graded: row.get(3)?,
+            released: row.get(4)?
+        })
+    ).optional()?;
+    let mut grade = match grade {
+        Some(grade) => grade,
+        None => return Ok(None)
+    };
+
+    let mut stmt = conn.prepare(
+        "SELECT gi.item_id FROM fr_grade_items gi
+        JOIN rubric_items ri ON ri.item_id = gi.item_id
+        WHERE gi.token = ?1 AND gi.quiz_id = ?2 AND gi.question_id = ?3
+        ORDER BY ri.position"
+    )?;
+    grade.items = stmt.query_map(params![token, quiz_id, question_id], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(Some(grade))
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return token's free response to question_id in quiz_id if
+//          it has been graded, in which case it may no longer change
+pub fn graded_response(conn: &Connection, token: &str, quiz_id: i64, question_id: i64) -> rusqlite::Result<Option<String>> {
+    conn.query_row(
+        "SELECT r.free_response FROM responses r
+        JOIN fr_grades g
+            ON g.token = r.token AND g.quiz_id = r.quiz_id AND g.question_id = r.question_id
+        WHERE r.token = ?1 AND r.quiz_id = ?2 AND r.question_id = ?3",
+        params![token, quiz_id, question_id],
+        |row| row.get(0)
+    ).optional()
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the free responses to question_id with their grades,
+//          ungraded ones first, then by token and quiz
+pub fn submissions(conn: &Connection, question_id: i64) -> rusqlite::Result<Vec<Submission>> {
+    let mut stmt = conn.prepare(
+        "SELECT r.token, r.quiz_id, r.free_response, r.answer, r.response FROM responses r
+        LEFT JOIN fr_grades g
+            ON g.token = r.token AND g.quiz_id = r.quiz_id AND g.question_id = r.question_id
+        WHERE r.question_id = ?1 AND r.free_response <> ''
+        ORDER BY g.token IS NOT NULL, r.token, r.quiz_id"
+    )?;
+    let rows : Vec<(String, i64, String, i64, String)> = stmt.query_map(
+        params![question_id],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+    )?.collect::<rusqlite::Result<_>>()?;
+
+    let mut submissions = Vec::new();
+    for (token, quiz_id, free_response, answer, response) in rows {
+        let grade = grade(conn, &token, quiz_id, question_id)?;
+        submissions.push(Submission {
+            // answer = -2 means the user has not submitted an answer yet
+            answer: Some(Answer::from_columns(answer, &response)).filter(|_| answer != -2),
+            token,
+            quiz_id,
+            free_response,
+            grade
+        });
+    }
+    Ok(submissions)
+}
+
+// JSON body of PUT /admin/grading/{question_id}/{token}/
+// quiz_id is the quiz of the response graded, see Submission
+// score defaults to the points of the items awarded
+#[derive(Deserialize, Debug)]
+pub struct GradeForm {
+    pub quiz_id: i64,
+    #[serde(default)]
+    pub items: Vec<i64>,
+    #[serde(default)]
+    pub score: Option<i64>,
+    #[serde(default)]
+    pub comment: String
+}
+
+// REQUIRES: the items of form are in question_id's rubric
+// MODIFIES: fr_grades, fr_grade_items
+// EFFECTS: Grade token's free response to question_id in form.quiz_id
+//          in a single transaction, replacing any earlier grade but
+//          keeping whether it was released
+pub fn save_grade(
+    conn: &mut Connection,
+    token: &str,
+    question_id: i64,
+    form: &GradeForm,
+    grader: &str,
+    now: i64
+) -> rusqlite::Result<()> {
+    let points : i64 = rubric(conn, question_id)?.iter()
+        .filter(|item| form.items.contains(&item.item_id))
+        .map(|item| item.points)
+        .sum();
+
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT INTO fr_grades (token, quiz_id, question_id, score, comment, grader, graded)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        ON CONFLICT (token, quiz_id, question_id) DO UPDATE SET
+            score = excluded.score, comment = excluded.comment,
+            grader = excluded.grader, graded = excluded.graded",
+        params![token, form.quiz_id, question_id, form.score.unwrap_or(points), &form.comment, grader, now]
+    )?;
+    tx.execute(
+        "DELETE FROM fr_grade_items
+        WHERE token = ?1 AND quiz_id = ?2 AND question_id = ?3",
+        params![token, form.quiz_id, question_id]
+    )?;
+    for item_id in &form.items {
+        tx.execute(
+            "INSERT OR IGNORE INTO fr_grade_items (token, quiz_id, question_id, item_id)
+            VALUES (?1, ?2, ?3, ?4)",
+            params![token, form.quiz_id, question_id, item_id]
+        )?;
+    }
+    tx.commit()
+}
+
+// REQUIRES: n/a
+// MODIFIES: fr_grades
+// EFFECTS: Show students every grade of free responses to question_id
+//          Returns the number of grades newly released
+pub fn release(conn: &Connection, question_id: i64) -> rusqlite::Result<usize> {
+    conn.execute(
+        "UPDATE fr_grades SET released = 1
+        WHERE question_id = ?1 AND NOT released",
+        params![question_id]
+    )
+}
+
+// Released grade of a free response as its student sees it
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct Feedback {
+    pub qid: u32, // position the student sees the question at
+    pub score: i64,
+    // sum of the rubric's positive points
+    pub max_score: i64,
+    pub comment: String,
+    // descriptions of the rubric items awarded
+    pub items: Vec<String>
+}
+
+// REQUIRES: token belongs to a user
+// MODIFIES: arrangements
+// EFFECTS: Return the released grades of token's free responses to
+//          quiz_id by the positions token sees
+pub fn feedback(conn: &Connection, token: &str, quiz_id: i64) -> rusqlite::Result<Vec<Feedback>> {
+    let arrangement = shuffle::arrangement(conn, token, quiz_id)?;
+    let mut feedback = Vec::new();
+    for (position, shown) in arrangement.questions.iter().enumerate() {
+        let grade = match grade(conn, token, quiz_id, shown.question_id)? {
+            Some(grade) if grade.released => grade,
+            _ => continue
+        };
+        let rubric = rubric(conn, shown.question_id)?;
+        feedback.push(Feedback {
+            qid: position as u32,
+            score: grade.score,
+            max_score: rubric.iter().map(|item| item.points.max(0)).sum(),
+            items: rubric.into_iter()
+                .filter(|item| grade.items.contains(&item.item_id))
+                .map(|item| item.description)
+                .collect(),
+            comment: grade.comment
+        });
+    }
+    Ok(feedback)
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return true if question_id is a free response question
+fn is_free_response(conn: &Connection, question_id: i64) -> bool {
+    conn.query_row(
+        "SELECT contains_fr FROM questions
+        WHERE question_id = ?1",
+        params![question_id],
+        |row| row.get(0)
+    ).optional().unwrap() == Some(true)
+}
+
+// REQUIRES: GET method, logged in admin
+// MODIFIES: n/a
+// EFFECTS: List free response questions and how many responses
+//          to each are graded and released
+pub async fn grading_queue(session: Session) -> HttpResponse {
+    let conn = db::connect();
+    if let Err(res) = require_admin(&session, &conn) {
+        return res
+    }
+
+    HttpResponse::Ok()
+        .json(queue(&conn).unwrap())
+}
+
+#[derive(Serialize)]
+struct Responses {
+    rubric: Vec<RubricItem>,
+    submissions: Vec<Submission>
+}
+
+// REQUIRES: GET method, logged in admin
+// MODIFIES: n/a
+// EFFECTS: Return the rubric of a free response question and the
+//          responses to grade, ungraded ones first
+//          e.g.: GET /admin/grading/0/ returns
+//              {
+//                  rubric: [{item_id: 1, description: 'Mentions the move', points: 2}],
+//                  submissions: [
+//                      {token: 'student-1', quiz_id: 1, free_response: '...', answer: 0, grade: null},
+//                      ...
+//                  ]
+//              }
+pub async fn list_responses(
+    session: Session,
+    question_id: web::Path<i64>
+) -> HttpResponse {
+    let conn = db::connect();
+    if let Err(res) = require_admin(&session, &conn) {
+        return res
+    }
+
+    if !is_free_response(&conn, *question_id) {
+        return HttpResponse::NotFound()
+            .body("No such free response question!")
+    }
+    HttpResponse::Ok()
+        .json(Responses {
+            rubric: rubric(&conn, *question_id).unwrap(),
+            submissions: submissions(&conn, *question_id).unwrap()
+        })
+}
+
+// REQUIRES: PUT method, logged in admin
+// MODIFIES: Database
+// EFFECTS: Set the rubric of a free response question, see set_rubric
+//          e.g.: PUT /admin/grading/0/rubric/ with
+//              [{"item_id": 3, "description": "Mentions the move", "points": 2},
+//               {"description": "Names the owner", "points": 1}]
+pub async fn update_rubric(
+    session: Session,
+    question_id: web::Path<i64>,
+    items: web::Json<Vec<RubricItem>>
+) -> HttpResponse {
+    let mut conn = db::connect();
+    if let Err(res) = require_admin(&session, &conn) {
+        return res
+    }
+
+    if !is_free_response(&conn, *question_id) {
+        return HttpResponse::NotFound()
+            .body("No such free response question!")
+    }
+    if items.iter().any(|item| item.description.trim().is_empty()) {
+        return HttpResponse::BadRequest()
+            .body("Rubric items need a description!")
+    }
+    let current = rubric(&conn, *question_id).unwrap();
+    for (i, item) in items.iter().enumerate() {
+        let known = current.iter().any(|old| old.item_id == item.item_id);
+        let repeated = items[..i].iter().any(|other| other.item_id == item.item_id);
+        if item.item_id != 0 && (!known || repeated) {
+            return HttpResponse::BadRequest()
+                .body("No such rubric item!")
+        }
+    }
+
+    set_rubric(&mut conn, *question_id, &items).unwrap();
+    HttpResponse::Ok()
+        .json(rubric(&conn, *question_id).unwrap())
+}
+
+// REQUIRES: PUT method, logged in admin
+// MODIFIES: Database
+// EFFECTS: Grade a user's free response
+//          e.g.: PUT /admin/grading/0/student-1/ with
+//              {"quiz_id": 1, "items": [1], "comment": "Right, s is moved"}
+pub async fn update_grade(
+    session: Session,
+    path: web::Path<(i64, String)>,
+    form: web::Json<GradeForm>
+) -> HttpResponse {
+    let mut conn = db::connect();
+    let grader = match require_admin(&session, &conn) {
+        Ok(grader) => grader,
+        Err(res) => return res
+    };
+    let (question_id, token) = path.into_inner();
+
+    let answered : bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM responses
+        WHERE token = ?1 AND quiz_id = ?2 AND question_id = ?3 AND free_response <> ''",
+        params![&token, form.quiz_id, question_id],
+        |row| row.get(0)
+    ).unwrap();
+    if !answered {
+        return HttpResponse::NotFound()
+            .body("No such response!")
+    }
+    let rubric = rubric(&conn, question_id).unwrap();
+    if form.items.iter().any(|id| !rubric.iter().any(|item| item.item_id == *id)) {
+        return HttpResponse::BadRequest()
+            .body("No such rubric item!")
+    }
+
+    save_grade(&mut conn, &token, question_id, &form, &grader, Utc::now().timestamp()).unwrap();
+    HttpResponse::Ok()
+        .finish()
+}
+
+#[derive(Serialize)]
+struct Released {
+    released: usize
+}
+
+// REQUIRES: POST method, logged in admin
+// MODIFIES: Database
+// EFFECTS: Show students their grades of a free response question
+pub async fn release_grades(
+    session: Session,
+    question_id: web::Path<i64>
+) -> HttpResponse {
+    let conn = db::connect();
+    if let Err(res) = require_admin(&session, &conn) {
+        return res
+    }
+
+    if !is_free_response(&conn, *question_id) {
+        return HttpResponse::NotFound()
+            .body("No such free response question!")
+    }
+    HttpResponse::Ok()
+        .json(Released { released: release(&conn, *question_id).unwrap() })
+}
+
+// REQUIRES: GET method, logged in user
+// MODIFIES: n/a
+// EFFECTS: Return the released grades of the user's free responses
+//          to a quiz by the positions they see
+//          e.g.: GET /quiz/ownership/feedback/ returns
+//              [{qid: 2, score: 2, max_score: 3, comment: '...',
+//                items: ['Mentions the move']}]
+pub async fn quiz_feedback(
+    session: Session,
+    slug: web::Path<String>
+) -> HttpResponse {
+    let user = match session.get::<String>(LOGIN_KEY).unwrap() {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized()
+            .body("Please log in!")
+    };
+
+    let conn = db::connect();
+    let quiz_id = match find_quiz(&conn, &slug).unwrap() {
+        Some(quiz_id) => quiz_id,
+        None => return HttpResponse::NotFound()
+            .body("No such quiz!")
+    };
+
+    HttpResponse::Ok()
+        .json(feedback(&conn, &user, quiz_id).unwrap())
+}
@@ -0,0 +1,170 @@
+// crates.io
+use actix_web::{web, HttpResponse};
+use actix_session::{Session};
+use serde::{Deserialize, Serialize};
+use rusqlite::{params, Connection, OptionalExtension, NO_PARAMS};
+use uuid::Uuid;
+// local
+use crate::db;
+use crate::api::accounts::require_admin;
+
+// Length of generated invitation codes
+const CODE_LEN: usize = 10;
+// Upper bound on codes generated per request
+const MAX_BATCH: u32 = 500;
+
+// REQUIRES: called inside the transaction that creates the user
+// MODIFIES: Database
+// EFFECTS: Use up one redemption of the invite code
+//          Returns the invite's section, or None if the code does not
+//          exist, was revoked or has no uses left
+pub fn redeem(conn: &Connection, code: &str) -> rusqlite::Result<Option<String>> {
+    let n = conn.execute(
+        "UPDATE invites
+        SET uses = uses + 1
+        WHERE code = ?1 AND revoked = 0 AND uses < max_uses",
+        params![code]
+    )?;
+    if n == 0 {
+        return Ok(None)
+    }
+
+    conn.query_row(
+        "SELECT section FROM invites
+        WHERE code = ?1",
+        params![code],
+        |row| row.get(0)
+    ).optional()
+}
+
+// REQUIRES: max_uses > 0
+// MODIFIES: Database
+// EFFECTS: Generate count new codes for section, each usable max_uses times
+//          Returns the new codes
+pub fn generate(
+    conn: &mut Connection,
+    section: &str,
+    max_uses: u32,
+    count: u32,
+    created_by: Option<&str>
+) -> rusqlite::Result<Vec<String>> {
+    let tx = conn.transaction()?;
+    let mut codes = Vec::new();
+    for _ in 0..count {
+        let code : String = Uuid::new_v4().to_simple().to_string()
+            .to_uppercase().chars().take(CODE_LEN).collect();
+        tx.execute(
+            "INSERT INTO invites (code, section, max_uses, created_by)
+            VALUES (?1, ?2, ?3, ?4)",
+            params![&code, section, max_uses, created_by]
+        )?;
+        codes.push(code);
+    }
+    tx.commit()?;
+    Ok(codes)
+}
+
+// JSON body of POST /admin/invites/
+// e.g.: { "section": "eecs490-001", "max_uses": 1, "count": 30 }
+#[derive(Deserialize)]
+pub struct NewInvites {
+    section: String,
+    #[serde(default = "one")]
+    max_uses: u32,
+    #[serde(default = "one")]
+    count: u32
+}
+
+fn one() -> u32 { 1 }
+
+// REQUIRES: POST method, logged in admin
+// MODIFIES: Database
+// EFFECTS: Generate a batch of invitation codes for a section
+pub async fn create_invites(
+    session: Session,
+    form: web::Json<NewInvites>
+) -> HttpResponse {
+    let mut conn = db::connect();
+    let admin = match require_admin(&session, &conn) {
+        Ok(admin) => admin,
+        Err(res) => return res
+    };
+
+    let section = form.section.trim();
+    if section.is_empty() || form.max_uses == 0
+        || form.count == 0 || form.count > MAX_BATCH {
+        return HttpResponse::BadRequest()
+            .body(format!(
+                "section must be non-empty, max_uses positive and count between 1 and {}!",
+                MAX_BATCH
+            ))
+    }
+
+    let codes = generate(&mut conn, section, form.max_uses, form.count, Some(&admin)).unwrap();
+    HttpResponse::Created()
+        .json(codes)
+}
+
+#[derive(Serialize)]
+pub struct Invite {
+    code: String,
+    section: String,
+    max_uses: u32,
+    uses: u32,
+    revoked: bool,
+    created: String
+}
+
+// REQUIRES: GET method, logged in admin
+// MODIFIES: n/a
+// EFFECTS: List all invitation codes, newest first
+pub async fn list_invites(session: Session) -> HttpResponse {
+    let conn = db::connect();
+    if let Err(res) = require_admin(&session, &conn) {
+        return res
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT code, section, max_uses, uses, revoked, created
+        FROM invites ORDER BY created DESC, code"
+    ).unwrap();
+    let invites : Vec<Invite> = stmt.query_map(NO_PARAMS, |row| {
+        Ok(Invite {
+            code: row.get_unwrap(0),
+            section: row.get_unwrap(1),
+            max_uses: row.get_unwrap(2),
+            uses: row.get_unwrap(3),
+            revoked: row.get_unwrap(4),
+            created: row.get_unwrap(5)
+        })
+    }).unwrap().map(|r| r.unwrap()).collect();
+
+    HttpResponse::Ok()
+        .json(invites)
+}
+
+// REQUIRES: POST method, logged in admin
+// MODIFIES: Database
+// EFFECTS: Revoke an invitation code so it can no longer be used
+pub async fn revoke_invite(
+    session: Session,
+    code: web::Path<String>
+) -> HttpResponse {
+    let conn = db::connect();
+    if let Err(res) = require_admin(&session, &conn) {
+        return res
+    }
+
+    let n = conn.execute(
+        "UPDATE invites SET revoked = 1
+        WHERE code = ?1",
+        params![code.as_str()]
+    ).unwrap();
+
+    if n == 0 {
+        return HttpResponse::NotFound()
+            .body("No such invitation code!")
+    }
+    HttpResponse::Ok()
+        .finish()
+}
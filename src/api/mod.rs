@@ -1,3 +1,12 @@
-// pub mod encrypt;
+pub mod encrypt;
 pub mod accounts;
 pub mod action;
+pub mod invites;
+pub mod sso;
+pub mod nav;
+pub mod sessions;
+pub mod study;
+pub mod consent;
+pub mod quizzes;
+pub mod grading;
+pub mod events;
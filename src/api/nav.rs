@@ -0,0 +1,126 @@
+// crates.io
+use actix_web::{http::header, HttpResponse};
+use actix_session::{Session};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+// local
+use crate::db;
+use crate::middleware::csrf::csrf_token;
+
+// Where users land after logging in when no next page was asked for
+pub const HOME: &str = "/rust-tutorial";
+
+// Session key of messages waiting to be shown on the next rendered page
+const FLASH_KEY: &str = "flash";
+
+// A one-time message shown on the next rendered page
+// level is used as a CSS class, e.g. "flash-error"
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Flash {
+    pub level: String,
+    pub message: String
+}
+
+impl Flash {
+    pub fn info(message: &str) -> Self {
+        Flash { level: "info".to_string(), message: message.to_string() }
+    }
+
+    pub fn error(message: &str) -> Self {
+        Flash { level: "error".to_string(), message: message.to_string() }
+    }
+}
+
+// REQUIRES: n/a
+// MODIFIES: Cookies
+// EFFECTS: Queue flash for the next page the user sees
+pub fn flash(session: &Session, flash: Flash) {
+    let mut flashes = session.get::<Vec<Flash>>(FLASH_KEY).unwrap_or(None).unwrap_or_default();
+    flashes.push(flash);
+    session.set(FLASH_KEY, flashes).unwrap();
+}
+
+// REQUIRES: n/a
+// MODIFIES: Cookies
+// EFFECTS: Return and forget the queued flash messages
+pub fn take_flashes(session: &Session) -> Vec<Flash> {
+    let flashes = session.get::<Vec<Flash>>(FLASH_KEY).unwrap_or(None).unwrap_or_default();
+    if !flashes.is_empty() {
+        session.remove(FLASH_KEY);
+    }
+    flashes
+}
+
+// Fields used by rustviz/html/layout.html, shared by all server-rendered
+// pages; page contexts include it with #[serde(flatten)]
+#[derive(Serialize, Debug)]
+pub struct Layout {
+    // username or token of the logged in user, None if logged out
+    pub user: Option<String>,
+    pub csrf_token: String,
+    pub flashes: Vec<Flash>
+}
+
+impl Layout {
+    // REQUIRES: n/a
+    // MODIFIES: Cookies
+    // EFFECTS: Collect the layout fields for the session's user,
+    //          taking the queued flash messages
+    pub fn new(session: &Session) -> Self {
+        let user = session.get::<String>("login").unwrap_or(None).map(|token| {
+            let username : Option<String> = db::connect().query_row(
+                "SELECT username FROM users
+                WHERE token = ?1",
+                params![&token],
+                |row| row.get(0)
+            ).optional().unwrap().flatten();
+            username.unwrap_or(token)
+        });
+        Layout {
+            user,
+            csrf_token: csrf_token(session),
+            flashes: take_flashes(session)
+        }
+    }
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return next if it is a path on this site, else None
+//          Only absolute paths are accepted, so "//evil.example",
+//          "/\evil.example" and "https://evil.example" are refused
+pub fn safe_next(next: &str) -> Option<&str> {
+    let next = next.trim();
+    let local = next.starts_with('/')
+        && !next.starts_with("//")
+        && !next.chars().any(|c| c == '\\' || c.is_control());
+    Some(next).filter(|_| local)
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the page to go to after logging in
+pub fn next_or_home(next: &str) -> &str {
+    safe_next(next).unwrap_or(HOME)
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return "?next=<next>" to carry next to another page,
+//          or "" if next is not a path on this site
+pub fn next_query(next: &str) -> String {
+    match safe_next(next) {
+        Some(next) => format!("?{}", serde_urlencoded::to_string([("next", next)]).unwrap()),
+        None => String::new()
+    }
+}
+
+// REQUIRES: location is a path on this site
+// MODIFIES: n/a
+// EFFECTS: Redirect to location with 303 See Other, so the
+//          browser follows a POST with a GET
+pub fn redirect(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .header(header::LOCATION, location)
+        .finish()
+}
@@ -0,0 +1,390 @@
+// crates.io
+use actix_web::{web, HttpResponse};
+use actix_session::{Session};
+use rusqlite::{params, Connection, TransactionBehavior, NO_PARAMS};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+// local
+use crate::db::{self, QuestionSpec};
+use crate::db::import::{self, Format, QuizFile, Visualizations};
+use crate::api::accounts::require_admin;
+use crate::route::limits::{self, Settings};
+use crate::route::quiz::find_quiz;
+
+// Body of 400 responses listing everything wrong with a submission
+#[derive(Serialize)]
+struct Invalid {
+    errors: Vec<String>
+}
+
+fn invalid(errors: Vec<String>) -> HttpResponse {
+    HttpResponse::BadRequest()
+        .json(Invalid { errors })
+}
+
+// REQUIRES: GET method, logged in admin
+// MODIFIES: n/a
+// EFFECTS: List all questions with their choices, by id
+pub async fn list_questions(session: Session) -> HttpResponse {
+    let conn = db::connect();
+    if let Err(res) = require_admin(&session, &conn) {
+        return res
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT question_id FROM questions
+        ORDER BY question_id"
+    ).unwrap();
+    let ids : Vec<i64> = stmt.query_map(NO_PARAMS, |row| row.get(0))
+        .unwrap().map(|r| r.unwrap()).collect();
+    let questions : Vec<QuestionSpec> = ids.into_iter()
+        .filter_map(|id| db::load_question(&conn, id).unwrap())
+        .collect();
+
+    HttpResponse::Ok()
+        .json(questions)
+}
+
+// REQUIRES: GET method, logged in admin
+// MODIFIES: n/a
+// EFFECTS: Return a question with its choices
+pub async fn get_question(
+    session: Session,
+    question_id: web::Path<i64>
+) -> HttpResponse {
+    let conn = db::connect();
+    if let Err(res) = require_admin(&session, &conn) {
+        return res
+    }
+
+    match db::load_question(&conn, *question_id).unwrap() {
+        Some(question) => HttpResponse::Ok()
+            .json(question),
+        None => HttpResponse::NotFound()
+            .body("No such question!")
+    }
+}
+
+// REQUIRES: POST method, logged in admin
+// MODIFIES: Database
+// EFFECTS: Create a question from a JSON QuestionSpec
+//          e.g.: POST /admin/questions/ with
+//              {
+//                  "question_id": 11,
+//                  "answer": 0,
+//                  "filename": "vis_04_01_01",
+//                  "prompt": "What is the output of the function?",
+//                  "choices": ["Does not compile", "5", "15"],
+//                  "explanation": "s is moved into takes_ownership"
+//              }
+pub async fn create_question(
+    session: Session,
+    question: web::Json<QuestionSpec>
+) -> HttpResponse {
+    let mut conn = db::connect();
+    if let Err(res) = require_admin(&session, &conn) {
+        return res
+    }
+
+    let errors = import::validate_question(&question, &Visualizations::from_env());
+    if !errors.is_empty() {
+        return invalid(errors)
+    }
+    if db::load_question(&conn, question.question_id).unwrap().is_some() {
+        return HttpResponse::Conflict()
+            .body("Question id is taken!")
+    }
+
+    db::import_questions(&mut conn, &[question.into_inner()]).unwrap();
+    HttpResponse::Created()
+        .finish()
+}
+
+// REQUIRES: PUT method, logged in admin
+// MODIFIES: Database
+// EFFECTS: Replace a question and its choices, keeping it in its quizzes
+//          The id in the path wins over the one in the body
+//          Its kind, choices and answer are fixed once it has been
+//          answered, as stored answers and grades refer to them
+pub async fn update_question(
+    session: Session,
+    question_id: web::Path<i64>,
+    question: web::Json<QuestionSpec>
+) -> HttpResponse {
+    let mut conn = db::connect();
+    if let Err(res) = require_admin(&session, &conn) {
+        return res
+    }
+
+    let mut question = question.into_inner();
+    question.question_id = *question_id;
+    let errors = import::validate_question(&question, &Visualizations::from_env());
+
+    // check and write at once, so no answer comes in between
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate).unwrap();
+    let old = match db::load_question(&tx, question.question_id).unwrap() {
+        Some(old) => old,
+        None => return HttpResponse::NotFound()
+            .body("No such question!")
+    };
+    if !errors.is_empty() {
+        return invalid(errors)
+    }
+    if db::regrades(&old, &question) && db::num_answered(&tx, question.question_id).unwrap() > 0 {
+        return HttpResponse::Conflict()
+            .body("Question has been answered, add a new question to change its choices or answer!")
+    }
+
+    db::write_questions(&tx, &[question]).unwrap();
+    tx.commit().unwrap();
+    HttpResponse::Ok()
+        .finish()
+}
+
+// REQUIRES: DELETE method, logged in admin
+// MODIFIES: Database
+// EFFECTS: Delete a question, removing it from its quizzes
+pub async fn delete_question(
+    session: Session,
+    question_id: web::Path<i64>
+) -> HttpResponse {
+    let mut conn = db::connect();
+    if let Err(res) = require_admin(&session, &conn) {
+        return res
+    }
+
+    if !db::remove_question(&mut conn, *question_id).unwrap() {
+        return HttpResponse::NotFound()
+            .body("No such question!")
+    }
+    HttpResponse::Ok()
+        .finish()
+}
+
+// JSON body of POST /admin/quizzes/
+// questions are ids of existing questions, in order
+#[derive(Deserialize, Debug)]
+pub struct QuizSpec {
+    slug: String,
+    title: String,
+    #[serde(default)]
+    chapter: Option<String>,
+    questions: Vec<i64>
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return what is wrong with a list of question ids for a quiz
+fn validate_ids(conn: &Connection, ids: &[i64]) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut seen = HashSet::new();
+    for id in ids {
+        if !seen.insert(id) {
+            errors.push(format!("question {}: id is used more than once", id));
+        }
+        let exists : bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM questions
+            WHERE question_id = ?1",
+            params![id],
+            |row| row.get(0)
+        ).unwrap();
+        if !exists {
+            errors.push(format!("question {}: no such question", id));
+        }
+    }
+    errors
+}
+
+// REQUIRES: POST method, logged in admin
+// MODIFIES: Database
+// EFFECTS: Create a quiz of existing questions, or replace the quiz
+//          with the same slug
+pub async fn create_quiz(
+    session: Session,
+    quiz: web::Json<QuizSpec>
+) -> HttpResponse {
+    let mut conn = db::connect();
+    if let Err(res) = require_admin(&session, &conn) {
+        return res
+    }
+
+    let mut errors : Vec<String> = import::validate_slug(&quiz.slug).into_iter().collect();
+    if quiz.title.trim().is_empty() {
+        errors.push("title is empty".to_string());
+    }
+    errors.extend(validate_ids(&conn, &quiz.questions));
+    if !errors.is_empty() {
+        return invalid(errors)
+    }
+
+    db::create_quiz(&mut conn, &quiz.slug, &quiz.title, &quiz.questions).unwrap();
+    db::attach_quiz(&conn, &quiz.slug, quiz.chapter.as_deref()).unwrap();
+    HttpResponse::Created()
+        .finish()
+}
+
+// REQUIRES: PUT method, logged in admin
+// MODIFIES: Database
+// EFFECTS: Set which questions a quiz has and their order
+//          e.g.: PUT /admin/quizzes/ownership/questions/ with [2, 0, 1]
+pub async fn reorder_quiz(
+    session: Session,
+    slug: web::Path<String>,
+    ids: web::Json<Vec<i64>>
+) -> HttpResponse {
+    let mut conn = db::connect();
+    if let Err(res) = require_admin(&session, &conn) {
+        return res
+    }
+
+    let title : String = match find_quiz(&conn, &slug).unwrap() {
+        Some(quiz_id) => conn.query_row(
+            "SELECT title FROM quizzes
+            WHERE quiz_id = ?1",
+            params![quiz_id],
+            |row| row.get(0)
+        ).unwrap(),
+        None => return HttpResponse::NotFound()
+            .body("No such quiz!")
+    };
+    let errors = validate_ids(&conn, &ids);
+    if !errors.is_empty() {
+        return invalid(errors)
+    }
+
+    db::create_quiz(&mut conn, &slug, &title, &ids).unwrap();
+    HttpResponse::Ok()
+        .finish()
+}
+
+// REQUIRES: PUT method, logged in admin
+// MODIFIES: Database
+// EFFECTS: Set a quiz's attempt and time limits, null for none,
+//          and when users get feedback on their answers
+//          e.g.: PUT /admin/quizzes/ownership/settings/ with
+//              {"max_attempts": 2, "time_limit": 900, "feedback": "immediate"}
+pub async fn update_settings(
+    session: Session,
+    slug: web::Path<String>,
+    settings: web::Json<Settings>
+) -> HttpResponse {
+    let conn = db::connect();
+    if let Err(res) = require_admin(&session, &conn) {
+        return res
+    }
+
+    let mut errors = Vec::new();
+    if settings.max_attempts == Some(0) {
+        errors.push("max_attempts must be 1 or more".to_string());
+    }
+    if settings.time_limit == Some(0) {
+        errors.push("time_limit must be 1 second or more".to_string());
+    }
+    if !errors.is_empty() {
+        return invalid(errors)
+    }
+
+    if !limits::set_settings(&conn, &slug, &settings).unwrap() {
+        return HttpResponse::NotFound()
+            .body("No such quiz!")
+    }
+    HttpResponse::Ok()
+        .finish()
+}
+
+// REQUIRES: DELETE method, logged in admin
+// MODIFIES: Database
+// EFFECTS: Delete a quiz and everyone's progress on it
+//          Its questions and responses to them are kept
+pub async fn delete_quiz(
+    session: Session,
+    slug: web::Path<String>
+) -> HttpResponse {
+    let conn = db::connect();
+    if let Err(res) = require_admin(&session, &conn) {
+        return res
+    }
+
+    let n = conn.execute(
+        "DELETE FROM quizzes
+        WHERE slug = ?1",
+        params![slug.as_str()]
+    ).unwrap();
+    if n == 0 {
+        return HttpResponse::NotFound()
+            .body("No such quiz!")
+    }
+    HttpResponse::Ok()
+        .finish()
+}
+
+// REQUIRES: GET method, logged in admin
+// MODIFIES: n/a
+// EFFECTS: List how often each variant of each question pool was drawn,
+//          answered and answered correctly
+pub async fn list_pools(session: Session) -> HttpResponse {
+    let conn = db::connect();
+    if let Err(res) = require_admin(&session, &conn) {
+        return res
+    }
+
+    HttpResponse::Ok()
+        .json(db::pool_stats(&conn).unwrap())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ImportQuery {
+    format: String,
+    #[serde(default)]
+    dry_run: bool
+}
+
+#[derive(Serialize)]
+struct Imported {
+    changes: Vec<import::Change>,
+    applied: bool
+}
+
+// REQUIRES: POST method, logged in admin
+// MODIFIES: Database unless dry_run
+// EFFECTS: Import a quiz file sent as the body and return the changes
+//          it makes, only listing them if dry_run
+//          Nothing is imported if it would regrade answered questions
+//          e.g.: POST /admin/quizzes/import/?format=md&dry_run=true
+pub async fn import_quiz(
+    session: Session,
+    query: web::Query<ImportQuery>,
+    body: String
+) -> HttpResponse {
+    let mut conn = db::connect();
+    if let Err(res) = require_admin(&session, &conn) {
+        return res
+    }
+
+    let format = match Format::from_name(&query.format) {
+        Some(format) => format,
+        None => return invalid(vec!["format must be toml, yaml or md".to_string()])
+    };
+    let quiz : QuizFile = match import::parse(&body, format) {
+        Ok(quiz) => quiz,
+        Err(e) => return invalid(vec![e])
+    };
+    let errors = import::validate(&quiz, &Visualizations::from_env());
+    if !errors.is_empty() {
+        return invalid(errors)
+    }
+
+    let changes = if query.dry_run {
+        import::diff(&conn, &quiz).unwrap()
+    }
+    else {
+        import::apply(&mut conn, &quiz).unwrap()
+    };
+    if changes.iter().any(import::Change::is_conflict) {
+        return HttpResponse::Conflict()
+            .json(Imported { changes, applied: false })
+    }
+    let applied = !query.dry_run && !changes.is_empty();
+    HttpResponse::Ok()
+        .json(Imported { changes, applied })
+}
@@ -0,0 +1,136 @@
+// crates.io
+use actix_web::{web, HttpResponse};
+use actix_session::{Session};
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+// local
+use crate::db;
+use crate::api::accounts::require_admin;
+use crate::middleware::sessions::{self, SessionConfig, LOGIN_KEY, SID_KEY};
+
+// REQUIRES: GET method, logged in
+// MODIFIES: n/a
+// EFFECTS: List the user's active sessions, marking the current one
+pub async fn list_sessions(session: Session) -> HttpResponse {
+    let user = match session.get::<String>(LOGIN_KEY).unwrap() {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized()
+            .body("Please log in!")
+    };
+    let sid = session.get::<String>(SID_KEY).unwrap();
+
+    let conn = db::connect();
+    let idle = SessionConfig::from_env().idle;
+    let list = sessions::list(&conn, &user, sid.as_deref(), idle, Utc::now().timestamp()).unwrap();
+
+    HttpResponse::Ok()
+        .json(list)
+}
+
+// REQUIRES: POST method, logged in
+// MODIFIES: Database, Cookies
+// EFFECTS: End one of the user's sessions
+//          Ending the current session logs the user out
+pub async fn revoke_session(
+    session: Session,
+    id: web::Path<String>
+) -> HttpResponse {
+    let user = match session.get::<String>(LOGIN_KEY).unwrap() {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized()
+            .body("Please log in!")
+    };
+
+    let conn = db::connect();
+    if !sessions::revoke(&conn, &user, &id).unwrap() {
+        return HttpResponse::NotFound()
+            .body("No such session!")
+    }
+    if session.get::<String>(SID_KEY).unwrap().as_deref() == Some(id.as_str()) {
+        session.remove(LOGIN_KEY);
+    }
+
+    HttpResponse::Ok()
+        .finish()
+}
+
+// REQUIRES: POST method, logged in
+// MODIFIES: Database
+// EFFECTS: End all of the user's sessions except the current one
+pub async fn revoke_other_sessions(session: Session) -> HttpResponse {
+    let user = match session.get::<String>(LOGIN_KEY).unwrap() {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized()
+            .body("Please log in!")
+    };
+    let sid = session.get::<String>(SID_KEY).unwrap();
+
+    let conn = db::connect();
+    let n = sessions::revoke_all(&conn, &user, sid.as_deref()).unwrap();
+
+    HttpResponse::Ok()
+        .json(n)
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the token of the user with the given username,
+//          or token for token-only participants
+pub fn find_user(conn: &Connection, user: &str) -> Option<String> {
+    conn.query_row(
+        "SELECT token FROM users
+        WHERE username = ?1 OR (token = ?1 AND username IS NULL)",
+        params![user],
+        |row| row.get(0)
+    ).optional().unwrap()
+}
+
+// REQUIRES: GET method, logged in admin
+// MODIFIES: n/a
+// EFFECTS: List a user's active sessions
+pub async fn list_user_sessions(
+    session: Session,
+    user: web::Path<String>
+) -> HttpResponse {
+    let conn = db::connect();
+    if let Err(res) = require_admin(&session, &conn) {
+        return res
+    }
+    let token = match find_user(&conn, &user) {
+        Some(token) => token,
+        None => return HttpResponse::NotFound()
+            .body("No such user!")
+    };
+
+    let sid = session.get::<String>(SID_KEY).unwrap();
+    let idle = SessionConfig::from_env().idle;
+    let list = sessions::list(&conn, &token, sid.as_deref(), idle, Utc::now().timestamp()).unwrap();
+
+    HttpResponse::Ok()
+        .json(list)
+}
+
+// REQUIRES: POST method, logged in admin
+// MODIFIES: Database
+// EFFECTS: End all of a user's sessions, logging them out everywhere
+pub async fn revoke_user_sessions(
+    session: Session,
+    user: web::Path<String>
+) -> HttpResponse {
+    let conn = db::connect();
+    if let Err(res) = require_admin(&session, &conn) {
+        return res
+    }
+    let token = match find_user(&conn, &user) {
+        Some(token) => token,
+        None => return HttpResponse::NotFound()
+            .body("No such user!")
+    };
+
+    // instructors revoking themselves keep the session they are using
+    let sid = session.get::<String>(SID_KEY).unwrap();
+    let n = sessions::revoke_all(&conn, &token, sid.as_deref()).unwrap();
+
+    HttpResponse::Ok()
+        .json(n)
+}
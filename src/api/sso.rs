@@ -0,0 +1,556 @@
+// crates.io
+use actix_web::{web, http::header, HttpResponse};
+use actix_session::{Session};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use jsonwebtoken::jwk::JwkSet;
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+// local
+use crate::db;
+use crate::config::env_or;
+use crate::api::accounts::MAX_TOKEN_LEN;
+use crate::api::invites;
+use crate::api::nav::{self, Flash};
+
+// Seconds the user may spend at the identity provider
+const STATE_TTL: i64 = 600;
+// How long discovered provider metadata and keys are trusted
+const PROVIDER_TTL: Duration = Duration::from_secs(3600);
+
+// Identity provider settings, read from
+//      FPLAB_OIDC_ISSUER         issuer URL, SSO is disabled if unset
+//      FPLAB_OIDC_CLIENT_ID      client id registered with the provider
+//      FPLAB_OIDC_CLIENT_SECRET  client secret, unset for public clients
+//      FPLAB_OIDC_REDIRECT_URL   e.g. https://fplab.eecs.umich.edu/accounts/sso/callback/
+//      FPLAB_OIDC_SECTION        section of users created on first sign-in;
+//                                if unset, new users need an invite code
+// Password accounts are only linked to a provider account by their owner,
+// never by a matching name, since users choose their own usernames
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_url: String,
+    pub section: Option<String>
+}
+
+impl OidcConfig {
+    pub fn from_env() -> Option<Self> {
+        let var = |key: &str| Some(env_or(key, String::new())).filter(|v| !v.is_empty());
+        Some(OidcConfig {
+            issuer: var("FPLAB_OIDC_ISSUER")?.trim_end_matches('/').to_string(),
+            client_id: var("FPLAB_OIDC_CLIENT_ID")?,
+            client_secret: var("FPLAB_OIDC_CLIENT_SECRET"),
+            redirect_url: var("FPLAB_OIDC_REDIRECT_URL")?,
+            section: var("FPLAB_OIDC_SECTION")
+        })
+    }
+}
+
+// Endpoints from the provider's /.well-known/openid-configuration
+#[derive(Deserialize, Debug, Clone)]
+pub struct Provider {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String
+}
+
+// Shared across app threads: configuration and discovered provider
+// config is None when SSO is not set up, which disables the /accounts/sso routes
+pub struct Sso {
+    pub config: Option<OidcConfig>,
+    provider: Mutex<Option<(Provider, JwkSet, Instant)>>
+}
+
+impl Sso {
+    pub fn new(config: Option<OidcConfig>) -> Self {
+        Sso {
+            config,
+            provider: Mutex::new(None)
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Sso::new(OidcConfig::from_env())
+    }
+
+    // REQUIRES: config is set
+    // MODIFIES: provider cache
+    // EFFECTS: Return the provider's endpoints and signing keys,
+    //          fetching them again once the cached copy expired
+    pub fn provider(&self) -> Result<(Provider, JwkSet), SsoError> {
+        if let Some((provider, jwks, fetched)) = &*self.provider.lock().unwrap() {
+            if fetched.elapsed() < PROVIDER_TTL {
+                return Ok((provider.clone(), jwks.clone()))
+            }
+        }
+
+        let config = self.config.as_ref().ok_or(SsoError::Disabled)?;
+        let provider = discover(&config.issuer)?;
+        let jwks : JwkSet = ureq::get(&provider.jwks_uri)
+            .call()
+            .map_err(|e| SsoError::Http(e.to_string()))?
+            .into_json()
+            .map_err(|e| SsoError::Http(e.to_string()))?;
+        *self.provider.lock().unwrap() = Some((provider.clone(), jwks.clone(), Instant::now()));
+        Ok((provider, jwks))
+    }
+}
+
+#[derive(Debug)]
+pub enum SsoError {
+    Disabled,
+    Http(String),
+    Jwt(jsonwebtoken::errors::Error),
+    // a required claim is missing or has an unexpected value
+    InvalidClaim(&'static str),
+    InvalidState,
+    // the provider account or local user is already linked elsewhere
+    AlreadyLinked,
+    // no linked user and no way to create one
+    NoAccount,
+    InvalidInvite,
+    Db(rusqlite::Error)
+}
+
+impl fmt::Display for SsoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SsoError::Disabled => write!(f, "single sign-on is not configured"),
+            SsoError::Http(msg) => write!(f, "request to identity provider failed: {}", msg),
+            SsoError::Jwt(e) => write!(f, "invalid token: {}", e),
+            SsoError::InvalidClaim(claim) => write!(f, "invalid claim: {}", claim),
+            SsoError::InvalidState => write!(f, "unknown or expired sign-in attempt"),
+            SsoError::AlreadyLinked => write!(f, "this account is already linked to another user"),
+            SsoError::NoAccount => write!(f, "no account is linked, please sign up with an invite code first"),
+            SsoError::InvalidInvite => write!(f, "invalid invitation code"),
+            SsoError::Db(e) => write!(f, "{}", e)
+        }
+    }
+}
+
+impl std::error::Error for SsoError {}
+
+impl From<jsonwebtoken::errors::Error> for SsoError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        SsoError::Jwt(e)
+    }
+}
+
+impl From<rusqlite::Error> for SsoError {
+    fn from(e: rusqlite::Error) -> Self {
+        SsoError::Db(e)
+    }
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Fetch the provider's metadata, checking it is for issuer
+pub fn discover(issuer: &str) -> Result<Provider, SsoError> {
+    let url = format!("{}/.well-known/openid-configuration", issuer);
+    let provider : Provider = ureq::get(&url)
+        .call()
+        .map_err(|e| SsoError::Http(e.to_string()))?
+        .into_json()
+        .map_err(|e| SsoError::Http(e.to_string()))?;
+    if provider.issuer.trim_end_matches('/') != issuer {
+        return Err(SsoError::InvalidClaim("issuer"))
+    }
+    Ok(provider)
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the S256 PKCE code challenge for verifier
+pub fn pkce_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return a fresh random PKCE code verifier (64 chars)
+pub fn pkce_verifier() -> String {
+    format!("{}{}", Uuid::new_v4().to_simple(), Uuid::new_v4().to_simple())
+}
+
+// A sign-in started at the identity provider
+#[derive(Debug, PartialEq)]
+pub struct PendingLogin {
+    pub nonce: String,
+    pub verifier: String,
+    // user whose account the provider account gets linked to
+    pub link: Option<String>,
+    pub invite: Option<String>
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database
+// EFFECTS: Remember a sign-in until the provider redirects back with state
+pub fn save_state(
+    conn: &Connection,
+    state: &str,
+    pending: &PendingLogin,
+    now: i64
+) -> rusqlite::Result<()> {
+    // forget sign-ins that never completed
+    conn.execute(
+        "DELETE FROM oidc_states
+        WHERE created < ?1",
+        params![now - STATE_TTL]
+    )?;
+    conn.execute(
+        "INSERT INTO oidc_states (state, nonce, verifier, link_token, invite, created)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![state, &pending.nonce, &pending.verifier, &pending.link, &pending.invite, now]
+    )?;
+    Ok(())
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database
+// EFFECTS: Consume the sign-in saved with state, so the
+//          authorization response can only be used once
+pub fn take_state(conn: &Connection, state: &str, now: i64) -> Result<PendingLogin, SsoError> {
+    let row : Option<(PendingLogin, i64)> = conn.query_row(
+        "SELECT nonce, verifier, link_token, invite, created FROM oidc_states
+        WHERE state = ?1",
+        params![state],
+        |row| Ok((PendingLogin {
+            nonce: row.get(0)?,
+            verifier: row.get(1)?,
+            link: row.get(2)?,
+            invite: row.get(3)?
+        }, row.get(4)?))
+    ).optional()?;
+    conn.execute(
+        "DELETE FROM oidc_states
+        WHERE state = ?1",
+        params![state]
+    )?;
+
+    match row {
+        Some((pending, created)) if now - created <= STATE_TTL => Ok(pending),
+        _ => Err(SsoError::InvalidState)
+    }
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the URL sending the user to the provider
+//          to authorize with the PKCE challenge for verifier
+pub fn authorization_url(
+    config: &OidcConfig,
+    provider: &Provider,
+    state: &str,
+    pending: &PendingLogin
+) -> String {
+    let challenge = pkce_challenge(&pending.verifier);
+    let query = serde_urlencoded::to_string([
+        ("response_type", "code"),
+        ("scope", "openid email"),
+        ("client_id", &config.client_id),
+        ("redirect_uri", &config.redirect_url),
+        ("state", state),
+        ("nonce", &pending.nonce),
+        ("code_challenge", &challenge),
+        ("code_challenge_method", "S256")
+    ]).unwrap();
+    let sep = if provider.authorization_endpoint.contains('?') { '&' } else { '?' };
+    format!("{}{}{}", provider.authorization_endpoint, sep, query)
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Exchange the authorization code and PKCE verifier
+//          for the provider's id_token
+pub fn exchange_code(
+    config: &OidcConfig,
+    provider: &Provider,
+    code: &str,
+    verifier: &str
+) -> Result<String, SsoError> {
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", &config.redirect_url),
+        ("client_id", &config.client_id),
+        ("code_verifier", verifier)
+    ];
+    if let Some(secret) = &config.client_secret {
+        form.push(("client_secret", secret));
+    }
+    let res : TokenResponse = ureq::post(&provider.token_endpoint)
+        .send_form(&form)
+        .map_err(|e| SsoError::Http(e.to_string()))?
+        .into_json()
+        .map_err(|e| SsoError::Http(e.to_string()))?;
+    Ok(res.id_token)
+}
+
+// Claims used from a validated id_token
+#[derive(Deserialize, Debug)]
+pub struct IdClaims {
+    pub sub: String,
+    pub nonce: String,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool
+}
+
+impl IdClaims {
+    // EFFECTS: Return the email only if the provider verified it
+    pub fn verified_email(&self) -> Option<&str> {
+        self.email.as_deref().filter(|_| self.email_verified)
+    }
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Verify the id_token's signature against the provider's keys
+//          and check it was issued to this client for this sign-in
+pub fn validate_id_token(
+    config: &OidcConfig,
+    provider: &Provider,
+    jwks: &JwkSet,
+    id_token: &str,
+    nonce: &str
+) -> Result<IdClaims, SsoError> {
+    let header = decode_header(id_token)?;
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        // providers with a single key may leave out the kid
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None
+    }.ok_or(SsoError::InvalidClaim("kid"))?;
+    let key = DecodingKey::from_jwk(jwk)?;
+
+    let mut validation = Validation::new(Algorithm::RS256);
+    validation.set_audience(&[&config.client_id]);
+    validation.set_issuer(&[&provider.issuer]);
+    validation.set_required_spec_claims(&["exp", "iat", "iss", "aud", "sub"]);
+    let claims = decode::<IdClaims>(id_token, &key, &validation)?.claims;
+
+    if claims.nonce != nonce {
+        return Err(SsoError::InvalidClaim("nonce"))
+    }
+    Ok(claims)
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database
+// EFFECTS: Return the token of the user signed in as claims.sub at issuer:
+//          - the user already linked to the provider account, else
+//          - link, if the logged in user asked to link their account, else
+//          - a new user enrolled with invite, or in section if invite is None
+//          Returns SsoError::NoAccount if no user can be found or created
+pub fn map_user(
+    conn: &mut Connection,
+    issuer: &str,
+    claims: &IdClaims,
+    link: Option<&str>,
+    invite: Option<&str>,
+    section: Option<&str>
+) -> Result<String, SsoError> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    let linked : Option<String> = tx.query_row(
+        "SELECT token FROM oidc_users
+        WHERE issuer = ?1 AND sub = ?2",
+        params![issuer, &claims.sub],
+        |row| row.get(0)
+    ).optional()?;
+    if let Some(token) = linked {
+        return match link {
+            Some(link) if link != token => Err(SsoError::AlreadyLinked),
+            _ => Ok(token)
+        }
+    }
+
+    let token = match link {
+        Some(link) => link.to_string(),
+        None => {
+            let section = match invite {
+                Some(invite) => invites::redeem(&tx, invite)?
+                    .ok_or(SsoError::InvalidInvite)?,
+                None => section.ok_or(SsoError::NoAccount)?.to_string()
+            };
+            let token : String = Uuid::new_v4().to_simple().to_string()
+                .chars().take(MAX_TOKEN_LEN).collect();
+            tx.execute(
+                "INSERT INTO users (token, section)
+                VALUES (?1, ?2)",
+                params![&token, section]
+            )?;
+            token
+        }
+    };
+
+    // each user links at most one account per provider
+    match tx.execute(
+        "INSERT INTO oidc_users (issuer, sub, token, email)
+        VALUES (?1, ?2, ?3, ?4)",
+        params![issuer, &claims.sub, &token, claims.verified_email()]
+    ) {
+        Ok(_) => {},
+        Err(rusqlite::Error::SqliteFailure(e, _))
+            if e.code == rusqlite::ErrorCode::ConstraintViolation => return Err(SsoError::AlreadyLinked),
+        Err(e) => return Err(e.into())
+    }
+    tx.commit()?;
+    Ok(token)
+}
+
+// Query of GET /accounts/sso/
+// link=true links the provider account to the logged in user,
+// invite enrolls a new user created on first sign-in,
+// next is the page to go to afterwards
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SsoQuery {
+    #[serde(default)]
+    link: bool,
+    #[serde(default)]
+    invite: Option<String>,
+    #[serde(default)]
+    next: String
+}
+
+// REQUIRES: GET method
+// MODIFIES: Database, Cookies
+// EFFECTS: Start the authorization code flow with PKCE
+//          by redirecting to the identity provider
+pub async fn sso_login(
+    session: Session,
+    sso: web::Data<Sso>,
+    query: web::Query<SsoQuery>
+) -> HttpResponse {
+    let config = match &sso.config {
+        Some(config) => config,
+        None => return HttpResponse::NotFound().finish()
+    };
+
+    let user = session.get::<String>("login").unwrap();
+    let link = match (user, query.link) {
+        (Some(user), true) => Some(user),
+        (None, true) => return HttpResponse::Unauthorized()
+            .body("Please log in!"),
+        // already logged in, redirect to the next page
+        (Some(_), false) => return nav::redirect(nav::next_or_home(&query.next)),
+        (None, false) => None
+    };
+
+    let provider = match sso.provider() {
+        Ok((provider, _)) => provider,
+        Err(e) => {
+            eprintln!("sso: {}", e);
+            return HttpResponse::BadGateway()
+                .body(e.to_string())
+        }
+    };
+
+    let state = Uuid::new_v4().to_simple().to_string();
+    let pending = PendingLogin {
+        nonce: Uuid::new_v4().to_simple().to_string(),
+        verifier: pkce_verifier(),
+        link,
+        invite: query.invite.as_deref()
+            .map(str::trim)
+            .filter(|invite| !invite.is_empty())
+            .map(String::from)
+    };
+    let conn = db::connect();
+    save_state(&conn, &state, &pending, Utc::now().timestamp()).unwrap();
+    // bind the sign-in to this browser, so a callback
+    // from someone else's sign-in is refused
+    session.set("sso_state", &state).unwrap();
+    session.set("sso_next", nav::next_or_home(&query.next)).unwrap();
+
+    HttpResponse::Found()
+        .header(header::LOCATION, authorization_url(config, &provider, &state, &pending))
+        .finish()
+}
+
+// Authorization response from the identity provider
+#[derive(Deserialize, Debug)]
+pub struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>
+}
+
+// REQUIRES: GET method
+// MODIFIES: Database, Cookies
+// EFFECTS: Finish the sign-in: exchange the code, validate
+//          the id_token, then log in the mapped user
+pub async fn sso_callback(
+    session: Session,
+    sso: web::Data<Sso>,
+    query: web::Query<CallbackQuery>
+) -> HttpResponse {
+    let config = match &sso.config {
+        Some(config) => config,
+        None => return HttpResponse::NotFound().finish()
+    };
+    let expected = session.get::<String>("sso_state").unwrap();
+    let next = session.get::<String>("sso_next").unwrap().unwrap_or_default();
+    session.remove("sso_state");
+    session.remove("sso_next");
+
+    if let Some(error) = &query.error {
+        return failed(&session, &format!("Sign-in failed: {}", error))
+    }
+    let (code, state) = match (&query.code, &query.state) {
+        (Some(code), Some(state)) if expected.as_ref() == Some(state) => (code, state),
+        _ => return failed(&session, &format!("Sign-in failed: {}", SsoError::InvalidState))
+    };
+
+    let mut conn = db::connect();
+    let result = take_state(&conn, state, Utc::now().timestamp())
+        .and_then(|pending| {
+            let (provider, jwks) = sso.provider()?;
+            let id_token = exchange_code(config, &provider, code, &pending.verifier)?;
+            let claims = validate_id_token(config, &provider, &jwks, &id_token, &pending.nonce)?;
+            map_user(
+                &mut conn, &provider.issuer, &claims,
+                pending.link.as_deref(), pending.invite.as_deref(),
+                config.section.as_deref()
+            )
+        });
+
+    let token = match result {
+        Ok(token) => token,
+        Err(e @ SsoError::AlreadyLinked) | Err(e @ SsoError::NoAccount)
+            | Err(e @ SsoError::InvalidInvite) => return failed(&session, &e.to_string()),
+        Err(e) => {
+            eprintln!("sso: sign-in refused: {}", e);
+            return failed(&session, &format!("Sign-in failed: {}", e))
+        }
+    };
+
+    session.set("login", token).unwrap();
+    session.renew();
+
+    nav::redirect(nav::next_or_home(&next))
+}
+
+// REQUIRES: n/a
+// MODIFIES: Cookies
+// EFFECTS: Send the user back to the login page showing message
+fn failed(session: &Session, message: &str) -> HttpResponse {
+    nav::flash(session, Flash::error(message));
+    nav::redirect("/accounts/login/")
+}
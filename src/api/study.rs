@@ -0,0 +1,244 @@
+// crates.io
+use actix_web::{http::header, web, HttpResponse};
+use actix_session::{Session};
+use handlebars::Handlebars;
+use rusqlite::{params, types::ValueRef, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use uuid::Uuid;
+// local
+use crate::db;
+use crate::api::nav::{self, Flash, Layout};
+use crate::middleware::sessions::LOGIN_KEY;
+
+// Tables holding a participant's study data, exported as-is
+pub const EXPORT_TABLES: [&str; 12] = [
+    "responses", "response_events", "quiz_progress", "arrangements", "fr_grades", "fr_grade_items",
+    "events", "user_hover", "user_page", "edit_states", "scores", "consents"
+];
+
+// Tables linking a user to who they are elsewhere,
+// cleared when their data is anonymized
+const IDENTITY_TABLES: [&str; 4] = ["sessions", "oidc_users", "lti_users", "lti_lineitems"];
+
+// How a participant leaves the study
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Withdrawal {
+    // delete the account and every row about it
+    Delete,
+    // keep the study data under a random token no one can log in with
+    Anonymize
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the rows of a query as JSON objects keyed by column
+fn rows_as_json(conn: &Connection, sql: &str, token: &str) -> rusqlite::Result<Vec<Value>> {
+    let mut stmt = conn.prepare(sql)?;
+    let names : Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+    let mut rows = stmt.query(params![token])?;
+    let mut out = Vec::new();
+    while let Some(row) = rows.next()? {
+        let mut object = Map::new();
+        for (i, name) in names.iter().enumerate() {
+            let value = match row.get_raw(i) {
+                ValueRef::Null => Value::Null,
+                ValueRef::Integer(n) => json!(n),
+                ValueRef::Real(f) => json!(f),
+                ValueRef::Text(t) => json!(String::from_utf8_lossy(t)),
+                ValueRef::Blob(b) => json!(b)
+            };
+            object.insert(name.clone(), value);
+        }
+        out.push(Value::Object(object));
+    }
+    Ok(out)
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return everything the study recorded about token as one
+//          JSON object: the account and one array per EXPORT_TABLES
+//          Returns None if no such user exists
+pub fn export(conn: &Connection, token: &str) -> rusqlite::Result<Option<Value>> {
+    let mut account = rows_as_json(
+        conn,
+        "SELECT token, username, section, created FROM users
+        WHERE token = ?1",
+        token
+    )?;
+    if account.is_empty() {
+        return Ok(None)
+    }
+
+    let mut archive = Map::new();
+    archive.insert("account".to_string(), account.remove(0));
+    for table in EXPORT_TABLES.iter() {
+        let rows = rows_as_json(conn, &format!("SELECT * FROM {} WHERE token = ?1", table), token)?;
+        archive.insert(table.to_string(), Value::Array(rows));
+    }
+    Ok(Some(Value::Object(archive)))
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database
+// EFFECTS: Remove token from the study as chosen by how
+//          Anonymizing moves the study data to a new random token and
+//          drops the username, password, section, linked identities and
+//          login attempts, so the rows can no longer be traced back
+//          Returns false if no such user exists
+pub fn withdraw(conn: &mut Connection, token: &str, how: Withdrawal) -> rusqlite::Result<bool> {
+    let tx = conn.transaction()?;
+    let username : Option<Option<String>> = tx.query_row(
+        "SELECT username FROM users
+        WHERE token = ?1",
+        params![token],
+        |row| row.get(0)
+    ).optional()?;
+    let username = match username {
+        Some(username) => username,
+        None => return Ok(false)
+    };
+
+    // attempts and lockouts are keyed by what was typed, not by user
+    for credential in std::iter::once(token).chain(username.as_deref()) {
+        tx.execute(
+            "DELETE FROM failed_attempts
+            WHERE credential = ?1",
+            params![credential]
+        )?;
+        tx.execute(
+            "DELETE FROM lockouts
+            WHERE key = ?1",
+            params![format!("credential:{}", credential)]
+        )?;
+    }
+
+    match how {
+        Withdrawal::Delete => {
+            db::remove_user(&tx, token)?;
+        },
+        Withdrawal::Anonymize => {
+            for table in IDENTITY_TABLES.iter() {
+                tx.execute(&format!("DELETE FROM {} WHERE token = ?1", table), params![token])?;
+            }
+            // fits in the 20 characters of users.token
+            let anonymous = format!("anon-{}", &Uuid::new_v4().to_simple().to_string()[..15]);
+            // dependent rows follow through ON UPDATE CASCADE; a password
+            // no hash matches keeps the token from logging in
+            tx.execute(
+                "UPDATE users SET token = ?1, username = NULL, password = '!withdrawn',
+                    section = NULL, admin = 0
+                WHERE token = ?2",
+                params![&anonymous, token]
+            )?;
+        }
+    }
+    tx.commit()?;
+    Ok(true)
+}
+
+// REQUIRES: GET method, logged in
+// MODIFIES: n/a
+// EFFECTS: Send the user's study data as a JSON file download
+pub async fn download_data(session: Session) -> HttpResponse {
+    let user = match session.get::<String>(LOGIN_KEY).unwrap() {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized()
+            .body("Please log in!")
+    };
+
+    match export(&db::connect(), &user).unwrap() {
+        Some(archive) => HttpResponse::Ok()
+            .header(header::CONTENT_DISPOSITION, "attachment; filename=\"fplab-data.json\"")
+            .json(archive),
+        None => HttpResponse::NotFound()
+            .body("No such user!")
+    }
+}
+
+// Page information fed to Handlebars
+#[derive(Serialize)]
+struct Context {
+    #[serde(flatten)]
+    layout: Layout,
+    // validation errors by form field
+    errors: HashMap<&'static str, String>
+}
+
+// REQUIRES: n/a
+// MODIFIES: Cookies
+// EFFECTS: Render the withdraw page with errors next to their fields
+fn render_withdraw(
+    hb: &Handlebars<'_>,
+    session: &Session,
+    errors: HashMap<&'static str, String>
+) -> String {
+    let data = Context {
+        layout: Layout::new(session),
+        errors
+    };
+    hb.render("withdraw", &data).unwrap()
+}
+
+// REQUIRES: GET method
+// MODIFIES: Cookies
+// EFFECTS: Render the page to download data or withdraw from the study
+//          If logged out, redirect to the login page
+pub async fn render_withdraw_page(
+    session: Session,
+    hb: web::Data<Handlebars<'_>>
+) -> HttpResponse {
+    if session.get::<String>(LOGIN_KEY).unwrap().is_none() {
+        return nav::redirect(&format!("/accounts/login/{}", nav::next_query("/accounts/withdraw/")))
+    }
+
+    HttpResponse::Ok()
+        .body(render_withdraw(&hb, &session, HashMap::new()))
+}
+
+// Used for deserializing the withdraw form
+// confirm must repeat the username, or the token of token-only participants
+#[derive(Deserialize, Debug)]
+pub struct WithdrawForm {
+    confirm: String,
+    how: Withdrawal
+}
+
+// REQUIRES: POST method, logged in
+// MODIFIES: Database, Cookies
+// EFFECTS: Delete or anonymize the user's data, log them out
+//          and redirect home
+pub async fn withdraw_user(
+    session: Session,
+    hb: web::Data<Handlebars<'_>>,
+    form: web::Form<WithdrawForm>
+) -> HttpResponse {
+    let user = match session.get::<String>(LOGIN_KEY).unwrap() {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized()
+            .body("Please log in!")
+    };
+
+    let mut conn = db::connect();
+    let username : Option<String> = conn.query_row(
+        "SELECT username FROM users
+        WHERE token = ?1",
+        params![&user],
+        |row| row.get(0)
+    ).unwrap();
+    if form.confirm.trim() != username.as_deref().unwrap_or(&user) {
+        let errors = HashMap::from([("confirm", "Please type your username (or token) exactly to confirm!".to_string())]);
+        return HttpResponse::BadRequest()
+            .body(render_withdraw(&hb, &session, errors))
+    }
+
+    withdraw(&mut conn, &user, form.how).unwrap();
+    // sessions are gone already, forget the login on this browser too
+    session.remove(LOGIN_KEY);
+    nav::flash(&session, Flash::info("You have withdrawn from the study. Thank you for taking part!"));
+
+    nav::redirect(nav::HOME)
+}
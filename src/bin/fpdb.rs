@@ -0,0 +1,295 @@
+// fpdb: administer the FP Lab database
+//
+// Usage: fpdb [--db PATH] COMMAND [ARGS...]
+// crates.io
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+// local
+use fplab_server::api::accounts;
+use fplab_server::db;
+use fplab_server::db::backup::{self, BackupConfig};
+use fplab_server::db::import::{self, Format, Visualizations};
+use fplab_server::db::upgrade;
+
+const USAGE: &str = "\
+Usage: fpdb [--db PATH] COMMAND [ARGS...]
+
+Commands:
+    create                  create the database with schema and initial data
+    destroy                 delete the database
+    reset                   destroy, then create
+    dump                    print the database as SQL statements
+    backup [DIR]            take a consistent backup and prune old ones
+    restore FILE            replace the database with a verified backup
+    upgrade                 bring the database up to the current schema version,
+                            keeping its data
+    seed FILE...            run SQL files against the database
+    add-user TOKEN...       register user tokens
+    remove-user TOKEN...    delete users and all of their data
+    import-quiz [--dry-run] FILE...
+                            import quizzes from TOML, YAML or Markdown files,
+                            or bare question lists from JSON files;
+                            --dry-run only prints what would change;
+                            files regrading answered questions are refused
+    create-quiz SLUG TITLE QUESTION_ID...
+                            create or replace a quiz of questions in order
+    attach-quiz SLUG [CHAPTER]
+                            attach a quiz to a book chapter, or detach it
+    stats                   print a summary of collected data
+
+Options:
+    --db PATH               database file (default: db/fp.sqlite3)";
+
+fn main() {
+    let mut args : Vec<String> = std::env::args().skip(1).collect();
+
+    // parse options
+    let mut path = db::DB_PATH.to_string();
+    if args.first().map(String::as_str) == Some("--db") {
+        if args.len() < 2 {
+            usage();
+        }
+        path = args.remove(1);
+        args.remove(0);
+    }
+
+    if args.is_empty() {
+        usage();
+    }
+    let cmd = args.remove(0);
+
+    if let Err(e) = run(&cmd, &args, Path::new(&path)) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(1);
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database, file system
+// EFFECTS: Execute a single fpdb command against the database at path
+fn run(cmd: &str, args: &[String], path: &Path) -> Result<(), String> {
+    match (cmd, args.len()) {
+        ("create", 0) => create(path),
+        ("destroy", 0) => destroy(path),
+        ("reset", 0) => {
+            destroy(path)?;
+            create(path)
+        },
+        ("dump", 0) => {
+            let conn = connect(path)?;
+            let stdout = io::stdout();
+            db::dump::dump(&conn, &mut stdout.lock()).map_err(|e| e.to_string())
+        },
+        ("backup", n) if n <= 1 => {
+            connect(path)?;
+            let mut config = BackupConfig::from_env()?;
+            if let Some(dir) = args.first() {
+                config.dir = dir.into();
+            }
+            let file = backup::run_once(path, &config).map_err(|e| e.to_string())?;
+            println!("+ backed up to {}", file.display());
+            Ok(())
+        },
+        ("restore", 1) => {
+            let file = Path::new(&args[0]);
+            backup::restore(file, path).map_err(|e| format!("{}: {}", file.display(), e))?;
+            println!("+ restored {} from {}", path.display(), file.display());
+            Ok(())
+        },
+        ("upgrade", 0) => {
+            let mut conn = connect(path)?;
+            let found = upgrade::upgrade(&mut conn).map_err(|e| e.to_string())?;
+            if found == db::SCHEMA_VERSION {
+                println!("  {} is up to date", path.display());
+            }
+            else {
+                println!("+ upgraded {} from version {} to {}", path.display(), found, db::SCHEMA_VERSION);
+            }
+            Ok(())
+        },
+        ("seed", n) if n > 0 => {
+            let mut conn = connect(path)?;
+            for file in args {
+                let sql = fs::read_to_string(file)
+                    .map_err(|e| format!("{}: {}", file, e))?;
+                db::seed(&mut conn, &sql).map_err(|e| format!("{}: {}", file, e))?;
+                println!("+ seeded {}", file);
+            }
+            Ok(())
+        },
+        ("add-user", n) if n > 0 => {
+            // same rules as sign-up, checked before any user is added
+            for token in args {
+                accounts::validate_token(token)
+                    .map_err(|e| format!("invalid token {:?}: {}", token, e.message()))?;
+            }
+            let conn = connect(path)?;
+            for token in args {
+                if db::add_user(&conn, token).map_err(|e| e.to_string())? {
+                    println!("+ added {}", token);
+                }
+                else {
+                    println!("  {} already exists", token);
+                }
+            }
+            Ok(())
+        },
+        ("remove-user", n) if n > 0 => {
+            let conn = connect(path)?;
+            for token in args {
+                if db::remove_user(&conn, token).map_err(|e| e.to_string())? {
+                    println!("+ removed {}", token);
+                }
+                else {
+                    println!("  {} does not exist", token);
+                }
+            }
+            Ok(())
+        },
+        ("import-quiz", n) if n > 0 => {
+            let mut conn = connect(path)?;
+            let dry_run = args[0] == "--dry-run";
+            let files = if dry_run { &args[1..] } else { args };
+            if files.is_empty() {
+                usage();
+            }
+            for file in files {
+                import_file(&mut conn, Path::new(file), dry_run)
+                    .map_err(|e| format!("{}: {}", file, e))?;
+            }
+            Ok(())
+        },
+        ("create-quiz", n) if n > 2 => {
+            let mut conn = connect(path)?;
+            let question_ids = args[2..].iter()
+                .map(|id| id.parse::<i64>().map_err(|_| format!("invalid question id: {}", id)))
+                .collect::<Result<Vec<_>, _>>()?;
+            db::create_quiz(&mut conn, &args[0], &args[1], &question_ids)
+                .map_err(|e| e.to_string())?;
+            println!("+ created quiz {} with {} questions", args[0], question_ids.len());
+            Ok(())
+        },
+        ("attach-quiz", n) if n == 1 || n == 2 => {
+            let conn = connect(path)?;
+            let chapter = args.get(1).map(String::as_str);
+            if !db::attach_quiz(&conn, &args[0], chapter).map_err(|e| e.to_string())? {
+                return Err(format!("no such quiz: {}", args[0]))
+            }
+            match chapter {
+                Some(chapter) => println!("+ attached {} to {}", args[0], chapter),
+                None => println!("+ detached {}", args[0])
+            }
+            Ok(())
+        },
+        ("stats", 0) => {
+            let conn = connect(path)?;
+            let s = db::stats(&conn).map_err(|e| e.to_string())?;
+            println!("users:           {}", s.users);
+            println!("questions:       {}", s.questions);
+            println!("responses:       {} ({} answered)", s.responses, s.answered);
+            println!("hover events:    {}", s.hover_events);
+            println!("page visits:     {}", s.page_visits);
+            println!("problems passed: {}", s.problems_passed);
+            Ok(())
+        },
+        _ => usage()
+    }
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database unless dry_run
+// EFFECTS: Import the quiz in file after validating it, printing the
+//          changes it makes
+fn import_file(conn: &mut rusqlite::Connection, file: &Path, dry_run: bool) -> Result<(), String> {
+    let text = fs::read_to_string(file).map_err(|e| e.to_string())?;
+    let visualizations = Visualizations::from_env();
+
+    // bare list of questions, as exported before quizzes had files
+    if file.extension().is_some_and(|ext| ext == "json") {
+        let questions : Vec<db::QuestionSpec> = serde_json::from_str(&text)
+            .map_err(|e| e.to_string())?;
+        let errors : Vec<String> = questions.iter()
+            .flat_map(|q| import::validate_question(q, &visualizations))
+            .collect();
+        if !errors.is_empty() {
+            return Err(errors.join("\n"))
+        }
+        let conflicts = if dry_run {
+            db::regraded(conn, &questions)
+        }
+        else {
+            db::import_questions(conn, &questions)
+        }.map_err(|e| e.to_string())?;
+        if !conflicts.is_empty() {
+            return Err(format!("answered questions {:?} cannot be regraded, nothing imported", conflicts))
+        }
+        println!("+ imported {} questions from {}", questions.len(), file.display());
+        return Ok(())
+    }
+
+    let format = Format::from_path(file)
+        .ok_or("unknown format, expected .toml, .yaml, .yml, .md or .json")?;
+    let quiz = import::parse(&text, format)?;
+    let errors = import::validate(&quiz, &visualizations);
+    if !errors.is_empty() {
+        return Err(errors.join("\n"))
+    }
+
+    let changes = if dry_run {
+        import::diff(conn, &quiz)
+    }
+    else {
+        import::apply(conn, &quiz)
+    }.map_err(|e| e.to_string())?;
+    for change in &changes {
+        println!("{}", change);
+    }
+    if changes.iter().any(import::Change::is_conflict) {
+        return Err(format!("answered questions cannot be regraded, {} not changed", quiz.slug))
+    }
+    if changes.is_empty() {
+        println!("  {} is up to date", quiz.slug);
+    }
+    else if dry_run {
+        println!("  dry run, {} not changed", quiz.slug);
+    }
+    else {
+        println!("+ imported {} from {}", quiz.slug, file.display());
+    }
+    Ok(())
+}
+
+fn create(path: &Path) -> Result<(), String> {
+    if path.exists() {
+        return Err("database already exists".to_string())
+    }
+    db::create(path).map_err(|e| e.to_string())?;
+    println!("+ created {}", path.display());
+    Ok(())
+}
+
+fn destroy(path: &Path) -> Result<(), String> {
+    // WAL mode leaves -wal and -shm files next to the database
+    for suffix in &["", "-wal", "-shm"] {
+        let file = format!("{}{}", path.display(), suffix);
+        if Path::new(&file).exists() {
+            fs::remove_file(&file).map_err(|e| format!("{}: {}", file, e))?;
+        }
+    }
+    println!("+ rm -f {}", path.display());
+    Ok(())
+}
+
+fn connect(path: &Path) -> Result<rusqlite::Connection, String> {
+    if !path.exists() {
+        return Err(format!("{} does not exist, run `fpdb create`", path.display()))
+    }
+    db::open(path).map_err(|e| e.to_string())
+}
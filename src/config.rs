@@ -0,0 +1,13 @@
+// crates.io
+use std::str::FromStr;
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the environment variable key parsed as T,
+//          or default if it is unset or cannot be parsed
+pub fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    match std::env::var(key) {
+        Ok(val) => val.trim().parse().unwrap_or(default),
+        Err(_) => default
+    }
+}
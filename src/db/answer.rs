@@ -0,0 +1,145 @@
+// crates.io
+use serde::{Deserialize, Serialize};
+
+// How a question is answered and graded
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    // pick the one correct choice
+    #[default]
+    Choice,
+    // select all choices that apply, graded all or nothing
+    MultiSelect,
+    // type what the program prints, see normalize_output
+    Output,
+    // point at the line of the visualization that fails to compile
+    CompileFail,
+    // put every choice in the right order
+    Ordering
+}
+
+impl Kind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Choice => "choice",
+            Kind::MultiSelect => "multi_select",
+            Kind::Output => "output",
+            Kind::CompileFail => "compile_fail",
+            Kind::Ordering => "ordering"
+        }
+    }
+
+    // EFFECTS: Parse a kind as stored in questions.kind
+    pub fn from_name(name: &str) -> Option<Kind> {
+        [Kind::Choice, Kind::MultiSelect, Kind::Output, Kind::CompileFail, Kind::Ordering]
+            .iter().copied().find(|kind| kind.name() == name)
+    }
+}
+
+// An answer key, or what a user submitted, e.g. in JSON:
+//      1               choice id, or 1-based line number for compile_fail
+//      [0, 2]          selected choice ids, or choice ids in order
+//      "5\n15\n"       program output
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Answer {
+    Index(i64),
+    Indices(Vec<i64>),
+    Text(String)
+}
+
+impl Answer {
+    // EFFECTS: Split into the INTEGER and TEXT columns answers are stored in:
+    //          indices go in the first, anything else is JSON in the second
+    //          with -1 in the first
+    pub fn to_columns(&self) -> (i64, String) {
+        match self {
+            Answer::Index(i) => (*i, String::new()),
+            other => (-1, serde_json::to_string(other).unwrap())
+        }
+    }
+
+    // EFFECTS: Join columns written by to_columns
+    pub fn from_columns(index: i64, json: &str) -> Answer {
+        if json.is_empty() {
+            Answer::Index(index)
+        }
+        else {
+            serde_json::from_str(json).unwrap_or(Answer::Index(index))
+        }
+    }
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return output with line endings, trailing whitespace on each line
+//          and leading or trailing blank lines dropped, so typed output
+//          matches regardless of how it was pasted
+pub fn normalize_output(output: &str) -> String {
+    let lines : Vec<&str> = output.lines().map(str::trim_end).collect();
+    let start = lines.iter().position(|l| !l.is_empty()).unwrap_or(lines.len());
+    let end = lines.iter().rposition(|l| !l.is_empty()).map_or(start, |i| i + 1);
+    lines[start..end].join("\n")
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return true if ids are each choice id exactly once
+fn is_permutation(ids: &[i64], num_choices: usize) -> bool {
+    let mut sorted = ids.to_vec();
+    sorted.sort_unstable();
+    sorted.iter().copied().eq(0..num_choices as i64)
+}
+
+// Longest program output accepted, in bytes
+pub const MAX_OUTPUT: usize = 4096;
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return what is wrong with answer as an answer to a question
+//          of kind with num_choices choices, None if it can be graded
+pub fn check_answer(kind: Kind, num_choices: usize, answer: &Answer) -> Option<String> {
+    let in_range = |i: &i64| *i >= 0 && (*i as usize) < num_choices;
+    match (kind, answer) {
+        (Kind::Choice, Answer::Index(i)) if in_range(i) => None,
+        (Kind::Choice, _) => Some("answer must be one of the choices".to_string()),
+        (Kind::MultiSelect, Answer::Indices(ids)) => {
+            let mut unique = ids.clone();
+            unique.sort_unstable();
+            unique.dedup();
+            if !ids.iter().all(in_range) || unique.len() != ids.len() {
+                Some("answer must be distinct choices".to_string())
+            }
+            else {
+                None
+            }
+        },
+        (Kind::MultiSelect, _) => Some("answer must be a list of choices".to_string()),
+        (Kind::Output, Answer::Text(text)) if text.len() <= MAX_OUTPUT => None,
+        (Kind::Output, Answer::Text(_)) =>
+            Some(format!("output must be at most {} bytes", MAX_OUTPUT)),
+        (Kind::Output, _) => Some("answer must be the program output".to_string()),
+        (Kind::CompileFail, Answer::Index(line)) if *line >= 1 => None,
+        (Kind::CompileFail, _) => Some("answer must be a line number from 1".to_string()),
+        (Kind::Ordering, Answer::Indices(ids)) if is_permutation(ids, num_choices) => None,
+        (Kind::Ordering, _) => Some("answer must list every choice once".to_string())
+    }
+}
+
+// REQUIRES: check_answer(kind, _, response) is None
+// MODIFIES: n/a
+// EFFECTS: Return true if response matches the answer key
+pub fn grade(kind: Kind, key: &Answer, response: &Answer) -> bool {
+    match (kind, key, response) {
+        (Kind::MultiSelect, Answer::Indices(key), Answer::Indices(response)) => {
+            let mut key = key.clone();
+            let mut response = response.clone();
+            key.sort_unstable();
+            response.sort_unstable();
+            key == response
+        },
+        (Kind::Output, Answer::Text(key), Answer::Text(response)) =>
+            normalize_output(key) == normalize_output(response),
+        _ => key == response
+    }
+}
@@ -0,0 +1,248 @@
+// crates.io
+use chrono::Local;
+use rusqlite::{backup::Backup, Connection, NO_PARAMS};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use uuid::Uuid;
+// local
+use crate::config::env_or;
+use crate::db;
+
+// Backup files are named <PREFIX><timestamp>, so that
+// sorting them by name also sorts them by age
+const PREFIX: &str = "fp.sqlite3.bak.";
+
+// Pages copied per step of an online backup; the source database
+// is only locked while a step runs, so writers can make progress in between
+const PAGES_PER_STEP: i32 = 64;
+const PAUSE_BETWEEN_STEPS: Duration = Duration::from_millis(10);
+
+// Where and how often to back up the database, read from
+//      FPLAB_BACKUP_DIR       directory for backup files (default: ../backups)
+//      FPLAB_BACKUP_INTERVAL  seconds between backups, 0 disables (default: 3600)
+//      FPLAB_BACKUP_KEEP      number of most recent backups to keep, at least 1 (default: 48)
+#[derive(Debug, Clone)]
+pub struct BackupConfig {
+    pub dir: PathBuf,
+    pub interval: u64,
+    pub keep: usize
+}
+
+impl BackupConfig {
+    pub fn from_env() -> Result<Self, String> {
+        let keep = env_or("FPLAB_BACKUP_KEEP", 48);
+        // pruning to no backups would delete the one just taken
+        if keep < 1 {
+            return Err("FPLAB_BACKUP_KEEP must be at least 1".to_string())
+        }
+        Ok(BackupConfig {
+            dir: PathBuf::from(env_or("FPLAB_BACKUP_DIR", "../backups".to_string())),
+            interval: env_or("FPLAB_BACKUP_INTERVAL", 3600),
+            keep
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum BackupError {
+    Sqlite(rusqlite::Error),
+    Io(io::Error),
+    // PRAGMA integrity_check reported problems
+    Corrupt(String),
+    // backup was taken from a database with a different schema
+    SchemaVersion { expected: i32, found: i32 }
+}
+
+impl fmt::Display for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackupError::Sqlite(e) => write!(f, "{}", e),
+            BackupError::Io(e) => write!(f, "{}", e),
+            BackupError::Corrupt(msg) => write!(f, "integrity check failed: {}", msg),
+            BackupError::SchemaVersion { expected, found } => write!(
+                f, "schema version {} does not match expected version {}",
+                found, expected
+            )
+        }
+    }
+}
+
+impl std::error::Error for BackupError {}
+
+impl From<rusqlite::Error> for BackupError {
+    fn from(e: rusqlite::Error) -> Self {
+        BackupError::Sqlite(e)
+    }
+}
+
+impl From<io::Error> for BackupError {
+    fn from(e: io::Error) -> Self {
+        BackupError::Io(e)
+    }
+}
+
+// REQUIRES: n/a
+// MODIFIES: file system
+// EFFECTS: Copy conn's database into a new timestamped file in dir
+//          while the server keeps running, then check the copy
+//          Backups taken within the same second get numbered suffixes
+//          Returns the path of the verified backup
+pub fn backup(conn: &Connection, dir: &Path) -> Result<PathBuf, BackupError> {
+    fs::create_dir_all(dir)?;
+    let name = format!("{}{}", PREFIX, Local::now().format("%Y%m%d_%H%M%S"));
+    // write to a temporary file so a partial backup is never mistaken for a good one
+    let partial = dir.join(format!("{}.{}.partial", name, Uuid::new_v4().to_simple()));
+
+    {
+        let mut dst = Connection::open(&partial)?;
+        let b = Backup::new(conn, &mut dst)?;
+        b.run_to_completion(PAGES_PER_STEP, PAUSE_BETWEEN_STEPS, None)?;
+    }
+
+    if let Err(e) = verify(&partial) {
+        let _ = fs::remove_file(&partial);
+        return Err(e)
+    }
+    let path = publish(&partial, dir, &name);
+    let _ = fs::remove_file(&partial);
+    Ok(path?)
+}
+
+// REQUIRES: partial is a file in dir
+// MODIFIES: file system
+// EFFECTS: Link partial into dir as name, or name_001, name_002, ...
+//          if taken, never replacing an existing backup
+fn publish(partial: &Path, dir: &Path, name: &str) -> io::Result<PathBuf> {
+    let mut n = 0;
+    loop {
+        let path = match n {
+            0 => dir.join(name),
+            n => dir.join(format!("{}_{:03}", name, n))
+        };
+        match fs::hard_link(partial, &path) {
+            Ok(()) => return Ok(path),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(e)
+        }
+    }
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Check that the database at path passes PRAGMA integrity_check
+//          Databases of any schema version can be backed up, see restore
+pub fn verify(path: &Path) -> Result<(), BackupError> {
+    if !path.is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} does not exist", path.display())
+        ).into())
+    }
+    let conn = Connection::open(path)?;
+
+    let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+    let problems : Vec<String> = stmt
+        .query_map(NO_PARAMS, |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    if problems != ["ok"] {
+        return Err(BackupError::Corrupt(problems.join("; ")))
+    }
+    Ok(())
+}
+
+// REQUIRES: n/a
+// MODIFIES: file system
+// EFFECTS: Delete all but the keep most recent backups in dir
+//          Returns the paths that were deleted
+pub fn prune(dir: &Path, keep: usize) -> io::Result<Vec<PathBuf>> {
+    let mut backups = list(dir)?;
+    let num_old = backups.len().saturating_sub(keep);
+    let old : Vec<PathBuf> = backups.drain(..num_old).collect();
+    for path in &old {
+        fs::remove_file(path)?;
+    }
+    Ok(old)
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the backups in dir, oldest first
+pub fn list(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new())
+    }
+    let mut backups : Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| name.starts_with(PREFIX) && !name.ends_with(".partial"))
+                .unwrap_or(false)
+        })
+        .collect();
+    backups.sort();
+    Ok(backups)
+}
+
+// REQUIRES: server is not writing to the database at db_path
+// MODIFIES: Database
+// EFFECTS: Replace the contents of the database at db_path with the backup
+//          Refuses to restore a backup that fails verification or was
+//          taken with another schema version than the server's
+pub fn restore(backup_path: &Path, db_path: &Path) -> Result<(), BackupError> {
+    verify(backup_path)?;
+
+    let src = Connection::open(backup_path)?;
+    let found = db::schema_version(&src)?;
+    if found != db::SCHEMA_VERSION {
+        return Err(BackupError::SchemaVersion {
+            expected: db::SCHEMA_VERSION,
+            found
+        })
+    }
+
+    let mut dst = db::open(db_path)?;
+    {
+        let b = Backup::new(&src, &mut dst)?;
+        b.run_to_completion(PAGES_PER_STEP, PAUSE_BETWEEN_STEPS, None)?;
+    }
+
+    // make sure the restored database is sound before handing it back
+    let problems : String = dst.query_row("PRAGMA integrity_check", NO_PARAMS, |row| row.get(0))?;
+    if problems != "ok" {
+        return Err(BackupError::Corrupt(problems))
+    }
+    Ok(())
+}
+
+// REQUIRES: n/a
+// MODIFIES: file system
+// EFFECTS: Take one backup of the database at db_path and prune old ones
+pub fn run_once(db_path: &Path, config: &BackupConfig) -> Result<PathBuf, BackupError> {
+    let conn = db::open(db_path)?;
+    let path = backup(&conn, &config.dir)?;
+    prune(&config.dir, config.keep)?;
+    Ok(path)
+}
+
+// REQUIRES: n/a
+// MODIFIES: file system
+// EFFECTS: Back up the server's database every config.interval seconds
+//          on a background thread; does nothing if the interval is 0
+pub fn schedule(config: BackupConfig) {
+    if config.interval == 0 {
+        return
+    }
+
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(config.interval));
+        match run_once(Path::new(db::DB_PATH), &config) {
+            Ok(path) => eprintln!("backup: wrote {}", path.display()),
+            Err(e) => eprintln!("backup: failed: {}", e)
+        }
+    });
+}
@@ -0,0 +1,84 @@
+// crates.io
+use rusqlite::{types::ValueRef, Connection, NO_PARAMS};
+use std::io::{self, Write};
+// local
+use crate::db;
+
+// REQUIRES: n/a
+// MODIFIES: out
+// EFFECTS: Write the schema and contents of every table as SQL
+//          statements that recreate the database when replayed,
+//          similar to the sqlite3 shell's .dump
+pub fn dump<W: Write>(conn: &Connection, out: &mut W) -> io::Result<()> {
+    writeln!(out, "PRAGMA foreign_keys=OFF;")?;
+    writeln!(out, "BEGIN TRANSACTION;")?;
+    // so the replayed database is not mistaken for an unversioned one
+    let version = db::schema_version(conn).map_err(to_io)?;
+    writeln!(out, "PRAGMA user_version={};", version)?;
+
+    let mut stmt = conn.prepare(
+        "SELECT name, sql FROM sqlite_master
+        WHERE type = 'table' AND name NOT LIKE 'sqlite_%'
+        ORDER BY rowid"
+    ).map_err(to_io)?;
+    let tables : Vec<(String, String)> = stmt
+        .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(to_io)?
+        .collect::<rusqlite::Result<_>>()
+        .map_err(to_io)?;
+
+    for (name, sql) in tables {
+        writeln!(out, "{};", sql)?;
+
+        let mut stmt = conn
+            .prepare(&format!("SELECT * FROM {}", quote_ident(&name)))
+            .map_err(to_io)?;
+        let num_cols = stmt.column_count();
+        let mut rows = stmt.query(NO_PARAMS).map_err(to_io)?;
+        while let Some(row) = rows.next().map_err(to_io)? {
+            let values : Vec<String> = (0..num_cols)
+                .map(|i| row.get_raw(i))
+                .map(quote_value)
+                .collect();
+            writeln!(
+                out, "INSERT INTO {} VALUES({});",
+                quote_ident(&name), values.join(",")
+            )?;
+        }
+    }
+
+    // indices, triggers and views after all data is in place
+    let mut stmt = conn.prepare(
+        "SELECT sql FROM sqlite_master
+        WHERE type IN ('index', 'trigger', 'view') AND sql IS NOT NULL
+        ORDER BY rowid"
+    ).map_err(to_io)?;
+    let mut rows = stmt.query(NO_PARAMS).map_err(to_io)?;
+    while let Some(row) = rows.next().map_err(to_io)? {
+        let sql : String = row.get(0).map_err(to_io)?;
+        writeln!(out, "{};", sql)?;
+    }
+
+    writeln!(out, "COMMIT;")
+}
+
+fn to_io(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn quote_value(v: ValueRef) -> String {
+    match v {
+        ValueRef::Null => "NULL".to_string(),
+        ValueRef::Integer(i) => i.to_string(),
+        ValueRef::Real(f) => f.to_string(),
+        ValueRef::Text(t) => format!("'{}'", String::from_utf8_lossy(t).replace('\'', "''")),
+        ValueRef::Blob(b) => {
+            let hex : String = b.iter().map(|byte| format!("{:02x}", byte)).collect();
+            format!("X'{}'", hex)
+        }
+    }
+}
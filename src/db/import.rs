@@ -0,0 +1,521 @@
+// crates.io
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+// local
+use crate::config::env_or;
+use crate::db::{self, QuestionSpec};
+use crate::db::answer::{check_answer, normalize_output, Answer, Kind};
+
+// A quiz and its questions as written by instructors, e.g. in TOML:
+//      slug = "ownership"
+//      title = "What is Ownership?"
+//      chapter = "ch04-01-what-is-ownership.html"
+//
+//      [[questions]]
+//      id = 0
+//      file = "vis_04_01_01"
+//      prompt = "What is the output of the function?"
+//      choices = ["Does not compile", "5", "15"]
+//      answer = 0
+//      explanation = "s is moved into takes_ownership"
+//
+//      [[questions]]
+//      id = 1
+//      kind = "multi_select"
+//      ...
+//      answer = [0, 2]
+// YAML uses the same fields, see parse_markdown for Markdown
+// Only the first question of each pool becomes part of the quiz, standing
+// for the rest of its pool
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct QuizFile {
+    pub slug: String,
+    pub title: String,
+    #[serde(default)]
+    pub chapter: Option<String>,
+    #[serde(default)]
+    pub questions: Vec<QuestionSpec>
+}
+
+impl QuizFile {
+    // EFFECTS: Return the ids of the questions that make up the quiz, in order
+    pub fn slots(&self) -> Vec<i64> {
+        let mut pools = HashSet::new();
+        self.questions.iter()
+            .filter(|q| q.pool.as_ref().is_none_or(|pool| pools.insert(pool)))
+            .map(|q| q.question_id)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Toml,
+    Yaml,
+    Markdown
+}
+
+impl Format {
+    // EFFECTS: Guess the format from a file extension
+    pub fn from_path(path: &Path) -> Option<Format> {
+        Format::from_name(path.extension()?.to_str()?)
+    }
+
+    // EFFECTS: Parse a format name or extension, e.g. "toml" or "md"
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name.to_ascii_lowercase().as_str() {
+            "toml" => Some(Format::Toml),
+            "yaml" | "yml" => Some(Format::Yaml),
+            "md" | "markdown" => Some(Format::Markdown),
+            _ => None
+        }
+    }
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Read a quiz written in format, without validating it
+pub fn parse(text: &str, format: Format) -> Result<QuizFile, String> {
+    match format {
+        Format::Toml => toml::from_str(text).map_err(|e| e.to_string()),
+        Format::Yaml => serde_yaml::from_str(text).map_err(|e| e.to_string()),
+        Format::Markdown => parse_markdown(text)
+    }
+}
+
+// Quiz fields in the YAML front matter of a Markdown quiz
+#[derive(Deserialize)]
+struct FrontMatter {
+    slug: String,
+    title: String,
+    #[serde(default)]
+    chapter: Option<String>
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Read a quiz written in Markdown, e.g.:
+//      ---
+//      slug: ownership
+//      title: What is Ownership?
+//      chapter: ch04-01-what-is-ownership.html
+//      ---
+//
+//      ## What is the output of the function?
+//      id: 0
+//      file: vis_04_01_01
+//      free-response: yes
+//
+//      - [x] Does not compile
+//      - [ ] 5
+//        > s cannot be printed after the move
+//      - [ ] 15
+//
+//      > s is moved into takes_ownership
+//
+//      ## What does the program print?
+//      id: 1
+//      file: vis_04_01_02
+//      kind: output
+//
+//      ```
+//      hello
+//      ```
+//  Each "## " heading starts a question, its text being the prompt;
+//  "- [x]" marks the answer and "> " lines make up the explanation,
+//  or that of the choice above them if indented
+//  Other kinds of questions set kind before their choices, then
+//      multi_select    marks every correct choice with "- [x]"
+//      output          puts the expected output in a ``` block
+//      compile_fail    names the failing line, e.g. "line: 7"
+//      ordering        lists choice ids in order, e.g. "order: 2, 0, 1"
+//  Variants of a question name the same pool, e.g. "pool: move-into-function"
+pub fn parse_markdown(text: &str) -> Result<QuizFile, String> {
+    let mut lines = text.lines().enumerate().peekable();
+
+    // front matter
+    if lines.next().map(|(_, l)| l.trim()) != Some("---") {
+        return Err("line 1: expected front matter starting with ---".to_string())
+    }
+    let mut front = String::new();
+    loop {
+        match lines.next() {
+            Some((_, l)) if l.trim() == "---" => break,
+            Some((_, l)) => {
+                front.push_str(l);
+                front.push('\n');
+            },
+            None => return Err("front matter is not closed with ---".to_string())
+        }
+    }
+    let front : FrontMatter = serde_yaml::from_str(&front)
+        .map_err(|e| format!("front matter: {}", e))?;
+
+    let mut questions : Vec<QuestionSpec> = Vec::new();
+    // expected output read so far while inside a ``` block
+    let mut output : Option<String> = None;
+    for (i, raw) in lines {
+        let n = i + 1;
+        let line = raw.trim();
+        if let Some(text) = output.as_mut() {
+            if line.starts_with("```") {
+                // blocks only open inside a question
+                questions.last_mut().unwrap().answer = Answer::Text(output.take().unwrap());
+            }
+            else {
+                text.push_str(raw);
+                text.push('\n');
+            }
+            continue
+        }
+        if line.is_empty() {
+            continue
+        }
+        if let Some(prompt) = line.strip_prefix("## ") {
+            questions.push(QuestionSpec {
+                question_id: -1,
+                kind: Kind::Choice,
+                answer: Answer::Index(-1),
+                filename: String::new(),
+                prompt: prompt.trim().to_string(),
+                contains_fr: false,
+                choices: Vec::new(),
+                explanation: String::new(),
+                choice_explanations: Vec::new(),
+                pool: None
+            });
+            continue
+        }
+        let q = match questions.last_mut() {
+            Some(q) => q,
+            None => return Err(format!("line {}: expected a \"## \" question heading", n))
+        };
+
+        if let Some(choice) = line.strip_prefix("- [ ]") {
+            q.choices.push(choice.trim().to_string());
+        }
+        else if let Some(choice) = line.strip_prefix("- [x]").or_else(|| line.strip_prefix("- [X]")) {
+            let id = q.choices.len() as i64;
+            match (q.kind, &mut q.answer) {
+                (Kind::Choice, Answer::Index(answer)) if *answer >= 0 =>
+                    return Err(format!("line {}: more than one answer is marked", n)),
+                (Kind::Choice, answer) => *answer = Answer::Index(id),
+                (Kind::MultiSelect, Answer::Indices(answers)) => answers.push(id),
+                _ => return Err(format!("line {}: only choice and multi_select questions mark answers", n))
+            }
+            q.choices.push(choice.trim().to_string());
+        }
+        else if line.starts_with("```") {
+            if q.kind != Kind::Output {
+                return Err(format!("line {}: only output questions have an output block", n))
+            }
+            output = Some(String::new());
+        }
+        else if let Some(explanation) = line.strip_prefix('>') {
+            let text = if raw.starts_with(char::is_whitespace) && !q.choices.is_empty() {
+                q.choice_explanations.resize(q.choices.len(), String::new());
+                q.choice_explanations.last_mut().unwrap()
+            }
+            else {
+                &mut q.explanation
+            };
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            text.push_str(explanation.trim());
+        }
+        else if let Some((key, value)) = line.split_once(':') {
+            let value = value.trim();
+            match key.trim() {
+                "id" => q.question_id = value.parse()
+                    .map_err(|_| format!("line {}: id must be a number", n))?,
+                "file" => q.filename = value.to_string(),
+                "free-response" => q.contains_fr = matches!(value, "yes" | "true"),
+                "pool" => q.pool = Some(value.to_string()),
+                "kind" => {
+                    if !q.choices.is_empty() {
+                        return Err(format!("line {}: kind must come before the choices", n))
+                    }
+                    q.kind = Kind::from_name(&value.replace('-', "_"))
+                        .ok_or_else(|| format!("line {}: unknown kind {}", n, value))?;
+                    q.answer = match q.kind {
+                        Kind::MultiSelect | Kind::Ordering => Answer::Indices(Vec::new()),
+                        Kind::Output => Answer::Text(String::new()),
+                        Kind::Choice | Kind::CompileFail => Answer::Index(-1)
+                    };
+                },
+                "line" => q.answer = Answer::Index(value.parse()
+                    .map_err(|_| format!("line {}: line must be a number", n))?),
+                "order" => q.answer = Answer::Indices(value.split(',')
+                    .map(|id| id.trim().parse())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| format!("line {}: order must be choice ids like 2, 0, 1", n))?),
+                key => return Err(format!("line {}: unknown field {}", n, key))
+            }
+        }
+        else {
+            return Err(format!("line {}: unexpected text", n))
+        }
+    }
+    if output.is_some() {
+        return Err("output block is not closed with ```".to_string())
+    }
+    // choices after the last explained one have none
+    for q in questions.iter_mut().filter(|q| !q.choice_explanations.is_empty()) {
+        q.choice_explanations.resize(q.choices.len(), String::new());
+    }
+
+    Ok(QuizFile {
+        slug: front.slug,
+        title: front.title,
+        chapter: front.chapter,
+        questions
+    })
+}
+
+// Where the visualizations questions are shown with live, read from
+//      FPLAB_VISUALIZATIONS  directory of the book's visualization SVGs
+//                            (default: ../rustviz-tutorial/book/assets/img)
+#[derive(Debug, Clone)]
+pub struct Visualizations {
+    pub dir: PathBuf
+}
+
+impl Visualizations {
+    pub fn from_env() -> Self {
+        Visualizations {
+            dir: env_or("FPLAB_VISUALIZATIONS", PathBuf::from("../rustviz-tutorial/book/assets/img"))
+        }
+    }
+
+    // EFFECTS: Return the number of source lines visualization filename
+    //          shows, None if it cannot be read
+    //          RustViz draws each line of the source as text of class code
+    pub fn source_lines(&self, filename: &str) -> Option<usize> {
+        let svg = fs::read_to_string(self.dir.join(format!("{}.svg", filename))).ok()?;
+        Some(svg.matches("class=\"code\"").count()).filter(|n| *n > 0)
+    }
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return what is wrong with a question, empty if nothing
+//          Lines of compile_fail questions are checked against the
+//          visualization they point at, if it can be read
+pub fn validate_question(q: &QuestionSpec, visualizations: &Visualizations) -> Vec<String> {
+    let mut errors = Vec::new();
+    let name = format!("question {}", q.question_id);
+    if q.question_id < 0 {
+        errors.push(format!("{}: id must be 0 or more", name));
+    }
+    if q.prompt.trim().is_empty() {
+        errors.push(format!("{}: prompt is empty", name));
+    }
+    if q.filename.is_empty()
+        || !q.filename.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        errors.push(format!("{}: file must be a visualization name like vis_04_01_01", name));
+    }
+    match q.kind {
+        Kind::Choice | Kind::MultiSelect | Kind::Ordering if q.choices.len() < 2 =>
+            errors.push(format!("{}: needs at least 2 choices", name)),
+        Kind::Output | Kind::CompileFail if !q.choices.is_empty() =>
+            errors.push(format!("{}: {} questions have no choices", name, q.kind.name())),
+        _ => ()
+    }
+    if q.choices.iter().any(|c| c.trim().is_empty()) {
+        errors.push(format!("{}: has an empty choice", name));
+    }
+    if !q.choice_explanations.is_empty() && q.choice_explanations.len() != q.choices.len() {
+        errors.push(format!("{}: has {} choice explanations for {} choices",
+            name, q.choice_explanations.len(), q.choices.len()));
+    }
+    let mut seen = HashSet::new();
+    if q.choices.iter().any(|c| !seen.insert(c.trim())) {
+        errors.push(format!("{}: has duplicate choices", name));
+    }
+    if let Some(error) = check_answer(q.kind, q.choices.len(), &q.answer) {
+        errors.push(format!("{}: {}", name, error));
+    }
+    if let (Kind::CompileFail, Answer::Index(line)) = (q.kind, &q.answer) {
+        match visualizations.source_lines(&q.filename) {
+            Some(lines) if *line > lines as i64 =>
+                errors.push(format!("{}: line {} is past the end of {}, which has {} lines",
+                    name, line, q.filename, lines)),
+            _ => ()
+        }
+    }
+    match &q.pool {
+        Some(pool) if !is_slug(pool) =>
+            errors.push(format!("{}: pool {:?} must be 1 to 64 lowercase letters, digits or -", name, pool)),
+        _ => ()
+    }
+    match &q.answer {
+        Answer::Indices(ids) if q.kind == Kind::MultiSelect && ids.is_empty() =>
+            errors.push(format!("{}: no answer is marked", name)),
+        Answer::Text(text) if normalize_output(text).is_empty() =>
+            errors.push(format!("{}: expected output is empty", name)),
+        _ => ()
+    }
+    errors
+}
+
+// EFFECTS: Return true if name is fit for URLs
+fn is_slug(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 64
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return a slug unfit for URLs, if any
+pub fn validate_slug(slug: &str) -> Option<String> {
+    if is_slug(slug) {
+        None
+    }
+    else {
+        Some(format!("slug {:?} must be 1 to 64 lowercase letters, digits or -", slug))
+    }
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return what is wrong with a quiz, empty if it can be applied
+pub fn validate(quiz: &QuizFile, visualizations: &Visualizations) -> Vec<String> {
+    let mut errors : Vec<String> = validate_slug(&quiz.slug).into_iter().collect();
+    if quiz.title.trim().is_empty() {
+        errors.push("title is empty".to_string());
+    }
+    if quiz.questions.is_empty() {
+        errors.push("quiz has no questions".to_string());
+    }
+    let mut ids = HashSet::new();
+    for q in &quiz.questions {
+        if !ids.insert(q.question_id) {
+            errors.push(format!("question {}: id is used more than once", q.question_id));
+        }
+        errors.extend(validate_question(q, visualizations));
+    }
+    errors
+}
+
+// A difference between a quiz file and the database
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum Change {
+    AddQuiz { slug: String },
+    UpdateQuiz { slug: String, fields: Vec<&'static str> },
+    AddQuestion { question_id: i64 },
+    UpdateQuestion { question_id: i64, fields: Vec<&'static str> },
+    // changes how an answered question is graded, which apply refuses
+    Regrade { question_id: i64, answered: i64 }
+}
+
+impl Change {
+    pub fn is_conflict(&self) -> bool {
+        matches!(self, Change::Regrade { .. })
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::AddQuiz { slug } => write!(f, "+ quiz {}", slug),
+            Change::UpdateQuiz { slug, fields } => write!(f, "~ quiz {}: {}", slug, fields.join(", ")),
+            Change::AddQuestion { question_id } => write!(f, "+ question {}", question_id),
+            Change::UpdateQuestion { question_id, fields } =>
+                write!(f, "~ question {}: {}", question_id, fields.join(", ")),
+            Change::Regrade { question_id, answered } => write!(
+                f, "! question {}: answered {} times, its kind, choices and answer cannot change",
+                question_id, answered
+            )
+        }
+    }
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: List what applying quiz would change, empty if nothing
+pub fn diff(conn: &Connection, quiz: &QuizFile) -> rusqlite::Result<Vec<Change>> {
+    let mut changes = Vec::new();
+
+    let existing : Option<(i64, String, Option<String>)> = conn.query_row(
+        "SELECT quiz_id, title, chapter FROM quizzes
+        WHERE slug = ?1",
+        params![&quiz.slug],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+    ).optional()?;
+    match existing {
+        None => changes.push(Change::AddQuiz { slug: quiz.slug.clone() }),
+        Some((quiz_id, title, chapter)) => {
+            let ids = quiz.slots();
+            let mut fields = Vec::new();
+            if title != quiz.title {
+                fields.push("title");
+            }
+            if chapter != quiz.chapter {
+                fields.push("chapter");
+            }
+            if db::quiz_question_ids(conn, quiz_id)? != ids {
+                fields.push("questions");
+            }
+            if !fields.is_empty() {
+                changes.push(Change::UpdateQuiz { slug: quiz.slug.clone(), fields });
+            }
+        }
+    }
+
+    for q in &quiz.questions {
+        let old = match db::load_question(conn, q.question_id)? {
+            Some(old) => old,
+            None => {
+                changes.push(Change::AddQuestion { question_id: q.question_id });
+                continue
+            }
+        };
+        if db::regrades(&old, q) {
+            let answered = db::num_answered(conn, q.question_id)?;
+            if answered > 0 {
+                changes.push(Change::Regrade { question_id: q.question_id, answered });
+                continue
+            }
+        }
+        let fields : Vec<&'static str> = [
+            ("kind", old.kind != q.kind),
+            ("prompt", old.prompt != q.prompt),
+            ("file", old.filename != q.filename),
+            ("choices", old.choices != q.choices),
+            ("answer", old.answer != q.answer),
+            ("free-response", old.contains_fr != q.contains_fr),
+            ("explanation", old.explanation != q.explanation),
+            ("choice explanations", old.choice_explanations != q.choice_explanations),
+            ("pool", old.pool != q.pool)
+        ].iter().filter(|(_, changed)| *changed).map(|(field, _)| *field).collect();
+        if !fields.is_empty() {
+            changes.push(Change::UpdateQuestion { question_id: q.question_id, fields });
+        }
+    }
+    Ok(changes)
+}
+
+// REQUIRES: validate(quiz) is empty
+// MODIFIES: Database
+// EFFECTS: Write the quiz, its questions and chapter in a single transaction
+//          and return the changes made, as listed by diff
+//          Nothing is written if any change is a conflict
+pub fn apply(conn: &mut Connection, quiz: &QuizFile) -> rusqlite::Result<Vec<Change>> {
+    // check and write at once, so no answer comes in between
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let changes = diff(&tx, quiz)?;
+    if changes.iter().any(Change::is_conflict) {
+        return Ok(changes)
+    }
+    db::write_questions(&tx, &quiz.questions)?;
+    db::write_quiz(&tx, &quiz.slug, &quiz.title, &quiz.slots())?;
+    db::attach_quiz(&tx, &quiz.slug, quiz.chapter.as_deref())?;
+    tx.commit()?;
+    Ok(changes)
+}
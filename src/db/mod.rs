@@ -0,0 +1,481 @@
+pub mod answer;
+pub mod backup;
+pub mod dump;
+pub mod import;
+pub mod upgrade;
+
+// crates.io
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior, NO_PARAMS};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+// local
+use crate::db::answer::{Answer, Kind};
+
+// Default location of the server's database,
+// relative to the repository root
+pub const DB_PATH: &str = "db/fp.sqlite3";
+
+// Table definitions and initial data, compiled into the binaries
+// so that the admin CLI does not depend on the sqlite3 shell
+pub const SCHEMA: &str = include_str!("../../sql/schema.sql");
+pub const DATA: &str = include_str!("../../sql/data.sql");
+
+// Stored in PRAGMA user_version by SCHEMA
+// Must match the version at the top of sql/schema.sql
+// Bumping it needs a step in upgrade::STEPS for existing databases
+pub const SCHEMA_VERSION: i32 = 1;
+
+// REQUIRES: database at DB_PATH has been created
+// MODIFIES: n/a
+// EFFECTS: Open a connection to the server's database
+//          with foreign key constraints enforced
+pub fn connect() -> Connection {
+    open(DB_PATH).unwrap()
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Open a connection to the database at path
+//          with foreign key constraints enforced and a busy timeout
+pub fn open<P: AsRef<Path>>(path: P) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+    // wait for concurrent writers instead of failing with SQLITE_BUSY
+    conn.busy_timeout(Duration::from_secs(5))?;
+    Ok(conn)
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the schema version the database was created with
+pub fn schema_version(conn: &Connection) -> rusqlite::Result<i32> {
+    conn.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))
+}
+
+// REQUIRES: no database exists at path
+// MODIFIES: file system
+// EFFECTS: Create a new database at path with the schema
+//          and initial data, creating parent directories as needed
+pub fn create<P: AsRef<Path>>(path: P) -> rusqlite::Result<Connection> {
+    if let Some(dir) = path.as_ref().parent() {
+        // failing to create the directory surfaces as an open error below
+        let _ = fs::create_dir_all(dir);
+    }
+    let conn = open(path)?;
+    conn.execute_batch(SCHEMA)?;
+    conn.execute_batch(DATA)?;
+    Ok(conn)
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database
+// EFFECTS: Run every statement in sql inside a single transaction,
+//          so a failing seed file leaves the database untouched
+pub fn seed(conn: &mut Connection, sql: &str) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute_batch(sql)?;
+    tx.commit()
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database
+// EFFECTS: Register a new user with the given token
+//          Returns false if the token is already taken
+pub fn add_user(conn: &Connection, token: &str) -> rusqlite::Result<bool> {
+    let n = conn.execute(
+        "INSERT OR IGNORE INTO users (token)
+        VALUES (?1)",
+        params![token]
+    )?;
+    Ok(n == 1)
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database
+// EFFECTS: Delete the user with the given token along with
+//          all of their rows in dependent tables
+//          Returns false if no such user exists
+pub fn remove_user(conn: &Connection, token: &str) -> rusqlite::Result<bool> {
+    let n = conn.execute(
+        "DELETE FROM users
+        WHERE token = ?1",
+        params![token]
+    )?;
+    Ok(n == 1)
+}
+
+// Quiz question as described in an import file
+// e.g.:
+//      {
+//          "question_id": 0,
+//          "kind": "choice",
+//          "answer": 1,
+//          "filename": "vis_04_01_01",
+//          "prompt": "What is the output of the function?",
+//          "contains_fr": true,
+//          "choices": ["Does not compile", "5", "15"],
+//          "explanation": "x is moved into f, so it cannot be printed",
+//          "choice_explanations": ["", "x cannot be used after the move", ""],
+//          "pool": "move-into-function"
+//      }
+// Quiz files may write id for question_id and file for filename
+// kind defaults to choice, see answer::Answer for answers to other kinds
+// Questions in the same pool are variants of each other, see route::shuffle
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct QuestionSpec {
+    #[serde(alias = "id")]
+    pub question_id: i64,
+    #[serde(default)]
+    pub kind: Kind,
+    pub answer: Answer,
+    #[serde(alias = "file")]
+    pub filename: String,
+    pub prompt: String,
+    #[serde(default)]
+    pub contains_fr: bool,
+    pub choices: Vec<String>,
+    #[serde(default)]
+    pub explanation: String,
+    // one per choice, or none at all
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub choice_explanations: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool: Option<String>
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database
+// EFFECTS: Insert or replace each question and its choices
+//          in a single transaction
+//          Returns the answered questions it would regrade, see regraded,
+//          importing none of the questions if there are any
+pub fn import_questions(
+    conn: &mut Connection,
+    questions: &[QuestionSpec]
+) -> rusqlite::Result<Vec<i64>> {
+    // check and write at once, so no answer comes in between
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let conflicts = regraded(&tx, questions)?;
+    if conflicts.is_empty() {
+        write_questions(&tx, questions)?;
+        tx.commit()?;
+    }
+    Ok(conflicts)
+}
+
+// REQUIRES: called inside a transaction
+// MODIFIES: Database
+// EFFECTS: Insert or replace each question and its choices
+pub fn write_questions(conn: &Connection, questions: &[QuestionSpec]) -> rusqlite::Result<()> {
+    for q in questions {
+        conn.execute(
+            "DELETE FROM choices
+            WHERE question_id = ?1",
+            params![q.question_id]
+        )?;
+        // update in place, REPLACE would delete the question
+        // from its quizzes through ON DELETE CASCADE
+        let (answer, solution) = q.answer.to_columns();
+        conn.execute(
+            "INSERT INTO questions (
+                question_id, kind, answer, solution, filename,
+                prompt, contains_fr, explanation, pool
+            )
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            ON CONFLICT (question_id) DO UPDATE SET
+                kind = excluded.kind, answer = excluded.answer,
+                solution = excluded.solution, filename = excluded.filename,
+                prompt = excluded.prompt, contains_fr = excluded.contains_fr,
+                explanation = excluded.explanation, pool = excluded.pool",
+            params![
+                q.question_id, q.kind.name(), answer, &solution, &q.filename,
+                &q.prompt, q.contains_fr, &q.explanation, &q.pool
+            ]
+        )?;
+        for (ans_id, text) in q.choices.iter().enumerate() {
+            let explanation = q.choice_explanations.get(ans_id).map_or("", String::as_str);
+            conn.execute(
+                "INSERT INTO choices (question_id, ans_id, choice_text, explanation)
+                VALUES (?1, ?2, ?3, ?4)",
+                params![q.question_id, ans_id as i64, text, explanation]
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the question with its choices in order
+pub fn load_question(conn: &Connection, question_id: i64) -> rusqlite::Result<Option<QuestionSpec>> {
+    let question = conn.query_row(
+        "SELECT kind, answer, solution, filename, prompt, contains_fr, explanation, pool
+        FROM questions WHERE question_id = ?1",
+        params![question_id],
+        |row| Ok(QuestionSpec {
+            question_id,
+            kind: Kind::from_name(&row.get::<_, String>(0)?).unwrap_or_default(),
+            answer: Answer::from_columns(row.get(1)?, &row.get::<_, String>(2)?),
+            filename: row.get(3)?,
+            prompt: row.get(4)?,
+            contains_fr: row.get(5)?,
+            choices: Vec::new(),
+            explanation: row.get(6)?,
+            choice_explanations: Vec::new(),
+            pool: row.get(7)?
+        })
+    ).optional()?;
+    let mut question = match question {
+        Some(question) => question,
+        None => return Ok(None)
+    };
+
+    let mut stmt = conn.prepare(
+        "SELECT choice_text, explanation FROM choices
+        WHERE question_id = ?1
+        ORDER BY ans_id"
+    )?;
+    let choices : Vec<(String, String)> = stmt.query_map(params![question_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    let (texts, explanations) : (Vec<String>, Vec<String>) = choices.into_iter().unzip();
+    question.choices = texts;
+    // none at all rather than all empty
+    if explanations.iter().any(|e| !e.is_empty()) {
+        question.choice_explanations = explanations;
+    }
+    Ok(Some(question))
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return true if replacing old with new changes how answers
+//          to it are read or graded: its kind, choices or answer key
+pub fn regrades(old: &QuestionSpec, new: &QuestionSpec) -> bool {
+    old.kind != new.kind || old.choices != new.choices || old.answer != new.answer
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the number of answers submitted to question_id
+pub fn num_answered(conn: &Connection, question_id: i64) -> rusqlite::Result<i64> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM responses
+        WHERE question_id = ?1 AND answer <> -2",
+        params![question_id],
+        |row| row.get(0)
+    )
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the ids of the answered questions that writing
+//          questions over the stored ones would regrade
+pub fn regraded(conn: &Connection, questions: &[QuestionSpec]) -> rusqlite::Result<Vec<i64>> {
+    let mut ids = Vec::new();
+    for q in questions {
+        if let Some(old) = load_question(conn, q.question_id)? {
+            if regrades(&old, q) && num_answered(conn, q.question_id)? > 0 {
+                ids.push(q.question_id);
+            }
+        }
+    }
+    Ok(ids)
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database
+// EFFECTS: Delete the question and its choices, closing the gap
+//          it leaves in the order of every quiz it was part of
+//          Responses to it are kept for analysis
+//          Returns false if no such question exists
+pub fn remove_question(conn: &mut Connection, question_id: i64) -> rusqlite::Result<bool> {
+    let tx = conn.transaction()?;
+    let quiz_ids : Vec<i64> = tx.prepare(
+        "SELECT quiz_id FROM quiz_questions
+        WHERE question_id = ?1"
+    )?.query_map(params![question_id], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let n = tx.execute(
+        "DELETE FROM questions
+        WHERE question_id = ?1",
+        params![question_id]
+    )?;
+
+    for quiz_id in quiz_ids {
+        let remaining = quiz_question_ids(&tx, quiz_id)?;
+        write_quiz_questions(&tx, quiz_id, &remaining)?;
+    }
+    tx.commit()?;
+    Ok(n == 1)
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the ids of quiz_id's questions in order
+pub fn quiz_question_ids(conn: &Connection, quiz_id: i64) -> rusqlite::Result<Vec<i64>> {
+    let mut stmt = conn.prepare(
+        "SELECT question_id FROM quiz_questions
+        WHERE quiz_id = ?1
+        ORDER BY position"
+    )?;
+    let ids = stmt.query_map(params![quiz_id], |row| row.get(0))?;
+    ids.collect()
+}
+
+// REQUIRES: called inside a transaction
+// MODIFIES: Database
+// EFFECTS: Make quiz_id consist of question_ids in the given order
+fn write_quiz_questions(conn: &Connection, quiz_id: i64, question_ids: &[i64]) -> rusqlite::Result<()> {
+    conn.execute(
+        "DELETE FROM quiz_questions
+        WHERE quiz_id = ?1",
+        params![quiz_id]
+    )?;
+    for (position, question_id) in question_ids.iter().enumerate() {
+        conn.execute(
+            "INSERT INTO quiz_questions (quiz_id, position, question_id)
+            VALUES (?1, ?2, ?3)",
+            params![quiz_id, position as i64, question_id]
+        )?;
+    }
+    Ok(())
+}
+
+// REQUIRES: every question id exists
+// MODIFIES: Database
+// EFFECTS: Create the quiz slug, or rename an existing one to title,
+//          made up of question_ids in the given order
+//          Progress of users on an existing quiz is kept
+pub fn create_quiz(
+    conn: &mut Connection,
+    slug: &str,
+    title: &str,
+    question_ids: &[i64]
+) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    write_quiz(&tx, slug, title, question_ids)?;
+    tx.commit()
+}
+
+// REQUIRES: called inside a transaction, every question id exists
+// MODIFIES: Database
+// EFFECTS: Create or update the quiz slug as in create_quiz,
+//          returning its id
+pub fn write_quiz(
+    conn: &Connection,
+    slug: &str,
+    title: &str,
+    question_ids: &[i64]
+) -> rusqlite::Result<i64> {
+    let existing : Option<i64> = conn.query_row(
+        "SELECT quiz_id FROM quizzes
+        WHERE slug = ?1",
+        params![slug],
+        |row| row.get(0)
+    ).optional()?;
+    let quiz_id = match existing {
+        Some(quiz_id) => {
+            conn.execute(
+                "UPDATE quizzes SET title = ?1
+                WHERE quiz_id = ?2",
+                params![title, quiz_id]
+            )?;
+            quiz_id
+        },
+        None => {
+            conn.execute(
+                "INSERT INTO quizzes (slug, title)
+                VALUES (?1, ?2)",
+                params![slug, title]
+            )?;
+            conn.last_insert_rowid()
+        }
+    };
+    write_quiz_questions(conn, quiz_id, question_ids)?;
+    Ok(quiz_id)
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database
+// EFFECTS: Attach the quiz slug to a book chapter, or detach it if None
+//          Returns false if no such quiz exists
+pub fn attach_quiz(conn: &Connection, slug: &str, chapter: Option<&str>) -> rusqlite::Result<bool> {
+    let n = conn.execute(
+        "UPDATE quizzes SET chapter = ?1
+        WHERE slug = ?2",
+        params![chapter, slug]
+    )?;
+    Ok(n == 1)
+}
+
+// Summary of the data collected so far
+#[derive(Serialize, Debug)]
+pub struct Stats {
+    pub users: i64,
+    pub questions: i64,
+    pub responses: i64,
+    pub answered: i64,
+    pub hover_events: i64,
+    pub page_visits: i64,
+    pub problems_passed: i64
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Count rows of interest in each table
+pub fn stats(conn: &Connection) -> rusqlite::Result<Stats> {
+    let count = |sql: &str| -> rusqlite::Result<i64> {
+        conn.query_row(sql, NO_PARAMS, |row| row.get(0))
+    };
+
+    Ok(Stats {
+        users: count("SELECT COUNT(*) FROM users")?,
+        questions: count("SELECT COUNT(*) FROM questions")?,
+        responses: count("SELECT COUNT(*) FROM responses")?,
+        // answer = -2 means the user has not submitted an answer yet
+        answered: count("SELECT COUNT(*) FROM responses WHERE answer <> -2")?,
+        hover_events: count("SELECT COALESCE(SUM(hover_times), 0) FROM user_hover")?,
+        page_visits: count("SELECT COUNT(*) FROM user_page")?,
+        problems_passed: count("SELECT COUNT(*) FROM scores WHERE score > 0")?
+    })
+}
+
+// How one variant of a question pool fared, to compare their difficulty
+#[derive(Serialize, Debug, PartialEq)]
+pub struct VariantStats {
+    pub pool: String,
+    pub question_id: i64,
+    // users it was drawn for in some quiz
+    pub shown: i64,
+    pub answered: i64,
+    pub correct: i64
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the stats of every question in a pool, by pool and id
+pub fn pool_stats(conn: &Connection) -> rusqlite::Result<Vec<VariantStats>> {
+    let mut stmt = conn.prepare(
+        "SELECT q.pool, q.question_id,
+            (SELECT COUNT(DISTINCT a.token) FROM arrangements a
+                WHERE a.question_id = q.question_id),
+            (SELECT COUNT(*) FROM responses r
+                WHERE r.question_id = q.question_id AND r.answer <> -2),
+            (SELECT COUNT(*) FROM responses r
+                WHERE r.question_id = q.question_id AND r.answer <> -2 AND r.correct)
+        FROM questions q
+        WHERE q.pool IS NOT NULL
+        ORDER BY q.pool, q.question_id"
+    )?;
+    let stats = stmt.query_map(NO_PARAMS, |row| Ok(VariantStats {
+        pool: row.get(0)?,
+        question_id: row.get(1)?,
+        shown: row.get(2)?,
+        answered: row.get(3)?,
+        correct: row.get(4)?
+    }))?;
+    stats.collect()
+}
@@ -0,0 +1,352 @@
+// crates.io
+use rusqlite::{Connection, NO_PARAMS};
+use std::fmt;
+// local
+use crate::db;
+
+// Oldest schema version upgrade can bring up to date
+// Version 0 is the schema from before versions were recorded
+pub const OLDEST_UPGRADABLE: i32 = 0;
+
+// Statements bringing a database to each version from the one before it
+// Every step must end in the tables of sql/schema.sql at that version
+const STEPS: [(i32, &str); 1] = [
+    // accounts, sessions, LMS integration, quizzes and analytics
+    // Users kept their place in the only quiz there was, which becomes
+    // quiz 1 as in sql/data.sql, and responses are kept per quiz
+    (1, "
+        CREATE TABLE users_v1 (
+          token VARCHAR(20) NOT NULL,
+          created DATETIME DEFAULT CURRENT_TIMESTAMP,
+          username VARCHAR(64) UNIQUE,
+          password TEXT,
+          admin INTEGER NOT NULL DEFAULT 0,
+          section VARCHAR(64),
+          PRIMARY KEY (token)
+        );
+        INSERT INTO users_v1 (token, created)
+        SELECT token, created FROM users;
+        UPDATE users_v1 SET admin = 1
+        WHERE token IN ('f6e035e8c2a2898938e59e5a361b9faddf4e68e', '59e5a361b9faddf4e68ef6e035e8c2a2898938e');
+
+        CREATE TABLE questions_v1 (
+          question_id INTEGER NOT NULL,
+          kind VARCHAR(16) NOT NULL DEFAULT 'choice',
+          answer INTEGER NOT NULL,
+          solution TEXT NOT NULL DEFAULT '',
+          filename VARCHAR(20) NOT NULL,
+          prompt VARCHAR(256) NOT NULL,
+          contains_fr INTEGER NOT NULL DEFAULT 0,
+          explanation TEXT NOT NULL DEFAULT '',
+          pool VARCHAR(64),
+          PRIMARY KEY (question_id)
+        );
+        INSERT INTO questions_v1 (question_id, answer, filename, prompt, contains_fr)
+        SELECT question_id, answer, filename, prompt, contains_fr FROM questions;
+        ALTER TABLE choices ADD COLUMN explanation TEXT NOT NULL DEFAULT '';
+
+        CREATE TABLE quizzes (
+          quiz_id INTEGER PRIMARY KEY AUTOINCREMENT,
+          slug VARCHAR(64) NOT NULL UNIQUE,
+          title VARCHAR(256) NOT NULL,
+          chapter VARCHAR(256),
+          max_attempts INTEGER,
+          time_limit INTEGER,
+          feedback VARCHAR(16) NOT NULL DEFAULT 'end'
+        );
+        INSERT INTO quizzes (quiz_id, slug, title, chapter)
+        VALUES (1, 'ownership', 'What is Ownership?', 'ch04-01-what-is-ownership.html');
+
+        CREATE TABLE quiz_questions (
+          quiz_id INTEGER NOT NULL,
+          position INTEGER NOT NULL,
+          question_id INTEGER NOT NULL,
+          PRIMARY KEY (quiz_id, position),
+          UNIQUE (quiz_id, question_id),
+          FOREIGN KEY (quiz_id) REFERENCES quizzes(quiz_id) ON UPDATE CASCADE ON DELETE CASCADE,
+          FOREIGN KEY (question_id) REFERENCES questions(question_id) ON UPDATE CASCADE ON DELETE CASCADE
+        );
+        INSERT INTO quiz_questions (quiz_id, position, question_id)
+        SELECT 1, (SELECT COUNT(*) FROM questions_v1 p WHERE p.question_id < q.question_id), q.question_id
+        FROM questions_v1 q;
+
+        CREATE TABLE quiz_progress (
+          token VARCHAR(20) NOT NULL,
+          quiz_id INTEGER NOT NULL,
+          curr_ques INTEGER NOT NULL DEFAULT 0,
+          started INTEGER,
+          submitted INTEGER,
+          PRIMARY KEY (token, quiz_id),
+          FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE,
+          FOREIGN KEY (quiz_id) REFERENCES quizzes(quiz_id) ON UPDATE CASCADE ON DELETE CASCADE
+        );
+        INSERT INTO quiz_progress (token, quiz_id, curr_ques)
+        SELECT token, 1, curr_ques FROM users
+        WHERE curr_ques > 0;
+
+        CREATE TABLE responses_v1 (
+          token INTEGER NOT NULL,
+          quiz_id INTEGER NOT NULL,
+          question_id INTEGER NOT NULL,
+          answer INTEGER NOT NULL DEFAULT -2,
+          time_elapsed INTEGER NOT NULL DEFAULT 0,
+          hover_time INTEGER NOT NULL DEFAULT 0,
+          free_response TEXT NOT NULL DEFAULT '',
+          correct INTEGER NOT NULL DEFAULT 0,
+          response TEXT NOT NULL DEFAULT '',
+          attempts INTEGER NOT NULL DEFAULT 0,
+          PRIMARY KEY(token, quiz_id, question_id)
+          FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE
+        );
+        INSERT INTO responses_v1 (token, quiz_id, question_id, answer, time_elapsed,
+            hover_time, free_response, correct, attempts)
+        SELECT r.token, 1, r.question_id, r.answer, r.time_elapsed, r.hover_time, r.free_response,
+            COALESCE(r.answer = q.answer, 0), r.answer != -2
+        FROM responses r
+        LEFT JOIN questions q ON q.question_id = r.question_id;
+
+        DROP TABLE responses;
+        DROP TABLE questions;
+        DROP TABLE users;
+        ALTER TABLE users_v1 RENAME TO users;
+        ALTER TABLE questions_v1 RENAME TO questions;
+        ALTER TABLE responses_v1 RENAME TO responses;
+
+        CREATE TABLE invites (
+          code VARCHAR(20) NOT NULL,
+          section VARCHAR(64) NOT NULL,
+          max_uses INTEGER NOT NULL DEFAULT 1,
+          uses INTEGER NOT NULL DEFAULT 0,
+          revoked INTEGER NOT NULL DEFAULT 0,
+          created_by VARCHAR(20),
+          created DATETIME DEFAULT CURRENT_TIMESTAMP,
+          PRIMARY KEY (code),
+          FOREIGN KEY (created_by) REFERENCES users(token) ON UPDATE CASCADE ON DELETE SET NULL
+        );
+
+        CREATE TABLE sessions (
+          id VARCHAR(64) NOT NULL,
+          token VARCHAR(20) NOT NULL,
+          created INTEGER NOT NULL,
+          last_seen INTEGER NOT NULL,
+          user_agent VARCHAR(256),
+          PRIMARY KEY (id),
+          FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE
+        );
+
+        CREATE TABLE consents (
+          ID INTEGER PRIMARY KEY AUTOINCREMENT,
+          token VARCHAR(20) NOT NULL,
+          version INTEGER NOT NULL,
+          consented INTEGER NOT NULL,
+          created INTEGER NOT NULL,
+          FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE
+        );
+
+        CREATE TABLE oidc_states (
+          state VARCHAR(64) NOT NULL,
+          nonce VARCHAR(64) NOT NULL,
+          verifier VARCHAR(128) NOT NULL,
+          link_token VARCHAR(20),
+          invite VARCHAR(20),
+          created INTEGER NOT NULL,
+          PRIMARY KEY (state)
+        );
+
+        CREATE TABLE oidc_users (
+          issuer VARCHAR(256) NOT NULL,
+          sub VARCHAR(256) NOT NULL,
+          token VARCHAR(20) NOT NULL,
+          email VARCHAR(256),
+          PRIMARY KEY (issuer, sub),
+          UNIQUE (issuer, token),
+          FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE
+        );
+
+        CREATE TABLE lockouts (
+          key VARCHAR(300) NOT NULL,
+          failures INTEGER NOT NULL DEFAULT 0,
+          last_failure INTEGER NOT NULL,
+          locked_until INTEGER NOT NULL DEFAULT 0,
+          PRIMARY KEY (key)
+        );
+
+        CREATE TABLE failed_attempts (
+          ID INTEGER PRIMARY KEY AUTOINCREMENT,
+          route VARCHAR(256) NOT NULL,
+          ip VARCHAR(64) NOT NULL,
+          credential VARCHAR(256),
+          status INTEGER NOT NULL,
+          created DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE TABLE lti_states (
+          state VARCHAR(64) NOT NULL,
+          nonce VARCHAR(64) NOT NULL,
+          issuer VARCHAR(256) NOT NULL,
+          created INTEGER NOT NULL,
+          PRIMARY KEY (state)
+        );
+
+        CREATE TABLE lti_users (
+          issuer VARCHAR(256) NOT NULL,
+          sub VARCHAR(256) NOT NULL,
+          token VARCHAR(20) NOT NULL,
+          PRIMARY KEY (issuer, sub),
+          FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE
+        );
+
+        CREATE TABLE lti_lineitems (
+          token VARCHAR(20) NOT NULL,
+          path VARCHAR(256) NOT NULL,
+          issuer VARCHAR(256) NOT NULL,
+          lineitem TEXT NOT NULL,
+          client_id VARCHAR(256) NOT NULL DEFAULT '',
+          PRIMARY KEY (token, path),
+          FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE
+        );
+
+        CREATE TABLE response_events (
+          event_id INTEGER PRIMARY KEY AUTOINCREMENT,
+          token VARCHAR(20) NOT NULL,
+          quiz_id INTEGER,
+          question_id INTEGER NOT NULL,
+          answer INTEGER NOT NULL,
+          response TEXT NOT NULL DEFAULT '',
+          free_response TEXT NOT NULL DEFAULT '',
+          correct INTEGER NOT NULL DEFAULT 0,
+          time_elapsed INTEGER NOT NULL DEFAULT 0,
+          hover_time INTEGER NOT NULL DEFAULT 0,
+          created INTEGER NOT NULL,
+          FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE,
+          FOREIGN KEY (quiz_id) REFERENCES quizzes(quiz_id) ON UPDATE CASCADE ON DELETE SET NULL
+        );
+
+        CREATE TABLE arrangements (
+          token VARCHAR(20) NOT NULL,
+          quiz_id INTEGER NOT NULL,
+          position INTEGER NOT NULL,
+          slot INTEGER NOT NULL,
+          question_id INTEGER NOT NULL,
+          choice_order TEXT NOT NULL DEFAULT '[]',
+          PRIMARY KEY (token, quiz_id, position),
+          FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE,
+          FOREIGN KEY (quiz_id) REFERENCES quizzes(quiz_id) ON UPDATE CASCADE ON DELETE CASCADE,
+          FOREIGN KEY (slot) REFERENCES questions(question_id) ON UPDATE CASCADE ON DELETE CASCADE,
+          FOREIGN KEY (question_id) REFERENCES questions(question_id) ON UPDATE CASCADE ON DELETE CASCADE
+        );
+
+        CREATE TABLE rubric_items (
+          item_id INTEGER PRIMARY KEY AUTOINCREMENT,
+          question_id INTEGER NOT NULL,
+          position INTEGER NOT NULL,
+          description TEXT NOT NULL,
+          points INTEGER NOT NULL,
+          FOREIGN KEY (question_id) REFERENCES questions(question_id) ON UPDATE CASCADE ON DELETE CASCADE
+        );
+
+        CREATE TABLE fr_grades (
+          token VARCHAR(20) NOT NULL,
+          quiz_id INTEGER NOT NULL,
+          question_id INTEGER NOT NULL,
+          score INTEGER NOT NULL,
+          comment TEXT NOT NULL DEFAULT '',
+          grader VARCHAR(20),
+          graded INTEGER NOT NULL,
+          released INTEGER NOT NULL DEFAULT 0,
+          PRIMARY KEY (token, quiz_id, question_id),
+          FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE,
+          FOREIGN KEY (grader) REFERENCES users(token) ON UPDATE CASCADE ON DELETE SET NULL,
+          FOREIGN KEY (question_id) REFERENCES questions(question_id) ON UPDATE CASCADE ON DELETE CASCADE
+        );
+
+        CREATE TABLE fr_grade_items (
+          token VARCHAR(20) NOT NULL,
+          quiz_id INTEGER NOT NULL,
+          question_id INTEGER NOT NULL,
+          item_id INTEGER NOT NULL,
+          PRIMARY KEY (token, quiz_id, question_id, item_id),
+          FOREIGN KEY (token, quiz_id, question_id) REFERENCES fr_grades(token, quiz_id, question_id) ON UPDATE CASCADE ON DELETE CASCADE,
+          FOREIGN KEY (item_id) REFERENCES rubric_items(item_id) ON DELETE CASCADE
+        );
+
+        CREATE TABLE events (
+          event_id INTEGER PRIMARY KEY AUTOINCREMENT,
+          token VARCHAR(20) NOT NULL,
+          kind VARCHAR(32) NOT NULL,
+          client_time INTEGER NOT NULL,
+          received INTEGER NOT NULL,
+          data TEXT NOT NULL DEFAULT '{}',
+          FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE
+        );
+    ")
+];
+
+#[derive(Debug)]
+pub enum UpgradeError {
+    Sqlite(rusqlite::Error),
+    // created by a newer server, or older than OLDEST_UPGRADABLE
+    Unsupported(i32),
+    // the upgraded rows break foreign key constraints
+    ForeignKeys(i64)
+}
+
+impl fmt::Display for UpgradeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UpgradeError::Sqlite(e) => write!(f, "{}", e),
+            UpgradeError::Unsupported(found) if *found > db::SCHEMA_VERSION => write!(
+                f, "schema version {} is newer than this server's version {}",
+                found, db::SCHEMA_VERSION
+            ),
+            UpgradeError::Unsupported(found) => write!(
+                f, "schema version {} is older than {}", found, OLDEST_UPGRADABLE
+            ),
+            UpgradeError::ForeignKeys(n) => write!(f, "upgrade would break {} foreign keys", n)
+        }
+    }
+}
+
+impl std::error::Error for UpgradeError {}
+
+impl From<rusqlite::Error> for UpgradeError {
+    fn from(e: rusqlite::Error) -> Self {
+        UpgradeError::Sqlite(e)
+    }
+}
+
+// REQUIRES: server is not using the database
+// MODIFIES: Database
+// EFFECTS: Bring the database up to SCHEMA_VERSION in a single transaction,
+//          returning the version it had
+//          A database that is already up to date is left as it is
+pub fn upgrade(conn: &mut Connection) -> Result<i32, UpgradeError> {
+    let found = db::schema_version(conn)?;
+    if found == db::SCHEMA_VERSION {
+        return Ok(found)
+    }
+    if !(OLDEST_UPGRADABLE..db::SCHEMA_VERSION).contains(&found) {
+        return Err(UpgradeError::Unsupported(found))
+    }
+
+    // tables are rebuilt, which foreign keys would cascade into;
+    // the pragma has no effect inside a transaction
+    conn.execute_batch("PRAGMA foreign_keys = OFF;")?;
+    let result = run_steps(conn, found);
+    conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+    result.map(|_| found)
+}
+
+fn run_steps(conn: &mut Connection, found: i32) -> Result<(), UpgradeError> {
+    let tx = conn.transaction()?;
+    for (version, sql) in STEPS.iter().filter(|(version, _)| *version > found) {
+        tx.execute_batch(sql)?;
+        tx.execute_batch(&format!("PRAGMA user_version = {};", version))?;
+    }
+    let broken : i64 = tx.query_row("SELECT COUNT(*) FROM pragma_foreign_key_check", NO_PARAMS, |row| row.get(0))?;
+    if broken > 0 {
+        return Err(UpgradeError::ForeignKeys(broken))
+    }
+    tx.commit()?;
+    Ok(())
+}
@@ -1,2 +1,6 @@
 pub mod route;
-pub mod api;
\ No newline at end of file
+pub mod api;
+pub mod db;
+pub mod config;
+pub mod middleware;
+pub mod lti;
@@ -0,0 +1,174 @@
+// crates.io
+use chrono::{SecondsFormat, Utc};
+use jsonwebtoken::{encode, Algorithm, Header};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use std::thread;
+use uuid::Uuid;
+// local
+use crate::db;
+use crate::lti::*;
+use crate::route::assignment::NUM_PROBLEMS;
+
+// Path of the content whose gradebook column receives assignment scores
+pub const ASSIGNMENT_PATH: &str = "/assignment/";
+
+// Seconds a client assertion is valid for
+const ASSERTION_TTL: i64 = 300;
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Build the signed JWT authenticating this tool
+//          at the platform's token endpoint
+pub fn client_assertion(config: &LtiConfig, platform: &Platform, now: i64) -> Result<String, LtiError> {
+    let claims = json!({
+        "iss": platform.client_id,
+        "sub": platform.client_id,
+        "aud": platform.auth_token_url,
+        "iat": now,
+        "exp": now + ASSERTION_TTL,
+        "jti": Uuid::new_v4().to_simple().to_string()
+    });
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some(config.kid.clone());
+    Ok(encode(&header, &claims, &config.signing_key)?)
+}
+
+#[derive(Deserialize)]
+struct AccessToken {
+    access_token: String
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Request an access token for scope with the
+//          OAuth2 client credentials grant
+pub fn access_token(config: &LtiConfig, platform: &Platform, scope: &str) -> Result<String, LtiError> {
+    let assertion = client_assertion(config, platform, Utc::now().timestamp())?;
+    let res : AccessToken = ureq::post(&platform.auth_token_url)
+        .send_form(&[
+            ("grant_type", "client_credentials"),
+            ("client_assertion_type", "urn:ietf:params:oauth:client-assertion-type:jwt-bearer"),
+            ("client_assertion", &assertion),
+            ("scope", scope)
+        ])
+        .map_err(|e| LtiError::Http(e.to_string()))?
+        .into_json()
+        .map_err(|e| LtiError::Http(e.to_string()))?;
+    Ok(res.access_token)
+}
+
+// REQUIRES: lineitem is a line item URL, possibly with a query string
+// MODIFIES: n/a
+// EFFECTS: Return the line item's scores endpoint
+pub fn scores_url(lineitem: &str) -> String {
+    match lineitem.find('?') {
+        Some(i) => format!("{}/scores{}", &lineitem[..i], &lineitem[i..]),
+        None => format!("{}/scores", lineitem)
+    }
+}
+
+// REQUIRES: n/a
+// MODIFIES: platform gradebook
+// EFFECTS: Publish a score for the platform user to the line item
+pub fn post_score(
+    config: &LtiConfig,
+    platform: &Platform,
+    lineitem: &str,
+    user_id: &str,
+    given: f64,
+    maximum: f64
+) -> Result<(), LtiError> {
+    let token = access_token(config, platform, SCOPE_SCORE)?;
+    let score = json!({
+        "userId": user_id,
+        "scoreGiven": given,
+        "scoreMaximum": maximum,
+        "activityProgress": if given >= maximum { "Completed" } else { "InProgress" },
+        "gradingProgress": "FullyGraded",
+        "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+    });
+    ureq::post(&scores_url(lineitem))
+        .set("Authorization", &format!("Bearer {}", token))
+        .set("Content-Type", "application/vnd.ims.lis.v1.score+json")
+        .send_string(&score.to_string())
+        .map_err(|e| LtiError::Http(e.to_string()))?;
+    Ok(())
+}
+
+// Where a user's score for some content goes
+#[derive(Debug, PartialEq)]
+pub struct Passback {
+    pub issuer: String,
+    // tool's client id at the platform, empty if saved before it was kept
+    pub client_id: String,
+    pub user_id: String,
+    pub lineitem: String
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the gradebook column for the user's score on the
+//          content at path, or None if they never launched it from an LMS
+pub fn passback_for(conn: &Connection, token: &str, path: &str) -> rusqlite::Result<Option<Passback>> {
+    conn.query_row(
+        "SELECT l.issuer, l.client_id, u.sub, l.lineitem
+        FROM lti_lineitems l
+        JOIN lti_users u ON u.issuer = l.issuer AND u.token = l.token
+        WHERE l.token = ?1 AND l.path = ?2",
+        params![token, path],
+        |row| Ok(Passback {
+            issuer: row.get(0)?,
+            client_id: row.get(1)?,
+            user_id: row.get(2)?,
+            lineitem: row.get(3)?
+        })
+    ).optional()
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the number of assignment problems the user solved
+pub fn assignment_score(conn: &Connection, token: &str) -> rusqlite::Result<i64> {
+    conn.query_row(
+        "SELECT COALESCE(SUM(score), 0) FROM scores
+        WHERE token = ?1",
+        params![token],
+        |row| row.get(0)
+    )
+}
+
+// REQUIRES: n/a
+// MODIFIES: platform gradebook
+// EFFECTS: Send the user's assignment score to their LMS gradebook
+//          on a background thread, if they launched the assignment via LTI
+pub fn sync_assignment(lti: Arc<Lti>, token: String) {
+    if lti.config.is_none() {
+        return
+    }
+
+    thread::spawn(move || {
+        let config = lti.config.as_ref().unwrap();
+        let conn = db::connect();
+        let passback = match passback_for(&conn, &token, ASSIGNMENT_PATH).unwrap() {
+            Some(passback) => passback,
+            None => return
+        };
+        // the issuer may be registered under several client ids
+        let client_id = Some(passback.client_id.as_str()).filter(|id| !id.is_empty());
+        let platform = match config.platform(&passback.issuer, client_id) {
+            Some(platform) => platform,
+            None => return
+        };
+
+        let score = assignment_score(&conn, &token).unwrap();
+        if let Err(e) = post_score(
+            config, platform, &passback.lineitem, &passback.user_id,
+            score as f64, NUM_PROBLEMS as f64
+        ) {
+            eprintln!("lti: score passback for {} failed: {}", token, e);
+        }
+    });
+}
@@ -0,0 +1,546 @@
+// crates.io
+use actix_web::{web, http::header, HttpMessage, HttpRequest, HttpResponse};
+use actix_session::{Session};
+use chrono::Utc;
+use handlebars::Handlebars;
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, Header, Validation};
+use jsonwebtoken::jwk::JwkSet;
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use uuid::Uuid;
+// local
+use crate::db;
+use crate::api::accounts::MAX_TOKEN_LEN;
+use crate::route::assignment::NUM_PROBLEMS;
+use crate::lti::*;
+use crate::middleware::csrf::{csrf_token, tokens_match};
+
+// Seconds a login initiation may take before the launch arrives
+const STATE_TTL: i64 = 600;
+// Cookie holding the state of a login initiation in the browser that
+// started it, so launches are only accepted from that browser
+pub const STATE_COOKIE: &str = "lti_state";
+// Seconds a deep linking response is valid for
+const RESPONSE_TTL: i64 = 300;
+
+const RESOURCE_LINK_REQUEST: &str = "LtiResourceLinkRequest";
+const DEEP_LINKING_REQUEST: &str = "LtiDeepLinkingRequest";
+
+// Content an instructor can place in a course through deep linking
+#[derive(Serialize)]
+pub struct ContentItem {
+    pub slug: &'static str,
+    pub title: &'static str,
+    // path on this server the link launches into
+    pub path: &'static str,
+    // maximum score passed back to the gradebook, None if ungraded
+    pub score_maximum: Option<i32>
+}
+
+pub const CONTENT_ITEMS: &[ContentItem] = &[
+    ContentItem {
+        slug: "quiz",
+        title: "RustViz Quiz",
+        path: "/fp/",
+        score_maximum: None
+    },
+    ContentItem {
+        slug: "assignment",
+        title: "EECS 490 Assignment",
+        path: "/assignment/",
+        score_maximum: Some(NUM_PROBLEMS)
+    }
+];
+
+// Claims of a validated id_token
+#[derive(Deserialize, Debug)]
+pub struct LaunchClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: Value,
+    #[serde(default)]
+    pub azp: Option<String>,
+    pub nonce: String,
+    // LTI-specific claims, keyed by their full URIs
+    #[serde(flatten)]
+    pub lti: HashMap<String, Value>
+}
+
+impl LaunchClaims {
+    pub fn claim_str(&self, name: &str) -> Option<&str> {
+        self.lti.get(name).and_then(Value::as_str)
+    }
+}
+
+// Deep linking request details kept in the session
+// until the instructor picks the content to link
+#[derive(Deserialize, Serialize, Debug)]
+pub struct DeepLinkSettings {
+    pub issuer: String,
+    pub client_id: String,
+    pub deployment_id: String,
+    pub return_url: String,
+    pub data: Option<String>
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database
+// EFFECTS: Remember state and nonce of a login initiation
+pub fn save_state(
+    conn: &Connection,
+    state: &str,
+    nonce: &str,
+    issuer: &str,
+    now: i64
+) -> rusqlite::Result<()> {
+    // forget initiations that never completed
+    conn.execute(
+        "DELETE FROM lti_states
+        WHERE created < ?1",
+        params![now - STATE_TTL]
+    )?;
+    conn.execute(
+        "INSERT INTO lti_states (state, nonce, issuer, created)
+        VALUES (?1, ?2, ?3, ?4)",
+        params![state, nonce, issuer, now]
+    )?;
+    Ok(())
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database
+// EFFECTS: Consume the state of a login initiation, so that each
+//          state and nonce can only be used for one launch
+//          Returns (nonce, issuer) saved with the state
+pub fn take_state(conn: &Connection, state: &str, now: i64) -> Result<(String, String), LtiError> {
+    let row : Option<(String, String, i64)> = conn.query_row(
+        "SELECT nonce, issuer, created FROM lti_states
+        WHERE state = ?1",
+        params![state],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+    ).optional()?;
+    conn.execute(
+        "DELETE FROM lti_states
+        WHERE state = ?1",
+        params![state]
+    )?;
+
+    match row {
+        Some((nonce, issuer, created)) if now - created <= STATE_TTL => Ok((nonce, issuer)),
+        _ => Err(LtiError::InvalidState)
+    }
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the Set-Cookie header value remembering state in the
+//          browser until the launch, or forgetting it if state is empty
+//          The launch is posted from the platform's site, so the cookie
+//          needs SameSite=None, which the cookie builder cannot write
+pub fn state_cookie(config: &LtiConfig, state: &str) -> String {
+    let max_age = if state.is_empty() { 0 } else { STATE_TTL };
+    let mut cookie = format!(
+        "{}={}; Path=/lti/launch/; Max-Age={}; HttpOnly",
+        STATE_COOKIE, state, max_age
+    );
+    // browsers drop SameSite=None cookies that are not Secure
+    if config.tool_url.starts_with("https://") {
+        cookie.push_str("; Secure; SameSite=None");
+    }
+    cookie
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Check the state a launch was posted with against the one
+//          the browser was given at login initiation
+pub fn check_state_cookie(state: &str, cookie: Option<&str>) -> Result<(), LtiError> {
+    match cookie {
+        Some(cookie) if !state.is_empty() && tokens_match(state, cookie) => Ok(()),
+        _ => Err(LtiError::InvalidState)
+    }
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Verify the id_token's signature against the platform's keyset
+//          and check the claims required for a launch from this platform
+pub fn validate_id_token(
+    id_token: &str,
+    platform: &Platform,
+    jwks: &JwkSet,
+    nonce: &str
+) -> Result<LaunchClaims, LtiError> {
+    let kid = decode_header(id_token)?
+        .kid
+        .ok_or(LtiError::InvalidClaim("kid"))?;
+    let jwk = jwks.find(&kid).ok_or(LtiError::InvalidClaim("kid"))?;
+    let key = DecodingKey::from_jwk(jwk)?;
+
+    let mut validation = Validation::new(Algorithm::RS256);
+    validation.set_audience(&[&platform.client_id]);
+    validation.set_issuer(&[&platform.issuer]);
+    validation.set_required_spec_claims(&["exp", "iat", "iss", "aud", "sub"]);
+    let claims = decode::<LaunchClaims>(id_token, &key, &validation)?.claims;
+
+    if claims.nonce != nonce {
+        return Err(LtiError::InvalidClaim("nonce"))
+    }
+    // with several audiences, azp must name this tool
+    if claims.aud.as_array().is_some_and(|aud| aud.len() > 1)
+        && claims.azp.as_deref() != Some(platform.client_id.as_str()) {
+        return Err(LtiError::InvalidClaim("azp"))
+    }
+    if claims.claim_str(CLAIM_VERSION) != Some("1.3.0") {
+        return Err(LtiError::InvalidClaim(CLAIM_VERSION))
+    }
+    match claims.claim_str(CLAIM_DEPLOYMENT_ID) {
+        Some(id) if platform.deployment_ids.iter().any(|d| d == id) => {},
+        _ => return Err(LtiError::InvalidClaim(CLAIM_DEPLOYMENT_ID))
+    }
+    match claims.claim_str(CLAIM_MESSAGE_TYPE) {
+        Some(RESOURCE_LINK_REQUEST) | Some(DEEP_LINKING_REQUEST) => {},
+        _ => return Err(LtiError::InvalidClaim(CLAIM_MESSAGE_TYPE))
+    }
+    Ok(claims)
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database
+// EFFECTS: Return the token of the user mapped to the platform user,
+//          creating a user enrolled in section on their first launch
+pub fn map_user(
+    conn: &mut Connection,
+    issuer: &str,
+    sub: &str,
+    section: Option<&str>
+) -> Result<String, LtiError> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    let token : Option<String> = tx.query_row(
+        "SELECT token FROM lti_users
+        WHERE issuer = ?1 AND sub = ?2",
+        params![issuer, sub],
+        |row| row.get(0)
+    ).optional()?;
+    if let Some(token) = token {
+        return Ok(token)
+    }
+
+    let token : String = Uuid::new_v4().to_simple().to_string()
+        .chars().take(MAX_TOKEN_LEN).collect();
+    tx.execute(
+        "INSERT INTO users (token, section)
+        VALUES (?1, ?2)",
+        params![&token, section]
+    )?;
+    tx.execute(
+        "INSERT INTO lti_users (issuer, sub, token)
+        VALUES (?1, ?2, ?3)",
+        params![issuer, sub, &token]
+    )?;
+    tx.commit()?;
+    Ok(token)
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database
+// EFFECTS: Remember the gradebook line item of the link the user
+//          launched from the platform, so scores for path can be passed back
+pub fn save_lineitem(
+    conn: &Connection,
+    token: &str,
+    path: &str,
+    platform: &Platform,
+    lineitem: &str
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO lti_lineitems (token, path, issuer, lineitem, client_id)
+        VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![token, path, &platform.issuer, lineitem, &platform.client_id]
+    )?;
+    Ok(())
+}
+
+// REQUIRES: settings come from a validated deep linking request
+// MODIFIES: n/a
+// EFFECTS: Build the signed LtiDeepLinkingResponse placing items in the course
+pub fn deep_link_response(
+    config: &LtiConfig,
+    settings: &DeepLinkSettings,
+    items: &[&ContentItem],
+    now: i64
+) -> Result<String, LtiError> {
+    let content_items : Vec<Value> = items.iter().map(|item| {
+        let mut link = json!({
+            "type": "ltiResourceLink",
+            "title": item.title,
+            "url": format!("{}{}", config.tool_url, item.path)
+        });
+        if let Some(max) = item.score_maximum {
+            link["lineItem"] = json!({
+                "scoreMaximum": max,
+                "label": item.title,
+                "resourceId": item.slug
+            });
+        }
+        link
+    }).collect();
+
+    let mut claims = json!({
+        "iss": settings.client_id,
+        "aud": [settings.issuer],
+        "iat": now,
+        "exp": now + RESPONSE_TTL,
+        "nonce": Uuid::new_v4().to_simple().to_string(),
+        CLAIM_MESSAGE_TYPE: "LtiDeepLinkingResponse",
+        CLAIM_VERSION: "1.3.0",
+        CLAIM_DEPLOYMENT_ID: settings.deployment_id,
+        CLAIM_CONTENT_ITEMS: content_items
+    });
+    if let Some(data) = &settings.data {
+        claims[CLAIM_DATA] = json!(data);
+    }
+
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some(config.kid.clone());
+    Ok(encode(&header, &claims, &config.signing_key)?)
+}
+
+// Third-party login initiation, sent by GET or POST
+#[derive(Deserialize, Serialize, Debug)]
+pub struct LoginInitiation {
+    iss: String,
+    login_hint: String,
+    target_link_uri: String,
+    lti_message_hint: Option<String>,
+    client_id: Option<String>,
+    lti_deployment_id: Option<String>
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database, Cookies
+// EFFECTS: Start the OIDC flow by redirecting to the platform's
+//          authentication endpoint with a fresh state and nonce
+fn initiate(lti: &Lti, params: &LoginInitiation) -> HttpResponse {
+    let config = match &lti.config {
+        Some(config) => config,
+        None => return HttpResponse::NotFound().finish()
+    };
+    let platform = match config.platform(&params.iss, params.client_id.as_deref()) {
+        Some(platform) => platform,
+        None => return HttpResponse::BadRequest()
+            .body(LtiError::UnknownPlatform.to_string())
+    };
+
+    let state = Uuid::new_v4().to_simple().to_string();
+    let nonce = Uuid::new_v4().to_simple().to_string();
+    let conn = db::connect();
+    save_state(&conn, &state, &nonce, &platform.issuer, Utc::now().timestamp()).unwrap();
+
+    let mut query = vec![
+        ("scope", "openid"),
+        ("response_type", "id_token"),
+        ("response_mode", "form_post"),
+        ("prompt", "none"),
+        ("client_id", platform.client_id.as_str()),
+        ("login_hint", params.login_hint.as_str()),
+        ("state", state.as_str()),
+        ("nonce", nonce.as_str())
+    ];
+    let redirect_uri = format!("{}/lti/launch/", config.tool_url);
+    query.push(("redirect_uri", &redirect_uri));
+    if let Some(hint) = &params.lti_message_hint {
+        query.push(("lti_message_hint", hint));
+    }
+
+    let sep = if platform.auth_login_url.contains('?') { '&' } else { '?' };
+    let location = format!(
+        "{}{}{}", platform.auth_login_url, sep,
+        serde_urlencoded::to_string(&query).unwrap()
+    );
+    HttpResponse::Found()
+        .header(header::LOCATION, location)
+        .header(header::SET_COOKIE, state_cookie(config, &state))
+        .finish()
+}
+
+// REQUIRES: GET method
+// MODIFIES: Database, Cookies
+// EFFECTS: Handle login initiation sent as query parameters
+pub async fn login_get(
+    lti: web::Data<Lti>,
+    params: web::Query<LoginInitiation>
+) -> HttpResponse {
+    initiate(&lti, &params)
+}
+
+// REQUIRES: POST method
+// MODIFIES: Database, Cookies
+// EFFECTS: Handle login initiation sent as form data
+pub async fn login_post(
+    lti: web::Data<Lti>,
+    params: web::Form<LoginInitiation>
+) -> HttpResponse {
+    initiate(&lti, &params)
+}
+
+// Authentication response posted by the platform
+#[derive(Deserialize)]
+pub struct LaunchForm {
+    id_token: String,
+    state: String
+}
+
+#[derive(Serialize)]
+struct DeepLinkContext<'a> {
+    items: &'a [ContentItem],
+    csrf_token: String
+}
+
+// REQUIRES: POST method
+// MODIFIES: Database, Cookies
+// EFFECTS: Validate the launch, log the mapped user in and either
+//          redirect to the launched content or, for deep linking,
+//          render the content selection page
+//          Only the browser that initiated the login may launch, see state_cookie
+pub async fn launch(
+    req: HttpRequest,
+    session: Session,
+    lti: web::Data<Lti>,
+    hb: web::Data<Handlebars<'_>>,
+    form: web::Form<LaunchForm>
+) -> HttpResponse {
+    let config = match &lti.config {
+        Some(config) => config,
+        None => return HttpResponse::NotFound().finish()
+    };
+
+    let cookie = req.cookie(STATE_COOKIE);
+    if let Err(e) = check_state_cookie(&form.state, cookie.as_ref().map(|c| c.value())) {
+        return HttpResponse::Unauthorized()
+            .body(format!("LTI launch failed: {}", e))
+    }
+
+    let mut conn = db::connect();
+    let now = Utc::now().timestamp();
+    let (nonce, issuer) = match take_state(&conn, &form.state, now) {
+        Ok(state) => state,
+        Err(e) => return HttpResponse::Unauthorized()
+            .body(format!("LTI launch failed: {}", e))
+    };
+
+    // the same issuer may be registered under several client ids
+    let mut result = Err(LtiError::UnknownPlatform);
+    for platform in config.platforms.iter().filter(|p| p.issuer == issuer) {
+        result = lti.keyset(platform)
+            .and_then(|jwks| validate_id_token(&form.id_token, platform, &jwks, &nonce))
+            .map(|claims| (platform, claims));
+        if result.is_ok() {
+            break
+        }
+    }
+    let (platform, claims) = match result {
+        Ok(launch) => launch,
+        Err(e) => {
+            eprintln!("lti: launch from {} refused: {}", issuer, e);
+            return HttpResponse::Unauthorized()
+                .body(format!("LTI launch failed: {}", e))
+        }
+    };
+
+    let section = claims.lti.get(CLAIM_CONTEXT)
+        .and_then(|ctx| ctx.get("label").or_else(|| ctx.get("id")))
+        .and_then(Value::as_str);
+    let token = map_user(&mut conn, &claims.iss, &claims.sub, section).unwrap();
+
+    // link the gradebook column so scores can be passed back later
+    let path = claims.claim_str(CLAIM_TARGET_LINK_URI)
+        .and_then(|uri| config.local_path(uri));
+    let lineitem = claims.lti.get(CLAIM_AGS_ENDPOINT)
+        .and_then(|ags| ags.get("lineitem"))
+        .and_then(Value::as_str);
+    if let (Some(path), Some(lineitem)) = (&path, lineitem) {
+        save_lineitem(&conn, &token, path, platform, lineitem).unwrap();
+    }
+
+    session.set("login", token).unwrap();
+    session.renew();
+
+    if claims.claim_str(CLAIM_MESSAGE_TYPE) == Some(DEEP_LINKING_REQUEST) {
+        let settings = claims.lti.get(CLAIM_DEEP_LINKING_SETTINGS);
+        let return_url = match settings.and_then(|s| s.get("deep_link_return_url")).and_then(Value::as_str) {
+            Some(url) => url.to_string(),
+            None => return HttpResponse::BadRequest()
+                .body(LtiError::InvalidClaim(CLAIM_DEEP_LINKING_SETTINGS).to_string())
+        };
+        session.set("lti_deep_link", DeepLinkSettings {
+            issuer: platform.issuer.clone(),
+            client_id: platform.client_id.clone(),
+            deployment_id: claims.claim_str(CLAIM_DEPLOYMENT_ID).unwrap_or_default().to_string(),
+            return_url,
+            data: settings.and_then(|s| s.get("data")).and_then(Value::as_str).map(String::from)
+        }).unwrap();
+
+        let body = hb.render("lti_deep_link", &DeepLinkContext {
+            items: CONTENT_ITEMS,
+            csrf_token: csrf_token(&session)
+        }).unwrap();
+        return HttpResponse::Ok()
+            .header(header::SET_COOKIE, state_cookie(config, ""))
+            .body(body)
+    }
+
+    HttpResponse::Found()
+        .header(header::LOCATION, path.unwrap_or_else(|| "/rust-tutorial".to_string()))
+        .header(header::SET_COOKIE, state_cookie(config, ""))
+        .finish()
+}
+
+#[derive(Deserialize)]
+pub struct DeepLinkSelection {
+    item: String
+}
+
+#[derive(Serialize)]
+struct FormPostContext {
+    url: String,
+    jwt: String
+}
+
+// REQUIRES: POST method, deep linking launch earlier in this session
+// MODIFIES: Cookies
+// EFFECTS: Send the selected content item back to the platform
+//          through an auto-submitting form
+pub async fn deep_link(
+    session: Session,
+    lti: web::Data<Lti>,
+    hb: web::Data<Handlebars<'_>>,
+    form: web::Form<DeepLinkSelection>
+) -> HttpResponse {
+    let config = match &lti.config {
+        Some(config) => config,
+        None => return HttpResponse::NotFound().finish()
+    };
+    let settings = match session.get::<DeepLinkSettings>("lti_deep_link").unwrap() {
+        Some(settings) => settings,
+        None => return HttpResponse::BadRequest()
+            .body("No deep linking request in progress!")
+    };
+    let item = match CONTENT_ITEMS.iter().find(|item| item.slug == form.item) {
+        Some(item) => item,
+        None => return HttpResponse::BadRequest()
+            .body("Unknown content item!")
+    };
+
+    let jwt = deep_link_response(config, &settings, &[item], Utc::now().timestamp()).unwrap();
+    session.remove("lti_deep_link");
+
+    let body = hb.render("lti_form_post", &FormPostContext {
+        url: settings.return_url,
+        jwt
+    }).unwrap();
+    HttpResponse::Ok().body(body)
+}
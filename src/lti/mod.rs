@@ -0,0 +1,244 @@
+pub mod ags;
+pub mod launch;
+
+// crates.io
+use actix_web::{web, HttpResponse};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::EncodingKey;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+// local
+use crate::config::env_or;
+
+// Claims defined by the LTI 1.3 and LTI Advantage specifications
+pub const CLAIM_MESSAGE_TYPE: &str = "https://purl.imsglobal.org/spec/lti/claim/message_type";
+pub const CLAIM_VERSION: &str = "https://purl.imsglobal.org/spec/lti/claim/version";
+pub const CLAIM_DEPLOYMENT_ID: &str = "https://purl.imsglobal.org/spec/lti/claim/deployment_id";
+pub const CLAIM_TARGET_LINK_URI: &str = "https://purl.imsglobal.org/spec/lti/claim/target_link_uri";
+pub const CLAIM_RESOURCE_LINK: &str = "https://purl.imsglobal.org/spec/lti/claim/resource_link";
+pub const CLAIM_CONTEXT: &str = "https://purl.imsglobal.org/spec/lti/claim/context";
+pub const CLAIM_DEEP_LINKING_SETTINGS: &str = "https://purl.imsglobal.org/spec/lti-dl/claim/deep_linking_settings";
+pub const CLAIM_CONTENT_ITEMS: &str = "https://purl.imsglobal.org/spec/lti-dl/claim/content_items";
+pub const CLAIM_DATA: &str = "https://purl.imsglobal.org/spec/lti-dl/claim/data";
+pub const CLAIM_AGS_ENDPOINT: &str = "https://purl.imsglobal.org/spec/lti-ags/claim/endpoint";
+pub const SCOPE_SCORE: &str = "https://purl.imsglobal.org/spec/lti-ags/scope/score";
+
+// How long a platform's fetched keyset is trusted before refetching
+const KEYSET_TTL: Duration = Duration::from_secs(3600);
+
+// An LMS registered with this tool
+#[derive(Deserialize, Debug, Clone)]
+pub struct Platform {
+    pub issuer: String,
+    pub client_id: String,
+    // launches from other deployments are refused
+    pub deployment_ids: Vec<String>,
+    // OIDC authentication endpoint the login initiation redirects to
+    pub auth_login_url: String,
+    // OAuth2 token endpoint for Assignment and Grade Services
+    pub auth_token_url: String,
+    pub jwks_url: String,
+    // keyset used instead of fetching jwks_url, e.g. for a local mock platform
+    #[serde(default)]
+    pub jwks: Option<JwkSet>
+}
+
+// Tool configuration as stored in the file named by FPLAB_LTI_CONFIG
+// (default: lti.json)
+// e.g.:
+//      {
+//          "tool_url": "https://fplab.eecs.umich.edu",
+//          "kid": "fplab-1",
+//          "private_key": "keys/lti_private.pem",
+//          "jwks": "keys/lti_jwks.json",
+//          "platforms": [{ "issuer": "https://canvas.instructure.com", ... }]
+//      }
+#[derive(Deserialize, Debug)]
+struct ConfigFile {
+    tool_url: String,
+    kid: String,
+    private_key: String,
+    jwks: String,
+    platforms: Vec<Platform>
+}
+
+pub struct LtiConfig {
+    // public base URL of this server, without trailing slash
+    pub tool_url: String,
+    // key id of the tool's signing key in its published keyset
+    pub kid: String,
+    pub signing_key: EncodingKey,
+    // the tool's public keyset, served at /lti/jwks/
+    pub jwks: String,
+    pub platforms: Vec<Platform>
+}
+
+impl LtiConfig {
+    // REQUIRES: n/a
+    // MODIFIES: n/a
+    // EFFECTS: Read tool configuration from path; key file paths
+    //          are relative to the configuration file
+    pub fn load(path: &Path) -> Result<Self, LtiError> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| LtiError::Config(format!("{}: {}", path.display(), e)))?;
+        let file : ConfigFile = serde_json::from_str(&text)
+            .map_err(|e| LtiError::Config(format!("{}: {}", path.display(), e)))?;
+
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let read = |name: &str| fs::read_to_string(dir.join(name))
+            .map_err(|e| LtiError::Config(format!("{}: {}", name, e)));
+        let signing_key = EncodingKey::from_rsa_pem(read(&file.private_key)?.as_bytes())?;
+
+        Ok(LtiConfig {
+            tool_url: file.tool_url.trim_end_matches('/').to_string(),
+            kid: file.kid,
+            signing_key,
+            jwks: read(&file.jwks)?,
+            platforms: file.platforms
+        })
+    }
+
+    // REQUIRES: n/a
+    // MODIFIES: n/a
+    // EFFECTS: Find the platform registered for issuer, and client_id
+    //          if the platform sent one
+    pub fn platform(&self, issuer: &str, client_id: Option<&str>) -> Option<&Platform> {
+        self.platforms.iter().find(|p| {
+            p.issuer == issuer && client_id.is_none_or(|id| p.client_id == id)
+        })
+    }
+
+    // REQUIRES: n/a
+    // MODIFIES: n/a
+    // EFFECTS: Return the path of url on this server, e.g. "/assignment/",
+    //          or None if url points elsewhere
+    pub fn local_path(&self, url: &str) -> Option<String> {
+        let rest = url.strip_prefix(&self.tool_url)?;
+        if rest.is_empty() {
+            Some("/".to_string())
+        }
+        else if rest.starts_with('/') {
+            Some(rest.to_string())
+        }
+        else {
+            None
+        }
+    }
+}
+
+// Shared across app threads: configuration and cached platform keysets
+// config is None when LTI is not set up, which disables the /lti routes
+pub struct Lti {
+    pub config: Option<LtiConfig>,
+    keysets: Mutex<HashMap<String, (JwkSet, Instant)>>
+}
+
+impl Lti {
+    pub fn new(config: Option<LtiConfig>) -> Self {
+        Lti {
+            config,
+            keysets: Mutex::new(HashMap::new())
+        }
+    }
+
+    // REQUIRES: n/a
+    // MODIFIES: n/a
+    // EFFECTS: Load configuration from FPLAB_LTI_CONFIG if the file exists
+    pub fn from_env() -> Self {
+        let path : String = env_or("FPLAB_LTI_CONFIG", "lti.json".to_string());
+        let path = Path::new(&path);
+        if !path.exists() {
+            return Lti::new(None)
+        }
+        match LtiConfig::load(path) {
+            Ok(config) => Lti::new(Some(config)),
+            Err(e) => {
+                eprintln!("lti: disabled: {}", e);
+                Lti::new(None)
+            }
+        }
+    }
+
+    // REQUIRES: n/a
+    // MODIFIES: keyset cache
+    // EFFECTS: Return the platform's keyset, fetching jwks_url
+    //          if it is not configured inline or the cached copy expired
+    pub fn keyset(&self, platform: &Platform) -> Result<JwkSet, LtiError> {
+        if let Some(jwks) = &platform.jwks {
+            return Ok(jwks.clone())
+        }
+
+        if let Some((jwks, fetched)) = self.keysets.lock().unwrap().get(&platform.issuer) {
+            if fetched.elapsed() < KEYSET_TTL {
+                return Ok(jwks.clone())
+            }
+        }
+
+        let jwks : JwkSet = ureq::get(&platform.jwks_url)
+            .call()
+            .map_err(|e| LtiError::Http(e.to_string()))?
+            .into_json()
+            .map_err(|e| LtiError::Http(e.to_string()))?;
+        self.keysets.lock().unwrap()
+            .insert(platform.issuer.clone(), (jwks.clone(), Instant::now()));
+        Ok(jwks)
+    }
+}
+
+#[derive(Debug)]
+pub enum LtiError {
+    Config(String),
+    UnknownPlatform,
+    Jwt(jsonwebtoken::errors::Error),
+    // a required claim is missing or has an unexpected value
+    InvalidClaim(&'static str),
+    InvalidState,
+    Http(String),
+    Db(rusqlite::Error)
+}
+
+impl fmt::Display for LtiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LtiError::Config(msg) => write!(f, "configuration: {}", msg),
+            LtiError::UnknownPlatform => write!(f, "platform is not registered"),
+            LtiError::Jwt(e) => write!(f, "invalid token: {}", e),
+            LtiError::InvalidClaim(claim) => write!(f, "invalid claim: {}", claim),
+            LtiError::InvalidState => write!(f, "unknown or expired state"),
+            LtiError::Http(msg) => write!(f, "request to platform failed: {}", msg),
+            LtiError::Db(e) => write!(f, "{}", e)
+        }
+    }
+}
+
+impl std::error::Error for LtiError {}
+
+impl From<jsonwebtoken::errors::Error> for LtiError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        LtiError::Jwt(e)
+    }
+}
+
+impl From<rusqlite::Error> for LtiError {
+    fn from(e: rusqlite::Error) -> Self {
+        LtiError::Db(e)
+    }
+}
+
+// REQUIRES: GET method
+// MODIFIES: n/a
+// EFFECTS: Serve the tool's public keyset so platforms can verify
+//          deep linking responses and AGS client assertions
+pub async fn jwks(lti: web::Data<Lti>) -> HttpResponse {
+    match &lti.config {
+        Some(config) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(config.jwks.clone()),
+        None => HttpResponse::NotFound().finish()
+    }
+}
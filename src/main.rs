@@ -6,10 +6,15 @@ use actix_web::{
 use actix_files::Files as fs;
 use actix_session::{CookieSession};
 use handlebars::Handlebars;
-use env_logger;
 // local
 use fplab_server::route::*;
 use fplab_server::api::*;
+use fplab_server::db::{self, backup::{self, BackupConfig}};
+use fplab_server::middleware::rate_limit::RateLimit;
+use fplab_server::middleware::csrf::Csrf;
+use fplab_server::middleware::sessions::{ServerSessions, SessionConfig};
+use fplab_server::lti::{self, Lti};
+use fplab_server::api::sso::Sso;
 
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
@@ -27,10 +32,53 @@ async fn main() -> std::io::Result<()> {
         .unwrap();
     let handlebars_ref = web::Data::new(handlebars);
 
+    // LMS integration is disabled unless FPLAB_LTI_CONFIG names a configuration
+    let lti_ref = web::Data::new(Lti::from_env());
+    // single sign-on is disabled unless FPLAB_OIDC_* are set
+    let sso_ref = web::Data::new(Sso::from_env());
+
+    // refuse to serve a database created for another version of the schema
+    let version = db::schema_version(&db::connect()).unwrap();
+    if version != db::SCHEMA_VERSION {
+        eprintln!(
+            "{} has schema version {}, expected {}: run `fpdb upgrade`",
+            db::DB_PATH, version, db::SCHEMA_VERSION
+        );
+        std::process::exit(1);
+    }
+
+    // periodically take online backups of the database
+    match BackupConfig::from_env() {
+        Ok(config) => backup::schedule(config),
+        Err(e) => {
+            eprintln!("backup: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // user actions are buffered in memory and written in batches
+    let telemetry_ref = web::Data::new(action::Telemetry::from_env());
+    action::schedule(telemetry_ref.clone());
+    let telemetry = telemetry_ref.clone();
+
     HttpServer::new(move || {  // move fp_data into closure
         App::new()
             // store data across app threads
             .app_data(handlebars_ref.clone())
+            .app_data(lti_ref.clone())
+            .app_data(sso_ref.clone())
+            .app_data(telemetry_ref.clone())
+            // refuse cross-site form and JSON posts; the LMS posts
+            // launches cross-site, which are verified by their id_token;
+            // beacons cannot set headers and send their token in the body
+            .wrap(
+                Csrf::new()
+                    .exempt("/lti/login/")
+                    .exempt("/lti/launch/")
+                    .exempt("/action/close")
+            )
+            // keep logins on the server, the cookie only holds a session id
+            .wrap(ServerSessions::new(SessionConfig::from_env()))
             // normalize path: Merges multiple slashes into one, appends a trailing slash if one is not present.
             .wrap(middleware::NormalizePath)
             // log information
@@ -97,19 +145,86 @@ async fn main() -> std::io::Result<()> {
             // redirect route
             .route("/rust-tutorial", web::get().to(fp_web::index))
             // routes handlers
-            .route("/accounts/login/", web::get().to(accounts::render_login))
-            .route("/accounts/login/", web::post().to(accounts::user_login))
+            // throttle repeated failures on routes that check secrets
+            .service(
+                web::resource("/accounts/login/")
+                    .route(web::get().to(accounts::render_login))
+                    .route(web::post().to(accounts::user_login))
+                    .wrap(RateLimit::new(&["token", "username"]))
+            )
+            .route("/accounts/sso/", web::get().to(sso::sso_login))
+            .route("/accounts/sso/callback/", web::get().to(sso::sso_callback))
             .route("/accounts/logout/", web::post().to(accounts::user_logout))
-            .route("/accounts/create/", web::get().to(accounts::render_create))
-            .route("/accounts/create/", web::post().to(accounts::user_create))
+            .service(
+                web::resource("/accounts/create/")
+                    .route(web::get().to(accounts::render_create))
+                    .route(web::post().to(accounts::user_create))
+                    .wrap(RateLimit::new(&["invite"]))
+            )
+            .route("/accounts/data/", web::get().to(study::download_data))
+            .route("/accounts/withdraw/", web::get().to(study::render_withdraw_page))
+            .route("/accounts/withdraw/", web::post().to(study::withdraw_user))
+            .route("/accounts/sessions/", web::get().to(sessions::list_sessions))
+            .route("/accounts/sessions/revoke/", web::post().to(sessions::revoke_other_sessions))
+            .route("/accounts/sessions/{id}/revoke/", web::post().to(sessions::revoke_session))
+            .service(
+                web::resource("/accounts/password/")
+                    .route(web::post().to(accounts::change_password))
+                    .wrap(RateLimit::new(&[]))
+            )
+            // instructor-only routes
+            .route("/admin/invites/", web::get().to(invites::list_invites))
+            .route("/admin/invites/", web::post().to(invites::create_invites))
+            .route("/admin/invites/{code}/revoke/", web::post().to(invites::revoke_invite))
+            .route("/admin/users/{username}/password/", web::post().to(accounts::reset_password))
+            .route("/admin/users/{user}/sessions/", web::get().to(sessions::list_user_sessions))
+            .route("/admin/users/{user}/sessions/revoke/", web::post().to(sessions::revoke_user_sessions))
+            .route("/admin/quizzes/{slug}/chapter/", web::post().to(quiz::attach_chapter))
+            // quiz authoring
+            .route("/admin/questions/", web::get().to(quizzes::list_questions))
+            .route("/admin/questions/", web::post().to(quizzes::create_question))
+            .route("/admin/questions/{id}/", web::get().to(quizzes::get_question))
+            .route("/admin/questions/{id}/", web::put().to(quizzes::update_question))
+            .route("/admin/questions/{id}/", web::delete().to(quizzes::delete_question))
+            .route("/admin/pools/", web::get().to(quizzes::list_pools))
+            .route("/admin/quizzes/", web::post().to(quizzes::create_quiz))
+            .route("/admin/quizzes/import/", web::post().to(quizzes::import_quiz))
+            .route("/admin/quizzes/{slug}/", web::delete().to(quizzes::delete_quiz))
+            .route("/admin/quizzes/{slug}/questions/", web::put().to(quizzes::reorder_quiz))
+            .route("/admin/quizzes/{slug}/settings/", web::put().to(quizzes::update_settings))
+            .route("/admin/quizzes/{slug}/history/{user}/", web::get().to(history::user_history))
+            // free response grading
+            .route("/admin/grading/", web::get().to(grading::grading_queue))
+            .route("/admin/grading/{id}/", web::get().to(grading::list_responses))
+            .route("/admin/grading/{id}/rubric/", web::put().to(grading::update_rubric))
+            .route("/admin/grading/{id}/release/", web::post().to(grading::release_grades))
+            .route("/admin/grading/{id}/{user}/", web::put().to(grading::update_grade))
+            // LMS integration (LTI 1.3)
+            .route("/lti/login/", web::get().to(lti::launch::login_get))
+            .route("/lti/login/", web::post().to(lti::launch::login_post))
+            .route("/lti/launch/", web::post().to(lti::launch::launch))
+            .route("/lti/deep-link/", web::post().to(lti::launch::deep_link))
+            .route("/lti/jwks/", web::get().to(lti::jwks))
+            // research consent, required for the user action routes
+            .route("/consent/", web::get().to(consent::render_consent))
+            .route("/consent/", web::post().to(consent::record_consent))
             // detect user action
             .route("/action/hover", web::post().to(action::user_hover))
-            // .route("/action/close", web::post().to(action::user_close))
+            .route("/action/close", web::post().to(action::user_close))
             .route("/action/switch", web::post().to(action::user_switch))
+            .service(
+                web::resource("/events")
+                    .app_data(events::json_config())
+                    .route(web::post().to(events::post_events))
+            )
             // quiz-related routes
-            .route("/submit", web::post().to(quiz::record_response))
-            .route("/question/{qid_url_slug}/", web::get().to(quiz::get_question))
-            .route("/question/", web::get().to(quiz::init_quiz))
+            .route("/quiz/", web::get().to(quiz::list_quizzes))
+            .route("/quiz/{slug}/", web::get().to(quiz::init_quiz))
+            .route("/quiz/{slug}/question/{n}/", web::get().to(quiz::get_question))
+            .route("/quiz/{slug}/submit/", web::post().to(quiz::record_response))
+            .route("/quiz/{slug}/finish/", web::post().to(quiz::finish_quiz))
+            .route("/quiz/{slug}/results/", web::get().to(quiz::quiz_results))
+            .route("/quiz/{slug}/feedback/", web::get().to(grading::quiz_feedback))
             // default to error page
             .default_service(
                 web::route().to(fp_web::error)
@@ -120,5 +235,9 @@ async fn main() -> std::io::Result<()> {
     .bind(("localhost", 8000))?
     .shutdown_timeout(10)
     .run()
-    .await
+    .await?;
+
+    // write what was buffered since the last flush
+    telemetry.flush_pending();
+    Ok(())
 }
@@ -0,0 +1,218 @@
+// crates.io
+use actix_http::h1;
+use actix_session::{Session, UserSession};
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::{header, Cookie, Method},
+    web::BytesMut, Error, HttpMessage, HttpResponse
+};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use futures::StreamExt;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use uuid::Uuid;
+
+// Session key of the synchronizer token
+pub const SESSION_KEY: &str = "csrf";
+// Hidden field HTML forms send the token in
+pub const FORM_FIELD: &str = "csrf_token";
+// Header scripts send the token in
+pub const HEADER: &str = "X-CSRF-Token";
+// Cookie scripts read the token from, not HttpOnly on purpose
+pub const COOKIE: &str = "fplab_csrf";
+
+// Forms are small, larger bodies are refused rather than buffered
+const MAX_FORM_BODY: usize = 64 * 1024;
+
+// REQUIRES: n/a
+// MODIFIES: Cookies
+// EFFECTS: Return the session's CSRF token, creating one if needed
+//          Templates with POST forms put it in a hidden csrf_token field
+pub fn csrf_token(session: &Session) -> String {
+    if let Some(token) = session.get::<String>(SESSION_KEY).unwrap_or(None) {
+        return token
+    }
+    let token = format!("{}{}", Uuid::new_v4().to_simple(), Uuid::new_v4().to_simple());
+    session.set(SESSION_KEY, &token).unwrap();
+    token
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Compare tokens in time independent of where they differ
+pub fn tokens_match(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes().zip(b.bytes()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Check that the URL in an Origin or Referer header
+//          points to host (host[:port] of the request)
+pub fn same_origin(url: &str, host: &str) -> bool {
+    let rest = match url.split_once("://") {
+        Some((_, rest)) => rest,
+        None => return false
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    authority.eq_ignore_ascii_case(host)
+}
+
+// Middleware refusing cross-site POST, PUT, PATCH and DELETE requests
+// - the Origin (or Referer) header, when sent, must be this site
+// - HTML forms must send the session's token in the csrf_token field
+// - other requests (JSON) must send it in the X-CSRF-Token header,
+//   which cross-site pages cannot set without a CORS preflight
+// The token is also set in the fplab_csrf cookie for the frontends
+// e.g.: App::new().wrap(Csrf::new().exempt("/lti/launch/"))
+pub struct Csrf {
+    exempt: Rc<Vec<&'static str>>
+}
+
+impl Csrf {
+    pub fn new() -> Self {
+        Csrf {
+            exempt: Rc::new(Vec::new())
+        }
+    }
+
+    // Skip checks for paths starting with prefix, for routes
+    // other sites post to by design and which verify requests themselves
+    pub fn exempt(mut self, prefix: &'static str) -> Self {
+        Rc::make_mut(&mut self.exempt).push(prefix);
+        self
+    }
+}
+
+impl Default for Csrf {
+    fn default() -> Self {
+        Csrf::new()
+    }
+}
+
+impl<S, B> Transform<S> for Csrf
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CsrfMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CsrfMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            exempt: self.exempt.clone()
+        })
+    }
+}
+
+pub struct CsrfMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    exempt: Rc<Vec<&'static str>>
+}
+
+impl<S, B> Service for CsrfMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let exempt = self.exempt.iter().any(|prefix| req.path().starts_with(prefix));
+        let safe = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE);
+
+        Box::pin(async move {
+            let token = csrf_token(&req.get_session());
+            let cookie = req.cookie(COOKIE).map(|c| c.value().to_string());
+
+            if !safe && !exempt {
+                if let Err(reason) = check(&mut req, &token).await? {
+                    eprintln!("csrf: refused {} {}: {}", req.method(), req.path(), reason);
+                    let res = HttpResponse::Forbidden()
+                        .body("Request refused, please reload the page and try again!");
+                    return Ok(req.into_response(res.into_body()))
+                }
+            }
+
+            let fut = service.borrow_mut().call(req);
+            let mut res = fut.await?;
+
+            // keep the cookie in sync with the session for scripts
+            if cookie.as_deref() != Some(token.as_str()) {
+                let cookie = Cookie::build(COOKIE, token)
+                    .path("/")
+                    .same_site(actix_web::cookie::SameSite::Strict)
+                    .finish();
+                res.response_mut().add_cookie(&cookie)?;
+            }
+            Ok(res)
+        })
+    }
+}
+
+// REQUIRES: req is a POST, PUT, PATCH or DELETE request
+// MODIFIES: req payload (read and restored)
+// EFFECTS: Ok(Err(reason)) if the request may be cross-site forged
+async fn check(req: &mut ServiceRequest, token: &str) -> Result<Result<(), &'static str>, Error> {
+    let host = req.connection_info().host().to_string();
+    let origin = req.headers().get(header::ORIGIN)
+        .or_else(|| req.headers().get(header::REFERER))
+        .map(|v| v.to_str().unwrap_or_default().to_string());
+    if let Some(origin) = origin {
+        if !same_origin(&origin, &host) {
+            return Ok(Err("cross-origin request"))
+        }
+    }
+
+    // scripts send the header, whatever the content type
+    if let Some(sent) = req.headers().get(HEADER) {
+        return Ok(match sent.to_str() {
+            Ok(sent) if tokens_match(sent, token) => Ok(()),
+            _ => Err("invalid token header")
+        })
+    }
+
+    if req.content_type() != "application/x-www-form-urlencoded" {
+        return Ok(Err("missing token header"))
+    }
+
+    // buffer the form to read the token, then hand it back
+    let mut body = BytesMut::new();
+    let mut payload = req.take_payload();
+    while let Some(chunk) = payload.next().await {
+        body.extend_from_slice(&chunk?);
+        if body.len() > MAX_FORM_BODY {
+            return Ok(Err("form too large"))
+        }
+    }
+    let body = body.freeze();
+    let sent = serde_urlencoded::from_bytes::<Vec<(String, String)>>(&body)
+        .unwrap_or_default()
+        .into_iter()
+        .find(|(k, _)| k == FORM_FIELD)
+        .map(|(_, v)| v);
+    let (_, mut restored) = h1::Payload::create(true);
+    restored.unread_data(body);
+    req.set_payload(restored.into());
+
+    Ok(match sent {
+        Some(sent) if tokens_match(&sent, token) => Ok(()),
+        _ => Err("invalid form token")
+    })
+}
@@ -0,0 +1,308 @@
+// crates.io
+use actix_http::h1;
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::{header, Method, StatusCode},
+    web::BytesMut, Error, HttpMessage, HttpResponse
+};
+use chrono::Utc;
+use futures::future::{ok, LocalBoxFuture, Ready};
+use futures::StreamExt;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::cell::RefCell;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+// local
+use crate::config::env_or;
+use crate::db;
+
+// Login forms are tiny, larger bodies are refused rather than buffered
+const MAX_BODY: usize = 16 * 1024;
+
+// Thresholds for throttling failed attempts, read from
+//      FPLAB_RATE_MAX_FAILURES     failures per credential before lockouts (default: 5)
+//      FPLAB_RATE_IP_MAX_FAILURES  failures per IP before lockouts (default: 50)
+//      FPLAB_RATE_BASE_DELAY       seconds of the first lockout (default: 30)
+//      FPLAB_RATE_MAX_DELAY        longest lockout in seconds (default: 3600)
+//      FPLAB_RATE_WINDOW           seconds without failures that reset the count (default: 900)
+//      FPLAB_TRUST_PROXY           use X-Forwarded-For for the client IP (default: false)
+// Each failure past the threshold doubles the lockout, up to the max delay
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    pub max_failures: u32,
+    pub ip_max_failures: u32,
+    pub base_delay: i64,
+    pub max_delay: i64,
+    pub window: i64,
+    pub trust_proxy: bool
+}
+
+impl RateLimitConfig {
+    pub fn from_env() -> Self {
+        RateLimitConfig {
+            max_failures: env_or("FPLAB_RATE_MAX_FAILURES", 5),
+            ip_max_failures: env_or("FPLAB_RATE_IP_MAX_FAILURES", 50),
+            base_delay: env_or("FPLAB_RATE_BASE_DELAY", 30),
+            max_delay: env_or("FPLAB_RATE_MAX_DELAY", 3600),
+            window: env_or("FPLAB_RATE_WINDOW", 900),
+            trust_proxy: env_or("FPLAB_TRUST_PROXY", false)
+        }
+    }
+
+    // REQUIRES: failures >= threshold
+    // EFFECTS: Seconds to lock out after the given number of failures
+    pub fn delay(&self, failures: u32, threshold: u32) -> i64 {
+        let doublings = (failures - threshold).min(30);
+        self.base_delay.saturating_mul(1 << doublings).min(self.max_delay)
+    }
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the unix time key is locked out until,
+//          or None if it is not locked out at now
+pub fn locked_until(conn: &Connection, key: &str, now: i64) -> rusqlite::Result<Option<i64>> {
+    let until : Option<i64> = conn.query_row(
+        "SELECT locked_until FROM lockouts
+        WHERE key = ?1",
+        params![key],
+        |row| row.get(0)
+    ).optional()?;
+    Ok(until.filter(|until| *until > now))
+}
+
+// REQUIRES: threshold > 0
+// MODIFIES: Database
+// EFFECTS: Count a failed attempt for key, locking it out with
+//          exponential backoff once threshold failures happened
+//          within config.window seconds of each other
+//          Returns the new lockout time, if any
+pub fn record_failure(
+    conn: &Connection,
+    key: &str,
+    threshold: u32,
+    config: &RateLimitConfig,
+    now: i64
+) -> rusqlite::Result<Option<i64>> {
+    let prev : Option<(u32, i64)> = conn.query_row(
+        "SELECT failures, last_failure FROM lockouts
+        WHERE key = ?1",
+        params![key],
+        |row| Ok((row.get(0)?, row.get(1)?))
+    ).optional()?;
+
+    let failures = match prev {
+        Some((failures, last)) if now - last <= config.window => failures + 1,
+        _ => 1
+    };
+    let until = if failures >= threshold {
+        Some(now + config.delay(failures, threshold))
+    }
+    else {
+        None
+    };
+
+    conn.execute(
+        "INSERT OR REPLACE INTO lockouts (key, failures, last_failure, locked_until)
+        VALUES (?1, ?2, ?3, ?4)",
+        params![key, failures, now, until.unwrap_or(0)]
+    )?;
+    Ok(until)
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database
+// EFFECTS: Forget past failures for key
+pub fn record_success(conn: &Connection, key: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "DELETE FROM lockouts
+        WHERE key = ?1",
+        params![key]
+    )?;
+    Ok(())
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database
+// EFFECTS: Add a failed or refused attempt to the audit log
+pub fn log_attempt(
+    conn: &Connection,
+    route: &str,
+    ip: &str,
+    credential: Option<&str>,
+    status: StatusCode
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO failed_attempts (route, ip, credential, status)
+        VALUES (?1, ?2, ?3, ?4)",
+        params![route, ip, credential, status.as_u16()]
+    )?;
+    Ok(())
+}
+
+// Middleware throttling failed POSTs to the wrapped routes
+// A response of 401 or 403 counts as a failure for the client IP and
+// for each credential form field, a 2xx or 3xx clears the credentials
+// e.g.: web::resource("/accounts/login/")
+//          .wrap(RateLimit::new(&["token", "username"]))
+pub struct RateLimit {
+    fields: Rc<Vec<&'static str>>,
+    config: Rc<RateLimitConfig>
+}
+
+impl RateLimit {
+    // fields: names of url-encoded form fields identifying the account
+    pub fn new(fields: &[&'static str]) -> Self {
+        RateLimit {
+            fields: Rc::new(fields.to_vec()),
+            config: Rc::new(RateLimitConfig::from_env())
+        }
+    }
+}
+
+impl<S, B> Transform<S> for RateLimit
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RateLimitMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            fields: self.fields.clone(),
+            config: self.config.clone()
+        })
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    fields: Rc<Vec<&'static str>>,
+    config: Rc<RateLimitConfig>
+}
+
+impl<S, B> Service for RateLimitMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        // only form submissions can fail
+        if req.method() != Method::POST {
+            return Box::pin(self.service.borrow_mut().call(req))
+        }
+
+        let service = self.service.clone();
+        let fields = self.fields.clone();
+        let config = self.config.clone();
+
+        Box::pin(async move {
+            // buffer the body to read the credentials, then hand it back
+            let mut body = BytesMut::new();
+            let mut payload = req.take_payload();
+            while let Some(chunk) = payload.next().await {
+                body.extend_from_slice(&chunk?);
+                if body.len() > MAX_BODY {
+                    let res = HttpResponse::PayloadTooLarge().finish();
+                    return Ok(req.into_response(res.into_body()))
+                }
+            }
+            let body = body.freeze();
+            let credentials : Vec<String> = serde_urlencoded::from_bytes::<Vec<(String, String)>>(&body)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|(k, v)| fields.contains(&k.as_str()) && !v.trim().is_empty())
+                .map(|(_, v)| v.trim().to_string())
+                .collect();
+            let (_, mut restored) = h1::Payload::create(true);
+            restored.unread_data(body);
+            req.set_payload(restored.into());
+
+            let ip = client_ip(&req, config.trust_proxy);
+            let route = req.path().to_string();
+            let mut keys = vec![(format!("ip:{}", ip), config.ip_max_failures)];
+            keys.extend(credentials.iter().map(|c| (format!("credential:{}", c), config.max_failures)));
+
+            let conn = db::connect();
+            let now = Utc::now().timestamp();
+
+            // refuse without running the handler while any key is locked out
+            let mut until = None;
+            for (key, _) in &keys {
+                until = until.max(locked_until(&conn, key, now).unwrap());
+            }
+            if let Some(until) = until {
+                log_attempt(
+                    &conn, &route, &ip,
+                    credentials.first().map(String::as_str),
+                    StatusCode::TOO_MANY_REQUESTS
+                ).unwrap();
+                let res = HttpResponse::TooManyRequests()
+                    .header(header::RETRY_AFTER, (until - now).to_string())
+                    .body("Too many attempts, please try again later!");
+                return Ok(req.into_response(res.into_body()))
+            }
+
+            let fut = service.borrow_mut().call(req);
+            let res = fut.await?;
+
+            let status = res.status();
+            if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+                log_attempt(
+                    &conn, &route, &ip,
+                    credentials.first().map(String::as_str), status
+                ).unwrap();
+                for (key, threshold) in &keys {
+                    if let Some(until) = record_failure(&conn, key, *threshold, &config, now).unwrap() {
+                        eprintln!("rate_limit: {} locked out of {} for {}s", key, route, until - now);
+                    }
+                }
+            }
+            else if status.is_success() || status.is_redirection() {
+                // the IP count only decays with time, so a valid login
+                // cannot be used to reset it between guesses
+                for (key, _) in keys.iter().skip(1) {
+                    record_success(&conn, key).unwrap();
+                }
+            }
+
+            Ok(res)
+        })
+    }
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the client's IP address, taken from the
+//          X-Forwarded-For header only if the proxy is trusted
+fn client_ip(req: &ServiceRequest, trust_proxy: bool) -> String {
+    if trust_proxy {
+        if let Some(remote) = req.connection_info().remote() {
+            // strip the port, if any
+            return remote.parse::<SocketAddr>()
+                .map(|addr| addr.ip().to_string())
+                .unwrap_or_else(|_| remote.to_string())
+        }
+    }
+    req.peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
@@ -0,0 +1,279 @@
+// crates.io
+use actix_session::UserSession;
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::header, Error
+};
+use chrono::Utc;
+use futures::future::{ok, LocalBoxFuture, Ready};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use uuid::Uuid;
+// local
+use crate::config::env_or;
+use crate::db;
+
+// Session key handlers read the logged in user's token from
+// Only set while a request is handled, never stored in the cookie
+pub const LOGIN_KEY: &str = "login";
+// Session key of the server-side session id kept in the cookie
+pub const SID_KEY: &str = "sid";
+
+// last_seen is only written once this many seconds have passed
+const TOUCH_INTERVAL: i64 = 60;
+// User agents are cut to the column's length
+const MAX_USER_AGENT: usize = 256;
+
+// Lifetime of server-side sessions, read from
+//      FPLAB_SESSION_IDLE  seconds a session may go unused (default: 604800, 1 week)
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    pub idle: i64
+}
+
+impl SessionConfig {
+    pub fn from_env() -> Self {
+        SessionConfig {
+            idle: env_or("FPLAB_SESSION_IDLE", 604800)
+        }
+    }
+}
+
+// A login on one browser, as listed to users and instructors
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SessionInfo {
+    pub id: String,
+    pub created: i64,
+    pub last_seen: i64,
+    pub user_agent: Option<String>,
+    // the session making the request
+    pub current: bool
+}
+
+// REQUIRES: token belongs to a user
+// MODIFIES: Database
+// EFFECTS: Start a session for token and return its id
+//          Sessions idle for longer than idle seconds are removed
+pub fn create(
+    conn: &Connection,
+    token: &str,
+    user_agent: Option<&str>,
+    idle: i64,
+    now: i64
+) -> rusqlite::Result<String> {
+    conn.execute(
+        "DELETE FROM sessions
+        WHERE last_seen <= ?1",
+        params![now - idle]
+    )?;
+
+    let id = format!("{}{}", Uuid::new_v4().to_simple(), Uuid::new_v4().to_simple());
+    let user_agent = user_agent.map(|ua| ua.chars().take(MAX_USER_AGENT).collect::<String>());
+    conn.execute(
+        "INSERT INTO sessions (id, token, created, last_seen, user_agent)
+        VALUES (?1, ?2, ?3, ?3, ?4)",
+        params![&id, token, now, user_agent]
+    )?;
+    Ok(id)
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database
+// EFFECTS: Return the token of session id if it was used within
+//          the last idle seconds, recording that it was seen at now
+pub fn resolve(
+    conn: &Connection,
+    id: &str,
+    idle: i64,
+    now: i64
+) -> rusqlite::Result<Option<String>> {
+    let row : Option<(String, i64)> = conn.query_row(
+        "SELECT token, last_seen FROM sessions
+        WHERE id = ?1",
+        params![id],
+        |row| Ok((row.get(0)?, row.get(1)?))
+    ).optional()?;
+
+    let (token, last_seen) = match row {
+        Some(row) if row.1 > now - idle => row,
+        _ => return Ok(None)
+    };
+    if now - last_seen >= TOUCH_INTERVAL {
+        conn.execute(
+            "UPDATE sessions SET last_seen = ?1
+            WHERE id = ?2",
+            params![now, id]
+        )?;
+    }
+    Ok(Some(token))
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: List token's sessions used within the last idle seconds,
+//          most recently seen first, flagging the one with id current
+pub fn list(
+    conn: &Connection,
+    token: &str,
+    current: Option<&str>,
+    idle: i64,
+    now: i64
+) -> rusqlite::Result<Vec<SessionInfo>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, created, last_seen, user_agent FROM sessions
+        WHERE token = ?1 AND last_seen > ?2
+        ORDER BY last_seen DESC, created DESC"
+    )?;
+    let rows = stmt.query_map(params![token, now - idle], |row| {
+        let id : String = row.get(0)?;
+        Ok(SessionInfo {
+            current: current == Some(id.as_str()),
+            id,
+            created: row.get(1)?,
+            last_seen: row.get(2)?,
+            user_agent: row.get(3)?
+        })
+    })?;
+    rows.collect()
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database
+// EFFECTS: End session id if it belongs to token
+//          Returns false if there is no such session
+pub fn revoke(conn: &Connection, token: &str, id: &str) -> rusqlite::Result<bool> {
+    let n = conn.execute(
+        "DELETE FROM sessions
+        WHERE id = ?1 AND token = ?2",
+        params![id, token]
+    )?;
+    Ok(n > 0)
+}
+
+// REQUIRES: n/a
+// MODIFIES: Database
+// EFFECTS: End all of token's sessions except the one with id keep
+//          Returns the number of sessions ended
+pub fn revoke_all(conn: &Connection, token: &str, keep: Option<&str>) -> rusqlite::Result<usize> {
+    conn.execute(
+        "DELETE FROM sessions
+        WHERE token = ?1 AND id IS NOT ?2",
+        params![token, keep]
+    )
+}
+
+// Middleware keeping logins on the server
+// - the cookie only holds the session id, under "sid"
+// - for each request the id is looked up and, if the session is
+//   still active, the user's token is put under "login" for handlers
+// - handlers log in and out by setting and removing "login" as before;
+//   afterwards a new session is started or the old one ended
+// Revoking a session therefore logs its browser out on the next request
+// Must be wrapped inside the cookie session middleware
+// e.g.: App::new().wrap(ServerSessions::new(SessionConfig::from_env()))
+pub struct ServerSessions {
+    config: Rc<SessionConfig>
+}
+
+impl ServerSessions {
+    pub fn new(config: SessionConfig) -> Self {
+        ServerSessions {
+            config: Rc::new(config)
+        }
+    }
+}
+
+impl<S, B> Transform<S> for ServerSessions
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ServerSessionsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ServerSessionsMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            config: self.config.clone()
+        })
+    }
+}
+
+pub struct ServerSessionsMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    config: Rc<SessionConfig>
+}
+
+impl<S, B> Service for ServerSessionsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let config = self.config.clone();
+
+        Box::pin(async move {
+            let session = req.get_session();
+            let idle = config.idle;
+            let user_agent = req.headers().get(header::USER_AGENT)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string());
+
+            // logins stored in the cookie itself, e.g. from before
+            // sessions were kept on the server, are not trusted
+            session.remove(LOGIN_KEY);
+            let sid = session.get::<String>(SID_KEY).unwrap_or(None);
+            let user = match &sid {
+                Some(id) => resolve(&db::connect(), id, idle, Utc::now().timestamp()).unwrap(),
+                None => None
+            };
+            match &user {
+                Some(user) => session.set(LOGIN_KEY, user).unwrap(),
+                // revoked or expired
+                None if sid.is_some() => session.remove(SID_KEY),
+                None => ()
+            }
+
+            let fut = service.borrow_mut().call(req);
+            let res = fut.await;
+
+            let login = session.get::<String>(LOGIN_KEY).unwrap_or(None);
+            session.remove(LOGIN_KEY);
+            if res.is_ok() && login != user {
+                let conn = db::connect();
+                // logged out, or logged in as someone else
+                if let (Some(id), Some(user)) = (&sid, &user) {
+                    revoke(&conn, user, id).unwrap();
+                }
+                match login {
+                    Some(token) => {
+                        let now = Utc::now().timestamp();
+                        let id = create(&conn, &token, user_agent.as_deref(), idle, now).unwrap();
+                        session.set(SID_KEY, id).unwrap();
+                    },
+                    None => session.remove(SID_KEY)
+                }
+            }
+            res
+        })
+    }
+}
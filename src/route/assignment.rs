@@ -2,12 +2,15 @@
 use actix_web::{web, HttpResponse};
 use actix_session::{Session};
 use serde::{Deserialize, Serialize};
-use rusqlite::{NO_PARAMS, params, Connection};
+use rusqlite::params;
 use std::process::{Command, Stdio};
 use std::fs;
 use execute::Execute;
+// local
+use crate::db;
+use crate::lti::{ags, Lti};
 
-const NUM_PROBLEMS: i32 = 6;
+pub const NUM_PROBLEMS: i32 = 6;
 
 // URL form deserializes into this struct
 // e.g.: POST "/submit" with some JSON body
@@ -34,17 +37,15 @@ pub struct SubmissionResult {
 
 pub async fn check(
     session: Session,
+    lti: web::Data<Lti>,
     form: web::Json<Submission>
 ) -> HttpResponse {
     // if not logged in, require authentication
-    let user;
-    if let None = session.get::<String>("login").unwrap() {
-        return HttpResponse::Unauthorized()
+    let user = match session.get::<String>("login").unwrap() {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized()
             .body("Please log in!")
-    }
-    else {
-        user = session.get::<String>("login").unwrap().unwrap();
-    }
+    };
 
     let mut command = Command::new("docker");
 
@@ -65,7 +66,7 @@ pub async fn check(
     let score = if error { 0 } else { 1 };
 
     // insert score
-    let conn = Connection::open("db/fp.sqlite3").unwrap();
+    let conn = db::connect();
 
     // check if form ques_id is valid
     if form.problem >= NUM_PROBLEMS {
@@ -125,30 +126,31 @@ pub async fn check(
             ]
         ).unwrap();
 
-    return HttpResponse::Ok()
+    // pass the new score back to the LMS gradebook, if launched from one
+    ags::sync_assignment(lti.into_inner(), user);
+
+    HttpResponse::Ok()
     .json(
         SubmissionResult {
             correct: !error,
-            output: output
+            output
         }
-    );
+    )
 }
 
 pub async fn reset(
     session: Session,
+    lti: web::Data<Lti>
 ) -> HttpResponse {
     // if not logged in, require authentication
-    let user;
-    if let None = session.get::<String>("login").unwrap() {
-        return HttpResponse::Unauthorized()
+    let user = match session.get::<String>("login").unwrap() {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized()
             .body("Please log in!")
-    }
-    else {
-        user = session.get::<String>("login").unwrap().unwrap();
-    }
+    };
 
     // insert score
-    let conn = Connection::open("db/fp.sqlite3").unwrap();
+    let conn = db::connect();
 
     // set score for all problems to 0
     for problem_id in 0..NUM_PROBLEMS {
@@ -205,7 +207,9 @@ pub async fn reset(
             ]
         ).unwrap();
 
-    return HttpResponse::Ok().finish();
+    ags::sync_assignment(lti.into_inner(), user);
+
+    HttpResponse::Ok().finish()
 }
 
 #[derive(Deserialize, Serialize)]
@@ -233,17 +237,14 @@ pub async fn get_edit_state(
     session: Session
 ) -> HttpResponse {
     // if not logged in, require authentication
-    let user;
-    if let None = session.get::<String>("login").unwrap() {
-        return HttpResponse::Unauthorized()
+    let user = match session.get::<String>("login").unwrap() {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized()
             .body("Please log in!")
-    }
-    else {
-        user = session.get::<String>("login").unwrap().unwrap();
-    }
+    };
     
     // open connection
-    let conn = Connection::open("db/fp.sqlite3").unwrap();
+    let conn = db::connect();
 
     let answers: String;
     // fetch question filename and prompt
@@ -305,9 +306,9 @@ pub async fn get_edit_state(
 
     }
 
-    return HttpResponse::Ok()
+    HttpResponse::Ok()
         .json(EditState {
             answers,
             scores
-        });
+        })
 }
\ No newline at end of file
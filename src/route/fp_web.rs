@@ -6,9 +6,13 @@ use actix_files::NamedFile;
 use actix_session::{Session};
 use handlebars::Handlebars;
 use serde::Serialize;
+// local
+use crate::api::nav::Layout;
 
 #[derive(Serialize)]
 struct Context {
+    #[serde(flatten)]
+    layout: Layout,
     logged_in: u32
 }
 
@@ -22,21 +26,23 @@ pub async fn index(
 ) -> HttpResponse {
     if let Some(_id) = session.get::<String>("login").unwrap() {
         let data = Context {
+            layout: Layout::new(&session),
             logged_in: 1
         };
         let body = hb.render("index", &data).unwrap();
 
         // redirect
-        return HttpResponse::Ok().body(body)
+        HttpResponse::Ok().body(body)
     }
     else {
         let data = Context {
+            layout: Layout::new(&session),
             logged_in: 0
         };
         let body = hb.render("index", &data).unwrap();
 
         // redirect
-        return HttpResponse::Ok().body(body)
+        HttpResponse::Ok().body(body)
     }
 }
 
@@ -0,0 +1,97 @@
+// crates.io
+use actix_web::{web, HttpResponse};
+use actix_session::{Session};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+// local
+use crate::db;
+use crate::db::answer::Answer;
+use crate::api::accounts::require_admin;
+use crate::api::sessions::find_user;
+use crate::route::quiz::find_quiz;
+
+// One answer a user submitted, as it stood right after submitting it
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ResponseEvent {
+    pub quiz_id: Option<i64>, // None once the quiz is deleted
+    pub question_id: i64,
+    pub answer: Answer,
+    pub free_response: String,
+    pub correct: bool,
+    pub time_elapsed: i64, // total ms spent on the question so far
+    pub hover_time: i64, // total ms spent hovering so far
+    pub created: i64 // unix time
+}
+
+// REQUIRES: token answered question_id in quiz_id, i.e. has a row in responses
+// MODIFIES: response_events
+// EFFECTS: Append token's latest response to question_id to their history,
+//          so changed answers and when they changed are kept
+pub fn log(conn: &Connection, token: &str, quiz_id: i64, question_id: i64, now: i64) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO response_events (
+            token, quiz_id, question_id, answer, response,
+            free_response, correct, time_elapsed, hover_time, created
+        )
+        SELECT token, quiz_id, question_id, answer, response,
+            free_response, correct, time_elapsed, hover_time, ?4
+        FROM responses
+        WHERE token = ?1 AND question_id = ?2 AND quiz_id = ?3",
+        params![token, question_id, quiz_id, now]
+    )?;
+    Ok(())
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return every answer token submitted in quiz_id, oldest first
+pub fn history(conn: &Connection, token: &str, quiz_id: i64) -> rusqlite::Result<Vec<ResponseEvent>> {
+    let mut stmt = conn.prepare(
+        "SELECT question_id, answer, response, free_response, correct,
+            time_elapsed, hover_time, created
+        FROM response_events
+        WHERE token = ?1 AND quiz_id = ?2
+        ORDER BY event_id"
+    )?;
+    let events = stmt.query_map(params![token, quiz_id], |row| {
+        Ok(ResponseEvent {
+            quiz_id: Some(quiz_id),
+            question_id: row.get(0)?,
+            answer: Answer::from_columns(row.get(1)?, &row.get::<_, String>(2)?),
+            free_response: row.get(3)?,
+            correct: row.get(4)?,
+            time_elapsed: row.get(5)?,
+            hover_time: row.get(6)?,
+            created: row.get(7)?
+        })
+    })?;
+    events.collect()
+}
+
+// REQUIRES: GET method, logged in admin
+// MODIFIES: n/a
+// EFFECTS: Return every answer a user submitted in a quiz, oldest first
+//          e.g.: GET /admin/quizzes/ownership/history/student-1/
+pub async fn user_history(
+    session: Session,
+    path: web::Path<(String, String)>
+) -> HttpResponse {
+    let conn = db::connect();
+    if let Err(res) = require_admin(&session, &conn) {
+        return res
+    }
+    let (slug, user) = path.into_inner();
+
+    let quiz_id = match find_quiz(&conn, &slug).unwrap() {
+        Some(quiz_id) => quiz_id,
+        None => return HttpResponse::NotFound()
+            .body("No such quiz!")
+    };
+    let token = match find_user(&conn, &user) {
+        Some(token) => token,
+        None => return HttpResponse::NotFound()
+            .body("No such user!")
+    };
+    HttpResponse::Ok()
+        .json(history(&conn, &token, quiz_id).unwrap())
+}
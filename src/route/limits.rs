@@ -0,0 +1,157 @@
+// crates.io
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+// Answers sent right before the deadline may arrive a little late
+pub const GRACE_SECS: i64 = 10;
+
+// When users learn whether their answers are correct
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedbackMode {
+    // as each answer is submitted, for practice quizzes
+    Immediate,
+    // in the results once the quiz is finished, for exams
+    #[default]
+    End
+}
+
+impl FeedbackMode {
+    // EFFECTS: Return the name stored in quizzes.feedback
+    pub fn name(self) -> &'static str {
+        match self {
+            FeedbackMode::Immediate => "immediate",
+            FeedbackMode::End => "end"
+        }
+    }
+
+    // EFFECTS: Parse a name stored in quizzes.feedback
+    pub fn from_name(name: &str) -> Option<FeedbackMode> {
+        match name {
+            "immediate" => Some(FeedbackMode::Immediate),
+            "end" => Some(FeedbackMode::End),
+            _ => None
+        }
+    }
+}
+
+// Per-quiz settings set by instructors, None for no limit
+// e.g.: {"max_attempts": 2, "time_limit": 900, "feedback": "immediate"}
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Settings {
+    // answers a user may submit to each question
+    pub max_attempts: Option<u32>,
+    // seconds from when a user opens the quiz
+    pub time_limit: Option<u32>,
+    #[serde(default)]
+    pub feedback: FeedbackMode
+}
+
+// Whether a user can still change their answers to a quiz
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Lock {
+    // submitted or out of time
+    pub locked: bool,
+    pub submitted: bool,
+    // seconds, None if the quiz is not timed
+    pub time_left: Option<i64>,
+    pub max_attempts: Option<u32>
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the settings of quiz_id
+pub fn settings(conn: &Connection, quiz_id: i64) -> rusqlite::Result<Settings> {
+    conn.query_row(
+        "SELECT max_attempts, time_limit, feedback FROM quizzes
+        WHERE quiz_id = ?1",
+        params![quiz_id],
+        |row| Ok(Settings {
+            max_attempts: row.get(0)?,
+            time_limit: row.get(1)?,
+            feedback: FeedbackMode::from_name(&row.get::<_, String>(2)?).unwrap_or_default()
+        })
+    ).optional().map(Option::unwrap_or_default)
+}
+
+// REQUIRES: n/a
+// MODIFIES: quizzes
+// EFFECTS: Set the settings of the quiz slug
+//          Returns false if no such quiz exists
+pub fn set_settings(conn: &Connection, slug: &str, settings: &Settings) -> rusqlite::Result<bool> {
+    let n = conn.execute(
+        "UPDATE quizzes SET max_attempts = ?1, time_limit = ?2, feedback = ?3
+        WHERE slug = ?4",
+        params![settings.max_attempts, settings.time_limit, settings.feedback.name(), slug]
+    )?;
+    Ok(n == 1)
+}
+
+// REQUIRES: token belongs to a user
+// MODIFIES: quiz_progress
+// EFFECTS: Record now as when token started quiz_id, unless they already had
+pub fn start(conn: &Connection, token: &str, quiz_id: i64, now: i64) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO quiz_progress (token, quiz_id, started)
+        VALUES (?1, ?2, ?3)
+        ON CONFLICT (token, quiz_id) DO UPDATE SET
+            started = COALESCE(started, excluded.started)",
+        params![token, quiz_id, now]
+    )?;
+    Ok(())
+}
+
+// REQUIRES: token belongs to a user
+// MODIFIES: quiz_progress
+// EFFECTS: Lock token's answers to quiz_id, keeping the time
+//          of the first submission
+pub fn submit(conn: &Connection, token: &str, quiz_id: i64, now: i64) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO quiz_progress (token, quiz_id, started, submitted)
+        VALUES (?1, ?2, ?3, ?3)
+        ON CONFLICT (token, quiz_id) DO UPDATE SET
+            started = COALESCE(started, excluded.started),
+            submitted = COALESCE(submitted, excluded.submitted)",
+        params![token, quiz_id, now]
+    )?;
+    Ok(())
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return whether token can still answer quiz_id at now
+//          A timed quiz locks once its time limit has passed since token
+//          started it, give or take GRACE_SECS
+pub fn lock(conn: &Connection, token: &str, quiz_id: i64, now: i64) -> rusqlite::Result<Lock> {
+    let settings = settings(conn, quiz_id)?;
+    let (started, submitted) : (Option<i64>, Option<i64>) = conn.query_row(
+        "SELECT started, submitted FROM quiz_progress
+        WHERE token = ?1 AND quiz_id = ?2",
+        params![token, quiz_id],
+        |row| Ok((row.get(0)?, row.get(1)?))
+    ).optional()?.unwrap_or((None, None));
+
+    // time starts running when the quiz is first opened
+    let time_left = settings.time_limit.map(|limit| match started {
+        Some(started) => started + limit as i64 - now,
+        None => limit as i64
+    });
+    Ok(Lock {
+        locked: submitted.is_some() || time_left.is_some_and(|left| left < -GRACE_SECS),
+        submitted: submitted.is_some(),
+        time_left: time_left.map(|left| left.max(0)),
+        max_attempts: settings.max_attempts
+    })
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return how many answers token submitted to question_id in quiz_id
+pub fn attempts(conn: &Connection, token: &str, quiz_id: i64, question_id: i64) -> rusqlite::Result<u32> {
+    conn.query_row(
+        "SELECT attempts FROM responses
+        WHERE token = ?1 AND quiz_id = ?2 AND question_id = ?3",
+        params![token, quiz_id, question_id],
+        |row| row.get(0)
+    ).optional().map(Option::unwrap_or_default)
+}
@@ -1,3 +1,6 @@
 pub mod fp_web;
+pub mod history;
+pub mod limits;
 pub mod quiz;
+pub mod shuffle;
 pub mod assignment;
\ No newline at end of file
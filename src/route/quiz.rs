@@ -1,212 +1,461 @@
 // crates.io
 use actix_web::{web, HttpResponse};
 use actix_session::{Session};
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
-use rusqlite::{NO_PARAMS, params, Connection};
-
-// URL form deserializes into this struct
-// e.g.: POST "/submit" with some JSON body
-//       will deserialize into:
-//          Question {
-//              ques_id: 0,
-//              ans_id: 7,
-//              free_response: "Does not compile because ...",
-//              time_elapsed_question: 2000,
-//              time_elapsed_hover: 12345
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior, params};
+// local
+use crate::db;
+use crate::db::answer::{check_answer, grade, Answer, Kind};
+use crate::api::accounts::require_admin;
+use crate::api::grading;
+use crate::middleware::sessions::LOGIN_KEY;
+use crate::route::history;
+use crate::route::limits::{self, FeedbackMode, Lock};
+use crate::route::shuffle::{self, Arrangement, Shuffled};
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the id of the quiz with the given slug
+pub fn find_quiz(conn: &Connection, slug: &str) -> rusqlite::Result<Option<i64>> {
+    conn.query_row(
+        "SELECT quiz_id FROM quizzes
+        WHERE slug = ?1",
+        params![slug],
+        |row| row.get(0)
+    ).optional()
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the number of questions in quiz_id
+pub fn num_questions(conn: &Connection, quiz_id: i64) -> rusqlite::Result<u32> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM quiz_questions
+        WHERE quiz_id = ?1",
+        params![quiz_id],
+        |row| row.get(0)
+    )
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the id of the question at position in quiz_id,
+//          None if the quiz has no such position
+//          Question ids need not be contiguous, so positions are checked
+//          by looking them up rather than against num_questions
+pub fn question_at(conn: &Connection, quiz_id: i64, position: u32) -> rusqlite::Result<Option<i64>> {
+    conn.query_row(
+        "SELECT question_id FROM quiz_questions
+        WHERE quiz_id = ?1 AND position = ?2",
+        params![quiz_id, position],
+        |row| row.get(0)
+    ).optional()
+}
+
+// Longest time a single request may add to a question, in milliseconds
+// Clients post time spent since their last request, so anything longer
+// is a clock glitch or a forged payload rather than a student thinking
+pub const MAX_ELAPSED_MS: u64 = 6 * 60 * 60 * 1000;
+
+// What a request to POST /quiz/{slug}/submit/ is, see UserResponse
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestType {
+    // time spent on a question, without answering it
+    Heartbeat,
+    Answer
+}
+
+// JSON body of POST /quiz/{slug}/submit/
+// ques_id is the position the user sees the question at, and ans_id
+// and answer use the choice ids the user sees, see route::shuffle
+// e.g.:
+//          {
+//              "type": "answer",
+//              "ques_id": 0,
+//              "ans_id": 2,
+//              "free_response": "Does not compile because ...",
+//              "time_elapsed_question": 2000,
+//              "time_elapsed_hover": 12345
 //          }
-#[derive(Deserialize)]
+// Questions other than choice ones are answered with answer instead
+// of ans_id, e.g. "answer": [0, 2] for a multi_select question
+// type may be left out, making requests with an answer answers and
+// the others heartbeats; see validate for what is rejected
+#[derive(Deserialize, Debug)]
 pub struct UserResponse {
-    ques_id: u8,
-    ans_id: Option<u8>,
+    #[serde(default, rename = "type")]
+    request: Option<RequestType>,
+    ques_id: u32,
+    #[serde(default)]
+    ans_id: Option<i64>,
+    #[serde(default)]
+    answer: Option<Answer>,
+    #[serde(default)]
     free_response: Option<String>,
     time_elapsed_question: u64, // in milliseconds
     time_elapsed_hover: u64 // in milliseconds
 }
 
-// REQUIRES: valid user, POST method, valid ques_id, ans_id
-// MODIFIES: results, user_ans
-// EFFECTS: Record user's num of correct answers
-//          Record user's response to each question
-//          Increases user's num_correct by 1
-//          if response submitted is correct
+// Time a request adds to a question, in milliseconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timing {
+    pub question: i64,
+    pub hover: i64
+}
+
+// A UserResponse that makes sense, in the choice ids the user sees
+#[derive(Debug, Clone, PartialEq)]
+pub enum Submission {
+    Heartbeat {
+        ques_id: u32,
+        timing: Timing
+    },
+    Answer {
+        ques_id: u32,
+        answer: Answer,
+        free_response: String,
+        timing: Timing
+    }
+}
+
+impl UserResponse {
+    // EFFECTS: Return the submission the request stands for, or why it is
+    //          inconsistent: durations over MAX_ELAPSED_MS, both or neither
+    //          of ans_id and answer in an answer, or a heartbeat or
+    //          free_response carrying what an answer would
+    pub fn validate(self) -> Result<Submission, String> {
+        let mut errors = Vec::new();
+        for (field, ms) in [
+            ("time_elapsed_question", self.time_elapsed_question),
+            ("time_elapsed_hover", self.time_elapsed_hover)
+        ] {
+            if ms > MAX_ELAPSED_MS {
+                errors.push(format!("{} must be at most {} ms", field, MAX_ELAPSED_MS));
+            }
+        }
+        let timing = Timing {
+            question: self.time_elapsed_question as i64,
+            hover: self.time_elapsed_hover as i64
+        };
+
+        if self.ans_id.is_some() && self.answer.is_some() {
+            errors.push("send either ans_id or answer, not both".to_string());
+        }
+        let answer = self.answer.or(self.ans_id.map(Answer::Index));
+        let request = self.request.unwrap_or(match answer {
+            Some(_) => RequestType::Answer,
+            None => RequestType::Heartbeat
+        });
+        let submission = match (request, answer) {
+            (RequestType::Heartbeat, None) if self.free_response.is_some() => {
+                errors.push("free_response needs an answer".to_string());
+                None
+            },
+            (RequestType::Heartbeat, None) =>
+                Some(Submission::Heartbeat { ques_id: self.ques_id, timing }),
+            (RequestType::Heartbeat, Some(_)) => {
+                errors.push("heartbeats carry no answer".to_string());
+                None
+            },
+            (RequestType::Answer, None) => {
+                errors.push("answers need ans_id or answer".to_string());
+                None
+            },
+            (RequestType::Answer, Some(answer)) => Some(Submission::Answer {
+                ques_id: self.ques_id,
+                answer,
+                // questions without a free response field send null
+                free_response: self.free_response.unwrap_or_default(),
+                timing
+            })
+        };
+
+        match submission {
+            Some(submission) if errors.is_empty() => Ok(submission),
+            _ => Err(errors.join("; "))
+        }
+    }
+}
+
+impl Submission {
+    // EFFECTS: Return the position of the question submitted to
+    pub fn ques_id(&self) -> u32 {
+        match self {
+            Submission::Heartbeat { ques_id, .. } | Submission::Answer { ques_id, .. } => *ques_id
+        }
+    }
+}
+
+// REQUIRES: token answered or is answering question_id in quiz_id
+// MODIFIES: responses
+// EFFECTS: Add timing to the time token spent on question_id in quiz_id,
+//          creating an unanswered response the first time
+//          Quizzes may share questions, so responses are kept per quiz
+pub fn add_time(conn: &Connection, token: &str, quiz_id: i64, question_id: i64, timing: Timing) -> rusqlite::Result<()> {
+    // Note:
+    //      If answer = -2, user has not submitted answer
+    conn.execute(
+        "INSERT INTO responses (token, quiz_id, question_id, time_elapsed, hover_time)
+        VALUES (?1, ?2, ?3, ?4, ?5)
+        ON CONFLICT (token, quiz_id, question_id) DO UPDATE SET
+            time_elapsed = time_elapsed + excluded.time_elapsed,
+            hover_time = hover_time + excluded.hover_time",
+        params![token, quiz_id, question_id, timing.question, timing.hover]
+    )?;
+    Ok(())
+}
+
+// Returned for each answer to quizzes giving immediate feedback,
+// in the choice ids the user sees
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct AnswerFeedback {
+    pub correct: bool,
+    pub correct_answer: Answer,
+    pub explanation: String,
+    pub choice_explanations: Vec<String>
+}
+
+// REQUIRES: explanations are of shown's question by canonical ans_id
+// MODIFIES: n/a
+// EFFECTS: Return the explanations of shown's choices in displayed order,
+//          empty if its question has none
+pub fn choice_explanations(shown: &Shuffled, explanations: &[String]) -> Vec<String> {
+    if explanations.is_empty() {
+        return Vec::new()
+    }
+    shown.choices.iter()
+        .map(|ans_id| explanations.get(*ans_id as usize).cloned().unwrap_or_default())
+        .collect()
+}
+
+// REQUIRES: valid user, POST method, valid quiz slug, ques_id, answer
+// MODIFIES: responses, response_events, quiz_progress
+// EFFECTS: Record user's response to each question
+//          by canonical question and choice ids
+//          responses holds the latest answer, and every answer is
+//          appended to response_events, see history::log
+//          Marks the response correct if it matches the
+//          question's answer, see grade and quiz_results
+//          Inconsistent requests are rejected with 400, see
+//          UserResponse::validate
+//          Refused once the quiz is locked, see limits::lock, or the
+//          user used up their attempts at the question
+//          Quizzes giving immediate feedback return an AnswerFeedback
+//          for each answer, others stay silent until the results
 pub async fn record_response(
     session: Session,
+    slug: web::Path<String>,
     form: web::Json<UserResponse>
 ) -> HttpResponse {
     // if not logged in, require authentication
-    let user;
-    if let None = session.get::<String>("login").unwrap() {
-        return HttpResponse::Unauthorized()
+    let user = match session.get::<String>("login").unwrap() {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized()
             .body("Please log in!")
-    }
-    else {
-        user = session.get::<String>("login").unwrap().unwrap();
-    }
+    };
+
+    let submission = match form.into_inner().validate() {
+        Ok(submission) => submission,
+        Err(error) => return HttpResponse::BadRequest()
+            .body(error)
+    };
+    let ques_id = submission.ques_id();
 
     // insert answer
-    let conn = Connection::open("db/fp.sqlite3").unwrap();
+    let mut conn = db::connect();
 
-    // fetch total num of questions
-    let mut stmt = conn.prepare(
-        "SELECT COUNT(*) AS num_questions
-        FROM questions"
-    ).unwrap();
-    let mut rows = stmt.query(NO_PARAMS).unwrap();
-    let num_questions : u8 = 
-        if let Some(row) = rows.next().unwrap() { row.get_unwrap(0) }
-        else { 0 };
+    let quiz_id = match find_quiz(&conn, &slug).unwrap() {
+        Some(quiz_id) => quiz_id,
+        None => return HttpResponse::NotFound()
+            .body("No such quiz!")
+    };
 
     // check if form ques_id is valid
-    if form.ques_id >= num_questions {
-        return HttpResponse::BadRequest()
-            .finish()
+    let arrangement = shuffle::arrangement(&conn, &user, quiz_id).unwrap();
+    let shown = match arrangement.at(ques_id) {
+        Some(shown) => shown,
+        None => return HttpResponse::BadRequest()
+            .body("No such question!")
+    };
+    let qid = shown.question_id;
+
+    // check limits and record the answer at once, so concurrent
+    // submissions cannot both pass the lock or the attempt limit
+    let conn = conn.transaction_with_behavior(TransactionBehavior::Immediate).unwrap();
+
+    // check if responses are still open
+    let now = Utc::now().timestamp();
+    let lock = limits::lock(&conn, &user, quiz_id, now).unwrap();
+    if lock.submitted {
+        return HttpResponse::Forbidden()
+            .body("Quiz is already submitted!")
     }
+    if lock.locked {
+        return HttpResponse::Forbidden()
+            .body("Time is up!")
+    }
+    limits::start(&conn, &user, quiz_id, now).unwrap();
+    let mut feedback = None;
 
-    // if ans_id or free_response are None
-    // only update time elapsed
-    match (
-        form.ques_id, form.ans_id,
-        form.free_response.as_ref(),
-        form.time_elapsed_question,
-        form.time_elapsed_hover
-    ) {
-        (qid, None, _, time, hover_time) | (qid, _, None, time, hover_time) => {
-            // check if already submitted
-            let mut stmt = conn.prepare(
-                "SELECT question_id FROM responses
-                WHERE token = ?1 AND question_id = ?2"
-            ).unwrap();
+    match submission {
+        // only update time elapsed
+        Submission::Heartbeat { timing, .. } => {
+            add_time(&conn, &user, quiz_id, qid, timing).unwrap();
+        },
 
-            if let Ok(mut rows) = stmt.query(&[&user, &qid.to_string()]) {
-                // if question has not yet been submitted,
-                //  initialize time_elapsed_question in db and update answer later
-                if let None = rows.next().unwrap() {
-                    // Note:
-                    //      If answer = -2, user has not submitted answer
-                    conn.execute(
-                        "INSERT INTO responses (token, question_id)
-                        VALUES (?1, ?2)",
-                        params![&user, qid.to_string()]
-                    ).unwrap();
-                }
+        Submission::Answer { answer, free_response, timing, .. } => {
+            let answer = match shown.to_canonical(&answer) {
+                Some(answer) => answer,
+                None => return HttpResponse::BadRequest()
+                    .body("No such choice!")
             };
+            let question = db::load_question(&conn, qid).unwrap().unwrap();
 
-            conn.execute(
-                "UPDATE responses
-                SET time_elapsed = time_elapsed + ?1,
-                    hover_time = hover_time + ?2
-                WHERE token = ?3 AND question_id = ?4",
-                params![
-                    time.to_string(),
-                    hover_time.to_string(),
-                    &user, qid.to_string()
-                ]
-            ).unwrap();
-        },
-
-        (qid, Some(aid), Some(free_res), time, hover_time) => {
-            // fetch total num of choices
-            let mut stmt = conn.prepare(
-                "SELECT COUNT(*) AS num_choices
-                FROM choices WHERE question_id=?"
-            ).unwrap();
-            let mut rows = stmt.query(params![qid]).unwrap();
-            let num_choices : u8 = 
-                if let Some(row) = rows.next().unwrap() { row.get_unwrap(0) }
-                else { 0 };
+            // check if user has attempts left
+            let max = lock.max_attempts.unwrap_or(u32::MAX);
+            if limits::attempts(&conn, &user, quiz_id, qid).unwrap() >= max {
+                return HttpResponse::Forbidden()
+                    .body("No attempts left!")
+            }
 
-            // check if form ans_id is valid
-            if aid >= num_choices {
+            // check if answer fits the kind of question
+            if let Some(error) = check_answer(question.kind, question.choices.len(), &answer) {
                 return HttpResponse::BadRequest()
-                    .finish()
+                    .body(error)
             }
 
-            // check if already submitted
-            let mut stmt = conn.prepare(
-                "SELECT question_id FROM responses
-                WHERE token = ?1 AND question_id = ?2"
-            ).unwrap();
+            // grades are of the text graded, so it may not change since
+            if let Some(graded) = grading::graded_response(&conn, &user, quiz_id, qid).unwrap() {
+                if graded != free_response {
+                    return HttpResponse::Forbidden()
+                        .body("Your free response has already been graded!")
+                }
+            }
+
+            let correct = grade(question.kind, &question.answer, &answer);
+            // Note:
+            //      If answer = -1, the response is in the response column
+            let (aid, response) = answer.to_columns();
 
             // NOTE:
             //      Ok to update free_response text even if
             //      question does not require free response field
-            match stmt.query(&[&user, &qid.to_string()]) {
-                Ok(mut rows) => {
-                    // if question has been answered, update answer
-                    if let Some(_row) = rows.next().unwrap() {
-                        conn.execute(
-                            "UPDATE responses
-                            SET answer = ?1,
-                                time_elapsed = time_elapsed + ?2,
-                                hover_time = hover_time + ?3,
-                                free_response = ?4
-                            WHERE token = ?5 AND question_id = ?6",
-                            params![
-                                aid.to_string(), time.to_string(),
-                                hover_time.to_string(), free_res,
-                                &user, qid.to_string()
-                            ]
-                        ).unwrap();
-                    }
-                    // else create new instance and insert into table
-                    else {
-                        conn.execute(
-                            "INSERT INTO responses (
-                                token, question_id, answer,
-                                time_elapsed,
-                                hover_time, free_response
-                            )
-                            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-                            params![
-                                &user, qid.to_string(),
-                                aid.to_string(), time.to_string(),
-                                hover_time.to_string(), free_res
-                            ]
-                        ).unwrap();
-                    }
-                },
-                _ => {
-                    return HttpResponse::Forbidden()
-                        .finish()
-                }
-            };
+            add_time(&conn, &user, quiz_id, qid, timing).unwrap();
+            conn.execute(
+                "UPDATE responses
+                SET answer = ?1,
+                    free_response = ?2,
+                    correct = ?3,
+                    response = ?4,
+                    attempts = attempts + 1
+                WHERE token = ?5 AND quiz_id = ?6 AND question_id = ?7",
+                params![aid, &free_response, correct, &response, &user, quiz_id, qid]
+            ).unwrap();
+            history::log(&conn, &user, quiz_id, qid, now).unwrap();
+
+            if limits::settings(&conn, quiz_id).unwrap().feedback == FeedbackMode::Immediate {
+                feedback = Some(AnswerFeedback {
+                    correct,
+                    correct_answer: shown.to_displayed(&question.answer).unwrap_or(question.answer),
+                    explanation: question.explanation,
+                    choice_explanations: choice_explanations(shown, &question.choice_explanations)
+                });
+            }
         }
     };
 
-        
-    // update user progress
+    // update user progress, keeping when they started
     conn.execute(
-        "UPDATE users
-        SET curr_ques = ?1
-        WHERE token = ?2",
-        params![(form.ques_id+1).to_string(), &user]
+        "INSERT INTO quiz_progress (token, quiz_id, curr_ques)
+        VALUES (?1, ?2, ?3)
+        ON CONFLICT (token, quiz_id) DO UPDATE SET
+            curr_ques = excluded.curr_ques",
+        params![&user, quiz_id, ques_id + 1]
     ).unwrap();
+    conn.commit().unwrap();
 
-    return HttpResponse::Created()
-        .finish()
+    match feedback {
+        Some(feedback) => HttpResponse::Created()
+            .json(feedback),
+        None => HttpResponse::Created()
+            .finish()
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct Question {
+    pub qid: u32,
+    pub kind: Kind,
+    pub filename: String,
+    pub prompt: String,
+    pub choices: Vec<Choice>,
+    pub contains_free_response: bool
 }
 
-#[derive(Deserialize, Serialize)]
-struct Question {
-    qid: u8,
-    filename: String,
-    prompt: String,
-    choices: Vec<Choice>,
-    contains_free_response: bool
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct Choice {
+    pub id: i64,
+    pub text: String
 }
 
-#[derive(Deserialize, Serialize)]
-struct Choice {
-    id: u8,
-    text: String
+// REQUIRES: arrangement is of the quiz being shown
+// MODIFIES: n/a
+// EFFECTS: Return the question displayed at position with its choices
+//          in displayed order, numbered as displayed
+//          None if the quiz has no such position
+pub fn question(conn: &Connection, arrangement: &Arrangement, position: u32) -> rusqlite::Result<Option<Question>> {
+    let shown = match arrangement.at(position) {
+        Some(shown) => shown,
+        None => return Ok(None)
+    };
+    let ques_id = shown.question_id;
+
+    // fetch question filename and prompt
+    let (kind, filename, prompt, contains_free_response) = conn.query_row(
+        "SELECT kind, filename, prompt, contains_fr
+        FROM questions WHERE question_id = ?1",
+        params![ques_id],
+        |row| Ok((
+            Kind::from_name(&row.get::<_, String>(0)?).unwrap_or_default(),
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?
+        ))
+    )?;
+
+    // fetch multiple choice answers
+    let mut stmt = conn.prepare(
+        "SELECT choice_text FROM choices
+        WHERE question_id = ?1 AND ans_id = ?2"
+    )?;
+    let mut choices = Vec::new();
+    for (id, ans_id) in shown.choices.iter().enumerate() {
+        choices.push(Choice {
+            id: id as i64,
+            text: stmt.query_row(params![ques_id, ans_id], |row| row.get(0))?
+        });
+    }
+
+    Ok(Some(Question {
+        qid: position,
+        kind,
+        filename,
+        prompt,
+        choices,
+        contains_free_response
+    }))
 }
 
-// REQUIRES: GET method, valid question id
+// REQUIRES: GET method
 // MODIFIES: n/a
-// EFFECTS: Returns qid_url_slug's information
-//          e.g.: GET /question/0/ returns info for question 0
+// EFFECTS: Returns the information of the question at a position in a quiz,
+//          404 if there is no such quiz or question
+//          e.g.: GET /quiz/ownership/question/0/ returns info for its first question
 //              {
 //                  qid: 0,
+//                  kind: 'choice',
 //                  filename: vis_04_01_01,
 //                  prompt: 'What is the output of the function?',
 //                  choices: [
@@ -216,145 +465,372 @@ struct Choice {
 //                  ],
 //                  contains_free_response: true
 //              }
+//          Logged in users see questions and choices in their own
+//          shuffled order, see route::shuffle
+//          compile_fail and output questions have no choices
 pub async fn get_question(
-    ques_id: web::Path<u8>
+    session: Session,
+    path: web::Path<(String, u32)>
 ) -> HttpResponse {
-    // convert to u8
-    let ques_id = ques_id.into_inner();
+    let (slug, position) = path.into_inner();
 
     // open connection
-    let conn = Connection::open("db/fp.sqlite3").unwrap();
-
-    // fetch question filename and prompt
-    let mut stmt = conn.prepare(
-        "SELECT filename, prompt, contains_fr
-        FROM questions WHERE question_id=?"
-    ).unwrap();
+    let conn = db::connect();
 
-    let mut filename = String::from("");
-    let mut prompt = String::from("");
-    let mut has_free_res = false;
-    let mut rows = stmt.query(params![ques_id]).unwrap();
-    if let Some(row) = rows.next().unwrap() {
-        filename = row.get_unwrap(0);
-        prompt = row.get_unwrap(1);
-        has_free_res = row.get_unwrap::<_, u8>(2) != 0; // convert INTEGER to bool
-    }
+    let quiz_id = match find_quiz(&conn, &slug).unwrap() {
+        Some(quiz_id) => quiz_id,
+        None => return HttpResponse::NotFound()
+            .body("No such quiz!")
+    };
 
-    // fetch multiple choice answers
-    let mut stmt = conn.prepare(
-        "SELECT ans_id AS id, choice_text AS text
-        FROM choices WHERE question_id=?"
-    ).unwrap();
+    let arrangement = match session.get::<String>(LOGIN_KEY).unwrap() {
+        Some(user) => shuffle::arrangement(&conn, &user, quiz_id),
+        None => shuffle::canonical(&conn, quiz_id)
+    }.unwrap();
 
-    // create vector of multiple choices
-    let mut choice_vec : Vec<Choice> = Vec::new();
-    if let Ok(rows) = stmt.query_map(params![ques_id], |row| {
-        Ok(Choice {
-            id: row.get_unwrap(0),
-            text: row.get_unwrap(1)
-        })
-    }) {
-        // compile into Vec<Choice>
-        choice_vec = rows.map(|r| r.unwrap()).collect();
+    match question(&conn, &arrangement, position).unwrap() {
+        Some(q) => HttpResponse::Ok()
+            .json(q),
+        None => HttpResponse::NotFound()
+            .body("No such question!")
     }
+}
 
-    let q = Question {
-        qid: ques_id,
-        filename: filename,
-        prompt: prompt,
-        choices: choice_vec,
-        contains_free_response: has_free_res
-    };
-
-    return HttpResponse::Ok()
-        .json(q)
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct Quiz {
+    pub total: u32,
+    pub current: u32,
+    pub saved_ans_vec: Vec<i64>,
+    pub saved_free_res: Vec<String>,
+    // submitted answers of any kind, null if unanswered
+    pub saved_responses: Vec<Option<Answer>>,
+    // answers submitted to each question
+    pub attempts: Vec<u32>,
+    #[serde(flatten)]
+    pub lock: Lock,
+    pub url: String
 }
 
-#[derive(Deserialize, Serialize)]
-struct Quiz {
-    total: u8,
-    current: u8,
-    saved_ans_vec: Vec<i8>,
-    saved_free_res: Vec<String>,
-    url: String
+// REQUIRES: quiz_id is the quiz with the given slug, token belongs to a user
+// MODIFIES: arrangements
+// EFFECTS: Return token's current position in quiz_id, its number of
+//          questions, token's saved answers by the positions and
+//          choice ids token sees and whether they are locked at now
+pub fn status(conn: &Connection, token: &str, slug: &str, quiz_id: i64, now: i64) -> rusqlite::Result<Quiz> {
+    // fetch quiz status, 0 if not started
+    let last_ques : u32 = conn.query_row(
+        "SELECT curr_ques FROM quiz_progress
+        WHERE token = ?1 AND quiz_id = ?2",
+        params![token, quiz_id],
+        |row| row.get(0)
+    ).optional()?.unwrap_or(0);
+
+    // fetch total num of questions in the order token sees them
+    let arrangement = shuffle::arrangement(conn, token, quiz_id)?;
+    let num_questions = arrangement.questions.len();
+
+    // prepare vector with current answers
+    let mut ans_vec : Vec<i64> = vec![-2; num_questions]; // -2 if unanswered
+    let mut fr_vec : Vec<String> = vec!["".to_string(); num_questions]; // "" if unanswered
+    let mut res_vec : Vec<Option<Answer>> = vec![None; num_questions];
+    let mut attempts : Vec<u32> = vec![0; num_questions];
+    // fetch current answers from db
+    let mut stmt = conn.prepare(
+        "SELECT answer, free_response, response, attempts FROM responses
+        WHERE token = ?1 AND quiz_id = ?2 AND question_id = ?3"
+    )?;
+    for (position, shown) in arrangement.questions.iter().enumerate() {
+        let row : Option<(i64, String, String, u32)> = stmt.query_row(
+            params![token, quiz_id, shown.question_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        ).optional()?;
+        let (a_id, fr_text, response, tries) = match row {
+            Some(row) => row,
+            None => continue
+        };
+        fr_vec[position] = fr_text;
+        attempts[position] = tries;
+        if a_id == -2 {
+            continue
+        }
+        // -1 if the answer is not a choice id or line number
+        let answer = shown.to_displayed(&Answer::from_columns(a_id, &response));
+        ans_vec[position] = match answer {
+            Some(Answer::Index(id)) => id,
+            _ => -1
+        };
+        res_vec[position] = answer;
+    }
+
+    Ok(Quiz {
+        total: num_questions as u32,
+        current: last_ques,
+        saved_ans_vec: ans_vec,
+        saved_free_res: fr_vec,
+        saved_responses: res_vec,
+        attempts,
+        lock: limits::lock(conn, token, quiz_id, now)?,
+        url: format!("/quiz/{}/question/{}/", slug, last_ques)
+    })
 }
 
 // REQUIRES: logged in user
-// MODIFIES: n/a
-// EFFECTS: Return current question id and uri,
-//          total nums of questions
+// MODIFIES: quiz_progress
+// EFFECTS: Return the position and uri of the user's current question
+//          in the quiz, total nums of questions, saved answers
+//          by position and whether they are locked
+//          Starts the clock of timed quizzes the first time
 pub async fn init_quiz(
-    session: Session
+    session: Session,
+    slug: web::Path<String>
 ) -> HttpResponse {
     // check for login
-    let user;
-    if let None = session.get::<String>("login").unwrap() {
-        return HttpResponse::Unauthorized()
+    let user = match session.get::<String>("login").unwrap() {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized()
             .body("Please log in!")
-    }
-    else {
-        user = session.get::<String>("login").unwrap().unwrap();
-    }
+    };
 
     // open connection
-    let conn = Connection::open("db/fp.sqlite3").unwrap();
+    let conn = db::connect();
 
-    // fetch quiz status
-    let mut stmt = conn.prepare(
-        "SELECT curr_ques
-        FROM users WHERE token=?"
-    ).unwrap();
-    let mut rows = stmt.query(params![&user]).unwrap();
+    let quiz_id = match find_quiz(&conn, &slug).unwrap() {
+        Some(quiz_id) => quiz_id,
+        None => return HttpResponse::NotFound()
+            .body("No such quiz!")
+    };
+
+    let now = Utc::now().timestamp();
+    limits::start(&conn, &user, quiz_id, now).unwrap();
+    HttpResponse::Ok()
+        .json(status(&conn, &user, &slug, quiz_id, now).unwrap())
+}
+
+// REQUIRES: POST method, logged in user
+// MODIFIES: quiz_progress
+// EFFECTS: Lock the user's answers to the quiz and return the lock state,
+//          after which results are shown even if questions are unanswered
+pub async fn finish_quiz(
+    session: Session,
+    slug: web::Path<String>
+) -> HttpResponse {
+    let user = match session.get::<String>("login").unwrap() {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized()
+            .body("Please log in!")
+    };
+
+    let conn = db::connect();
+    let quiz_id = match find_quiz(&conn, &slug).unwrap() {
+        Some(quiz_id) => quiz_id,
+        None => return HttpResponse::NotFound()
+            .body("No such quiz!")
+    };
+
+    let now = Utc::now().timestamp();
+    limits::submit(&conn, &user, quiz_id, now).unwrap();
+    HttpResponse::Ok()
+        .json(limits::lock(&conn, &user, quiz_id, now).unwrap())
+}
 
-    // Requires: user to be registered in db
-    let mut last_ques = 0;
-    if let Some(row) = rows.next().unwrap() {
-        last_ques = row.get_unwrap(0);
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct QuestionResult {
+    pub qid: u32,
+    pub kind: Kind,
+    pub prompt: String,
+    pub answer: Option<Answer>, // user's answer, None if not submitted
+    pub correct_answer: Answer,
+    pub correct: bool,
+    pub explanation: String,
+    // in displayed order, empty if the question has none
+    pub choice_explanations: Vec<String>
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct Results {
+    pub total: u32,
+    pub score: u32, // number of correct answers
+    pub questions: Vec<QuestionResult>
+}
+
+// REQUIRES: token belongs to a user
+// MODIFIES: arrangements
+// EFFECTS: Return token's graded answers to quiz_id by the positions and
+//          choice ids token sees once they can no longer answer at now,
+//          i.e. submitted or ran out of time, else None as explanations
+//          would give answers away
+//          Quizzes giving immediate feedback already revealed each answer,
+//          so their results are shown as soon as every question is answered
+pub fn results(conn: &Connection, token: &str, quiz_id: i64, now: i64) -> rusqlite::Result<Option<Results>> {
+    let locked = limits::lock(conn, token, quiz_id, now)?.locked;
+    let immediate = limits::settings(conn, quiz_id)?.feedback == FeedbackMode::Immediate;
+    if !locked && !immediate {
+        return Ok(None)
     }
 
-    // fetch total num of questions
+    let arrangement = shuffle::arrangement(conn, token, quiz_id)?;
     let mut stmt = conn.prepare(
-        "SELECT COUNT(*) AS num_questions
-        FROM questions"
-    ).unwrap();
-    let mut rows = stmt.query(NO_PARAMS).unwrap();
-    let num_questions : u8 = if let Some(row) = rows.next().unwrap() {
-        row.get_unwrap(0)
+        "SELECT q.kind, q.prompt, COALESCE(r.answer, -2),
+            COALESCE(r.response, ''), q.answer, q.solution,
+            COALESCE(r.correct, 0), q.explanation
+        FROM questions q
+        LEFT JOIN responses r
+            ON r.question_id = q.question_id AND r.token = ?1 AND r.quiz_id = ?2
+        WHERE q.question_id = ?3"
+    )?;
+    let mut questions = Vec::new();
+    for (position, shown) in arrangement.questions.iter().enumerate() {
+        let result = stmt.query_row(params![token, quiz_id, shown.question_id], |row| {
+            // answer = -2 means the user has not submitted an answer yet
+            let answer : i64 = row.get(2)?;
+            let response : String = row.get(3)?;
+            let solution : String = row.get(5)?;
+            let key = Answer::from_columns(row.get(4)?, &solution);
+            Ok(QuestionResult {
+                qid: position as u32,
+                kind: Kind::from_name(&row.get::<_, String>(0)?).unwrap_or_default(),
+                prompt: row.get(1)?,
+                answer: Some(Answer::from_columns(answer, &response))
+                    .filter(|_| answer != -2)
+                    .and_then(|answer| shown.to_displayed(&answer)),
+                correct_answer: shown.to_displayed(&key).unwrap_or(key),
+                correct: row.get(6)?,
+                explanation: row.get(7)?,
+                choice_explanations: Vec::new()
+            })
+        })?;
+        let explanations = db::load_question(conn, shown.question_id)?
+            .map(|q| q.choice_explanations)
+            .unwrap_or_default();
+        questions.push(QuestionResult {
+            choice_explanations: choice_explanations(shown, &explanations),
+            ..result
+        });
     }
-    else {
-        0
-    };
 
-    // prepare vector with current answers
-    let mut ans_vec : Vec<i8> = vec![-2; num_questions.into()]; // -2 if unanswered
-    let mut fr_vec : Vec<String> = vec!["".to_string(); num_questions.into()]; // "" if unanswered
-    // fetch current answers from db
-    let mut stmt = conn.prepare(
-        "SELECT question_id, answer, free_response
-        FROM responses WHERE token=?"
-    ).unwrap();
-    let mut rows = stmt.query(params![&user]).unwrap();
-    while let Some(row) = rows.next().unwrap() {
-        let q_id : i8 = row.get_unwrap(0);
-        let a_id : i8 = row.get_unwrap(1);
-        let fr_text : String = row.get_unwrap(2);
-        if let Some(elt) = ans_vec.get_mut(q_id as usize) {
-            *elt = a_id;
-        }
-        if let Some(elt) = fr_vec.get_mut(q_id as usize) {
-            *elt = fr_text;
-        }
+    if !locked && questions.iter().any(|q| q.answer.is_none()) {
+        return Ok(None)
     }
+    Ok(Some(Results {
+        total: questions.len() as u32,
+        score: questions.iter().filter(|q| q.correct).count() as u32,
+        questions
+    }))
+}
 
-    let q = Quiz {
-        total: num_questions,
-        current: last_ques,
-        saved_ans_vec: ans_vec,
-        saved_free_res: fr_vec,
-        url: format!("/question/{}/", last_ques)
+// REQUIRES: GET method, logged in user
+// MODIFIES: n/a
+// EFFECTS: Return per-question correctness, total score
+//          and explanations once the quiz is finished
+//          e.g.: GET /quiz/ownership/results/ returns
+//              {
+//                  total: 10,
+//                  score: 7,
+//                  questions: [
+//                      {qid: 0, kind: 'choice', prompt: '...', answer: 1,
+//                       correct_answer: 1, correct: true, explanation: '...',
+//                       choice_explanations: ['...', '...', '...']},
+//                      ...
+//                  ]
+//              }
+pub async fn quiz_results(
+    session: Session,
+    slug: web::Path<String>
+) -> HttpResponse {
+    let user = match session.get::<String>("login").unwrap() {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized()
+            .body("Please log in!")
     };
 
-    return HttpResponse::Ok()
-        .json(q)
+    let conn = db::connect();
+    let quiz_id = match find_quiz(&conn, &slug).unwrap() {
+        Some(quiz_id) => quiz_id,
+        None => return HttpResponse::NotFound()
+            .body("No such quiz!")
+    };
+
+    match results(&conn, &user, quiz_id, Utc::now().timestamp()).unwrap() {
+        Some(results) => HttpResponse::Ok()
+            .json(results),
+        None => HttpResponse::Conflict()
+            .body("Please finish the quiz first!")
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct QuizInfo {
+    pub slug: String,
+    pub title: String,
+    pub chapter: Option<String>,
+    pub total: u32
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return all quizzes, or only those attached to chapter
+pub fn list(conn: &Connection, chapter: Option<&str>) -> rusqlite::Result<Vec<QuizInfo>> {
+    let mut stmt = conn.prepare(
+        "SELECT z.slug, z.title, z.chapter, COUNT(qq.question_id)
+        FROM quizzes z
+        LEFT JOIN quiz_questions qq ON qq.quiz_id = z.quiz_id
+        WHERE ?1 IS NULL OR z.chapter = ?1
+        GROUP BY z.quiz_id
+        ORDER BY z.slug"
+    )?;
+    let quizzes = stmt.query_map(params![chapter], |row| {
+        Ok(QuizInfo {
+            slug: row.get(0)?,
+            title: row.get(1)?,
+            chapter: row.get(2)?,
+            total: row.get(3)?
+        })
+    })?;
+    quizzes.collect()
+}
+
+#[derive(Deserialize, Debug)]
+pub struct QuizQuery {
+    chapter: Option<String>
+}
+
+// REQUIRES: GET method
+// MODIFIES: n/a
+// EFFECTS: Return the quizzes, filtered by the chapter they are attached to
+//          e.g.: GET /quiz/?chapter=ch04-01-what-is-ownership.html returns
+//              [{slug: 'ownership', title: 'What is Ownership?',
+//                chapter: 'ch04-01-what-is-ownership.html', total: 11}]
+pub async fn list_quizzes(
+    query: web::Query<QuizQuery>
+) -> HttpResponse {
+    let quizzes = list(&db::connect(), query.chapter.as_deref()).unwrap();
+
+    HttpResponse::Ok()
+        .json(quizzes)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChapterForm {
+    chapter: Option<String>
+}
+
+// REQUIRES: POST method, logged in admin
+// MODIFIES: Database
+// EFFECTS: Attach a quiz to a book chapter, or detach it if chapter is null
+pub async fn attach_chapter(
+    session: Session,
+    slug: web::Path<String>,
+    form: web::Json<ChapterForm>
+) -> HttpResponse {
+    let conn = db::connect();
+    if let Err(res) = require_admin(&session, &conn) {
+        return res
+    }
+
+    if !db::attach_quiz(&conn, &slug, form.chapter.as_deref()).unwrap() {
+        return HttpResponse::NotFound()
+            .body("No such quiz!")
+    }
+    HttpResponse::Ok()
+        .finish()
 }
@@ -0,0 +1,329 @@
+// crates.io
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use rusqlite::{params, Connection, OptionalExtension, Transaction, TransactionBehavior};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+// local
+use crate::db;
+use crate::db::answer::Answer;
+
+// A question as one student sees it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shuffled {
+    // question of the quiz this one is shown for, see draw
+    pub slot: i64,
+    // slot itself, or the variant drawn from its pool
+    pub question_id: i64,
+    // canonical ans_id of each displayed choice, in displayed order
+    pub choices: Vec<i64>
+}
+
+// The order one student sees a quiz in: questions by displayed position
+#[derive(Debug, Clone, PartialEq)]
+pub struct Arrangement {
+    pub questions: Vec<Shuffled>
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return a generator seeded by token and the parts of key,
+//          so each student gets the same order every time
+fn seeded(token: &str, key: &[i64]) -> StdRng {
+    let mut hasher = Sha256::new();
+    hasher.update(token.as_bytes());
+    for part in key {
+        hasher.update(b":");
+        hasher.update(part.to_string().as_bytes());
+    }
+    StdRng::from_seed(hasher.finalize().into())
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the number of choices of question_id
+fn num_choices(conn: &Connection, question_id: i64) -> rusqlite::Result<i64> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM choices
+        WHERE question_id = ?1",
+        params![question_id],
+        |row| row.get(0)
+    )
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the pool question_id belongs to, if any
+fn pool(conn: &Connection, question_id: i64) -> rusqlite::Result<Option<String>> {
+    conn.query_row(
+        "SELECT pool FROM questions
+        WHERE question_id = ?1",
+        params![question_id],
+        |row| row.get(0)
+    ).optional().map(Option::flatten)
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return the questions of a pool by id
+fn pool_members(conn: &Connection, pool: &str) -> rusqlite::Result<Vec<i64>> {
+    let mut stmt = conn.prepare(
+        "SELECT question_id FROM questions
+        WHERE pool = ?1
+        ORDER BY question_id"
+    )?;
+    let ids = stmt.query_map(params![pool], |row| row.get(0))?;
+    ids.collect()
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return true if question_id may be shown for slot, being
+//          slot itself or in its pool
+fn is_variant(conn: &Connection, slot: i64, question_id: i64) -> rusqlite::Result<bool> {
+    if question_id == slot {
+        return Ok(true)
+    }
+    let slot_pool = pool(conn, slot)?;
+    Ok(slot_pool.is_some() && slot_pool == pool(conn, question_id)?)
+}
+
+// REQUIRES: the variants kept are distinct and each is a variant of its slot
+// MODIFIES: n/a
+// EFFECTS: Return the question token is shown for each question of
+//          quiz_id, in the quiz's order
+//          A question in a pool stands for any question of that pool:
+//          token gets one drawn at random, never the same one twice within
+//          the quiz, so answers are less shareable between students
+//          Slots in kept keep the variant drawn for them before
+fn draw(conn: &Connection, token: &str, quiz_id: i64, kept: &HashMap<i64, i64>) -> rusqlite::Result<Vec<(i64, i64)>> {
+    let slots = db::quiz_question_ids(conn, quiz_id)?;
+    let mut drawn : HashSet<i64> = kept.values().copied().collect();
+    let mut questions = Vec::new();
+    for slot in slots {
+        if let Some(question_id) = kept.get(&slot) {
+            questions.push((slot, *question_id));
+            continue
+        }
+        let question_id = match pool(conn, slot)? {
+            // a slot is a member of its own pool, and each pool has at least
+            // as many members as slots standing for it, so one is always left
+            Some(pool) => {
+                let left : Vec<i64> = pool_members(conn, &pool)?.into_iter()
+                    .filter(|id| !drawn.contains(id))
+                    .collect();
+                *left.choose(&mut seeded(token, &[quiz_id, slot])).unwrap_or(&slot)
+            },
+            None => slot
+        };
+        drawn.insert(question_id);
+        questions.push((slot, question_id));
+    }
+    Ok(questions)
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Draw the questions of quiz_id for token, then shuffle them
+//          and the choices of each question
+//          Choices are seeded by question as well, so editing one
+//          question leaves the choices of the others where they were
+pub fn shuffle(conn: &Connection, token: &str, quiz_id: i64) -> rusqlite::Result<Arrangement> {
+    reshuffle(conn, token, quiz_id, &Arrangement { questions: Vec::new() })
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Shuffle quiz_id for token like shuffle, keeping what stored
+//          still shows: slots still in the quiz keep their variant and
+//          their order, and their choices unless the number of them changed
+//          Slots added since are drawn and shuffled after them
+fn reshuffle(conn: &Connection, token: &str, quiz_id: i64, stored: &Arrangement) -> rusqlite::Result<Arrangement> {
+    let slots = db::quiz_question_ids(conn, quiz_id)?;
+    let mut kept = Vec::new();
+    let mut variants = HashMap::new();
+    for q in &stored.questions {
+        let unique = !variants.contains_key(&q.slot) && !variants.values().any(|id| *id == q.question_id);
+        if unique && slots.contains(&q.slot) && is_variant(conn, q.slot, q.question_id)? {
+            variants.insert(q.slot, q.question_id);
+            kept.push(q.clone());
+        }
+    }
+
+    let mut drawn = draw(conn, token, quiz_id, &variants)?;
+    drawn.shuffle(&mut seeded(token, &[quiz_id]));
+
+    let mut questions = kept;
+    questions.extend(drawn.into_iter()
+        .filter(|(slot, _)| !variants.contains_key(slot))
+        .map(|(slot, question_id)| Shuffled { slot, question_id, choices: Vec::new() }));
+    for q in &mut questions {
+        let n = num_choices(conn, q.question_id)?;
+        if q.choices.len() as i64 != n {
+            q.choices = (0..n).collect();
+            q.choices.shuffle(&mut seeded(token, &[quiz_id, q.question_id]));
+        }
+    }
+    Ok(Arrangement { questions })
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return quiz_id in the order it was written, for previews
+//          by users who are not logged in
+pub fn canonical(conn: &Connection, quiz_id: i64) -> rusqlite::Result<Arrangement> {
+    let mut questions = Vec::new();
+    for question_id in db::quiz_question_ids(conn, quiz_id)? {
+        let choices = (0..num_choices(conn, question_id)?).collect();
+        questions.push(Shuffled { slot: question_id, question_id, choices });
+    }
+    Ok(Arrangement { questions })
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return token's stored arrangement of quiz_id by position,
+//          empty if there is none, and whether it can be used as stored,
+//          which it cannot if positions have gaps, e.g. after one of its
+//          questions was deleted
+fn load(conn: &Connection, token: &str, quiz_id: i64) -> rusqlite::Result<(Arrangement, bool)> {
+    let mut stmt = conn.prepare(
+        "SELECT position, slot, question_id, choice_order FROM arrangements
+        WHERE token = ?1 AND quiz_id = ?2
+        ORDER BY position"
+    )?;
+    let rows = stmt.query_map(params![token, quiz_id], |row| {
+        let choices : String = row.get(3)?;
+        Ok((row.get::<_, i64>(0)?, Shuffled {
+            slot: row.get(1)?,
+            question_id: row.get(2)?,
+            choices: serde_json::from_str(&choices).unwrap_or_default()
+        }))
+    })?.collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let contiguous = rows.iter().enumerate().all(|(i, (position, _))| *position == i as i64);
+    let usable = !rows.is_empty() && contiguous;
+    Ok((Arrangement { questions: rows.into_iter().map(|(_, q)| q).collect() }, usable))
+}
+
+// REQUIRES: n/a
+// MODIFIES: n/a
+// EFFECTS: Return true if arrangement still covers exactly the questions
+//          of quiz_id with variants from their pools, and all of their choices
+fn is_current(conn: &Connection, quiz_id: i64, arrangement: &Arrangement) -> rusqlite::Result<bool> {
+    let mut ids = db::quiz_question_ids(conn, quiz_id)?;
+    let mut slots : Vec<i64> = arrangement.questions.iter().map(|q| q.slot).collect();
+    ids.sort_unstable();
+    slots.sort_unstable();
+    if ids != slots {
+        return Ok(false)
+    }
+    for q in &arrangement.questions {
+        if !is_variant(conn, q.slot, q.question_id)? || q.choices.len() as i64 != num_choices(conn, q.question_id)? {
+            return Ok(false)
+        }
+    }
+    Ok(true)
+}
+
+// REQUIRES: token belongs to a user
+// MODIFIES: arrangements
+// EFFECTS: Return the order token sees quiz_id in and the variants drawn
+//          for them, shuffling and storing it the first time or after the
+//          quiz's questions, pools or choices changed, see reshuffle
+//          Stored so responses can be mapped back to canonical ids for
+//          analysis no matter how the shuffle is computed later on
+pub fn arrangement(conn: &Connection, token: &str, quiz_id: i64) -> rusqlite::Result<Arrangement> {
+    let (stored, usable) = load(conn, token, quiz_id)?;
+    if usable && is_current(conn, quiz_id, &stored)? {
+        return Ok(stored)
+    }
+
+    // check again holding the write lock, so concurrent requests
+    // store one arrangement; callers in a transaction already hold it
+    if !conn.is_autocommit() {
+        return store(conn, token, quiz_id)
+    }
+    let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+    let arrangement = store(&tx, token, quiz_id)?;
+    tx.commit()?;
+    Ok(arrangement)
+}
+
+// REQUIRES: token belongs to a user
+// MODIFIES: arrangements
+// EFFECTS: Return token's stored arrangement of quiz_id if it is current,
+//          else reshuffle it and store the result
+fn store(conn: &Connection, token: &str, quiz_id: i64) -> rusqlite::Result<Arrangement> {
+    let (stored, usable) = load(conn, token, quiz_id)?;
+    if usable && is_current(conn, quiz_id, &stored)? {
+        return Ok(stored)
+    }
+
+    let arrangement = reshuffle(conn, token, quiz_id, &stored)?;
+    conn.execute(
+        "DELETE FROM arrangements
+        WHERE token = ?1 AND quiz_id = ?2",
+        params![token, quiz_id]
+    )?;
+    for (position, q) in arrangement.questions.iter().enumerate() {
+        conn.execute(
+            "INSERT INTO arrangements (token, quiz_id, position, slot, question_id, choice_order)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                token, quiz_id, position as i64, q.slot, q.question_id,
+                serde_json::to_string(&q.choices).unwrap()
+            ]
+        )?;
+    }
+    Ok(arrangement)
+}
+
+impl Arrangement {
+    // EFFECTS: Return the question displayed at position
+    pub fn at(&self, position: u32) -> Option<&Shuffled> {
+        self.questions.get(position as usize)
+    }
+
+    // EFFECTS: Return where question_id is displayed
+    pub fn position(&self, question_id: i64) -> Option<u32> {
+        self.questions.iter().position(|q| q.question_id == question_id).map(|p| p as u32)
+    }
+}
+
+impl Shuffled {
+    // EFFECTS: Return the canonical ans_id of a displayed choice
+    pub fn canonical(&self, displayed: i64) -> Option<i64> {
+        usize::try_from(displayed).ok().and_then(|i| self.choices.get(i)).copied()
+    }
+
+    // EFFECTS: Return where a canonical ans_id is displayed
+    pub fn displayed(&self, canonical: i64) -> Option<i64> {
+        self.choices.iter().position(|c| *c == canonical).map(|i| i as i64)
+    }
+
+    // EFFECTS: Translate an answer given in displayed choice ids to
+    //          canonical ones, None if it names a choice that is not shown
+    //          Answers to questions without choices are not choice ids
+    //          (line numbers, program output) and pass through unchanged
+    pub fn to_canonical(&self, answer: &Answer) -> Option<Answer> {
+        self.translate(answer, |id| self.canonical(id))
+    }
+
+    // EFFECTS: Translate an answer given in canonical choice ids to
+    //          displayed ones, see to_canonical
+    pub fn to_displayed(&self, answer: &Answer) -> Option<Answer> {
+        self.translate(answer, |id| self.displayed(id))
+    }
+
+    fn translate<F: Fn(i64) -> Option<i64>>(&self, answer: &Answer, map: F) -> Option<Answer> {
+        if self.choices.is_empty() {
+            return Some(answer.clone())
+        }
+        match answer {
+            Answer::Index(id) => map(*id).map(Answer::Index),
+            Answer::Indices(ids) => ids.iter().map(|id| map(*id)).collect::<Option<_>>().map(Answer::Indices),
+            Answer::Text(_) => Some(answer.clone())
+        }
+    }
+}
@@ -0,0 +1,169 @@
+mod common;
+
+// crates.io
+use rusqlite::{params, Connection, NO_PARAMS};
+use std::sync::{Arc, Barrier};
+use std::thread;
+// local
+use common::TempDb;
+use fplab_server::api::accounts::{
+    insert_password_user, insert_user, login_password, login_token,
+    set_password, validate_token, CreateError, MAX_TOKEN_LEN
+};
+use fplab_server::api::{encrypt, invites};
+use fplab_server::middleware::sessions;
+
+// invite code usable by every test account
+fn open_invite(conn: &mut Connection) -> String {
+    invites::generate(conn, "test", 1000, 1, None).unwrap().remove(0)
+}
+
+#[test]
+fn validate_token_rejects_bad_tokens() {
+    assert_eq!(validate_token(""), Err(CreateError::Empty));
+    assert_eq!(validate_token(&"a".repeat(MAX_TOKEN_LEN + 1)), Err(CreateError::TooLong(MAX_TOKEN_LEN)));
+    assert_eq!(validate_token("has space"), Err(CreateError::InvalidChar(' ')));
+    assert_eq!(validate_token("semi;colon"), Err(CreateError::InvalidChar(';')));
+    assert_eq!(validate_token(&"a".repeat(MAX_TOKEN_LEN)), Ok(()));
+    assert_eq!(validate_token("Abc-123_x"), Ok(()));
+}
+
+#[test]
+fn insert_user_rejects_duplicate_token() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    let invite = open_invite(&mut conn);
+
+    assert_eq!(insert_user(&mut conn, "student-1", &invite), Ok(()));
+    assert_eq!(insert_user(&mut conn, "student-1", &invite), Err(CreateError::Taken));
+    // a different token is still accepted
+    assert_eq!(insert_user(&mut conn, "student-2", &invite), Ok(()));
+}
+
+#[test]
+fn insert_user_does_not_insert_invalid_token() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    let invite = open_invite(&mut conn);
+
+    assert_eq!(insert_user(&mut conn, "bad token", &invite), Err(CreateError::InvalidChar(' ')));
+    let n : i64 = conn.query_row(
+        "SELECT COUNT(*) FROM users WHERE token = 'bad token'",
+        NO_PARAMS, |row| row.get(0)
+    ).unwrap();
+    assert_eq!(n, 0);
+}
+
+#[test]
+fn concurrent_inserts_of_same_token_create_one_user() {
+    const THREADS: usize = 8;
+    let db = TempDb::new();
+    let invite = open_invite(&mut db.connect());
+    let barrier = Arc::new(Barrier::new(THREADS));
+
+    let handles : Vec<_> = (0..THREADS).map(|_| {
+        let path = db.path.clone();
+        let barrier = barrier.clone();
+        let invite = invite.clone();
+        thread::spawn(move || {
+            let mut conn = fplab_server::db::open(&path).unwrap();
+            barrier.wait();
+            insert_user(&mut conn, "racer", &invite)
+        })
+    }).collect();
+
+    let results : Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+    assert!(results.iter().all(|r| r.is_ok() || *r == Err(CreateError::Taken)));
+
+    let n : i64 = db.connect().query_row(
+        "SELECT COUNT(*) FROM users WHERE token = 'racer'",
+        NO_PARAMS, |row| row.get(0)
+    ).unwrap();
+    assert_eq!(n, 1);
+}
+
+#[test]
+fn one_time_invite_is_used_up_and_revocable() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    let codes = invites::generate(&mut conn, "eecs490", 1, 2, None).unwrap();
+
+    assert_eq!(insert_user(&mut conn, "first", &codes[0]), Ok(()));
+    assert_eq!(insert_user(&mut conn, "second", &codes[0]), Err(CreateError::InvalidInvite));
+    assert_eq!(insert_user(&mut conn, "second", "NOSUCHCODE"), Err(CreateError::InvalidInvite));
+
+    // a failed insert does not use up the invite
+    assert_eq!(insert_user(&mut conn, "first", &codes[1]), Err(CreateError::Taken));
+    conn.execute("UPDATE invites SET revoked = 1 WHERE code = ?1", params![&codes[1]]).unwrap();
+    assert_eq!(insert_user(&mut conn, "second", &codes[1]), Err(CreateError::InvalidInvite));
+
+    let section : String = conn.query_row(
+        "SELECT section FROM users WHERE token = 'first'",
+        NO_PARAMS, |row| row.get(0)
+    ).unwrap();
+    assert_eq!(section, "eecs490");
+}
+
+#[test]
+fn password_accounts_log_in_with_password_only() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    let invite = open_invite(&mut conn);
+
+    assert_eq!(
+        insert_password_user(&mut conn, "alice", "short", &invite),
+        Err(CreateError::WeakPassword)
+    );
+    let token = insert_password_user(&mut conn, "alice", "correct horse", &invite).unwrap();
+
+    assert_eq!(login_password(&conn, "alice", "correct horse").unwrap(), Some(token.clone()));
+    assert_eq!(login_password(&conn, "alice", "wrong horse").unwrap(), None);
+    // the generated token alone is not enough
+    assert_eq!(login_token(&conn, &token).unwrap(), None);
+}
+
+#[test]
+fn new_passwords_end_other_sessions() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    let invite = open_invite(&mut conn);
+    let token = insert_password_user(&mut conn, "alice", "correct horse", &invite).unwrap();
+    let other = insert_password_user(&mut conn, "bob", "battery staple", &invite).unwrap();
+
+    let kept = sessions::create(&conn, &token, None, 3600, 0).unwrap();
+    let stolen = sessions::create(&conn, &token, None, 3600, 0).unwrap();
+    let bobs = sessions::create(&conn, &other, None, 3600, 0).unwrap();
+
+    assert!(set_password(&mut conn, "alice", "new horse shoe", Some(&kept)).unwrap());
+    assert_eq!(login_password(&conn, "alice", "new horse shoe").unwrap(), Some(token.clone()));
+    assert_eq!(login_password(&conn, "alice", "correct horse").unwrap(), None);
+    assert_eq!(sessions::resolve(&conn, &kept, 3600, 1).unwrap(), Some(token.clone()));
+    assert_eq!(sessions::resolve(&conn, &stolen, 3600, 1).unwrap(), None);
+    // other accounts are untouched
+    assert_eq!(sessions::resolve(&conn, &bobs, 3600, 1).unwrap(), Some(other));
+
+    // a reset keeps no session
+    assert!(set_password(&mut conn, "alice", "reset horse", None).unwrap());
+    assert_eq!(sessions::resolve(&conn, &kept, 3600, 1).unwrap(), None);
+    assert!(!set_password(&mut conn, "nobody", "reset horse", None).unwrap());
+}
+
+#[test]
+fn legacy_sha512_password_is_upgraded_on_login() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    conn.execute(
+        "INSERT INTO users (token, username, password)
+        VALUES ('legacy', 'bob', ?1)",
+        params![encrypt::format_credentials("hunter22")]
+    ).unwrap();
+
+    assert_eq!(login_password(&conn, "bob", "hunter22").unwrap(), Some("legacy".to_string()));
+    let stored : String = conn.query_row(
+        "SELECT password FROM users WHERE token = 'legacy'",
+        NO_PARAMS, |row| row.get(0)
+    ).unwrap();
+    assert!(stored.starts_with("$argon2"));
+    assert_eq!(login_password(&conn, "bob", "hunter22").unwrap(), Some("legacy".to_string()));
+}
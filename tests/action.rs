@@ -0,0 +1,139 @@
+mod common;
+
+// crates.io
+use rusqlite::{Connection, NO_PARAMS};
+// local
+use common::TempDb;
+use fplab_server::api::action::{
+    flush, parse_batch, parse_beacon, Action, HoverInfo, SwitchInfo,
+    Telemetry, TelemetryConfig, MAX_BATCH
+};
+use fplab_server::api::consent;
+
+// unix time the tests take place at
+const NOW: i64 = 1_600_000_000;
+
+fn students(conn: &Connection) {
+    conn.execute_batch("
+        INSERT INTO users (token) VALUES ('student-1');
+        INSERT INTO users (token) VALUES ('student-2');
+    ").unwrap();
+    consent::record(conn, "student-1", consent::current_version(), true, NOW).unwrap();
+}
+
+fn hover(token: &str, item: &str) -> Action {
+    Action::Hover(token.to_string(), HoverInfo {
+        svg_name: "vis_04_01_01".to_string(),
+        hover_item: item.to_string()
+    })
+}
+
+fn switch(token: &str, directory: &str) -> Action {
+    Action::Switch(token.to_string(), SwitchInfo {
+        directory: directory.to_string(),
+        time_elpse: 5000
+    })
+}
+
+fn count(conn: &Connection, sql: &str) -> i64 {
+    conn.query_row(sql, NO_PARAMS, |row| row.get(0)).unwrap()
+}
+
+#[test]
+fn single_actions_and_batches_parse() {
+    let one : Vec<HoverInfo> = parse_batch(br#"{"svg_name": "a.svg", "hover_item": "x"}"#).unwrap();
+    assert_eq!(one.len(), 1);
+    let many : Vec<SwitchInfo> = parse_batch(br#"[
+        {"directory": "ch04-01", "time_elpse": 5000},
+        {"directory": "ch04-02", "time_elpse": 300}
+    ]"#).unwrap();
+    assert_eq!(many[1].directory, "ch04-02");
+    assert!(parse_batch::<HoverInfo>(br#"{"svg_name": "a.svg"}"#).is_err());
+
+    let too_many = format!("[{}]", vec![r#"{"svg_name": "a.svg", "hover_item": "x"}"#; MAX_BATCH + 1].join(","));
+    assert!(parse_batch::<HoverInfo>(too_many.as_bytes()).is_err());
+}
+
+#[test]
+fn beacons_carry_their_csrf_token() {
+    let beacon = parse_beacon(br#"{"csrf_token": "abc",
+        "hovers": [{"svg_name": "a.svg", "hover_item": "x"}]}"#).unwrap();
+    assert_eq!(beacon.csrf_token, "abc");
+    assert_eq!((beacon.hovers.len(), beacon.switches.len()), (1, 0));
+    assert!(parse_beacon(br#"{"hovers": []}"#).is_err());
+}
+
+#[test]
+fn flushes_count_hovers_together() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    students(&conn);
+
+    let recorded = flush(&mut conn, &[
+        hover("student-1", "s"), hover("student-1", "s"), hover("student-1", "t"),
+        switch("student-1", "ch04-01")
+    ]).unwrap();
+    assert_eq!(recorded, 4);
+    flush(&mut conn, &[hover("student-1", "s")]).unwrap();
+
+    assert_eq!(count(&conn, "SELECT hover_times FROM user_hover WHERE hover_item = 's'"), 3);
+    assert_eq!(count(&conn, "SELECT hover_times FROM user_hover WHERE hover_item = 't'"), 1);
+    assert_eq!(count(&conn, "SELECT page_hover FROM user_page WHERE page_item = 'ch04-01'"), 5000);
+}
+
+#[test]
+fn flushes_drop_actions_without_consent() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    students(&conn);
+
+    // student-2 never consented and no-such-user was deleted since
+    let recorded = flush(&mut conn, &[
+        hover("student-2", "s"), switch("student-2", "ch04-01"),
+        hover("no-such-user", "s"), hover("student-1", "s")
+    ]).unwrap();
+    assert_eq!(recorded, 1);
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM user_hover"), 1);
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM user_page"), 0);
+}
+
+#[test]
+fn telemetry_buffers_until_full() {
+    let telemetry = Telemetry::new(TelemetryConfig { interval: 10, max_pending: 3 });
+    assert!(!telemetry.push(vec![hover("student-1", "s"), hover("student-1", "t")]));
+    assert!(telemetry.push(vec![switch("student-1", "ch04-01")]));
+    assert_eq!(telemetry.take().len(), 3);
+    assert!(telemetry.take().is_empty());
+
+    // without a flush interval every request flushes itself
+    let unbuffered = Telemetry::new(TelemetryConfig { interval: 0, max_pending: 3 });
+    assert!(unbuffered.push(vec![hover("student-1", "s")]));
+}
+
+#[test]
+fn failed_flushes_keep_their_actions() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    students(&conn);
+    let telemetry = Telemetry::new(TelemetryConfig { interval: 10, max_pending: 3 });
+    telemetry.push(vec![hover("student-1", "s"), switch("student-1", "ch04-01")]);
+
+    // the switch cannot be written, so nothing is
+    conn.execute_batch("DROP TABLE user_page;").unwrap();
+    assert!(telemetry.flush_to(&mut conn).is_err());
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM user_hover"), 0);
+
+    // retried actions come before newer ones, the oldest go past max_pending
+    telemetry.push(vec![hover("student-1", "t"), hover("student-1", "u")]);
+    assert!(telemetry.flush_to(&mut conn).is_err());
+    assert_eq!(telemetry.take(), vec![
+        switch("student-1", "ch04-01"), hover("student-1", "t"), hover("student-1", "u")
+    ]);
+
+    telemetry.push(vec![hover("student-1", "s")]);
+    conn.execute_batch("
+        CREATE TABLE user_page (token VARCHAR(20), page_item TEXT, page_hover INTEGER);
+    ").unwrap();
+    assert_eq!(telemetry.flush_to(&mut conn).unwrap(), 1);
+    assert!(telemetry.take().is_empty());
+}
@@ -0,0 +1,77 @@
+mod common;
+
+// crates.io
+use rusqlite::NO_PARAMS;
+use std::fs;
+// local
+use common::TempDb;
+use fplab_server::db::{self, backup::{backup, list, prune, restore, run_once, verify, BackupConfig, BackupError}};
+
+fn users(conn: &rusqlite::Connection) -> i64 {
+    conn.query_row("SELECT COUNT(*) FROM users", NO_PARAMS, |row| row.get(0)).unwrap()
+}
+
+#[test]
+fn backups_in_the_same_second_are_all_kept() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    let dir = db.path.parent().unwrap().join("backups");
+
+    let paths : Vec<_> = (0..3).map(|_| backup(&conn, &dir).unwrap()).collect();
+    assert_eq!(list(&dir).unwrap(), paths);
+    assert!(paths.iter().all(|path| verify(path).is_ok()));
+    // no partial files are left behind
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+
+    assert_eq!(prune(&dir, 1).unwrap(), paths[..2].to_vec());
+    assert_eq!(list(&dir).unwrap(), paths[2..].to_vec());
+}
+
+#[test]
+fn databases_of_any_version_are_backed_up() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    conn.execute_batch("PRAGMA user_version = 0;").unwrap();
+    let dir = db.path.parent().unwrap().join("backups");
+    let path = backup(&conn, &dir).unwrap();
+
+    // but only the current version is restored
+    match restore(&path, &db.path) {
+        Err(BackupError::SchemaVersion { expected, found: 0 }) => assert_eq!(expected, db::SCHEMA_VERSION),
+        other => panic!("expected a schema version error, got {:?}", other)
+    }
+}
+
+#[test]
+fn restores_replace_the_database() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    let dir = db.path.parent().unwrap().join("backups");
+    let before = users(&conn);
+    let path = backup(&conn, &dir).unwrap();
+
+    db::add_user(&conn, "student-1").unwrap();
+    drop(conn);
+    restore(&path, &db.path).unwrap();
+    assert_eq!(users(&db.connect()), before);
+
+    assert!(verify(&dir.join("missing")).is_err());
+    fs::write(dir.join("garbage"), b"not a database").unwrap();
+    assert!(restore(&dir.join("garbage"), &db.path).is_err());
+}
+
+#[test]
+fn scheduled_runs_keep_at_least_one_backup() {
+    let db = TempDb::new();
+    let dir = db.path.parent().unwrap().join("backups");
+
+    std::env::set_var("FPLAB_BACKUP_KEEP", "0");
+    assert!(BackupConfig::from_env().is_err());
+    std::env::set_var("FPLAB_BACKUP_KEEP", "1");
+    let config = BackupConfig { dir: dir.clone(), ..BackupConfig::from_env().unwrap() };
+    std::env::remove_var("FPLAB_BACKUP_KEEP");
+
+    run_once(&db.path, &config).unwrap();
+    let path = run_once(&db.path, &config).unwrap();
+    assert_eq!(list(&dir).unwrap(), vec![path]);
+}
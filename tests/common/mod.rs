@@ -0,0 +1,34 @@
+// crates.io
+use std::fs;
+use std::path::PathBuf;
+use uuid::Uuid;
+// local
+use fplab_server::db;
+
+// Fresh database created from sql/schema.sql and sql/data.sql,
+// deleted again when dropped
+pub struct TempDb {
+    pub path: PathBuf
+}
+
+impl TempDb {
+    pub fn new() -> Self {
+        let path = std::env::temp_dir()
+            .join(format!("fplab-test-{}", Uuid::new_v4()))
+            .join("fp.sqlite3");
+        db::create(&path).unwrap();
+        TempDb { path }
+    }
+
+    pub fn connect(&self) -> rusqlite::Connection {
+        db::open(&self.path).unwrap()
+    }
+}
+
+impl Drop for TempDb {
+    fn drop(&mut self) {
+        if let Some(dir) = self.path.parent() {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
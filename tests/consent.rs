@@ -0,0 +1,44 @@
+mod common;
+
+// crates.io
+use rusqlite::{params, Connection};
+// local
+use common::TempDb;
+use fplab_server::api::consent::{answer, has_consented, record};
+
+fn add_user(conn: &Connection, token: &str) {
+    conn.execute("INSERT INTO users (token) VALUES (?1)", params![token]).unwrap();
+}
+
+#[test]
+fn latest_answer_to_a_version_counts() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    add_user(&conn, "student-1");
+
+    assert_eq!(answer(&conn, "student-1", 1).unwrap(), None);
+    record(&conn, "student-1", 1, true, 1000).unwrap();
+    assert_eq!(answer(&conn, "student-1", 1).unwrap(), Some(true));
+    record(&conn, "student-1", 1, false, 2000).unwrap();
+    assert_eq!(answer(&conn, "student-1", 1).unwrap(), Some(false));
+
+    // a new version of the form has to be answered again
+    assert_eq!(answer(&conn, "student-1", 2).unwrap(), None);
+}
+
+#[test]
+fn only_opted_in_users_have_consented() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    add_user(&conn, "student-1");
+    add_user(&conn, "student-2");
+    add_user(&conn, "student-3");
+
+    // FPLAB_CONSENT_VERSION defaults to 1
+    record(&conn, "student-1", 1, true, 1000).unwrap();
+    record(&conn, "student-2", 1, false, 1000).unwrap();
+
+    assert!(has_consented(&conn, "student-1").unwrap());
+    assert!(!has_consented(&conn, "student-2").unwrap());
+    assert!(!has_consented(&conn, "student-3").unwrap());
+}
@@ -0,0 +1,79 @@
+// crates.io
+use actix_session::{CookieSession, Session};
+use actix_web::{http::{header, Cookie, StatusCode}, test, web, App, HttpResponse};
+// local
+use fplab_server::middleware::csrf::{same_origin, tokens_match, Csrf, COOKIE, HEADER};
+
+async fn ok(_session: Session) -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+#[test]
+fn origin_must_match_host() {
+    assert!(same_origin("http://localhost:8000", "localhost:8000"));
+    assert!(same_origin("https://fplab.eecs.umich.edu/fp/?q=1", "fplab.eecs.umich.edu"));
+    assert!(!same_origin("https://evil.example", "fplab.eecs.umich.edu"));
+    assert!(!same_origin("https://fplab.eecs.umich.edu.evil.example/", "fplab.eecs.umich.edu"));
+    assert!(!same_origin("null", "fplab.eecs.umich.edu"));
+
+    assert!(tokens_match("abc", "abc"));
+    assert!(!tokens_match("abc", "abd"));
+    assert!(!tokens_match("abc", "abcd"));
+}
+
+#[actix_rt::test]
+async fn posts_need_the_session_token() {
+    let mut app = test::init_service(
+        App::new()
+            .wrap(Csrf::new().exempt("/lti/launch/"))
+            .wrap(CookieSession::signed(&[0; 32]).name("fplab_session").secure(false))
+            .route("/page/", web::get().to(ok))
+            .route("/submit", web::post().to(ok))
+            .route("/lti/launch/", web::post().to(ok))
+    ).await;
+
+    // any page hands out the token in the session and the script cookie
+    let res = test::call_service(&mut app, test::TestRequest::get().uri("/page/").to_request()).await;
+    let cookies : Vec<Cookie> = res.response().cookies().map(|c| c.into_owned()).collect();
+    let session = cookies.iter().find(|c| c.name() == "fplab_session").unwrap().clone();
+    let token = cookies.iter().find(|c| c.name() == COOKIE).unwrap().value().to_string();
+
+    let post = |body: &str| test::TestRequest::post()
+        .uri("/submit")
+        .cookie(session.clone())
+        .header(header::HOST, "localhost:8000")
+        .set_payload(body.to_string());
+
+    // JSON without the header, or with a wrong one
+    let req = post("{}").header(header::CONTENT_TYPE, "application/json").to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::FORBIDDEN);
+    let req = post("{}").header(header::CONTENT_TYPE, "application/json").header(HEADER, "guess").to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::FORBIDDEN);
+    let req = post("{}").header(header::CONTENT_TYPE, "application/json").header(HEADER, token.as_str()).to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::OK);
+
+    // forms send the token as a field
+    let form = format!("token=student&csrf_token={}", token);
+    let req = post("token=student")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::FORBIDDEN);
+    let req = post(&form)
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::OK);
+
+    // a valid token does not help a cross-site page
+    let req = post(&form)
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header(header::ORIGIN, "https://evil.example")
+        .to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::FORBIDDEN);
+
+    // exempt routes verify requests themselves
+    let req = test::TestRequest::post()
+        .uri("/lti/launch/")
+        .header(header::ORIGIN, "https://lms.example.edu")
+        .to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::OK);
+}
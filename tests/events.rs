@@ -0,0 +1,145 @@
+mod common;
+
+// crates.io
+use actix_session::CookieSession;
+use actix_web::{http::{header, StatusCode}, test, web, App};
+use rusqlite::{Connection, NO_PARAMS};
+use serde_json::{json, Value};
+// local
+use common::TempDb;
+use fplab_server::api::events::{
+    json_config, post_events, record, schema, validate, Event, MAX_BATCH, MAX_BODY, REGISTRY
+};
+
+// unix time the tests take place at
+const NOW: i64 = 1_600_000_000;
+
+fn student(conn: &Connection) {
+    conn.execute("INSERT INTO users (token) VALUES ('student-1')", NO_PARAMS).unwrap();
+}
+
+fn events(json: &str) -> Vec<Event> {
+    serde_json::from_str(json).unwrap()
+}
+
+#[test]
+fn every_type_of_event_is_registered_once() {
+    for name in &["page_view", "hover", "quiz_interaction", "editor_edit", "visualization_step"] {
+        assert_eq!(schema(name).unwrap().name, *name);
+    }
+    assert!(schema("click").is_none());
+    for (i, registered) in REGISTRY.iter().enumerate() {
+        assert!(REGISTRY[i + 1..].iter().all(|other| other.name != registered.name));
+    }
+}
+
+#[test]
+fn well_formed_batches_are_valid() {
+    let batch = events(r#"[
+        {"type": "page_view", "client_time": 1600000000000,
+         "data": {"page": "ch04-01-what-is-ownership.html", "referrer": null}},
+        {"type": "hover", "client_time": 1600000001000,
+         "data": {"svg_name": "vis_04_01_01", "hover_item": "s", "duration_ms": 1500}},
+        {"type": "quiz_interaction", "client_time": 1600000002000,
+         "data": {"quiz": "ownership", "ques_id": 2, "action": "select", "ans_id": 1}},
+        {"type": "editor_edit", "client_time": 1600000003000,
+         "data": {"document": "main.rs", "chars_added": 12}},
+        {"type": "visualization_step", "client_time": 1600000004000,
+         "data": {"svg_name": "vis_04_01_01", "step": 3, "direction": "forward"}}
+    ]"#);
+    assert!(validate(&batch).is_empty());
+    assert!(validate(&[]).is_empty());
+}
+
+#[test]
+fn malformed_events_are_reported_by_index() {
+    let batch = events(r#"[
+        {"type": "page_view", "client_time": 1600000000000, "data": {"page": "index.html"}},
+        {"type": "click", "client_time": 1600000000000},
+        {"type": "hover", "client_time": -1, "data": {"svg_name": "vis_04_01_01"}},
+        {"type": "visualization_step", "client_time": 1600000000000,
+         "data": {"svg_name": 4, "step": -2, "speed": 1}}
+    ]"#);
+    assert_eq!(validate(&batch), vec![
+        "event 1: unknown type \"click\"",
+        "event 2: client_time must be unix time in milliseconds",
+        "event 2: hover_item is missing",
+        "event 3: svg_name must be a string",
+        "event 3: step must be a whole number from 0 to 10000",
+        "event 3: speed is not a field of visualization_step events"
+    ]);
+
+    let long = events(&format!(
+        r#"[{{"type": "page_view", "client_time": 0, "data": {{"page": "{}"}}}}]"#,
+        "a".repeat(257)
+    ));
+    assert_eq!(validate(&long), vec!["event 0: page must be at most 256 bytes"]);
+}
+
+#[test]
+fn batches_are_limited_in_size() {
+    let view = events(r#"[{"type": "page_view", "client_time": 0, "data": {"page": "index.html"}}]"#);
+    let batch = vec![view[0].clone(); MAX_BATCH + 1];
+    assert_eq!(validate(&batch).len(), 1);
+    assert!(validate(&batch[..MAX_BATCH]).is_empty());
+}
+
+#[test]
+fn batches_are_recorded_together() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    student(&conn);
+    let batch = events(r#"[
+        {"type": "page_view", "client_time": 1600000000000, "data": {"page": "index.html"}},
+        {"type": "visualization_step", "client_time": 1600000004000,
+         "data": {"svg_name": "vis_04_01_01", "step": 3}}
+    ]"#);
+    assert_eq!(record(&mut conn, "student-1", &batch, NOW).unwrap(), 2);
+
+    let rows : Vec<(String, i64, i64, String)> = conn.prepare(
+        "SELECT kind, client_time, received, data FROM events
+        WHERE token = 'student-1'
+        ORDER BY event_id"
+    ).unwrap().query_map(NO_PARAMS, |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+    }).unwrap().collect::<rusqlite::Result<_>>().unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!((rows[1].0.as_str(), rows[1].1, rows[1].2), ("visualization_step", 1_600_000_004_000, NOW));
+    let data : Value = serde_json::from_str(&rows[1].3).unwrap();
+    assert_eq!(data["step"], 3);
+
+    // nothing is recorded if any event of the batch fails
+    let n : i64 = conn.query_row("SELECT COUNT(*) FROM events", NO_PARAMS, |row| row.get(0)).unwrap();
+    assert!(record(&mut conn, "no-such-user", &batch, NOW).is_err());
+    let after : i64 = conn.query_row("SELECT COUNT(*) FROM events", NO_PARAMS, |row| row.get(0)).unwrap();
+    assert_eq!(n, after);
+}
+
+#[actix_rt::test]
+async fn large_valid_batches_reach_the_handler() {
+    let mut app = test::init_service(
+        App::new()
+            .wrap(CookieSession::signed(&[0; 32]).secure(false))
+            .service(
+                web::resource("/events")
+                    .app_data(json_config())
+                    .route(web::post().to(post_events))
+            )
+    ).await;
+
+    // the longest edits allowed, in source that escapes to more bytes than it holds
+    let content = "let s = \"a\";\n".repeat(64 * 1024 / 14);
+    let edit = json!({"type": "editor_edit", "client_time": 1600000000000u64,
+        "data": {"document": "main.rs", "content": content}});
+    let batch = Value::Array(vec![edit; MAX_BATCH]).to_string();
+    assert!(validate(&serde_json::from_str::<Vec<Event>>(&batch).unwrap()).is_empty());
+    assert!(batch.len() > 32 * 1024 && batch.len() <= MAX_BODY);
+
+    // parsed, then refused for want of a login rather than its size
+    let req = test::TestRequest::post()
+        .uri("/events")
+        .header(header::CONTENT_TYPE, "application/json")
+        .set_payload(batch)
+        .to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,189 @@
+-- sql/schema.sql and sql/data.sql as deployed before schema versions
+-- were recorded (version 0), see tests/upgrade.rs
+
+PRAGMA journal_mode=WAL;
+PRAGMA foreign_keys = ON;
+
+CREATE TABLE users (
+  token VARCHAR(20) NOT NULL, -- username, at most 20 chars, primary key
+  curr_ques INTEGER DEFAULT 0,
+	created DATETIME DEFAULT CURRENT_TIMESTAMP, -- created, DATETIME type, automatically set by SQL engine to current date/time
+	PRIMARY KEY (token)
+);
+
+/* QUIZ RELATED */
+
+CREATE TABLE responses (
+  token INTEGER NOT NULL,
+  question_id INTEGER NOT NULL,
+  answer INTEGER NOT NULL DEFAULT -2, -- default is -2 (not submited)
+  time_elapsed INTEGER NOT NULL DEFAULT 0, -- time in ms
+  hover_time INTEGER NOT NULL DEFAULT 0, -- time spent hovering in ms
+  free_response TEXT NOT NULL DEFAULT '',
+  PRIMARY KEY(token, question_id)
+  FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE TABLE questions (
+  question_id INTEGER NOT NULL,
+  answer INTEGER NOT NULL,
+  filename VARCHAR(20) NOT NULL,
+  prompt VARCHAR(256) NOT NULL,
+  contains_fr INTEGER NOT NULL DEFAULT 0, -- Boolean: contains free response, default is no
+  PRIMARY KEY (question_id)
+);
+
+CREATE TABLE choices (
+  question_id INTEGER NOT NULL,
+  ans_id INTEGER NOT NULL,
+  choice_text VARCHAR(256) NOT NULL,
+  PRIMARY KEY (question_id, ans_id)
+  FOREIGN KEY (question_id) REFERENCES questions(question_id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+/* ASSIGNMENT-RELATED */
+
+CREATE TABLE edit_states (
+  token INTEGER NOT NULL,
+  edit_state TEXT NOT NULL DEFAULT '',
+  FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE TABLE scores (
+  token INTEGER NOT NULL,
+  problem_id INTEGER NOT NULL, /* 0 - 5; 6 problems total*/
+  score INTEGER NOT NULL DEFAULT 0, -- default is 0, if answer compiled successfully then 1
+  PRIMARY KEY(token, problem_id)
+  FOREIGN KEY (token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE TABLE user_hover (
+  token INTEGER NOT NULL,
+  svg_name VARCHAR(256) NOT NULL,
+  hover_item VARCHAR(256) NOT NULL,
+  hover_times INTEGER NOT NULL,
+  PRIMARY KEY(token, svg_name, hover_item),
+  FOREIGN KEY(token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+-- CREATE TABLE user_page (
+--   token INTEGER NOT NULL,
+--   page_item VARCHAR(256) NOT NULL,
+--   page_hover INTEGER NOT NULL,
+--   page_visit INTEGER NOT NULL,
+--   PRIMARY KEY(token, page_item),
+--   FOREIGN KEY(token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE
+-- );
+
+CREATE TABLE user_page (
+  ID INTEGER PRIMARY KEY AUTOINCREMENT,
+  token INTEGER NOT NULL,
+  page_item VARCHAR(256) NOT NULL,
+  page_hover INTEGER NOT NULL,
+  FOREIGN KEY(token) REFERENCES users(token) ON UPDATE CASCADE ON DELETE CASCADE
+);
+PRAGMA foreign_keys = ON;
+
+-- INITIALIZE USERS TABLE (with admins)
+INSERT INTO users(token)
+VALUES ('f6e035e8c2a2898938e59e5a361b9faddf4e68e');
+INSERT INTO users(token)
+VALUES ('59e5a361b9faddf4e68ef6e035e8c2a2898938e');
+
+-- INITIALIZE QUESTIONS TABLE (with answers)
+INSERT INTO questions (question_id, answer, filename, prompt, contains_fr)
+VALUES (0, 0, 'vis_04_01_01', 'What qid is this (0)?', 1);
+
+INSERT INTO questions (question_id, answer, filename, prompt, contains_fr)
+VALUES (1, 0, 'vis_04_01_01', 'What qid is this (1)?', 1);
+
+INSERT INTO questions (question_id, answer, filename, prompt, contains_fr)
+VALUES (2, 0, 'vis_04_01_02', 'What qid is this (2)?', 1);
+
+INSERT INTO questions (question_id, answer, filename, prompt, contains_fr)
+VALUES (3, 0, 'vis_04_01_03', 'What qid is this (3)?', 1);
+
+INSERT INTO questions (question_id, answer, filename, prompt, contains_fr)
+VALUES (4, 0, 'vis_04_01_04', 'What qid is this (4)?', 1);
+
+INSERT INTO questions (question_id, answer, filename, prompt)
+VALUES (5, 0, 'vis_04_01_05', 'What qid is this (5)?');
+
+INSERT INTO questions (question_id, answer, filename, prompt)
+VALUES (6, 0, 'vis_04_01_06', 'What qid is this (6)?');
+
+INSERT INTO questions (question_id, answer, filename, prompt)
+VALUES (7, 0, 'vis_04_01_07', 'What qid is this (7)?');
+
+INSERT INTO questions (question_id, answer, filename, prompt)
+VALUES (8, 0, 'vis_04_01_08', 'What qid is this (8)?');
+
+INSERT INTO questions (question_id, answer, filename, prompt)
+VALUES (9, 0, 'vis_04_01_09', 'What qid is this (9)?');
+
+INSERT INTO questions (question_id, answer, filename, prompt)
+VALUES (10, 0, 'vis_04_01_10', 'What qid is this (#)?');
+
+-- INITIALIZE MULTIPLE CHOICE TABLE (with answers)
+INSERT INTO choices (question_id, ans_id, choice_text)
+VALUES (0, 0, 'option 0');
+
+INSERT INTO choices (question_id, ans_id, choice_text)
+VALUES (0, 1, 'option 1');
+
+INSERT INTO choices (question_id, ans_id, choice_text)
+VALUES (0, 2, 'option 2');
+
+INSERT INTO choices (question_id, ans_id, choice_text)
+VALUES (0, 3, 'option 3');
+
+INSERT INTO choices (question_id, ans_id, choice_text)
+VALUES (1, 0, 'option 0');
+
+INSERT INTO choices (question_id, ans_id, choice_text)
+VALUES (1, 1, 'option 1');
+
+INSERT INTO choices (question_id, ans_id, choice_text)
+VALUES (1, 2, 'option 2');
+
+INSERT INTO choices (question_id, ans_id, choice_text)
+VALUES (1, 3, 'option 3');
+
+INSERT INTO choices (question_id, ans_id, choice_text)
+VALUES (2, 0, 'option 0');
+
+INSERT INTO choices (question_id, ans_id, choice_text)
+VALUES (2, 1, 'option 1');
+
+INSERT INTO choices (question_id, ans_id, choice_text)
+VALUES (2, 2, 'option 2');
+
+INSERT INTO choices (question_id, ans_id, choice_text)
+VALUES (2, 3, 'option 3');
+
+INSERT INTO choices (question_id, ans_id, choice_text)
+VALUES (3, 0, 'option 0');
+
+INSERT INTO choices (question_id, ans_id, choice_text)
+VALUES (3, 1, 'option 1');
+
+INSERT INTO choices (question_id, ans_id, choice_text)
+VALUES (4, 0, 'option 4');
+
+INSERT INTO choices (question_id, ans_id, choice_text)
+VALUES (5, 0, 'option 5');
+
+INSERT INTO choices (question_id, ans_id, choice_text)
+VALUES (6, 0, 'option 6');
+
+INSERT INTO choices (question_id, ans_id, choice_text)
+VALUES (7, 0, 'option 7');
+
+INSERT INTO choices (question_id, ans_id, choice_text)
+VALUES (8, 0, 'option 8');
+
+INSERT INTO choices (question_id, ans_id, choice_text)
+VALUES (9, 0, 'option 9');
+
+INSERT INTO choices (question_id, ans_id, choice_text)
+VALUES (10, 0, 'option 10');
@@ -0,0 +1,150 @@
+mod common;
+
+// crates.io
+use rusqlite::{Connection, NO_PARAMS};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+// local
+use common::TempDb;
+use fplab_server::db;
+
+// run the fpdb binary against the database at path
+fn fpdb(path: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_fpdb"))
+        .arg("--db")
+        .arg(path)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+// path of a database that does not exist yet, deleted along with db
+fn new_path(db: &TempDb, name: &str) -> PathBuf {
+    db.path.with_file_name(name)
+}
+
+fn count(conn: &Connection, sql: &str) -> i64 {
+    conn.query_row(sql, NO_PARAMS, |row| row.get(0)).unwrap()
+}
+
+#[test]
+fn create_refuses_to_overwrite_a_database() {
+    let db = TempDb::new();
+    let path = new_path(&db, "cli.sqlite3");
+
+    assert!(fpdb(&path, &["create"]).status.success());
+    let conn = db::open(&path).unwrap();
+    assert_eq!(db::schema_version(&conn).unwrap(), db::SCHEMA_VERSION);
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM questions"), 11);
+    conn.execute("INSERT INTO users (token) VALUES ('student-1')", NO_PARAMS).unwrap();
+
+    assert!(!fpdb(&path, &["create"]).status.success());
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM users WHERE token = 'student-1'"), 1);
+}
+
+#[test]
+fn failing_seed_files_change_nothing() {
+    let db = TempDb::new();
+    let dir = db.path.parent().unwrap();
+    let good = dir.join("good.sql");
+    let bad = dir.join("bad.sql");
+    fs::write(&good, "INSERT INTO users (token) VALUES ('student-1');").unwrap();
+    fs::write(&bad, "INSERT INTO users (token) VALUES ('student-2');\nINSERT INTO nowhere VALUES (1);").unwrap();
+
+    let output = fpdb(&db.path, &["seed", bad.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(fpdb(&db.path, &["seed", good.to_str().unwrap()]).status.success());
+
+    let conn = db.connect();
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM users WHERE token = 'student-1'"), 1);
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM users WHERE token = 'student-2'"), 0);
+}
+
+#[test]
+fn add_user_takes_the_tokens_sign_up_does() {
+    let db = TempDb::new();
+
+    // one bad token and none are added
+    let output = fpdb(&db.path, &["add-user", "student-1", "bad token"]);
+    assert!(!output.status.success());
+    assert!(!fpdb(&db.path, &["add-user", &"x".repeat(21)]).status.success());
+    assert!(!fpdb(&db.path, &["add-user", ""]).status.success());
+    assert_eq!(count(&db.connect(), "SELECT COUNT(*) FROM users WHERE token = 'student-1'"), 0);
+
+    let output = fpdb(&db.path, &["add-user", "student-1", "student-2"]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "+ added student-1\n+ added student-2\n");
+    assert_eq!(stdout(&fpdb(&db.path, &["add-user", "student-1"])), "  student-1 already exists\n");
+}
+
+#[test]
+fn remove_user_deletes_their_data() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    db::add_user(&conn, "student-1").unwrap();
+    conn.execute_batch("
+        INSERT INTO responses (token, quiz_id, question_id, answer) VALUES ('student-1', 1, 0, 1);
+        INSERT INTO user_hover (token, svg_name, hover_item, hover_times) VALUES ('student-1', 'vis_04_01_01', 's', 3);
+    ").unwrap();
+
+    let output = fpdb(&db.path, &["remove-user", "student-1", "student-2"]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "+ removed student-1\n  student-2 does not exist\n");
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM responses"), 0);
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM user_hover"), 0);
+}
+
+#[test]
+fn stats_count_collected_data() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    let before = db::stats(&conn).unwrap();
+    db::add_user(&conn, "student-1").unwrap();
+    conn.execute_batch("
+        INSERT INTO responses (token, quiz_id, question_id, answer) VALUES ('student-1', 1, 0, 1);
+        INSERT INTO responses (token, quiz_id, question_id) VALUES ('student-1', 1, 1);
+        INSERT INTO user_hover (token, svg_name, hover_item, hover_times) VALUES ('student-1', 'vis_04_01_01', 's', 3);
+        INSERT INTO scores (token, problem_id, score) VALUES ('student-1', 0, 1), ('student-1', 1, 0);
+    ").unwrap();
+
+    let s = db::stats(&conn).unwrap();
+    assert_eq!(s.users, before.users + 1);
+    assert_eq!(s.questions, 11);
+    assert_eq!((s.responses, s.answered), (2, 1));
+    assert_eq!(s.hover_events, 3);
+    assert_eq!(s.problems_passed, 1);
+
+    let output = stdout(&fpdb(&db.path, &["stats"]));
+    assert!(output.contains(&format!("users:           {}\n", s.users)));
+    assert!(output.contains("responses:       2 (1 answered)\n"));
+}
+
+#[test]
+fn dumps_recreate_the_database() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    db::add_user(&conn, "student-1").unwrap();
+    conn.execute(
+        "INSERT INTO responses (token, quiz_id, question_id, answer, free_response)
+        VALUES ('student-1', 1, 0, 1, 'it''s moved')",
+        NO_PARAMS
+    ).unwrap();
+
+    let output = fpdb(&db.path, &["dump"]);
+    assert!(output.status.success());
+    let copy = db::open(new_path(&db, "copy.sqlite3")).unwrap();
+    copy.execute_batch(&stdout(&output)).unwrap();
+
+    let mut original = Vec::new();
+    db::dump::dump(&conn, &mut original).unwrap();
+    let mut replayed = Vec::new();
+    db::dump::dump(&copy, &mut replayed).unwrap();
+    assert_eq!(String::from_utf8(replayed).unwrap(), String::from_utf8(original).unwrap());
+    assert_eq!(db::schema_version(&copy).unwrap(), db::SCHEMA_VERSION);
+    assert_eq!(count(&copy, "SELECT COUNT(*) FROM responses WHERE free_response = 'it''s moved'"), 1);
+}
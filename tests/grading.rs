@@ -0,0 +1,108 @@
+mod common;
+
+// crates.io
+use rusqlite::{params, Connection, NO_PARAMS};
+// local
+use common::TempDb;
+use fplab_server::api::grading::{
+    feedback, grade, graded_response, queue, release, rubric, save_grade, set_rubric, submissions,
+    GradeForm, RubricItem
+};
+use fplab_server::route::shuffle::arrangement;
+
+// quiz created by sql/data.sql with every question in order
+const OWNERSHIP: i64 = 1;
+// unix time the tests take place at
+const NOW: i64 = 1_600_000_000;
+
+// students answering free response question 0, and an instructor
+fn class(conn: &Connection) {
+    conn.execute("INSERT INTO users (token, admin) VALUES ('teacher', 1)", NO_PARAMS).unwrap();
+    for (token, text) in [("student-1", "s is moved"), ("student-2", "it prints 5"), ("student-3", "")] {
+        conn.execute("INSERT INTO users (token) VALUES (?1)", params![token]).unwrap();
+        conn.execute(
+            "INSERT INTO responses (token, quiz_id, question_id, answer, free_response)
+            VALUES (?1, 1, 0, 0, ?2)",
+            params![token, text]
+        ).unwrap();
+    }
+}
+
+fn item(description: &str, points: i64) -> RubricItem {
+    RubricItem { item_id: 0, description: description.to_string(), points }
+}
+
+#[test]
+fn instructors_grade_free_responses_with_a_rubric() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    class(&conn);
+    set_rubric(&mut conn, 0, &[item("Mentions the move", 2), item("Names the owner", 1), item("Wrong output", -1)]).unwrap();
+    let items = rubric(&conn, 0).unwrap();
+    assert_eq!(items.iter().map(|i| i.points).collect::<Vec<_>>(), vec![2, 1, -1]);
+
+    // only non-empty free responses are queued
+    let pending = submissions(&conn, 0).unwrap();
+    assert_eq!(pending.iter().map(|s| s.token.as_str()).collect::<Vec<_>>(), vec!["student-1", "student-2"]);
+    assert!(pending.iter().all(|s| s.grade.is_none()));
+
+    // score defaults to the items awarded
+    let form = GradeForm { quiz_id: OWNERSHIP, items: vec![items[0].item_id, items[1].item_id], score: None, comment: "Good".to_string() };
+    save_grade(&mut conn, "student-1", 0, &form, "teacher", NOW).unwrap();
+    let graded = grade(&conn, "student-1", OWNERSHIP, 0).unwrap().unwrap();
+    assert_eq!((graded.score, graded.released), (3, false));
+    assert_eq!(graded.grader.as_deref(), Some("teacher"));
+    let form = GradeForm { quiz_id: OWNERSHIP, items: vec![items[2].item_id], score: Some(0), comment: String::new() };
+    save_grade(&mut conn, "student-2", 0, &form, "teacher", NOW).unwrap();
+    assert_eq!(grade(&conn, "student-2", OWNERSHIP, 0).unwrap().unwrap().score, 0);
+
+    let entry = queue(&conn).unwrap().into_iter().find(|e| e.question_id == 0).unwrap();
+    assert_eq!((entry.responses, entry.graded, entry.released), (2, 2, 0));
+
+    // editing the rubric keeps the items still in it awarded
+    let edited = RubricItem { points: 3, ..items[0].clone() };
+    set_rubric(&mut conn, 0, &[item("Explains the borrow", 1), edited, items[2].clone()]).unwrap();
+    let items_after = rubric(&conn, 0).unwrap();
+    assert_eq!(items_after[1].item_id, items[0].item_id);
+    assert_eq!(items_after.iter().map(|i| i.points).collect::<Vec<_>>(), vec![1, 3, -1]);
+    let regraded = grade(&conn, "student-1", OWNERSHIP, 0).unwrap().unwrap();
+    assert_eq!((regraded.score, regraded.items), (3, vec![items[0].item_id]));
+    assert_eq!(grade(&conn, "student-2", OWNERSHIP, 0).unwrap().unwrap().items, vec![items[2].item_id]);
+}
+
+#[test]
+fn graded_responses_are_locked() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    class(&conn);
+    assert_eq!(graded_response(&conn, "student-1", OWNERSHIP, 0).unwrap(), None);
+
+    let form = GradeForm { quiz_id: OWNERSHIP, items: Vec::new(), score: Some(1), comment: String::new() };
+    save_grade(&mut conn, "student-1", 0, &form, "teacher", NOW).unwrap();
+    assert_eq!(graded_response(&conn, "student-1", OWNERSHIP, 0).unwrap().as_deref(), Some("s is moved"));
+    assert_eq!(graded_response(&conn, "student-2", OWNERSHIP, 0).unwrap(), None);
+}
+
+#[test]
+fn students_see_feedback_once_released() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    class(&conn);
+    set_rubric(&mut conn, 0, &[item("Mentions the move", 2), item("Names the owner", 1)]).unwrap();
+    let items = rubric(&conn, 0).unwrap();
+    let form = GradeForm { quiz_id: OWNERSHIP, items: vec![items[0].item_id], score: None, comment: "Who owns it?".to_string() };
+    save_grade(&mut conn, "student-1", 0, &form, "teacher", NOW).unwrap();
+
+    assert!(feedback(&conn, "student-1", OWNERSHIP).unwrap().is_empty());
+    assert_eq!(release(&conn, 0).unwrap(), 1);
+    assert_eq!(release(&conn, 0).unwrap(), 0);
+
+    let shown = feedback(&conn, "student-1", OWNERSHIP).unwrap();
+    let position = arrangement(&conn, "student-1", OWNERSHIP).unwrap().position(0).unwrap();
+    assert_eq!(shown.len(), 1);
+    assert_eq!(shown[0].qid, position);
+    assert_eq!((shown[0].score, shown[0].max_score), (2, 3));
+    assert_eq!(shown[0].items, vec!["Mentions the move".to_string()]);
+    assert_eq!(shown[0].comment, "Who owns it?");
+    assert!(feedback(&conn, "student-2", OWNERSHIP).unwrap().is_empty());
+}
@@ -0,0 +1,65 @@
+mod common;
+
+// crates.io
+use rusqlite::{params, Connection, NO_PARAMS};
+// local
+use common::TempDb;
+use fplab_server::route::history::{history, log};
+use fplab_server::db::answer::Answer;
+
+// quiz created by sql/data.sql with every question in order
+const OWNERSHIP: i64 = 1;
+// unix time the tests take place at
+const NOW: i64 = 1_600_000_000;
+
+// answer question 3 the way record_response does
+fn answer(conn: &Connection, answer: &Answer, correct: bool, time: i64, now: i64) {
+    let (aid, response) = answer.to_columns();
+    conn.execute(
+        "INSERT INTO responses (token, quiz_id, question_id, answer, response, correct, time_elapsed)
+        VALUES ('student-1', 1, 3, ?1, ?2, ?3, ?4)
+        ON CONFLICT (token, quiz_id, question_id) DO UPDATE SET
+            answer = excluded.answer, response = excluded.response,
+            correct = excluded.correct, time_elapsed = time_elapsed + excluded.time_elapsed",
+        params![aid, response, correct, time]
+    ).unwrap();
+    log(conn, "student-1", OWNERSHIP, 3, now).unwrap();
+}
+
+#[test]
+fn every_answer_is_kept_in_order() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    conn.execute("INSERT INTO users (token) VALUES ('student-1')", NO_PARAMS).unwrap();
+    assert!(history(&conn, "student-1", OWNERSHIP).unwrap().is_empty());
+
+    answer(&conn, &Answer::Index(1), false, 4000, NOW);
+    answer(&conn, &Answer::Indices(vec![0, 2]), true, 1500, NOW + 30);
+    answer(&conn, &Answer::Index(0), true, 500, NOW + 45);
+
+    let events = history(&conn, "student-1", OWNERSHIP).unwrap();
+    assert!(events.iter().all(|e| e.question_id == 3 && e.quiz_id == Some(OWNERSHIP)));
+    let answers : Vec<Answer> = events.iter().map(|e| e.answer.clone()).collect();
+    assert_eq!(answers, vec![Answer::Index(1), Answer::Indices(vec![0, 2]), Answer::Index(0)]);
+    assert_eq!(events.iter().map(|e| e.correct).collect::<Vec<_>>(), vec![false, true, true]);
+    assert_eq!(events.iter().map(|e| e.time_elapsed).collect::<Vec<_>>(), vec![4000, 5500, 6000]);
+    assert_eq!(events.iter().map(|e| e.created).collect::<Vec<_>>(), vec![NOW, NOW + 30, NOW + 45]);
+
+    // responses only keeps the latest answer
+    let latest : i64 = conn.query_row(
+        "SELECT answer FROM responses WHERE token = 'student-1' AND question_id = 3",
+        NO_PARAMS,
+        |row| row.get(0)
+    ).unwrap();
+    assert_eq!(latest, 0);
+
+    // history outlives the quiz it was recorded in
+    conn.execute("DELETE FROM quizzes WHERE quiz_id = ?1", params![OWNERSHIP]).unwrap();
+    assert!(history(&conn, "student-1", OWNERSHIP).unwrap().is_empty());
+    let kept : i64 = conn.query_row(
+        "SELECT COUNT(*) FROM response_events WHERE token = 'student-1' AND quiz_id IS NULL",
+        NO_PARAMS,
+        |row| row.get(0)
+    ).unwrap();
+    assert_eq!(kept, 3);
+}
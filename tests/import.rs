@@ -0,0 +1,387 @@
+mod common;
+
+// crates.io
+use std::fs;
+use std::path::PathBuf;
+// local
+use common::TempDb;
+use fplab_server::db::{self, QuestionSpec};
+use fplab_server::db::import::{
+    apply, diff, parse, validate, validate_question, Change, Format, QuizFile, Visualizations
+};
+use fplab_server::db::answer::{Answer, Kind};
+use fplab_server::route::quiz::find_quiz;
+
+const TOML: &str = r#"
+slug = "borrowing"
+title = "References and Borrowing"
+chapter = "ch04-02-references-and-borrowing.html"
+
+[[questions]]
+id = 100
+file = "vis_04_02_01"
+prompt = "Does this compile?"
+choices = ["Yes", "No"]
+answer = 1
+explanation = "r1 and r2 borrow s mutably at once"
+"#;
+
+const YAML: &str = r#"
+slug: borrowing
+title: References and Borrowing
+chapter: ch04-02-references-and-borrowing.html
+questions:
+  - id: 100
+    file: vis_04_02_01
+    prompt: Does this compile?
+    choices: ["Yes", "No"]
+    answer: 1
+    explanation: r1 and r2 borrow s mutably at once
+"#;
+
+const MARKDOWN: &str = "---
+slug: borrowing
+title: References and Borrowing
+chapter: ch04-02-references-and-borrowing.html
+---
+
+## Does this compile?
+id: 100
+file: vis_04_02_01
+
+- [ ] Yes
+- [x] No
+
+> r1 and r2 borrow s mutably at once
+";
+
+// visualizations of tests/fixtures, vis_04_01_03 has 8 lines
+fn visualizations() -> Visualizations {
+    Visualizations { dir: PathBuf::from("tests/fixtures") }
+}
+
+fn quiz() -> QuizFile {
+    parse(TOML, Format::Toml).unwrap()
+}
+
+#[test]
+fn formats_parse_to_the_same_quiz() {
+    let quiz = quiz();
+    assert_eq!(quiz.slug, "borrowing");
+    assert_eq!(quiz.questions[0], QuestionSpec {
+        question_id: 100,
+        kind: Kind::Choice,
+        answer: Answer::Index(1),
+        filename: "vis_04_02_01".to_string(),
+        prompt: "Does this compile?".to_string(),
+        contains_fr: false,
+        choices: vec!["Yes".to_string(), "No".to_string()],
+        explanation: "r1 and r2 borrow s mutably at once".to_string(),
+        choice_explanations: Vec::new(),
+        pool: None
+    });
+    assert_eq!(parse(YAML, Format::Yaml).unwrap(), quiz);
+    assert_eq!(parse(MARKDOWN, Format::Markdown).unwrap(), quiz);
+
+    // the sample quiz shipped with the repository stays valid
+    let sample = parse(&fs::read_to_string("quizzes/example.md").unwrap(), Format::Markdown).unwrap();
+    assert_eq!(validate(&sample, &visualizations()), Vec::<String>::new());
+}
+
+#[test]
+fn markdown_errors_name_the_line() {
+    let twice = MARKDOWN.replace("- [ ] Yes", "- [x] Yes");
+    assert_eq!(parse(&twice, Format::Markdown).unwrap_err(), "line 12: more than one answer is marked");
+    let unknown = MARKDOWN.replace("id: 100", "points: 2");
+    assert_eq!(parse(&unknown, Format::Markdown).unwrap_err(), "line 8: unknown field points");
+}
+
+#[test]
+fn validate_lists_every_problem() {
+    let mut quiz = quiz();
+    assert!(validate(&quiz, &visualizations()).is_empty());
+
+    quiz.slug = "Borrowing!".to_string();
+    let mut copy = quiz.questions[0].clone();
+    copy.answer = Answer::Index(2);
+    copy.choices.push("no".to_string());
+    copy.choices.push("no".to_string());
+    quiz.questions.push(copy);
+    let errors = validate(&quiz, &visualizations());
+    assert_eq!(errors.len(), 3, "{:?}", errors);
+    assert!(errors[0].starts_with("slug"));
+    assert_eq!(errors[1], "question 100: id is used more than once");
+    assert_eq!(errors[2], "question 100: has duplicate choices");
+}
+
+#[test]
+fn dry_run_diff_leaves_the_database_unchanged() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    let mut quiz = quiz();
+
+    let changes = diff(&conn, &quiz).unwrap();
+    assert_eq!(changes, vec![
+        Change::AddQuiz { slug: "borrowing".to_string() },
+        Change::AddQuestion { question_id: 100 }
+    ]);
+    assert_eq!(find_quiz(&conn, "borrowing").unwrap(), None);
+    assert_eq!(db::load_question(&conn, 100).unwrap(), None);
+
+    apply(&mut conn, &quiz).unwrap();
+    assert!(diff(&conn, &quiz).unwrap().is_empty());
+    let quiz_id = find_quiz(&conn, "borrowing").unwrap().unwrap();
+    assert_eq!(db::quiz_question_ids(&conn, quiz_id).unwrap(), vec![100]);
+    assert_eq!(db::load_question(&conn, 100).unwrap().as_ref(), Some(&quiz.questions[0]));
+
+    quiz.title = "Borrowing".to_string();
+    quiz.questions[0].choices.push("Only in release mode".to_string());
+    assert_eq!(diff(&conn, &quiz).unwrap(), vec![
+        Change::UpdateQuiz { slug: "borrowing".to_string(), fields: vec!["title"] },
+        Change::UpdateQuestion { question_id: 100, fields: vec!["choices"] }
+    ]);
+}
+
+#[test]
+fn removing_a_question_closes_the_gap_in_its_quizzes() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    db::create_quiz(&mut conn, "short", "Short", &[3, 1, 2]).unwrap();
+    let quiz_id = find_quiz(&conn, "short").unwrap().unwrap();
+
+    assert!(db::remove_question(&mut conn, 1).unwrap());
+    assert!(!db::remove_question(&mut conn, 1).unwrap());
+    assert_eq!(db::quiz_question_ids(&conn, quiz_id).unwrap(), vec![3, 2]);
+}
+
+#[test]
+fn answered_questions_keep_how_they_are_graded() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    let old = db::load_question(&conn, 1).unwrap().unwrap();
+
+    // rewording is fine, anything stored answers refer to is not
+    let reworded = QuestionSpec { prompt: "Reworded".to_string(), ..old.clone() };
+    assert!(!db::regrades(&old, &reworded));
+    assert!(db::regrades(&old, &QuestionSpec { answer: Answer::Index(2), ..old.clone() }));
+    assert!(db::regrades(&old, &QuestionSpec { kind: Kind::Ordering, ..old.clone() }));
+    let mut fewer = old.clone();
+    fewer.choices.pop();
+    assert!(db::regrades(&old, &fewer));
+
+    assert_eq!(db::num_answered(&conn, 1).unwrap(), 0);
+    conn.execute("INSERT INTO users (token) VALUES ('student-1'), ('student-2')", rusqlite::NO_PARAMS).unwrap();
+    conn.execute(
+        "INSERT INTO responses (token, quiz_id, question_id, answer)
+        VALUES ('student-1', 1, 1, 0), ('student-2', 1, 1, -2)",
+        rusqlite::NO_PARAMS
+    ).unwrap();
+    assert_eq!(db::num_answered(&conn, 1).unwrap(), 1);
+}
+
+#[test]
+fn imports_do_not_regrade_answered_questions() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    let mut quiz = quiz();
+    apply(&mut conn, &quiz).unwrap();
+    conn.execute("INSERT INTO users (token) VALUES ('student-1')", rusqlite::NO_PARAMS).unwrap();
+    conn.execute(
+        "INSERT INTO responses (token, quiz_id, question_id, answer, correct)
+        VALUES ('student-1', 2, 100, 1, 1)",
+        rusqlite::NO_PARAMS
+    ).unwrap();
+    let stored = db::load_question(&conn, 100).unwrap();
+
+    quiz.title = "Borrowing".to_string();
+    quiz.questions[0].answer = Answer::Index(0);
+    let changes = vec![
+        Change::UpdateQuiz { slug: "borrowing".to_string(), fields: vec!["title"] },
+        Change::Regrade { question_id: 100, answered: 1 }
+    ];
+    assert_eq!(diff(&conn, &quiz).unwrap(), changes);
+
+    // nothing of the file is written
+    assert_eq!(apply(&mut conn, &quiz).unwrap(), changes);
+    assert_eq!(db::load_question(&conn, 100).unwrap(), stored);
+    let title : String = conn.query_row(
+        "SELECT title FROM quizzes WHERE slug = 'borrowing'",
+        rusqlite::NO_PARAMS, |row| row.get(0)
+    ).unwrap();
+    assert_eq!(title, "References and Borrowing");
+    assert_eq!(db::import_questions(&mut conn, &quiz.questions).unwrap(), vec![100]);
+    assert_eq!(db::load_question(&conn, 100).unwrap(), stored);
+
+    // rewording still goes through
+    quiz.questions[0].answer = Answer::Index(1);
+    quiz.questions[0].prompt = "Does this program compile?".to_string();
+    assert!(!apply(&mut conn, &quiz).unwrap().iter().any(Change::is_conflict));
+    assert_eq!(db::load_question(&conn, 100).unwrap().unwrap().prompt, "Does this program compile?");
+}
+
+const KINDS: &str = "---
+slug: kinds
+title: Every kind of question
+---
+
+## Which types implement Copy?
+id: 200
+file: vis_04_01_01
+kind: multi-select
+
+- [x] i32
+- [ ] String
+- [x] bool
+
+## What does the program print?
+id: 201
+file: vis_04_01_02
+kind: output
+
+```
+hello
+
+world
+```
+
+## Which line fails to compile?
+id: 202
+file: vis_04_01_03
+kind: compile_fail
+line: 7
+
+## Put the drops in order
+id: 203
+file: vis_04_01_04
+kind: ordering
+order: 2, 0, 1
+
+- [ ] s1
+- [ ] s2
+- [ ] s3
+";
+
+#[test]
+fn markdown_writes_every_kind_of_question() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    let quiz = parse(KINDS, Format::Markdown).unwrap();
+    assert_eq!(validate(&quiz, &visualizations()), Vec::<String>::new());
+
+    let kinds : Vec<(Kind, Answer)> = quiz.questions.iter().map(|q| (q.kind, q.answer.clone())).collect();
+    assert_eq!(kinds, vec![
+        (Kind::MultiSelect, Answer::Indices(vec![0, 2])),
+        (Kind::Output, Answer::Text("hello\n\nworld\n".to_string())),
+        (Kind::CompileFail, Answer::Index(7)),
+        (Kind::Ordering, Answer::Indices(vec![2, 0, 1]))
+    ]);
+
+    apply(&mut conn, &quiz).unwrap();
+    for q in &quiz.questions {
+        assert_eq!(db::load_question(&conn, q.question_id).unwrap().as_ref(), Some(q));
+    }
+
+    let late = KINDS.replace("kind: ordering\norder: 2, 0, 1\n\n- [ ] s1", "order: 2, 0, 1\n\n- [ ] s1\nkind: ordering");
+    assert!(parse(&late, Format::Markdown).unwrap_err().ends_with("kind must come before the choices"));
+    let open = KINDS.replace("world\n```", "world");
+    assert_eq!(parse(&open, Format::Markdown).unwrap_err(), "output block is not closed with ```");
+}
+
+#[test]
+fn each_kind_validates_its_answer() {
+    let mut quiz = parse(KINDS, Format::Markdown).unwrap();
+    let [select, output, line, order] = &mut quiz.questions[..] else { unreachable!() };
+
+    select.answer = Answer::Indices(Vec::new());
+    assert_eq!(validate_question(select, &visualizations()), vec!["question 200: no answer is marked"]);
+    output.answer = Answer::Text(" \n".to_string());
+    assert_eq!(validate_question(output, &visualizations()), vec!["question 201: expected output is empty"]);
+    line.choices.push("fn main() {}".to_string());
+    line.answer = Answer::Index(0);
+    assert_eq!(validate_question(line, &visualizations()), vec![
+        "question 202: compile_fail questions have no choices",
+        "question 202: answer must be a line number from 1"
+    ]);
+    order.answer = Answer::Indices(vec![0, 1]);
+    assert_eq!(validate_question(order, &visualizations()), vec!["question 203: answer must list every choice once"]);
+}
+
+#[test]
+fn compile_fail_lines_are_within_the_visualization() {
+    let mut quiz = parse(KINDS, Format::Markdown).unwrap();
+    let line = &mut quiz.questions[2];
+    assert_eq!(visualizations().source_lines("vis_04_01_03"), Some(8));
+
+    line.answer = Answer::Index(8);
+    assert!(validate_question(line, &visualizations()).is_empty());
+    line.answer = Answer::Index(9);
+    assert_eq!(validate_question(line, &visualizations()), vec![
+        "question 202: line 9 is past the end of vis_04_01_03, which has 8 lines"
+    ]);
+
+    // visualizations that cannot be read are not checked
+    line.filename = "vis_99_01_01".to_string();
+    assert_eq!(visualizations().source_lines("vis_99_01_01"), None);
+    assert!(validate_question(line, &visualizations()).is_empty());
+}
+
+#[test]
+fn only_the_first_question_of_a_pool_joins_the_quiz() {
+    let md = format!("{}
+## Does this compile now?
+id: 101
+file: vis_04_02_02
+pool: two-mutable-borrows
+
+- [x] Yes
+- [ ] No
+
+## Does this compile as well?
+id: 102
+file: vis_04_02_03
+pool: two-mutable-borrows
+
+- [ ] Yes
+- [x] No
+", MARKDOWN);
+    let quiz = parse(&md, Format::Markdown).unwrap();
+    assert!(validate(&quiz, &visualizations()).is_empty());
+    assert_eq!(quiz.questions[2].pool.as_deref(), Some("two-mutable-borrows"));
+    assert_eq!(quiz.slots(), vec![100, 101]);
+
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    apply(&mut conn, &quiz).unwrap();
+    let quiz_id = find_quiz(&conn, "borrowing").unwrap().unwrap();
+    assert_eq!(db::quiz_question_ids(&conn, quiz_id).unwrap(), vec![100, 101]);
+    assert_eq!(db::load_question(&conn, 102).unwrap().unwrap().pool.as_deref(), Some("two-mutable-borrows"));
+    assert!(diff(&conn, &quiz).unwrap().is_empty());
+
+    let mut renamed = quiz.clone();
+    renamed.questions[2].pool = Some("Two Borrows".to_string());
+    assert_eq!(validate(&renamed, &visualizations()).len(), 1);
+}
+
+#[test]
+fn indented_explanations_belong_to_the_choice_above() {
+    let md = MARKDOWN.replace("- [ ] Yes\n", "- [ ] Yes\n  > r1 is still used after r2 is created\n")
+        .replace("- [x] No\n", "- [x] No\n- [ ] Only in release builds\n");
+    let quiz = parse(&md, Format::Markdown).unwrap();
+    let q = &quiz.questions[0];
+    assert_eq!(q.explanation, "r1 and r2 borrow s mutably at once");
+    assert_eq!(q.choice_explanations, vec![
+        "r1 is still used after r2 is created".to_string(), String::new(), String::new()
+    ]);
+    assert!(validate(&quiz, &visualizations()).is_empty());
+
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    apply(&mut conn, &quiz).unwrap();
+    assert_eq!(db::load_question(&conn, 100).unwrap().as_ref(), Some(q));
+    // questions without them load none rather than empty ones
+    assert!(db::load_question(&conn, 0).unwrap().unwrap().choice_explanations.is_empty());
+
+    let mut short = quiz.clone();
+    short.questions[0].choice_explanations.pop();
+    assert_eq!(validate(&short, &visualizations()), vec!["question 100: has 2 choice explanations for 3 choices".to_string()]);
+}
@@ -0,0 +1,110 @@
+mod common;
+
+// crates.io
+use rusqlite::{Connection, NO_PARAMS};
+// local
+use common::TempDb;
+use fplab_server::route::limits::{attempts, lock, set_settings, settings, start, submit, FeedbackMode, Settings, GRACE_SECS};
+use fplab_server::route::quiz::{num_questions, results, status};
+
+// quiz created by sql/data.sql with every question in order
+const OWNERSHIP: i64 = 1;
+// unix time the tests take place at
+const NOW: i64 = 1_600_000_000;
+
+fn student(conn: &Connection) {
+    conn.execute("INSERT INTO users (token) VALUES ('student-1')", NO_PARAMS).unwrap();
+}
+
+#[test]
+fn quizzes_lock_once_submitted() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    student(&conn);
+
+    let open = lock(&conn, "student-1", OWNERSHIP, NOW).unwrap();
+    assert!(!open.locked && !open.submitted);
+    assert_eq!((open.time_left, open.max_attempts), (None, None));
+    assert_eq!(results(&conn, "student-1", OWNERSHIP, NOW).unwrap(), None);
+
+    submit(&conn, "student-1", OWNERSHIP, NOW).unwrap();
+    submit(&conn, "student-1", OWNERSHIP, NOW + 60).unwrap();
+    let submitted : i64 = conn.query_row(
+        "SELECT submitted FROM quiz_progress WHERE token = 'student-1'",
+        NO_PARAMS,
+        |row| row.get(0)
+    ).unwrap();
+    assert_eq!(submitted, NOW);
+    let closed = lock(&conn, "student-1", OWNERSHIP, NOW).unwrap();
+    assert!(closed.locked && closed.submitted);
+
+    // results no longer wait for every question to be answered
+    let graded = results(&conn, "student-1", OWNERSHIP, NOW).unwrap().unwrap();
+    assert_eq!(graded.total, num_questions(&conn, OWNERSHIP).unwrap());
+    assert_eq!(graded.score, 0);
+    assert!(graded.questions.iter().all(|q| q.answer.is_none()));
+}
+
+#[test]
+fn timed_quizzes_lock_after_their_limit() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    student(&conn);
+    let limits = Settings { time_limit: Some(600), ..Settings::default() };
+    assert!(set_settings(&conn, "ownership", &limits).unwrap());
+    assert!(!set_settings(&conn, "no-such-quiz", &limits).unwrap());
+    assert_eq!(settings(&conn, OWNERSHIP).unwrap(), limits);
+
+    // the clock starts when the quiz is first opened
+    assert_eq!(lock(&conn, "student-1", OWNERSHIP, NOW).unwrap().time_left, Some(600));
+    start(&conn, "student-1", OWNERSHIP, NOW).unwrap();
+    start(&conn, "student-1", OWNERSHIP, NOW + 300).unwrap();
+    assert_eq!(lock(&conn, "student-1", OWNERSHIP, NOW + 100).unwrap().time_left, Some(500));
+
+    let late = lock(&conn, "student-1", OWNERSHIP, NOW + 600 + GRACE_SECS).unwrap();
+    assert_eq!(late.time_left, Some(0));
+    assert!(!late.locked);
+    let over = lock(&conn, "student-1", OWNERSHIP, NOW + 601 + GRACE_SECS).unwrap();
+    assert!(over.locked && !over.submitted);
+    assert!(results(&conn, "student-1", OWNERSHIP, NOW + 601 + GRACE_SECS).unwrap().is_some());
+}
+
+#[test]
+fn status_reports_attempts_and_lock() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    student(&conn);
+    let limits = Settings { max_attempts: Some(2), ..Settings::default() };
+    set_settings(&conn, "ownership", &limits).unwrap();
+    conn.execute(
+        "INSERT INTO responses (token, quiz_id, question_id, answer, attempts)
+        VALUES ('student-1', 1, 3, 0, 2)",
+        NO_PARAMS
+    ).unwrap();
+    assert_eq!(attempts(&conn, "student-1", OWNERSHIP, 3).unwrap(), 2);
+    assert_eq!(attempts(&conn, "student-1", OWNERSHIP, 4).unwrap(), 0);
+
+    let quiz = status(&conn, "student-1", "ownership", OWNERSHIP, NOW).unwrap();
+    let arrangement = fplab_server::route::shuffle::arrangement(&conn, "student-1", OWNERSHIP).unwrap();
+    let position = arrangement.position(3).unwrap() as usize;
+    assert_eq!(quiz.attempts.iter().sum::<u32>(), 2);
+    assert_eq!(quiz.attempts[position], 2);
+    assert_eq!(quiz.lock.max_attempts, Some(2));
+    assert!(!quiz.lock.locked);
+
+    // the lock is part of the quiz's JSON
+    let json = serde_json::to_value(&quiz).unwrap();
+    assert_eq!(json["locked"], false);
+    assert_eq!(json["max_attempts"], 2);
+}
+
+#[test]
+fn feedback_mode_defaults_to_the_end_of_the_quiz() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    assert_eq!(settings(&conn, OWNERSHIP).unwrap().feedback, FeedbackMode::End);
+
+    let practice : Settings = serde_json::from_str(r#"{"max_attempts": null, "time_limit": null, "feedback": "immediate"}"#).unwrap();
+    set_settings(&conn, "ownership", &practice).unwrap();
+    assert_eq!(settings(&conn, OWNERSHIP).unwrap().feedback, FeedbackMode::Immediate);
+}
@@ -0,0 +1,298 @@
+mod common;
+
+// crates.io
+use chrono::Utc;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use uuid::Uuid;
+// local
+use common::TempDb;
+use fplab_server::lti::launch::{
+    check_state_cookie, deep_link_response, map_user, save_lineitem, save_state, state_cookie,
+    take_state, validate_id_token, DeepLinkSettings, CONTENT_ITEMS
+};
+use fplab_server::lti::{ags, LtiConfig, LtiError, Platform, CLAIM_CONTENT_ITEMS, CLAIM_VERSION};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+const ISSUER: &str = "https://lms.example.edu";
+const CLIENT_ID: &str = "fplab-client";
+const DEPLOYMENT_ID: &str = "deployment-1";
+const NONCE: &str = "nonce-123";
+
+// Tool configuration with the test platform at base_url
+fn config(base_url: &str) -> LtiConfig {
+    let dir = std::env::temp_dir().join(format!("fplab-lti-{}", Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    let platform_jwks : Value = serde_json::from_str(
+        &fs::read_to_string(Path::new(FIXTURES).join("lti_platform_jwks.json")).unwrap()
+    ).unwrap();
+    let file = dir.join("lti.json");
+    fs::write(&file, json!({
+        "tool_url": "https://fplab.example.edu/",
+        "kid": "tool-1",
+        "private_key": format!("{}/lti_tool.pem", FIXTURES),
+        "jwks": format!("{}/lti_tool_jwks.json", FIXTURES),
+        "platforms": [{
+            "issuer": ISSUER,
+            "client_id": CLIENT_ID,
+            "deployment_ids": [DEPLOYMENT_ID],
+            "auth_login_url": format!("{}/auth", base_url),
+            "auth_token_url": format!("{}/token", base_url),
+            "jwks_url": format!("{}/jwks", base_url),
+            "jwks": platform_jwks
+        }]
+    }).to_string()).unwrap();
+    let config = LtiConfig::load(&file).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    config
+}
+
+fn platform(config: &LtiConfig) -> &Platform {
+    config.platform(ISSUER, Some(CLIENT_ID)).unwrap()
+}
+
+// Claims of a resource link launch the mock platform would send
+fn launch_claims() -> Value {
+    let now = Utc::now().timestamp();
+    json!({
+        "iss": ISSUER,
+        "sub": "lms-user-42",
+        "aud": CLIENT_ID,
+        "iat": now,
+        "exp": now + 60,
+        "nonce": NONCE,
+        "https://purl.imsglobal.org/spec/lti/claim/message_type": "LtiResourceLinkRequest",
+        "https://purl.imsglobal.org/spec/lti/claim/version": "1.3.0",
+        "https://purl.imsglobal.org/spec/lti/claim/deployment_id": DEPLOYMENT_ID,
+        "https://purl.imsglobal.org/spec/lti/claim/target_link_uri": "https://fplab.example.edu/assignment/"
+    })
+}
+
+// Sign claims as the mock platform, or with another key under the platform's kid
+fn sign(claims: &Value, pem: &str) -> String {
+    let key = EncodingKey::from_rsa_pem(
+        &fs::read(Path::new(FIXTURES).join(pem)).unwrap()
+    ).unwrap();
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some("platform-1".to_string());
+    encode(&header, claims, &key).unwrap()
+}
+
+fn validate(claims: &Value) -> Result<(), LtiError> {
+    let config = config("http://127.0.0.1:9");
+    let platform = platform(&config);
+    let jwks : &JwkSet = platform.jwks.as_ref().unwrap();
+    validate_id_token(&sign(claims, "lti_platform.pem"), platform, jwks, NONCE).map(|_| ())
+}
+
+#[test]
+fn valid_launch_is_accepted() {
+    let config = config("http://127.0.0.1:9");
+    let platform = platform(&config);
+    let token = sign(&launch_claims(), "lti_platform.pem");
+    let claims = validate_id_token(&token, platform, platform.jwks.as_ref().unwrap(), NONCE).unwrap();
+    assert_eq!(claims.sub, "lms-user-42");
+    assert_eq!(claims.claim_str(CLAIM_VERSION), Some("1.3.0"));
+    assert_eq!(
+        config.local_path("https://fplab.example.edu/assignment/"),
+        Some("/assignment/".to_string())
+    );
+}
+
+#[test]
+fn invalid_launches_are_refused() {
+    let mut claims = launch_claims();
+    claims["nonce"] = json!("replayed");
+    assert!(matches!(validate(&claims), Err(LtiError::InvalidClaim("nonce"))));
+
+    let mut claims = launch_claims();
+    claims["aud"] = json!("some-other-tool");
+    assert!(matches!(validate(&claims), Err(LtiError::Jwt(_))));
+
+    let mut claims = launch_claims();
+    claims["exp"] = json!(Utc::now().timestamp() - 3600);
+    assert!(matches!(validate(&claims), Err(LtiError::Jwt(_))));
+
+    let mut claims = launch_claims();
+    claims["https://purl.imsglobal.org/spec/lti/claim/deployment_id"] = json!("unknown");
+    assert!(matches!(validate(&claims), Err(LtiError::InvalidClaim(_))));
+
+    let mut claims = launch_claims();
+    claims["aud"] = json!([CLIENT_ID, "some-other-tool"]);
+    assert!(matches!(validate(&claims), Err(LtiError::InvalidClaim("azp"))));
+    claims["azp"] = json!(CLIENT_ID);
+    assert!(validate(&claims).is_ok());
+
+    // signed by a key the platform never published
+    let config = config("http://127.0.0.1:9");
+    let platform = platform(&config);
+    let forged = sign(&launch_claims(), "lti_tool.pem");
+    assert!(matches!(
+        validate_id_token(&forged, platform, platform.jwks.as_ref().unwrap(), NONCE),
+        Err(LtiError::Jwt(_))
+    ));
+}
+
+#[test]
+fn state_is_single_use_and_expires() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    let now = Utc::now().timestamp();
+
+    save_state(&conn, "state-1", NONCE, ISSUER, now).unwrap();
+    assert_eq!(take_state(&conn, "state-1", now).unwrap(), (NONCE.to_string(), ISSUER.to_string()));
+    assert!(matches!(take_state(&conn, "state-1", now), Err(LtiError::InvalidState)));
+
+    save_state(&conn, "state-2", NONCE, ISSUER, now - 3600).unwrap();
+    assert!(matches!(take_state(&conn, "state-2", now), Err(LtiError::InvalidState)));
+}
+
+#[test]
+fn launches_come_from_the_browser_that_initiated_them() {
+    let config = config("https://fplab.example.edu");
+    let cookie = state_cookie(&config, "state-1");
+    assert!(cookie.starts_with("lti_state=state-1; Path=/lti/launch/; Max-Age=600;"));
+    assert!(cookie.ends_with("; Secure; SameSite=None"));
+    assert!(state_cookie(&config, "").contains("Max-Age=0"));
+
+    assert!(check_state_cookie("state-1", Some("state-1")).is_ok());
+    assert!(matches!(check_state_cookie("state-1", Some("state-2")), Err(LtiError::InvalidState)));
+    assert!(matches!(check_state_cookie("state-1", None), Err(LtiError::InvalidState)));
+    assert!(matches!(check_state_cookie("", Some("")), Err(LtiError::InvalidState)));
+}
+
+#[test]
+fn scores_go_back_through_the_client_launched_with() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    let config = config("https://fplab.example.edu");
+    let token = map_user(&mut conn, ISSUER, "lms-user-42", None).unwrap();
+    assert_eq!(ags::passback_for(&conn, &token, "/assignment/").unwrap(), None);
+
+    save_lineitem(&conn, &token, "/assignment/", platform(&config), "https://lms.example.edu/lineitems/7").unwrap();
+    let passback = ags::passback_for(&conn, &token, "/assignment/").unwrap().unwrap();
+    assert_eq!(passback.client_id, CLIENT_ID);
+    assert_eq!(passback.user_id, "lms-user-42");
+}
+
+#[test]
+fn platform_users_map_to_one_local_user() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+
+    let token = map_user(&mut conn, ISSUER, "lms-user-42", Some("eecs490")).unwrap();
+    assert_eq!(map_user(&mut conn, ISSUER, "lms-user-42", None).unwrap(), token);
+    assert_ne!(map_user(&mut conn, ISSUER, "lms-user-43", None).unwrap(), token);
+
+    let section : String = conn.query_row(
+        "SELECT section FROM users WHERE token = ?1",
+        &[&token],
+        |row| row.get(0)
+    ).unwrap();
+    assert_eq!(section, "eecs490");
+}
+
+#[test]
+fn deep_link_response_is_signed_by_tool() {
+    let config = config("http://127.0.0.1:9");
+    let settings = DeepLinkSettings {
+        issuer: ISSUER.to_string(),
+        client_id: CLIENT_ID.to_string(),
+        deployment_id: DEPLOYMENT_ID.to_string(),
+        return_url: format!("{}/deep_links", ISSUER),
+        data: Some("opaque".to_string())
+    };
+    let assignment = CONTENT_ITEMS.iter().find(|item| item.slug == "assignment").unwrap();
+    let jwt = deep_link_response(&config, &settings, &[assignment], Utc::now().timestamp()).unwrap();
+
+    let jwks : JwkSet = serde_json::from_str(&config.jwks).unwrap();
+    let mut validation = Validation::new(Algorithm::RS256);
+    validation.set_audience(&[ISSUER]);
+    validation.set_issuer(&[CLIENT_ID]);
+    let claims = decode::<Value>(
+        &jwt, &DecodingKey::from_jwk(jwks.find("tool-1").unwrap()).unwrap(), &validation
+    ).unwrap().claims;
+
+    let items = claims[CLAIM_CONTENT_ITEMS].as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["url"], "https://fplab.example.edu/assignment/");
+    assert_eq!(items[0]["lineItem"]["scoreMaximum"], 6);
+    assert_eq!(claims["https://purl.imsglobal.org/spec/lti-dl/claim/data"], "opaque");
+}
+
+// Answer n requests, reporting each request's first line, headers and body
+fn mock_platform(n: usize) -> (String, mpsc::Receiver<(String, String, String)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming().take(n) {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let mut headers = String::new();
+            let mut len = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break
+                }
+                let lower = header.to_lowercase();
+                if let Some(value) = lower.strip_prefix("content-length:") {
+                    len = value.trim().parse().unwrap();
+                }
+                headers.push_str(&lower);
+            }
+            let mut body = vec![0; len];
+            reader.read_exact(&mut body).unwrap();
+
+            let reply = if line.contains("/token") {
+                r#"{"access_token":"mock-token","token_type":"Bearer","expires_in":3600}"#
+            }
+            else {
+                "{}"
+            };
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                reply.len(), reply
+            ).unwrap();
+            tx.send((line, headers, String::from_utf8(body).unwrap())).unwrap();
+        }
+    });
+    (url, rx)
+}
+
+#[test]
+fn scores_are_posted_to_lineitem() {
+    let (url, rx) = mock_platform(2);
+    let config = config(&url);
+    let lineitem = format!("{}/lineitems/7/lineitem?type_id=1", url);
+    assert_eq!(ags::scores_url(&lineitem), format!("{}/lineitems/7/lineitem/scores?type_id=1", url));
+
+    ags::post_score(&config, platform(&config), &lineitem, "lms-user-42", 4.0, 6.0).unwrap();
+
+    let (line, _, body) = rx.recv().unwrap();
+    assert!(line.starts_with("POST /token "));
+    assert!(body.contains("grant_type=client_credentials"));
+    assert!(body.contains("client_assertion="));
+
+    let (line, headers, body) = rx.recv().unwrap();
+    assert!(line.starts_with("POST /lineitems/7/lineitem/scores?type_id=1 "));
+    assert!(headers.contains("authorization: bearer mock-token"));
+    assert!(headers.contains("content-type: application/vnd.ims.lis.v1.score+json"));
+    let score : Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(score["userId"], "lms-user-42");
+    assert_eq!(score["scoreGiven"], 4.0);
+    assert_eq!(score["scoreMaximum"], 6.0);
+    assert_eq!(score["activityProgress"], "InProgress");
+}
@@ -0,0 +1,58 @@
+// crates.io
+use actix_session::{CookieSession, Session};
+use actix_web::{http::StatusCode, test, web, App, HttpResponse};
+// local
+use fplab_server::api::nav::{flash, next_or_home, next_query, safe_next, take_flashes, Flash, HOME};
+
+#[test]
+fn only_local_paths_are_followed() {
+    assert_eq!(safe_next("/fp/"), Some("/fp/"));
+    assert_eq!(safe_next(" /assignment/?p=1 "), Some("/assignment/?p=1"));
+    assert_eq!(safe_next(""), None);
+    assert_eq!(safe_next("fp/"), None);
+    assert_eq!(safe_next("https://evil.example/"), None);
+    assert_eq!(safe_next("//evil.example/"), None);
+    assert_eq!(safe_next("/\\evil.example/"), None);
+    assert_eq!(safe_next("/\r\nSet-Cookie: x=1"), None);
+
+    assert_eq!(next_or_home("//evil.example/"), HOME);
+    assert_eq!(next_query("/fp/?q=1"), "?next=%2Ffp%2F%3Fq%3D1");
+    assert_eq!(next_query("https://evil.example/"), "");
+}
+
+async fn set(session: Session) -> HttpResponse {
+    flash(&session, Flash::info("You have been logged out."));
+    flash(&session, Flash::error("Incorrect token!"));
+    HttpResponse::Ok().finish()
+}
+
+async fn show(session: Session) -> HttpResponse {
+    HttpResponse::Ok().json(take_flashes(&session))
+}
+
+#[actix_rt::test]
+async fn flashes_are_shown_once() {
+    let mut app = test::init_service(
+        App::new()
+            .wrap(CookieSession::signed(&[0; 32]).secure(false))
+            .route("/set", web::get().to(set))
+            .route("/show", web::get().to(show))
+    ).await;
+
+    let res = test::call_service(&mut app, test::TestRequest::get().uri("/set").to_request()).await;
+    let cookie = res.response().cookies().next().unwrap().into_owned();
+
+    let req = test::TestRequest::get().uri("/show").cookie(cookie.clone()).to_request();
+    let res = test::call_service(&mut app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let cleared = res.response().cookies().next().unwrap().into_owned();
+    let shown : Vec<Flash> = serde_json::from_slice(&test::read_body(res).await).unwrap();
+    assert_eq!(shown, vec![
+        Flash::info("You have been logged out."),
+        Flash::error("Incorrect token!")
+    ]);
+
+    let req = test::TestRequest::get().uri("/show").cookie(cleared).to_request();
+    let shown : Vec<Flash> = test::read_response_json(&mut app, req).await;
+    assert!(shown.is_empty());
+}
@@ -0,0 +1,451 @@
+mod common;
+
+// crates.io
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use rusqlite::{params, Connection, NO_PARAMS};
+use std::collections::HashSet;
+// local
+use common::TempDb;
+use fplab_server::db::{self, attach_quiz, create_quiz, QuestionSpec};
+use fplab_server::db::answer::{check_answer, grade, normalize_output, Answer, Kind};
+use fplab_server::route::quiz::{
+    add_time, find_quiz, list, num_questions, question, question_at,
+    results, status, Submission, Timing, UserResponse, MAX_ELAPSED_MS
+};
+use fplab_server::route::limits::{set_settings, submit, FeedbackMode, Settings};
+use fplab_server::route::shuffle::{arrangement, canonical};
+
+// quiz created by sql/data.sql with every question in order
+const OWNERSHIP: i64 = 1;
+// unix time the tests take place at
+const NOW: i64 = 1_600_000_000;
+
+fn respond(conn: &Connection, token: &str, quiz_id: i64, qid: u32, answer: i64, correct: bool) {
+    conn.execute(
+        "INSERT OR REPLACE INTO responses (token, quiz_id, question_id, answer, correct)
+        VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![token, quiz_id, qid, answer, correct]
+    ).unwrap();
+}
+
+#[test]
+fn results_are_hidden_until_the_quiz_is_locked() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    conn.execute("INSERT INTO users (token) VALUES ('student-1')", NO_PARAMS).unwrap();
+    let total = num_questions(&conn, OWNERSHIP).unwrap();
+    assert!(total > 1);
+
+    assert_eq!(results(&conn, "student-1", OWNERSHIP, NOW).unwrap(), None);
+    // answering every question does not give the answers away,
+    // as answers could still be changed afterwards
+    for qid in 0..total {
+        respond(&conn, "student-1", OWNERSHIP, qid, 0, true);
+    }
+    assert_eq!(results(&conn, "student-1", OWNERSHIP, NOW).unwrap(), None);
+
+    submit(&conn, "student-1", OWNERSHIP, NOW).unwrap();
+    assert!(results(&conn, "student-1", OWNERSHIP, NOW).unwrap().is_some());
+}
+
+#[test]
+fn exams_stay_silent_until_submitted() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    conn.execute("INSERT INTO users (token) VALUES ('student-1')", NO_PARAMS).unwrap();
+    let exam = Settings { feedback: FeedbackMode::End, ..Settings::default() };
+    set_settings(&conn, "ownership", &exam).unwrap();
+    let total = num_questions(&conn, OWNERSHIP).unwrap();
+    for qid in 0..total {
+        respond(&conn, "student-1", OWNERSHIP, qid, 0, true);
+    }
+    assert_eq!(results(&conn, "student-1", OWNERSHIP, NOW).unwrap(), None);
+}
+
+#[test]
+fn practice_results_show_once_every_question_is_answered() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    conn.execute("INSERT INTO users (token) VALUES ('student-1')", NO_PARAMS).unwrap();
+    let practice = Settings { feedback: FeedbackMode::Immediate, ..Settings::default() };
+    set_settings(&conn, "ownership", &practice).unwrap();
+    let total = num_questions(&conn, OWNERSHIP).unwrap();
+    for qid in 0..total - 1 {
+        respond(&conn, "student-1", OWNERSHIP, qid, 0, true);
+    }
+    // unanswered questions would give their answers away
+    assert_eq!(results(&conn, "student-1", OWNERSHIP, NOW).unwrap(), None);
+    respond(&conn, "student-1", OWNERSHIP, total - 1, 0, true);
+    assert_eq!(results(&conn, "student-1", OWNERSHIP, NOW).unwrap().unwrap().score, total);
+}
+
+#[test]
+fn results_score_correct_answers() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    conn.execute("INSERT INTO users (token) VALUES ('student-1')", NO_PARAMS).unwrap();
+    conn.execute(
+        "UPDATE questions SET explanation = 'Because.' WHERE question_id = 0",
+        NO_PARAMS
+    ).unwrap();
+    conn.execute(
+        "UPDATE choices SET explanation = 'choice ' || ans_id WHERE question_id = 0",
+        NO_PARAMS
+    ).unwrap();
+    let total = num_questions(&conn, OWNERSHIP).unwrap();
+
+    respond(&conn, "student-1", OWNERSHIP, 0, 1, false);
+    for qid in 1..total {
+        respond(&conn, "student-1", OWNERSHIP, qid, 0, true);
+    }
+    submit(&conn, "student-1", OWNERSHIP, NOW).unwrap();
+
+    let results = results(&conn, "student-1", OWNERSHIP, NOW).unwrap().unwrap();
+    assert_eq!(results.total, total);
+    assert_eq!(results.score, total - 1);
+    // results are in the student's shuffled order and choice ids
+    let shown = arrangement(&conn, "student-1", OWNERSHIP).unwrap();
+    let position = shown.position(0).unwrap();
+    let first = &results.questions[position as usize];
+    let choices = &shown.questions[position as usize];
+    assert_eq!(first.qid, position);
+    assert_eq!(
+        (&first.answer, &first.correct_answer, first.correct),
+        (&choices.to_displayed(&Answer::Index(1)), &choices.to_displayed(&Answer::Index(0)).unwrap(), false)
+    );
+    assert_eq!(first.explanation, "Because.");
+    let explained : Vec<String> = choices.choices.iter().map(|c| format!("choice {}", c)).collect();
+    assert_eq!(first.choice_explanations, explained);
+    assert!(results.questions.iter().filter(|q| q.qid != position).all(|q| q.choice_explanations.is_empty()));
+    assert_eq!(results.questions.iter().filter(|q| q.correct).count() as u32, total - 1);
+}
+
+#[test]
+fn quizzes_order_their_own_questions() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    conn.execute("INSERT INTO users (token) VALUES ('student-1')", NO_PARAMS).unwrap();
+
+    create_quiz(&mut conn, "borrowing", "References and Borrowing", &[5, 2, 7]).unwrap();
+    let quiz_id = find_quiz(&conn, "borrowing").unwrap().unwrap();
+    assert_eq!(num_questions(&conn, quiz_id).unwrap(), 3);
+    assert_eq!(question_at(&conn, quiz_id, 0).unwrap(), Some(5));
+    assert_eq!(question_at(&conn, quiz_id, 2).unwrap(), Some(7));
+    assert_eq!(question_at(&conn, quiz_id, 3).unwrap(), None);
+    assert_eq!(find_quiz(&conn, "no-such-quiz").unwrap(), None);
+
+    // results are by position, only over the quiz's questions
+    for qid in [5, 2, 7] {
+        respond(&conn, "student-1", quiz_id, qid, 0, qid != 2);
+    }
+    submit(&conn, "student-1", quiz_id, NOW).unwrap();
+    let graded = results(&conn, "student-1", quiz_id, NOW).unwrap().unwrap();
+    assert_eq!((graded.total, graded.score), (3, 2));
+    let wrong = arrangement(&conn, "student-1", quiz_id).unwrap().position(2).unwrap();
+    assert_eq!(graded.questions[wrong as usize].qid, wrong);
+    assert!(!graded.questions[wrong as usize].correct);
+    assert_eq!(results(&conn, "student-1", OWNERSHIP, NOW).unwrap(), None);
+
+    // recreating replaces the questions and keeps the quiz
+    create_quiz(&mut conn, "borrowing", "Borrowing", &[3]).unwrap();
+    assert_eq!(find_quiz(&conn, "borrowing").unwrap(), Some(quiz_id));
+    assert_eq!(num_questions(&conn, quiz_id).unwrap(), 1);
+}
+
+#[test]
+fn quizzes_sharing_a_question_keep_their_own_responses() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    conn.execute("INSERT INTO users (token) VALUES ('student-1')", NO_PARAMS).unwrap();
+    create_quiz(&mut conn, "review", "Review", &[3]).unwrap();
+    let review = find_quiz(&conn, "review").unwrap().unwrap();
+
+    let total = num_questions(&conn, OWNERSHIP).unwrap();
+    for qid in 0..total {
+        respond(&conn, "student-1", OWNERSHIP, qid, 0, true);
+    }
+    submit(&conn, "student-1", OWNERSHIP, NOW).unwrap();
+    // answering question 3 again in another quiz leaves the first one alone
+    respond(&conn, "student-1", review, 3, 1, false);
+    add_time(&conn, "student-1", review, 3, Timing { question: 1000, hover: 0 }).unwrap();
+
+    let graded = results(&conn, "student-1", OWNERSHIP, NOW).unwrap().unwrap();
+    assert_eq!(graded.score, total);
+    let quiz = status(&conn, "student-1", "review", review, NOW).unwrap();
+    let shown = arrangement(&conn, "student-1", review).unwrap();
+    assert_eq!(quiz.saved_responses, vec![shown.questions[0].to_displayed(&Answer::Index(1))]);
+}
+
+#[test]
+fn quizzes_are_listed_by_chapter() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    create_quiz(&mut conn, "borrowing", "References and Borrowing", &[0, 1]).unwrap();
+
+    assert_eq!(list(&conn, None).unwrap().len(), 2);
+    assert!(list(&conn, Some("ch04-02-references-and-borrowing.html")).unwrap().is_empty());
+
+    assert!(attach_quiz(&conn, "borrowing", Some("ch04-02-references-and-borrowing.html")).unwrap());
+    let attached = list(&conn, Some("ch04-02-references-and-borrowing.html")).unwrap();
+    assert_eq!(attached.len(), 1);
+    assert_eq!((attached[0].slug.as_str(), attached[0].total), ("borrowing", 2));
+
+    assert!(attach_quiz(&conn, "borrowing", None).unwrap());
+    assert!(list(&conn, Some("ch04-02-references-and-borrowing.html")).unwrap().is_empty());
+    assert!(!attach_quiz(&conn, "no-such-quiz", None).unwrap());
+}
+
+#[test]
+fn answers_are_checked_against_the_kind_of_question() {
+    use Answer::{Index, Indices, Text};
+
+    assert_eq!(check_answer(Kind::Choice, 3, &Index(2)), None);
+    assert!(check_answer(Kind::Choice, 3, &Index(3)).is_some());
+    assert!(check_answer(Kind::Choice, 3, &Indices(vec![0])).is_some());
+    assert_eq!(check_answer(Kind::MultiSelect, 3, &Indices(vec![])), None);
+    assert!(check_answer(Kind::MultiSelect, 3, &Indices(vec![0, 0])).is_some());
+    assert!(check_answer(Kind::MultiSelect, 3, &Indices(vec![-1])).is_some());
+    assert_eq!(check_answer(Kind::Output, 0, &Text("5".to_string())), None);
+    assert!(check_answer(Kind::Output, 0, &Text("x".repeat(5000))).is_some());
+    assert!(check_answer(Kind::Output, 0, &Index(5)).is_some());
+    assert_eq!(check_answer(Kind::CompileFail, 0, &Index(7)), None);
+    assert!(check_answer(Kind::CompileFail, 0, &Index(0)).is_some());
+    assert_eq!(check_answer(Kind::Ordering, 3, &Indices(vec![2, 0, 1])), None);
+    assert!(check_answer(Kind::Ordering, 3, &Indices(vec![2, 0])).is_some());
+    assert!(check_answer(Kind::Ordering, 3, &Indices(vec![2, 0, 0])).is_some());
+}
+
+#[test]
+fn grading_depends_on_the_kind_of_question() {
+    use Answer::{Index, Indices, Text};
+
+    assert!(grade(Kind::Choice, &Index(1), &Index(1)));
+    assert!(!grade(Kind::Choice, &Index(1), &Index(0)));
+    // selections are sets, but all or nothing
+    assert!(grade(Kind::MultiSelect, &Indices(vec![0, 2]), &Indices(vec![2, 0])));
+    assert!(!grade(Kind::MultiSelect, &Indices(vec![0, 2]), &Indices(vec![0])));
+    assert!(!grade(Kind::MultiSelect, &Indices(vec![0, 2]), &Indices(vec![0, 1, 2])));
+    // orderings are sequences
+    assert!(grade(Kind::Ordering, &Indices(vec![2, 0, 1]), &Indices(vec![2, 0, 1])));
+    assert!(!grade(Kind::Ordering, &Indices(vec![2, 0, 1]), &Indices(vec![0, 2, 1])));
+    assert!(grade(Kind::CompileFail, &Index(7), &Index(7)));
+    assert!(!grade(Kind::CompileFail, &Index(7), &Index(6)));
+
+    assert!(grade(Kind::Output, &Text("5\n15\n".to_string()), &Text("\r\n5  \r\n15".to_string())));
+    assert!(!grade(Kind::Output, &Text("5\n15".to_string()), &Text("5 15".to_string())));
+    assert_eq!(normalize_output("\n\n  a \n\n b\t\n\n"), "  a\n\n b");
+    assert_eq!(normalize_output(" \n "), "");
+}
+
+#[test]
+fn answers_round_trip_through_their_columns() {
+    for answer in [Answer::Index(3), Answer::Indices(vec![2, 0]), Answer::Text("-1".to_string())] {
+        let (index, json) = answer.to_columns();
+        assert_eq!(Answer::from_columns(index, &json), answer);
+    }
+    // choice answers stay in the INTEGER column
+    assert_eq!(Answer::Index(3).to_columns(), (3, String::new()));
+}
+
+// number of random quizzes each property is checked on
+const CASES: u64 = 16;
+
+// REQUIRES: n/a
+// MODIFIES: Database
+// EFFECTS: Create the quiz slug of up to 300 new questions whose ids are
+//          far apart and mostly past what fits in a u8, in random order
+fn random_quiz(conn: &mut Connection, rng: &mut StdRng, slug: &str) -> (i64, Vec<i64>) {
+    let len = rng.gen_range(1, 300);
+    let mut seen = HashSet::new();
+    let ids : Vec<i64> = std::iter::repeat_with(|| rng.gen_range(100, 1_000_000))
+        .filter(|id| seen.insert(*id))
+        .take(len)
+        .collect();
+    let questions : Vec<QuestionSpec> = ids.iter().map(|id| QuestionSpec {
+        question_id: *id,
+        kind: Kind::Choice,
+        answer: Answer::Index(0),
+        filename: "vis_04_01_01".to_string(),
+        prompt: format!("Question {}", id),
+        contains_fr: false,
+        choices: vec!["yes".to_string(), "no".to_string()],
+        explanation: String::new(),
+        choice_explanations: Vec::new(),
+        pool: None
+    }).collect();
+    db::import_questions(conn, &questions).unwrap();
+    create_quiz(conn, slug, slug, &ids).unwrap();
+    (find_quiz(conn, slug).unwrap().unwrap(), ids)
+}
+
+#[test]
+fn positions_map_to_their_questions() {
+    for seed in 0..CASES {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let db = TempDb::new();
+        let mut conn = db.connect();
+        let (quiz_id, ids) = random_quiz(&mut conn, &mut rng, "random");
+        let len = ids.len() as u32;
+        let shown = canonical(&conn, quiz_id).unwrap();
+
+        assert_eq!(num_questions(&conn, quiz_id).unwrap(), len);
+        for (position, id) in ids.iter().enumerate() {
+            let position = position as u32;
+            assert_eq!(question_at(&conn, quiz_id, position).unwrap(), Some(*id), "seed {}", seed);
+            let q = question(&conn, &shown, position).unwrap().unwrap();
+            assert_eq!((q.qid, q.prompt), (position, format!("Question {}", id)));
+            assert_eq!(q.choices.iter().map(|c| c.id).collect::<Vec<_>>(), vec![0, 1]);
+        }
+        // past the end, including positions no u8 or i64 row could hold
+        for position in [len, len + rng.gen_range(0, 1000), u32::MAX] {
+            assert_eq!(question_at(&conn, quiz_id, position).unwrap(), None, "seed {}", seed);
+            assert_eq!(question(&conn, &shown, position).unwrap(), None, "seed {}", seed);
+        }
+        // positions belong to their own quiz
+        let ownership = canonical(&conn, OWNERSHIP).unwrap();
+        assert_eq!(question(&conn, &ownership, len.max(11)).unwrap(), None);
+    }
+}
+
+#[test]
+fn saved_answers_follow_positions() {
+    for seed in 0..CASES {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let db = TempDb::new();
+        let mut conn = db.connect();
+        conn.execute("INSERT INTO users (token) VALUES ('student-1')", NO_PARAMS).unwrap();
+        let (quiz_id, ids) = random_quiz(&mut conn, &mut rng, "random");
+        let shown = arrangement(&conn, "student-1", quiz_id).unwrap();
+
+        // responses are stored by canonical id, saved answers are as shown
+        let mut expected = vec![-2; ids.len()];
+        for (position, q) in shown.questions.iter().enumerate() {
+            if rng.gen_bool(0.5) {
+                expected[position] = rng.gen_range(0, 2);
+                conn.execute(
+                    "INSERT INTO responses (token, quiz_id, question_id, answer, free_response)
+                    VALUES ('student-1', ?1, ?2, ?3, ?4)",
+                    params![
+                        quiz_id, q.question_id, q.canonical(expected[position]).unwrap(),
+                        format!("because {}", position)
+                    ]
+                ).unwrap();
+            }
+        }
+
+        let quiz = status(&conn, "student-1", "random", quiz_id, NOW).unwrap();
+        assert_eq!(quiz.total as usize, ids.len());
+        assert_eq!(quiz.saved_ans_vec, expected, "seed {}", seed);
+        for (position, answer) in expected.iter().enumerate() {
+            let saved = &quiz.saved_responses[position];
+            let text = &quiz.saved_free_res[position];
+            if *answer == -2 {
+                assert_eq!((saved, text.as_str()), (&None, ""));
+            }
+            else {
+                assert_eq!(saved, &Some(Answer::Index(*answer)));
+                assert_eq!(text, &format!("because {}", position));
+            }
+        }
+        // results wait for the submission, however many were answered
+        assert_eq!(results(&conn, "student-1", quiz_id, NOW).unwrap(), None);
+        submit(&conn, "student-1", quiz_id, NOW).unwrap();
+        let graded = results(&conn, "student-1", quiz_id, NOW).unwrap().unwrap();
+        let answered = graded.questions.iter().map(|q| q.answer.is_some());
+        assert!(answered.eq(expected.iter().map(|a| *a != -2)), "seed {}", seed);
+    }
+}
+
+#[test]
+fn removing_questions_keeps_positions_contiguous() {
+    for seed in 0..CASES {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let db = TempDb::new();
+        let mut conn = db.connect();
+        let (quiz_id, ids) = random_quiz(&mut conn, &mut rng, "random");
+
+        let mut removed = ids.clone();
+        removed.shuffle(&mut rng);
+        removed.truncate(rng.gen_range(0, ids.len() + 1));
+        for id in &removed {
+            assert!(db::remove_question(&mut conn, *id).unwrap());
+        }
+
+        let kept : Vec<i64> = ids.iter().copied().filter(|id| !removed.contains(id)).collect();
+        assert_eq!(db::quiz_question_ids(&conn, quiz_id).unwrap(), kept, "seed {}", seed);
+        assert_eq!(num_questions(&conn, quiz_id).unwrap() as usize, kept.len());
+        for (position, id) in kept.iter().enumerate() {
+            assert_eq!(question_at(&conn, quiz_id, position as u32).unwrap(), Some(*id));
+        }
+        assert_eq!(question_at(&conn, quiz_id, kept.len() as u32).unwrap(), None);
+    }
+}
+
+fn submission(json: &str) -> Result<Submission, String> {
+    serde_json::from_str::<UserResponse>(json).unwrap().validate()
+}
+
+#[test]
+fn requests_are_answers_or_heartbeats() {
+    let timing = Timing { question: 2000, hover: 300 };
+    assert_eq!(
+        submission(r#"{"ques_id": 1, "time_elapsed_question": 2000, "time_elapsed_hover": 300}"#),
+        Ok(Submission::Heartbeat { ques_id: 1, timing })
+    );
+    // questions without a free response field send null
+    assert_eq!(
+        submission(r#"{"ques_id": 1, "ans_id": 2, "free_response": null, "time_elapsed_question": 2000, "time_elapsed_hover": 300}"#),
+        Ok(Submission::Answer { ques_id: 1, answer: Answer::Index(2), free_response: String::new(), timing })
+    );
+    assert_eq!(
+        submission(r#"{"type": "answer", "ques_id": 0, "answer": [0, 2], "free_response": "both", "time_elapsed_question": 2000, "time_elapsed_hover": 300}"#),
+        Ok(Submission::Answer { ques_id: 0, answer: Answer::Indices(vec![0, 2]), free_response: "both".to_string(), timing })
+    );
+}
+
+#[test]
+fn inconsistent_requests_are_rejected() {
+    let rejected = |json: &str| submission(json).unwrap_err();
+    assert_eq!(
+        rejected(r#"{"type": "heartbeat", "ques_id": 0, "ans_id": 1, "time_elapsed_question": 0, "time_elapsed_hover": 0}"#),
+        "heartbeats carry no answer"
+    );
+    assert_eq!(
+        rejected(r#"{"type": "answer", "ques_id": 0, "time_elapsed_question": 0, "time_elapsed_hover": 0}"#),
+        "answers need ans_id or answer"
+    );
+    assert_eq!(
+        rejected(r#"{"ques_id": 0, "free_response": "lost", "time_elapsed_question": 0, "time_elapsed_hover": 0}"#),
+        "free_response needs an answer"
+    );
+    assert_eq!(
+        rejected(r#"{"ques_id": 0, "ans_id": 1, "answer": 1, "time_elapsed_question": 0, "time_elapsed_hover": 0}"#),
+        "send either ans_id or answer, not both"
+    );
+    let long = format!(
+        r#"{{"ques_id": 0, "time_elapsed_question": {}, "time_elapsed_hover": {}}}"#,
+        MAX_ELAPSED_MS + 1, u64::MAX
+    );
+    assert_eq!(rejected(&long), format!(
+        "time_elapsed_question must be at most {0} ms; time_elapsed_hover must be at most {0} ms",
+        MAX_ELAPSED_MS
+    ));
+    assert!(submission(&format!(
+        r#"{{"ques_id": 0, "time_elapsed_question": {}, "time_elapsed_hover": 0}}"#, MAX_ELAPSED_MS
+    )).is_ok());
+}
+
+#[test]
+fn heartbeats_add_up_without_answering() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    conn.execute("INSERT INTO users (token) VALUES ('student-1')", NO_PARAMS).unwrap();
+    add_time(&conn, "student-1", OWNERSHIP, 3, Timing { question: 1000, hover: 10 }).unwrap();
+    add_time(&conn, "student-1", OWNERSHIP, 3, Timing { question: 500, hover: 5 }).unwrap();
+    let row : (i64, i64, i64) = conn.query_row(
+        "SELECT answer, time_elapsed, hover_time FROM responses WHERE token = 'student-1' AND question_id = 3",
+        NO_PARAMS,
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+    ).unwrap();
+    assert_eq!(row, (-2, 1500, 15));
+}
@@ -0,0 +1,55 @@
+mod common;
+
+// local
+use common::TempDb;
+use fplab_server::middleware::rate_limit::{
+    locked_until, record_failure, record_success, RateLimitConfig
+};
+
+fn config() -> RateLimitConfig {
+    RateLimitConfig {
+        max_failures: 3,
+        ip_max_failures: 10,
+        base_delay: 30,
+        max_delay: 100,
+        window: 900,
+        trust_proxy: false
+    }
+}
+
+#[test]
+fn lockout_starts_at_threshold_and_backs_off_exponentially() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    let config = config();
+    let key = "credential:guess";
+
+    assert_eq!(record_failure(&conn, key, 3, &config, 1000).unwrap(), None);
+    assert_eq!(record_failure(&conn, key, 3, &config, 1001).unwrap(), None);
+    assert_eq!(locked_until(&conn, key, 1001).unwrap(), None);
+
+    assert_eq!(record_failure(&conn, key, 3, &config, 1002).unwrap(), Some(1032));
+    assert_eq!(locked_until(&conn, key, 1010).unwrap(), Some(1032));
+    assert_eq!(locked_until(&conn, key, 1032).unwrap(), None);
+
+    assert_eq!(record_failure(&conn, key, 3, &config, 1040).unwrap(), Some(1100));
+    // capped at max_delay
+    assert_eq!(record_failure(&conn, key, 3, &config, 1100).unwrap(), Some(1200));
+}
+
+#[test]
+fn failures_reset_after_window_or_success() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    let config = config();
+    let key = "credential:student";
+
+    record_failure(&conn, key, 3, &config, 0).unwrap();
+    record_failure(&conn, key, 3, &config, 1).unwrap();
+    // a quiet window forgets earlier failures
+    assert_eq!(record_failure(&conn, key, 3, &config, 2000).unwrap(), None);
+
+    record_failure(&conn, key, 3, &config, 2001).unwrap();
+    record_success(&conn, key).unwrap();
+    assert_eq!(record_failure(&conn, key, 3, &config, 2002).unwrap(), None);
+}
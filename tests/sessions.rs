@@ -0,0 +1,90 @@
+mod common;
+
+// crates.io
+use rusqlite::{params, Connection};
+// local
+use common::TempDb;
+use fplab_server::middleware::sessions::{create, list, resolve, revoke, revoke_all};
+
+const IDLE: i64 = 3600;
+
+fn add_user(conn: &Connection, token: &str) {
+    conn.execute("INSERT INTO users (token) VALUES (?1)", params![token]).unwrap();
+}
+
+fn last_seen(conn: &Connection, id: &str) -> i64 {
+    conn.query_row("SELECT last_seen FROM sessions WHERE id = ?1", params![id], |row| row.get(0)).unwrap()
+}
+
+#[test]
+fn sessions_resolve_until_idle() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    add_user(&conn, "student-1");
+
+    let id = create(&conn, "student-1", Some("Firefox"), IDLE, 1000).unwrap();
+    assert_eq!(resolve(&conn, &id, IDLE, 1010).unwrap(), Some("student-1".to_string()));
+    // last_seen is only written once a minute
+    assert_eq!(last_seen(&conn, &id), 1000);
+    assert_eq!(resolve(&conn, &id, IDLE, 1100).unwrap(), Some("student-1".to_string()));
+    assert_eq!(last_seen(&conn, &id), 1100);
+
+    // idle sessions expire
+    assert_eq!(resolve(&conn, &id, IDLE, 1100 + IDLE).unwrap(), None);
+    assert_eq!(resolve(&conn, "made-up", IDLE, 1100).unwrap(), None);
+}
+
+#[test]
+fn users_list_and_revoke_only_their_sessions() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    add_user(&conn, "student-1");
+    add_user(&conn, "student-2");
+
+    let laptop = create(&conn, "student-1", Some("Firefox"), IDLE, 1000).unwrap();
+    let phone = create(&conn, "student-1", None, IDLE, 1200).unwrap();
+    let other = create(&conn, "student-2", Some("Chrome"), IDLE, 1300).unwrap();
+
+    let sessions = list(&conn, "student-1", Some(&laptop), IDLE, 1300).unwrap();
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions[0].id, phone);
+    assert!(!sessions[0].current);
+    assert_eq!(sessions[1].user_agent.as_deref(), Some("Firefox"));
+    assert!(sessions[1].current);
+
+    // another user's session cannot be revoked
+    assert!(!revoke(&conn, "student-1", &other).unwrap());
+    assert!(revoke(&conn, "student-1", &phone).unwrap());
+    assert_eq!(resolve(&conn, &phone, IDLE, 1300).unwrap(), None);
+    assert_eq!(resolve(&conn, &other, IDLE, 1300).unwrap(), Some("student-2".to_string()));
+}
+
+#[test]
+fn revoke_all_keeps_the_current_session() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    add_user(&conn, "student-1");
+
+    let ids : Vec<String> = (0..3)
+        .map(|i| create(&conn, "student-1", None, IDLE, 1000 + i).unwrap())
+        .collect();
+    assert_eq!(revoke_all(&conn, "student-1", Some(&ids[0])).unwrap(), 2);
+    assert_eq!(list(&conn, "student-1", None, IDLE, 1010).unwrap().len(), 1);
+
+    assert_eq!(revoke_all(&conn, "student-1", None).unwrap(), 1);
+    assert_eq!(resolve(&conn, &ids[0], IDLE, 1010).unwrap(), None);
+}
+
+#[test]
+fn creating_a_session_removes_idle_ones() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    add_user(&conn, "student-1");
+
+    let old = create(&conn, "student-1", None, IDLE, 0).unwrap();
+    create(&conn, "student-1", None, IDLE, IDLE + 1).unwrap();
+    let n : i64 = conn.query_row(
+        "SELECT COUNT(*) FROM sessions WHERE id = ?1", params![&old], |row| row.get(0)
+    ).unwrap();
+    assert_eq!(n, 0);
+}
@@ -0,0 +1,194 @@
+mod common;
+
+// crates.io
+use rusqlite::{params, Connection, NO_PARAMS};
+// local
+use common::TempDb;
+use fplab_server::db;
+use fplab_server::db::answer::Answer;
+use fplab_server::route::quiz::question;
+use fplab_server::route::shuffle::{arrangement, canonical, shuffle, Arrangement};
+
+// quiz created by sql/data.sql with every question in order
+const OWNERSHIP: i64 = 1;
+
+fn add_users(conn: &Connection, tokens: &[&str]) {
+    for token in tokens {
+        conn.execute("INSERT INTO users (token) VALUES (?1)", params![token]).unwrap();
+    }
+}
+
+fn question_ids(arrangement: &Arrangement) -> Vec<i64> {
+    arrangement.questions.iter().map(|q| q.question_id).collect()
+}
+
+#[test]
+fn each_student_gets_their_own_stable_order() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    add_users(&conn, &["student-1", "student-2"]);
+    let all = db::quiz_question_ids(&conn, OWNERSHIP).unwrap();
+    db::create_quiz(&mut db.connect(), "again", "Again", &all).unwrap();
+
+    let first = arrangement(&conn, "student-1", OWNERSHIP).unwrap();
+    assert_eq!(arrangement(&conn, "student-1", OWNERSHIP).unwrap(), first);
+    assert_eq!(shuffle(&conn, "student-1", OWNERSHIP).unwrap(), first);
+    let stored : i64 = conn.query_row(
+        "SELECT COUNT(*) FROM arrangements WHERE token = 'student-1'",
+        NO_PARAMS,
+        |row| row.get(0)
+    ).unwrap();
+    assert_eq!(stored as usize, all.len());
+
+    // every question and choice is shown exactly once
+    let mut ids = question_ids(&first);
+    ids.sort_unstable();
+    assert_eq!(ids, all);
+    for q in &first.questions {
+        let mut choices = q.choices.clone();
+        choices.sort_unstable();
+        let total = db::load_question(&conn, q.question_id).unwrap().unwrap().choices.len() as i64;
+        assert_eq!(choices, (0..total).collect::<Vec<_>>());
+    }
+
+    // seeded by token and quiz
+    let second = arrangement(&conn, "student-2", OWNERSHIP).unwrap();
+    assert_ne!(question_ids(&second), question_ids(&first));
+    let quiz_id = fplab_server::route::quiz::find_quiz(&conn, "again").unwrap().unwrap();
+    assert_ne!(question_ids(&arrangement(&conn, "student-1", quiz_id).unwrap()), question_ids(&first));
+}
+
+#[test]
+fn stored_order_is_kept_until_the_quiz_changes() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    add_users(&conn, &["student-1"]);
+    let first = arrangement(&conn, "student-1", OWNERSHIP).unwrap();
+
+    // the stored mapping wins over recomputing the shuffle
+    // in two steps so no two rows share a position in between
+    for flip in ["-1 - position", "11 + position"] {
+        conn.execute(
+            &format!("UPDATE arrangements SET position = {} WHERE token = 'student-1'", flip),
+            NO_PARAMS
+        ).unwrap();
+    }
+    let mut reversed = question_ids(&first);
+    reversed.reverse();
+    assert_eq!(question_ids(&arrangement(&conn, "student-1", OWNERSHIP).unwrap()), reversed);
+
+    // removing a question keeps the order of what is left
+    let removed = first.questions[5].question_id;
+    db::remove_question(&mut conn, removed).unwrap();
+    let after = arrangement(&conn, "student-1", OWNERSHIP).unwrap();
+    reversed.retain(|id| *id != removed);
+    assert_eq!(question_ids(&after), reversed);
+    let positions : Vec<i64> = conn.prepare(
+        "SELECT position FROM arrangements WHERE token = 'student-1' ORDER BY position"
+    ).unwrap().query_map(NO_PARAMS, |row| row.get(0)).unwrap().collect::<rusqlite::Result<_>>().unwrap();
+    assert_eq!(positions, (0..reversed.len() as i64).collect::<Vec<_>>());
+
+    // added questions are shown after them
+    db::create_quiz(&mut conn, "growing", "Growing", &[1, 2, 5]).unwrap();
+    let quiz_id = fplab_server::route::quiz::find_quiz(&conn, "growing").unwrap().unwrap();
+    let small = arrangement(&conn, "student-1", quiz_id).unwrap();
+    let extra = *reversed.iter().find(|id| ![1, 2, 5].contains(*id)).unwrap();
+    conn.execute("INSERT INTO quiz_questions (quiz_id, position, question_id) VALUES (?1, 3, ?2)", params![quiz_id, extra]).unwrap();
+    let grown = arrangement(&conn, "student-1", quiz_id).unwrap();
+    assert_eq!(grown.questions[..3], small.questions[..]);
+    assert_eq!(grown.questions[3].question_id, extra);
+}
+
+#[test]
+fn questions_are_shown_with_shuffled_choices() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    add_users(&conn, &["student-1"]);
+    let shown = arrangement(&conn, "student-1", OWNERSHIP).unwrap();
+
+    for (position, q) in shown.questions.iter().enumerate() {
+        let spec = db::load_question(&conn, q.question_id).unwrap().unwrap();
+        let displayed = question(&conn, &shown, position as u32).unwrap().unwrap();
+        assert_eq!(displayed.prompt, spec.prompt);
+        for (id, choice) in displayed.choices.iter().enumerate() {
+            assert_eq!(choice.id, id as i64);
+            assert_eq!(choice.text, spec.choices[q.choices[id] as usize]);
+        }
+    }
+
+    // logged out users see the quiz as written
+    let written = canonical(&conn, OWNERSHIP).unwrap();
+    assert_eq!(question_ids(&written), db::quiz_question_ids(&conn, OWNERSHIP).unwrap());
+    assert!(written.questions.iter().all(|q| q.choices.iter().enumerate().all(|(i, c)| *c == i as i64)));
+}
+
+#[test]
+fn answers_translate_between_shown_and_canonical_ids() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    add_users(&conn, &["student-1"]);
+    let shown = arrangement(&conn, "student-1", OWNERSHIP).unwrap();
+
+    for q in &shown.questions {
+        let n = q.choices.len() as i64;
+        for id in 0..n {
+            let canonical = q.to_canonical(&Answer::Index(id)).unwrap();
+            assert_eq!(canonical, Answer::Index(q.choices[id as usize]));
+            assert_eq!(q.to_displayed(&canonical), Some(Answer::Index(id)));
+        }
+        let all = Answer::Indices((0..n).rev().collect());
+        assert_eq!(q.to_displayed(&q.to_canonical(&all).unwrap()), Some(all));
+        assert_eq!(q.to_canonical(&Answer::Index(n)), None);
+        assert_eq!(q.to_canonical(&Answer::Indices(vec![0, -1])), None);
+        assert_eq!(q.to_canonical(&Answer::Text("5".to_string())), Some(Answer::Text("5".to_string())));
+    }
+}
+
+#[test]
+fn each_student_draws_one_variant_per_pooled_question() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    let students : Vec<String> = (0..20).map(|i| format!("student-{}", i)).collect();
+    add_users(&conn, &students.iter().map(String::as_str).collect::<Vec<_>>());
+    conn.execute("UPDATE questions SET pool = 'move' WHERE question_id IN (1, 2, 3)", NO_PARAMS).unwrap();
+    db::create_quiz(&mut conn, "pooled", "Pooled", &[1, 2, 5]).unwrap();
+    let quiz_id = fplab_server::route::quiz::find_quiz(&conn, "pooled").unwrap().unwrap();
+
+    let mut variants = std::collections::HashSet::new();
+    for student in &students {
+        let shown = arrangement(&conn, student, quiz_id).unwrap();
+        let mut slots : Vec<i64> = shown.questions.iter().map(|q| q.slot).collect();
+        slots.sort_unstable();
+        assert_eq!(slots, vec![1, 2, 5]);
+        // both pooled slots draw from the pool, never the same variant twice
+        let mut ids = question_ids(&shown);
+        ids.sort_unstable();
+        assert!(ids.contains(&5));
+        assert!(ids.iter().filter(|id| [1, 2, 3].contains(*id)).count() == 2);
+        ids.dedup();
+        assert_eq!(ids.len(), 3);
+        variants.extend(question_ids(&shown));
+    }
+    assert!(variants.contains(&3));
+
+    let stats = db::pool_stats(&conn).unwrap();
+    assert_eq!(stats.iter().map(|v| v.question_id).collect::<Vec<_>>(), vec![1, 2, 3]);
+    assert_eq!(stats.iter().map(|v| v.shown).sum::<i64>(), 2 * students.len() as i64);
+
+    // leaving the pool redraws for whoever had that variant
+    let student = students.iter()
+        .find(|s| question_ids(&arrangement(&conn, s, quiz_id).unwrap()).contains(&3))
+        .unwrap();
+    let before = arrangement(&conn, student, quiz_id).unwrap();
+    conn.execute("UPDATE questions SET pool = NULL WHERE question_id = 3", NO_PARAMS).unwrap();
+    let after = arrangement(&conn, student, quiz_id).unwrap();
+    assert!(!question_ids(&after).contains(&3));
+
+    // the other slots keep their variants, choices and places
+    for (old, new) in before.questions.iter().zip(&after.questions) {
+        assert_eq!(old.slot, new.slot);
+        if old.question_id != 3 {
+            assert_eq!(old, new);
+        }
+    }
+}
@@ -0,0 +1,268 @@
+mod common;
+
+// crates.io
+use chrono::Utc;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use rusqlite::Connection;
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+// local
+use common::TempDb;
+use fplab_server::api::accounts::insert_password_user;
+use fplab_server::api::invites;
+use fplab_server::api::sso::{
+    authorization_url, exchange_code, map_user, pkce_challenge, pkce_verifier,
+    save_state, take_state, validate_id_token, IdClaims, OidcConfig, PendingLogin, Sso, SsoError
+};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+const CLIENT_ID: &str = "fplab";
+const CODE: &str = "auth-code";
+const NONCE: &str = "nonce-123";
+
+// Local identity provider serving discovery, keys and the token endpoint
+// The token endpoint only accepts CODE with the verifier of challenge
+struct MockIdp {
+    issuer: String,
+    challenge: Arc<Mutex<String>>,
+    claims: Arc<Mutex<Value>>
+}
+
+impl MockIdp {
+    fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let challenge = Arc::new(Mutex::new(String::new()));
+        let claims = Arc::new(Mutex::new(Value::Null));
+        let jwks = fs::read_to_string(Path::new(FIXTURES).join("lti_platform_jwks.json")).unwrap();
+        let key = EncodingKey::from_rsa_pem(
+            &fs::read(Path::new(FIXTURES).join("lti_platform.pem")).unwrap()
+        ).unwrap();
+
+        let (iss, expected, id_claims) = (issuer.clone(), challenge.clone(), claims.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let (line, body) = read_request(&mut stream);
+                let (status, reply) = if line.contains("/.well-known/openid-configuration") {
+                    ("200 OK", json!({
+                        "issuer": iss,
+                        "authorization_endpoint": format!("{}/authorize", iss),
+                        "token_endpoint": format!("{}/token", iss),
+                        "jwks_uri": format!("{}/jwks", iss)
+                    }).to_string())
+                }
+                else if line.contains("/jwks") {
+                    ("200 OK", jwks.clone())
+                }
+                else {
+                    let form : Vec<(String, String)> = serde_urlencoded::from_str(&body).unwrap();
+                    let get = |k: &str| form.iter().find(|(key, _)| key == k).map(|(_, v)| v.clone());
+                    let verified = get("code_verifier")
+                        .is_some_and(|v| pkce_challenge(&v) == *expected.lock().unwrap());
+                    if get("code").as_deref() == Some(CODE) && verified {
+                        let mut header = Header::new(Algorithm::RS256);
+                        header.kid = Some("platform-1".to_string());
+                        let id_token = encode(&header, &*id_claims.lock().unwrap(), &key).unwrap();
+                        ("200 OK", json!({ "id_token": id_token, "token_type": "Bearer" }).to_string())
+                    }
+                    else {
+                        ("400 Bad Request", json!({ "error": "invalid_grant" }).to_string())
+                    }
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status, reply.len(), reply
+                ).unwrap();
+            }
+        });
+
+        MockIdp { issuer, challenge, claims }
+    }
+
+    fn config(&self) -> OidcConfig {
+        config(&self.issuer)
+    }
+}
+
+fn config(issuer: &str) -> OidcConfig {
+    OidcConfig {
+        issuer: issuer.to_string(),
+        client_id: CLIENT_ID.to_string(),
+        client_secret: None,
+        redirect_url: "https://fplab.example.edu/accounts/sso/callback/".to_string(),
+        section: Some("eecs490".to_string()),
+    }
+}
+
+fn read_request(stream: &mut std::net::TcpStream) -> (String, String) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let mut len = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).unwrap();
+        if header.trim().is_empty() {
+            break
+        }
+        if let Some(value) = header.to_lowercase().strip_prefix("content-length:") {
+            len = value.trim().parse().unwrap();
+        }
+    }
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).unwrap();
+    (line, String::from_utf8(body).unwrap())
+}
+
+fn id_claims(issuer: &str) -> Value {
+    let now = Utc::now().timestamp();
+    json!({
+        "iss": issuer,
+        "sub": "idp-user-1",
+        "aud": CLIENT_ID,
+        "iat": now,
+        "exp": now + 60,
+        "nonce": NONCE,
+        "email": "student@example.edu",
+        "email_verified": true
+    })
+}
+
+fn claims(sub: &str, email: Option<&str>) -> IdClaims {
+    IdClaims {
+        sub: sub.to_string(),
+        nonce: NONCE.to_string(),
+        email: email.map(String::from),
+        email_verified: true
+    }
+}
+
+fn count_users(conn: &Connection) -> i64 {
+    conn.query_row("SELECT COUNT(*) FROM users", rusqlite::NO_PARAMS, |row| row.get(0)).unwrap()
+}
+
+#[test]
+fn pkce_challenge_is_sha256_base64url() {
+    assert_eq!(
+        pkce_challenge("dBjftJeZ4CVP-mJ92K9qnhsYdH4Fzk9CBk0s5rxQ5OM"),
+        "OR9tO_nsLC0flcIW_jLK2u966lLNGkz7IhMcJRkGKVI"
+    );
+    let verifier = pkce_verifier();
+    assert!(verifier.len() >= 43 && verifier.len() <= 128);
+    assert_ne!(verifier, pkce_verifier());
+}
+
+#[test]
+fn authorization_code_flow_against_mock_idp() {
+    let idp = MockIdp::start();
+    let config = idp.config();
+    let sso = Sso::new(Some(config.clone()));
+    let (provider, jwks) = sso.provider().unwrap();
+
+    let pending = PendingLogin {
+        nonce: NONCE.to_string(),
+        verifier: pkce_verifier(),
+        link: None,
+        invite: None
+    };
+    let url = authorization_url(&config, &provider, "state-1", &pending);
+    assert!(url.starts_with(&format!("{}/authorize?", idp.issuer)));
+    assert!(url.contains("code_challenge_method=S256"));
+    assert!(url.contains(&format!("code_challenge={}", pkce_challenge(&pending.verifier))));
+
+    // the provider remembers the challenge it was sent
+    *idp.challenge.lock().unwrap() = pkce_challenge(&pending.verifier);
+    *idp.claims.lock().unwrap() = id_claims(&idp.issuer);
+
+    // a stolen code is useless without the verifier
+    assert!(matches!(exchange_code(&config, &provider, CODE, &pkce_verifier()), Err(SsoError::Http(_))));
+
+    let id_token = exchange_code(&config, &provider, CODE, &pending.verifier).unwrap();
+    let claims = validate_id_token(&config, &provider, &jwks, &id_token, NONCE).unwrap();
+    assert_eq!(claims.sub, "idp-user-1");
+    assert_eq!(claims.verified_email(), Some("student@example.edu"));
+
+    assert!(matches!(
+        validate_id_token(&config, &provider, &jwks, &id_token, "other-nonce"),
+        Err(SsoError::InvalidClaim("nonce"))
+    ));
+
+    let mut other = idp.config();
+    other.client_id = "another-client".to_string();
+    assert!(matches!(
+        validate_id_token(&other, &provider, &jwks, &id_token, NONCE),
+        Err(SsoError::Jwt(_))
+    ));
+}
+
+#[test]
+fn state_is_single_use() {
+    let db = TempDb::new();
+    let conn = db.connect();
+    let now = Utc::now().timestamp();
+    let pending = PendingLogin {
+        nonce: NONCE.to_string(),
+        verifier: pkce_verifier(),
+        link: Some("sso-user".to_string()),
+        invite: None
+    };
+
+    save_state(&conn, "state-1", &pending, now).unwrap();
+    assert_eq!(take_state(&conn, "state-1", now).unwrap(), pending);
+    assert!(matches!(take_state(&conn, "state-1", now), Err(SsoError::InvalidState)));
+}
+
+#[test]
+fn provider_accounts_map_to_users() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    let issuer = "https://idp.example.edu";
+    let users = count_users(&conn);
+
+    // first sign-in creates a user in the configured section
+    let token = map_user(&mut conn, issuer, &claims("a", None), None, None, Some("eecs490")).unwrap();
+    assert_eq!(count_users(&conn), users + 1);
+    assert_eq!(map_user(&mut conn, issuer, &claims("a", None), None, None, None).unwrap(), token);
+
+    // without a section or invite nobody can be created
+    assert!(matches!(
+        map_user(&mut conn, issuer, &claims("b", None), None, None, None),
+        Err(SsoError::NoAccount)
+    ));
+    assert!(matches!(
+        map_user(&mut conn, issuer, &claims("b", None), None, Some("NOPE"), None),
+        Err(SsoError::InvalidInvite)
+    ));
+    let invite = invites::generate(&mut conn, "sso", 1, 1, None).unwrap().remove(0);
+    map_user(&mut conn, issuer, &claims("b", None), None, Some(&invite), None).unwrap();
+
+    // a password account named like the email is never signed in,
+    // anyone could have registered that name
+    let open = invites::generate(&mut conn, "test", 10, 1, None).unwrap().remove(0);
+    let student = insert_password_user(&mut conn, "Student", "correct horse", &open).unwrap();
+    let email = claims("c", Some("student@example.edu"));
+    let created = map_user(&mut conn, issuer, &email, None, None, Some("eecs490")).unwrap();
+    assert_ne!(created, student);
+
+    // linking attaches the provider account to an existing token
+    let linked = insert_password_user(&mut conn, "linker", "correct horse", &open).unwrap();
+    assert_eq!(map_user(&mut conn, issuer, &claims("d", None), Some(&linked), None, None).unwrap(), linked);
+    assert_eq!(map_user(&mut conn, issuer, &claims("d", None), None, None, None).unwrap(), linked);
+
+    // but cannot steal another user's provider account or link a second one
+    assert!(matches!(
+        map_user(&mut conn, issuer, &claims("a", None), Some(&linked), None, None),
+        Err(SsoError::AlreadyLinked)
+    ));
+    assert!(matches!(
+        map_user(&mut conn, issuer, &claims("e", None), Some(&linked), None, None),
+        Err(SsoError::AlreadyLinked)
+    ));
+}
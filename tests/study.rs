@@ -0,0 +1,99 @@
+mod common;
+
+// crates.io
+use rusqlite::{params, Connection, NO_PARAMS};
+// local
+use common::TempDb;
+use fplab_server::api::accounts::{insert_password_user, login_password, login_token};
+use fplab_server::api::invites;
+use fplab_server::api::study::{export, withdraw, Withdrawal, EXPORT_TABLES};
+
+// password account with a row in every exported table
+fn participant(conn: &mut Connection) -> String {
+    let invite = invites::generate(conn, "eecs490", 10, 1, None).unwrap().remove(0);
+    let token = insert_password_user(conn, "alice", "correct horse", &invite).unwrap();
+    conn.execute_batch(&format!("
+        INSERT INTO responses (token, quiz_id, question_id, answer) VALUES ('{0}', 1, 1, 2);
+        INSERT INTO response_events (token, quiz_id, question_id, answer, created) VALUES ('{0}', 1, 1, 2, 1000);
+        INSERT INTO quiz_progress (token, quiz_id, curr_ques) VALUES ('{0}', 1, 2);
+        INSERT INTO arrangements (token, quiz_id, position, slot, question_id) VALUES ('{0}', 1, 0, 3, 3);
+        INSERT INTO rubric_items (question_id, position, description, points) VALUES (1, 0, 'Mentions the move', 2);
+        INSERT INTO fr_grades (token, quiz_id, question_id, score, graded) VALUES ('{0}', 1, 1, 2, 1000);
+        INSERT INTO fr_grade_items (token, quiz_id, question_id, item_id) VALUES ('{0}', 1, 1, (SELECT MAX(item_id) FROM rubric_items));
+        INSERT INTO events (token, kind, client_time, received) VALUES ('{0}', 'page_view', 1000000, 1000);
+        INSERT INTO user_hover (token, svg_name, hover_item, hover_times) VALUES ('{0}', 'a.svg', 'x', 3);
+        INSERT INTO user_page (token, page_item, page_hover) VALUES ('{0}', 'ch1', 10);
+        INSERT INTO edit_states (token, edit_state) VALUES ('{0}', 'fn main() {{}}');
+        INSERT INTO scores (token, problem_id, score) VALUES ('{0}', 0, 1);
+        INSERT INTO consents (token, version, consented, created) VALUES ('{0}', 1, 1, 1000);
+        INSERT INTO failed_attempts (route, ip, credential, status) VALUES ('/accounts/login/', '127.0.0.1', 'alice', 401);
+    ", token)).unwrap();
+    token
+}
+
+fn count(conn: &Connection, sql: &str) -> i64 {
+    conn.query_row(sql, NO_PARAMS, |row| row.get(0)).unwrap()
+}
+
+#[test]
+fn export_contains_every_study_table() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    let token = participant(&mut conn);
+
+    let archive = export(&conn, &token).unwrap().unwrap();
+    assert_eq!(archive["account"]["username"], "alice");
+    assert_eq!(archive["account"]["section"], "eecs490");
+    // passwords are never exported
+    assert!(archive["account"].get("password").is_none());
+    for table in EXPORT_TABLES.iter() {
+        assert_eq!(archive[*table].as_array().unwrap().len(), 1, "{}", table);
+    }
+    assert_eq!(archive["responses"][0]["answer"], 2);
+    assert_eq!(archive["edit_states"][0]["edit_state"], "fn main() {}");
+
+    assert_eq!(export(&conn, "nobody").unwrap(), None);
+}
+
+#[test]
+fn withdrawing_with_delete_removes_all_rows() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    let token = participant(&mut conn);
+
+    assert!(withdraw(&mut conn, &token, Withdrawal::Delete).unwrap());
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM users WHERE username = 'alice'"), 0);
+    for table in EXPORT_TABLES.iter() {
+        assert_eq!(count(&conn, &format!("SELECT COUNT(*) FROM {}", table)), 0, "{}", table);
+    }
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM failed_attempts"), 0);
+
+    assert!(!withdraw(&mut conn, &token, Withdrawal::Delete).unwrap());
+}
+
+#[test]
+fn withdrawing_with_anonymize_keeps_unlinked_rows() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    let token = participant(&mut conn);
+
+    assert!(withdraw(&mut conn, &token, Withdrawal::Anonymize).unwrap());
+    assert_eq!(export(&conn, &token).unwrap(), None);
+    assert_eq!(login_password(&conn, "alice", "correct horse").unwrap(), None);
+
+    let anonymous : String = conn.query_row(
+        "SELECT token FROM responses", NO_PARAMS, |row| row.get(0)
+    ).unwrap();
+    assert!(anonymous.starts_with("anon-"));
+    assert!(anonymous.len() <= 20);
+    for table in EXPORT_TABLES.iter() {
+        let n : i64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM {} WHERE token = ?1", table),
+            params![&anonymous], |row| row.get(0)
+        ).unwrap();
+        assert_eq!(n, 1, "{}", table);
+    }
+    // the new token cannot be used to log in
+    assert_eq!(login_token(&conn, &anonymous).unwrap(), None);
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM users WHERE section IS NOT NULL"), 0);
+}
@@ -0,0 +1,67 @@
+// crates.io
+use handlebars::Handlebars;
+use serde_json::json;
+
+// registry loaded the way main does
+fn registry() -> Handlebars<'static> {
+    let mut handlebars = Handlebars::new();
+    handlebars
+        .register_templates_directory(".html", "./rustviz/html")
+        .unwrap();
+    handlebars
+}
+
+#[test]
+fn login_page_shows_errors_and_entered_values() {
+    let page = registry().render("login", &json!({
+        "user": null,
+        "csrf_token": "t0k3n",
+        "flashes": [],
+        "next": "/fp/",
+        "next_query": "?next=%2Ffp%2F",
+        "values": { "username": "alice" },
+        "errors": { "username": "Incorrect username or password!" }
+    })).unwrap();
+
+    // shared layout
+    assert!(page.starts_with("<!DOCTYPE html>"));
+    assert!(page.contains("href=\"/accounts/create/\">Sign up</a>"));
+    // failed submission
+    assert!(page.contains("<p class=\"field-error\">Incorrect username or password!</p>"));
+    assert!(page.contains("value=\"alice\""));
+    assert!(!page.contains("type=\"password\" name=\"password\" placeholder=\"password\" value="));
+    assert!(page.contains("name=\"next\" value=\"/fp/\""));
+}
+
+#[test]
+fn layout_shows_logged_in_user_and_flashes() {
+    let page = registry().render("index", &json!({
+        "user": "alice",
+        "csrf_token": "t0k3n",
+        "flashes": [{ "level": "info", "message": "Welcome back!" }],
+        "logged_in": 1
+    })).unwrap();
+
+    assert!(page.contains("<title>An Interactive Visual Introduction to Rust</title>"));
+    assert!(page.contains("Logged in as <strong>alice</strong>"));
+    assert!(page.contains("action=\"/accounts/logout/\""));
+    assert!(page.contains("<p class=\"flash flash-info\">Welcome back!</p>"));
+    // logout form appears once, in the layout
+    assert_eq!(page.matches("/accounts/logout/").count(), 1);
+}
+
+#[test]
+fn create_page_escapes_entered_values() {
+    let page = registry().render("create", &json!({
+        "user": null,
+        "csrf_token": "t0k3n",
+        "flashes": [],
+        "next": "",
+        "next_query": "",
+        "values": { "invite": "\"><script>", "token": "", "username": "" },
+        "errors": { "invite": "Invalid invite code!" }
+    })).unwrap();
+
+    assert!(page.contains("<span class=\"field-error\">Invalid invite code!</span>"));
+    assert!(!page.contains("\"><script>"));
+}
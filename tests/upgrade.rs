@@ -0,0 +1,110 @@
+mod common;
+
+// crates.io
+use rusqlite::{Connection, NO_PARAMS};
+// local
+use common::TempDb;
+use fplab_server::db::{self, upgrade::{upgrade, UpgradeError}};
+
+// open a database created by the schema deployed before versions were
+// recorded, next to db so that it is deleted with it
+fn version_0(db: &TempDb) -> Connection {
+    let conn = db::open(db.path.with_file_name("v0.sqlite3")).unwrap();
+    conn.execute_batch(include_str!("fixtures/schema_v0.sql")).unwrap();
+    conn
+}
+
+fn count(conn: &Connection, sql: &str) -> i64 {
+    conn.query_row(sql, NO_PARAMS, |row| row.get(0)).unwrap()
+}
+
+// names and definitions of every table, to compare schemas with
+fn tables(conn: &Connection) -> Vec<(String, String)> {
+    let mut stmt = conn.prepare(
+        "SELECT name, sql FROM sqlite_master
+        WHERE type = 'table' AND name NOT LIKE 'sqlite_%'
+        ORDER BY name"
+    ).unwrap();
+    let rows = stmt.query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+    rows.map(|row| row.unwrap()).collect()
+}
+
+// strip comments, whitespace and the quotes renaming a table adds,
+// which differ between sql/schema.sql and the step
+fn normalize(sql: &str) -> String {
+    sql.replace('"', "")
+        .lines()
+        .map(|line| line.split("--").next().unwrap())
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<String>()
+}
+
+#[test]
+fn baseline_databases_keep_their_study_data() {
+    let db = TempDb::new();
+    let mut conn = version_0(&db);
+    conn.execute_batch("
+        INSERT INTO users (token, curr_ques) VALUES ('student-1', 4);
+        INSERT INTO users (token) VALUES ('student-2');
+        INSERT INTO responses (token, question_id, answer, time_elapsed, free_response)
+        VALUES ('student-1', 2, 0, 5300, 's is moved');
+        INSERT INTO responses (token, question_id, answer) VALUES ('student-1', 3, 1);
+        INSERT INTO responses (token, question_id) VALUES ('student-2', 0);
+        INSERT INTO user_hover (token, svg_name, hover_item, hover_times) VALUES ('student-1', 'vis_04_01_01', 's', 3);
+        INSERT INTO scores (token, problem_id, score) VALUES ('student-2', 1, 1);
+    ").unwrap();
+
+    assert_eq!(upgrade(&mut conn).unwrap(), 0);
+    assert_eq!(db::schema_version(&conn).unwrap(), db::SCHEMA_VERSION);
+
+    // the upgraded database has the tables of a new one
+    let fresh = tables(&db.connect());
+    let upgraded = tables(&conn);
+    assert_eq!(
+        upgraded.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+        fresh.iter().map(|(name, _)| name).collect::<Vec<_>>()
+    );
+    for ((name, sql), (_, expected)) in upgraded.iter().zip(fresh.iter()) {
+        assert_eq!(normalize(sql), normalize(expected), "{} differs", name);
+    }
+
+    // the only quiz is quiz 1, where users resume where they left off
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM quiz_questions WHERE quiz_id = 1"), 11);
+    assert_eq!(count(&conn, "SELECT curr_ques FROM quiz_progress WHERE token = 'student-1' AND quiz_id = 1"), 4);
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM quiz_progress WHERE token = 'student-2'"), 0);
+
+    // responses move to quiz 1 and are graded as they would have been
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM responses WHERE quiz_id = 1"), 3);
+    assert_eq!(count(&conn, "SELECT time_elapsed FROM responses WHERE question_id = 2"), 5300);
+    assert_eq!(count(&conn, "SELECT correct + attempts FROM responses WHERE question_id = 2"), 2);
+    assert_eq!(count(&conn, "SELECT correct FROM responses WHERE question_id = 3"), 0);
+    assert_eq!(count(&conn, "SELECT attempts FROM responses WHERE token = 'student-2'"), 0);
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM user_hover"), 1);
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM users WHERE admin = 1"), 2);
+
+    // study data still cascades from its users
+    conn.execute("DELETE FROM users WHERE token = 'student-1'", NO_PARAMS).unwrap();
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM responses"), 1);
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM quiz_progress"), 0);
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM user_hover"), 0);
+}
+
+#[test]
+fn current_databases_are_left_alone() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    assert_eq!(upgrade(&mut conn).unwrap(), db::SCHEMA_VERSION);
+}
+
+#[test]
+fn newer_databases_are_refused() {
+    let db = TempDb::new();
+    let mut conn = db.connect();
+    conn.execute_batch("PRAGMA user_version = 2;").unwrap();
+    match upgrade(&mut conn) {
+        Err(UpgradeError::Unsupported(2)) => (),
+        other => panic!("expected an unsupported version, got {:?}", other)
+    }
+    assert_eq!(db::schema_version(&conn).unwrap(), 2);
+}